@@ -104,7 +104,7 @@ JOYPAD_VECT:
     DB 0x00
 
     ; 0x014D (Complement check)
-    DB 0x00 ; TODO: You will have to do this manually.
+    DB 0x00 ; Filled in by the RomBuilder
 
     ; 0x014E-0x014F (Cartridge checksum)
     DW 0x00
@@ -0,0 +1,341 @@
+//! Generates the rust code that constructs a value of the AST, used to embed the parsed instructions in the
+//! expansion of the macro.
+//!
+//! The AST derives Serialize via the `serde-ast` feature of ggbasm, serde provides the name of each type and variant
+//! so the paths of the constructors are known without listing every [ggbasm::ast::Instruction] variant.
+
+use std::fmt::{self, Display, Write};
+
+use serde::ser::{self, Impossible, Serialize};
+
+/// Returns rust code that evaluates to the value, types of the AST are referred to via `::ggbasm::ast`.
+pub fn rust_code<T: Serialize>(value: &T) -> Result<String, Error> {
+    let mut code = String::new();
+    value.serialize(&mut Codegen { code: &mut code })?;
+    Ok(code)
+}
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+struct Codegen<'a> {
+    code: &'a mut String,
+}
+
+impl Codegen<'_> {
+    fn write(&mut self, text: impl Display) -> Result<(), Error> {
+        write!(self.code, "{}", text).map_err(ser::Error::custom)
+    }
+
+    /// Writes the start of a tuple like constructor and returns the serializer for its items.
+    fn open(
+        &mut self,
+        path: String,
+        open: &'static str,
+        close: &'static str,
+    ) -> Result<Items<'_>, Error> {
+        self.write(path)?;
+        self.write(open)?;
+        Ok(Items {
+            code: self.code,
+            close,
+        })
+    }
+}
+
+/// Writes the comma separated items of a tuple, vec or struct, followed by the closing delimiter.
+struct Items<'a> {
+    code: &'a mut String,
+    close: &'static str,
+}
+
+impl Items<'_> {
+    fn item<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize(&mut Codegen { code: self.code })?;
+        self.code.push_str(", ");
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.code.push_str(self.close);
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for Items<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Items::end(self)
+    }
+}
+
+impl ser::SerializeTuple for Items<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Items::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Items<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Items::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for Items<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Items::end(self)
+    }
+}
+
+impl ser::SerializeStruct for Items<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.code.push_str(key);
+        self.code.push_str(": ");
+        self.item(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Items::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for Items<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Items::end(self)
+    }
+}
+
+fn path(name: &str) -> String {
+    format!("::ggbasm::ast::{}", name)
+}
+
+fn variant_path(name: &str, variant: &str) -> String {
+    format!("::ggbasm::ast::{}::{}", name, variant)
+}
+
+impl<'a, 'b> ser::Serializer for &'a mut Codegen<'b> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Items<'a>;
+    type SerializeTuple = Items<'a>;
+    type SerializeTupleStruct = Items<'a>;
+    type SerializeTupleVariant = Items<'a>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Items<'a>;
+    type SerializeStructVariant = Items<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.write(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.write(format_args!("{}i8", v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.write(format_args!("{}i16", v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.write(format_args!("{}i32", v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.write(format_args!("{}i64", v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.write(format_args!("{}u8", v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.write(format_args!("{}u16", v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.write(format_args!("{}u32", v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.write(format_args!("{}u64", v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        self.write(format_args!("{:?}f32", v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.write(format_args!("{:?}f64", v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        // the escapes used by Debug are valid rust
+        self.write(format_args!("{:?}", v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.write(format_args!("::std::string::String::from({:?})", v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        let mut items = self.open(String::from("::std::vec!"), "[", "]")?;
+        for byte in v {
+            items.item(byte)?;
+        }
+        items.end()
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.write("::std::option::Option::None")
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        let mut items = self.open(String::from("::std::option::Option::Some"), "(", ")")?;
+        items.item(value)?;
+        items.end()
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.write("()")
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<(), Error> {
+        self.write(path(name))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.write(variant_path(name, variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        let mut items = self.open(path(name), "(", ")")?;
+        items.item(value)?;
+        items.end()
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        let mut items = self.open(variant_path(name, variant), "(", ")")?;
+        items.item(value)?;
+        items.end()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Items<'a>, Error> {
+        self.open(String::from("::std::vec!"), "[", "]")
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Items<'a>, Error> {
+        self.open(String::new(), "(", ")")
+    }
+
+    fn serialize_tuple_struct(self, name: &'static str, _len: usize) -> Result<Items<'a>, Error> {
+        self.open(path(name), "(", ")")
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Items<'a>, Error> {
+        self.open(variant_path(name, variant), "(", ")")
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(ser::Error::custom("maps are not part of the AST"))
+    }
+
+    /// Structs are wrapped in From::from as the struct can be boxed, e.g. the [ggbasm::ast::BinaryExpr] of an
+    /// [ggbasm::ast::Expr::Binary].
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Items<'a>, Error> {
+        self.open(
+            format!("::std::convert::From::from({}", path(name)),
+            " { ",
+            " })",
+        )
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Items<'a>, Error> {
+        self.open(variant_path(name, variant), " { ", " }")
+    }
+}
@@ -0,0 +1,165 @@
+//! The [gb_asm] macro, inline gameboy assembly for rust code using [ggbasm](https://docs.rs/ggbasm).
+//!
+//! The assembly is parsed when the rust code is compiled, so syntax errors and operands that do not fit are
+//! reported by the rust compiler, pointing at the offending assembly.
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+use ggbasm::ast::Instruction;
+use ggbasm::parser;
+
+mod codegen;
+
+/// Parses gameboy assembly at compile time, evaluating to a `Vec<Instruction>` of the instructions.
+///
+/// Instructions are separated by line breaks or `;`, as `;` cannot start an asm comment within rust tokens
+/// use rust comments instead.
+/// Empty lines are not included in the Vec.
+///
+/// ```
+/// use ggbasm::ast::{Expr, Instruction, Reg8};
+/// use ggbasm_macros::gb_asm;
+///
+/// let instructions: Vec<Instruction> = gb_asm! {
+///     Main:
+///         ld a, $05
+///         call Foo; halt
+/// };
+/// assert_eq!(instructions, vec![
+///     Instruction::Label(String::from("Main")),
+///     Instruction::LdR8I8(Reg8::A, Expr::Const(5)),
+///     Instruction::Call(ggbasm::ast::Flag::Always, Expr::Ident(String::from("Foo"))),
+///     Instruction::Halt,
+/// ]);
+/// ```
+///
+/// Invalid assembly fails to compile:
+/// ```compile_fail
+/// let instructions = ggbasm_macros::gb_asm! {
+///     ld a, 256
+/// };
+/// ```
+#[proc_macro]
+pub fn gb_asm(input: TokenStream) -> TokenStream {
+    let mut asm = Asm {
+        text: String::new(),
+        lines: vec![vec![]],
+        end: None,
+    };
+    asm.push_tokens(input);
+
+    let parsed = match parser::parse_asm_spanned(&asm.text) {
+        Ok(parsed) => parsed,
+        Err(err) => return compile_error(&err.to_string(), Span::call_site()),
+    };
+    let mut items = String::new();
+    for instruction in parsed {
+        match instruction {
+            Ok(spanned) if spanned.inner == Instruction::EmptyLine => {}
+            Ok(spanned) => match codegen::rust_code(&spanned.inner) {
+                Ok(code) => {
+                    items.push_str(&code);
+                    items.push_str(", ");
+                }
+                Err(err) => return compile_error(&err.to_string(), Span::call_site()),
+            },
+            Err(diagnostic) => {
+                // the first line of the diagnostic describes the error, the rest points at the text
+                let message = diagnostic.to_string();
+                let message = message.lines().next().unwrap_or_default();
+                let span = asm.span(diagnostic.line, diagnostic.columns.start);
+                return compile_error(message, span);
+            }
+        }
+    }
+    let code = format!(
+        "{{ let instructions: ::std::vec::Vec<::ggbasm::ast::Instruction> = ::std::vec![{}]; instructions }}",
+        items
+    );
+    code.parse().unwrap()
+}
+
+/// The asm text written from the tokens given to the macro.
+struct Asm {
+    text: String,
+    /// the start of each token in its line of the text and the span of the token, for each line
+    lines: Vec<Vec<(usize, Span)>>,
+    /// the line and column where the previous token ended, None at the start of a line
+    end: Option<(usize, usize)>,
+}
+
+impl Asm {
+    fn push_tokens(&mut self, tokens: TokenStream) {
+        for token in tokens {
+            match token {
+                TokenTree::Group(group) => self.push_group(group),
+                TokenTree::Punct(punct) if punct.as_char() == ';' => self.newline(),
+                token => self.push(&token.to_string(), token.span()),
+            }
+        }
+    }
+
+    fn push_group(&mut self, group: Group) {
+        let (open, close) = match group.delimiter() {
+            Delimiter::Parenthesis => ("(", ")"),
+            Delimiter::Bracket => ("[", "]"),
+            Delimiter::Brace => ("{", "}"),
+            Delimiter::None => ("", ""),
+        };
+        self.push(open, group.span_open());
+        self.push_tokens(group.stream());
+        self.push(close, group.span_close());
+    }
+
+    /// Appends the text of a token, separated from the previous token by the same whitespace as the source.
+    fn push(&mut self, text: &str, span: Span) {
+        let start = span.start();
+        match self.end {
+            Some((line, _)) if line != start.line() => self.newline(),
+            Some((_, column)) => {
+                let spaces = start.column().saturating_sub(column);
+                self.text.extend(std::iter::repeat_n(' ', spaces));
+            }
+            None => {}
+        }
+        let line_start = self.text.rfind('\n').map_or(0, |x| x + 1);
+        self.lines
+            .last_mut()
+            .unwrap()
+            .push((self.text.len() - line_start, span));
+        self.text.push_str(text);
+        let end = span.end();
+        self.end = Some((end.line(), end.column()));
+    }
+
+    fn newline(&mut self) {
+        self.text.push('\n');
+        self.lines.push(vec![]);
+        self.end = None;
+    }
+
+    /// Returns the span of the token at the byte offset in the 1 indexed line.
+    fn span(&self, line: usize, offset: usize) -> Span {
+        self.lines
+            .get(line.wrapping_sub(1))
+            .and_then(|tokens| tokens.iter().rev().find(|(start, _)| *start <= offset))
+            .map_or(Span::call_site(), |(_, span)| *span)
+    }
+}
+
+/// Returns `compile_error!(message)` with the span, so the rust compiler reports the error at the span.
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut group = Group::new(Delimiter::Brace, TokenTree::Literal(message).into());
+    group.set_span(span);
+    [
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(group),
+    ]
+    .into_iter()
+    .collect()
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use ggbasm::ast::*;
+use ggbasm::parser::parse_asm;
+use ggbasm_macros::gb_asm;
+
+fn parse(text: &str) -> Vec<Instruction> {
+    parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .filter(|x| *x != Instruction::EmptyLine)
+        .collect()
+}
+
+#[test]
+fn test_gb_asm() {
+    let instructions = gb_asm! {
+        Main:
+            ld a, $05
+            ld [hl+], a
+            ldh [$FF00 + $41], a
+            jr nz, Main + 2 * (3 - 1)
+            call Foo; halt
+            db "Hi", -1, %1010
+            dw 0x1234
+            SECTION "Table", ROMX[$4000], BANK[2]
+        Table:
+        :
+            jr :-
+            res 3, [hl]
+            push af
+            Count EQU 3 << 2
+    };
+    let expected = parse(
+        r#"Main:
+    ld a, $05
+    ld [hl+], a
+    ldh [$FF00 + $41], a
+    jr nz, Main + 2 * (3 - 1)
+    call Foo
+    halt
+    db "Hi", -1, %1010
+    dw 0x1234
+    SECTION "Table", ROMX[$4000], BANK[2]
+Table:
+:
+    jr :-
+    res 3, [hl]
+    push af
+    Count EQU 3 << 2
+"#,
+    );
+    assert_eq!(instructions, expected);
+    assert_eq!(instructions.len(), 16);
+}
+
+#[test]
+fn test_gb_asm_encodes() {
+    let instructions = gb_asm! {
+        ld a, 5
+        ld b, a
+        xor a
+    };
+    let mut rom = vec![];
+    for instruction in &instructions {
+        instruction.write_to_rom(&mut rom, &HashMap::new()).unwrap();
+    }
+    assert_eq!(rom, vec![0x3E, 0x05, 0x47, 0xAF]);
+    assert_eq!(gb_asm! {}, vec![]);
+}
@@ -0,0 +1,103 @@
+//! Typed rom banks and addresses, so an address within a bank cant be mistaken for an address within the entire rom.
+
+use std::fmt;
+
+use anyhow::{bail, Error};
+
+use crate::constants::ROM_BANK_SIZE;
+
+/// A rom bank, from 0 to 511.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bank(pub u16);
+
+/// An address as seen by the cpu while a rom bank is mapped in.
+/// Bank 0 is at 0x0000-0x3FFF and every other bank is at 0x4000-0x7FFF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BankAddr(pub u16);
+
+/// An address within the entire rom, e.g. 0x14000 is the start of bank 5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GlobalAddr(pub u32);
+
+impl Bank {
+    /// The last bank supported by any memory bank controller.
+    pub const MAX: Bank = Bank(511);
+
+    /// Returns an error if the bank is larger than [Bank::MAX].
+    pub fn new(bank: u32) -> Result<Bank, Error> {
+        if bank > Bank::MAX.0 as u32 {
+            bail!("Bank {} is larger than the last bank {}", bank, Bank::MAX.0);
+        }
+        Ok(Bank(bank as u16))
+    }
+}
+
+impl BankAddr {
+    /// Returns an error if the address is not in rom, i.e. 0x8000 or above.
+    pub fn new(address: u16) -> Result<BankAddr, Error> {
+        if address as u32 >= ROM_BANK_SIZE * 2 {
+            bail!(
+                "Address 0x{:04X} is not in rom, which is 0x0000-0x7FFF",
+                address
+            );
+        }
+        Ok(BankAddr(address))
+    }
+}
+
+impl GlobalAddr {
+    /// Returns the address within the entire rom of address in bank.
+    ///
+    /// Returns an error if the bank is larger than [Bank::MAX] or the address is not where the bank is mapped in.
+    pub fn new(bank: Bank, address: BankAddr) -> Result<GlobalAddr, Error> {
+        let bank = Bank::new(bank.0 as u32)?.0 as u32;
+        let address = BankAddr::new(address.0)?.0 as u32;
+        let offset = match (bank, address) {
+            (0, address) if address < ROM_BANK_SIZE => address,
+            (0, address) => bail!(
+                "Address 0x{:04X} is not in bank 0, which is mapped to 0x0000-0x3FFF",
+                address
+            ),
+            (bank, address) if address < ROM_BANK_SIZE => bail!(
+                "Address 0x{:04X} is not in bank {}, which is mapped to 0x4000-0x7FFF",
+                address,
+                bank
+            ),
+            (_, address) => address - ROM_BANK_SIZE,
+        };
+        Ok(GlobalAddr(bank * ROM_BANK_SIZE + offset))
+    }
+
+    /// Returns the bank the address is in.
+    pub fn bank(self) -> Bank {
+        Bank((self.0 / ROM_BANK_SIZE) as u16)
+    }
+
+    /// Returns the address as seen by the cpu while its bank is mapped in.
+    pub fn bank_addr(self) -> BankAddr {
+        let offset = (self.0 % ROM_BANK_SIZE) as u16;
+        if self.0 < ROM_BANK_SIZE {
+            BankAddr(offset)
+        } else {
+            BankAddr(offset + ROM_BANK_SIZE as u16)
+        }
+    }
+}
+
+impl fmt::Display for Bank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bank {}", self.0)
+    }
+}
+
+impl fmt::Display for BankAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:04X}", self.0)
+    }
+}
+
+impl fmt::Display for GlobalAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:X}", self.0)
+    }
+}
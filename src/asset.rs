@@ -0,0 +1,285 @@
+//! Converts image and audio files into rom data.
+//!
+//! Conversion is independent of the RomBuilder so it can run on worker threads.
+//! [crate::RomBuilder::add_assets] converts a list of [AssetJob] in parallel and adds the results in order.
+//! Alternatively convert jobs on your own threads with [AssetJob::convert] and add the results
+//! with [crate::RomBuilder::add_converted_asset].
+//!
+//! Assets registered as data regions can be hot reloaded into a compiled rom by converting them again
+//! and applying [ConvertedAsset::to_bytes] with [Layout::patch_data_region].
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+use anyhow::{bail, Error};
+
+use crate::address::GlobalAddr;
+use crate::ast::Instruction;
+use crate::audio::{self, AudioOptions};
+use crate::constants::ROM_BANK_SIZE;
+use crate::layout::Layout;
+use crate::Color;
+
+/// An image or audio file to be converted.
+#[derive(Debug, Clone)]
+pub enum AssetJob {
+    /// An image in the graphics folder, see [crate::RomBuilder::add_image]
+    Image {
+        file_name: String,
+        identifier: String,
+        color_map: HashMap<Color, u8>,
+    },
+    /// An audio file in the audio folder, see [crate::RomBuilder::add_audio_file_with_options]
+    Audio {
+        file_name: String,
+        options: AudioOptions,
+    },
+}
+
+/// The result of converting an [AssetJob].
+#[derive(Debug, Clone)]
+pub enum ConvertedAsset {
+    Image {
+        identifier: String,
+        bytes: Vec<u8>,
+    },
+    Audio {
+        file_name: String,
+        instructions: Vec<Instruction>,
+    },
+}
+
+impl ConvertedAsset {
+    /// Returns the bytes of the asset as they are compiled when placed at the address in the rom.
+    ///
+    /// Labels declared by the asset are placed relative to address, other identifiers are resolved via the symbols of the layout.
+    /// Used with [Layout::patch_data_region] to hot reload an asset without rebuilding the rom.
+    pub fn to_bytes(&self, address: u32, layout: &Layout) -> Result<Vec<u8>, Error> {
+        let instructions = match self {
+            ConvertedAsset::Image { bytes, .. } => return Ok(bytes.clone()),
+            ConvertedAsset::Audio { instructions, .. } => instructions,
+        };
+
+        // labels in the layout are addresses within the entire rom, but are referred to as seen by the cpu
+        let cpu_address = |x: u32| GlobalAddr(x).bank_addr().0 as i64;
+        let mut constants: HashMap<String, i64> = layout
+            .symbols
+            .iter()
+            .filter(|x| x.private_to.is_none())
+            .map(|x| {
+                let value = if x.label {
+                    cpu_address(x.value as u32)
+                } else {
+                    x.value
+                };
+                (x.name.clone(), value)
+            })
+            .collect();
+        let mut next_address = address;
+        for instruction in instructions {
+            if let Instruction::Label(label) = instruction {
+                constants.insert(label.clone(), cpu_address(next_address));
+            }
+            next_address += instruction.bytes_len((next_address % ROM_BANK_SIZE) as u16)? as u32;
+        }
+
+        // instructions such as jr and advance_address depend on their address so they are encoded at it
+        let mut bytes = vec![];
+        for instruction in instructions {
+            let current_address = address + bytes.len() as u32;
+            match instruction {
+                Instruction::AdvanceAddress(..) => {
+                    let len = instruction.advance_address_len(current_address, &constants)?;
+                    bytes.resize(bytes.len() + len as usize, 0x00);
+                }
+                instruction if instruction.encoding().is_some() => {
+                    bytes.extend(instruction.encode(current_address, &constants)?)
+                }
+                instruction => instruction.write_to_rom(&mut bytes, &constants)?,
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+impl AssetJob {
+    /// Converts the asset, reading it from the folders in root_dir.
+    ///
+    /// root_dir is usually [crate::RomBuilder::root_dir].
+    pub fn convert(&self, root_dir: &Path) -> Result<ConvertedAsset, Error> {
+        match self {
+            AssetJob::Image {
+                file_name,
+                identifier,
+                color_map,
+            } => Ok(ConvertedAsset::Image {
+                identifier: identifier.clone(),
+                bytes: convert_image(root_dir, file_name, color_map)?,
+            }),
+            AssetJob::Audio { file_name, options } => Ok(ConvertedAsset::Audio {
+                file_name: file_name.clone(),
+                instructions: convert_audio(root_dir, file_name, options)?,
+            }),
+        }
+    }
+}
+
+/// Converts every job using a thread per available CPU.
+///
+/// The results are in the same order as the jobs.
+/// Returns the error of the first failing job.
+pub fn convert_assets(root_dir: &Path, jobs: &[AssetJob]) -> Result<Vec<ConvertedAsset>, Error> {
+    let threads = thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1)
+        .min(jobs.len())
+        .max(1);
+    let chunk_size = jobs.len().div_ceil(threads).max(1);
+
+    let results: Vec<Vec<Result<ConvertedAsset, Error>>> = thread::scope(|scope| {
+        let handles: Vec<_> = jobs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || chunk.iter().map(|job| job.convert(root_dir)).collect())
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Asset conversion thread panicked"))
+            .collect()
+    });
+
+    results.into_iter().flatten().collect()
+}
+
+/// Converts the image in the graphics folder into gameboy tile data.
+pub(crate) fn convert_image(
+    root_dir: &Path,
+    file_name: &str,
+    color_map: &HashMap<Color, u8>,
+) -> Result<Vec<u8>, Error> {
+    let path = root_dir.join("graphics").join(file_name);
+    let image = match image::open(path) {
+        Ok(image) => image,
+        Err(err) => bail!("Cannot read file {} because: {}", file_name, err),
+    };
+    let mut bytes = vec![];
+    let image = image.to_rgb8();
+    // pixel count of every color in the image, used to report unmapped colors
+    let mut colors: HashMap<Color, usize> = HashMap::new();
+    for vert_tile in 0..(image.height() / 8) {
+        for hor_tile in 0..(image.width() / 8) {
+            for vert_line in 0..8 {
+                let mut byte0 = 0x00;
+                let mut byte1 = 0x00;
+                for hor_line in 0..8 {
+                    let x = hor_tile * 8 + hor_line;
+                    let y = vert_tile * 8 + vert_line;
+                    let rgb = image.get_pixel(x, y);
+                    let color = Color::new(rgb[0], rgb[1], rgb[2]);
+
+                    if let Some(gb_color) = color_map.get(&color) {
+                        byte0 |= (gb_color & 0b01) << (7 - hor_line);
+                        byte1 |= ((gb_color & 0b10) >> 1) << (7 - hor_line);
+                    }
+                    *colors.entry(color).or_insert(0) += 1;
+                }
+                bytes.push(byte0);
+                bytes.push(byte1);
+            }
+        }
+    }
+    if colors.keys().any(|x| !color_map.contains_key(x)) {
+        bail!(unmapped_colors_message(file_name, &colors, color_map));
+    }
+    Ok(bytes)
+}
+
+/// Converts the audio text file in the audio folder into audio data.
+pub(crate) fn convert_audio(
+    root_dir: &Path,
+    file_name: &str,
+    options: &AudioOptions,
+) -> Result<Vec<Instruction>, Error> {
+    let path = root_dir.join("audio").join(file_name);
+    let text = match fs::read_to_string(path) {
+        Ok(file) => file,
+        Err(err) => bail!("Cannot read audio file {} because: {}", file_name, err),
+    };
+
+    let lines = match audio::parse_audio_text(&text) {
+        Ok(lines) => lines,
+        Err(err) => bail!("Cannot parse audio file {} because: {}", file_name, err),
+    };
+
+    match audio::generate_audio_data_with_options(lines, options) {
+        Ok(lines) => Ok(lines),
+        Err(err) => bail!(
+            "Cannot generate audio from file {} because: {}",
+            file_name,
+            err
+        ),
+    }
+}
+
+/// Lists every unmapped color and its pixel count, most used first.
+/// When the image has at most 4 colors a color map that can be pasted into rust code is suggested.
+fn unmapped_colors_message(
+    file_name: &str,
+    colors: &HashMap<Color, usize>,
+    color_map: &HashMap<Color, u8>,
+) -> String {
+    let mut unmapped: Vec<(&Color, &usize)> = colors
+        .iter()
+        .filter(|(color, _)| !color_map.contains_key(color))
+        .collect();
+    unmapped.sort_by_key(|(color, count)| {
+        (
+            std::cmp::Reverse(**count),
+            color.red,
+            color.green,
+            color.blue,
+        )
+    });
+
+    let mut message = format!(
+        "Image {} contains {} colors that are not mapped to a gameboy color:\n",
+        file_name,
+        unmapped.len()
+    );
+    for (color, count) in unmapped {
+        message.push_str(&format!(
+            "    Color::new(0x{:x}, 0x{:x}, 0x{:x}) used by {} pixels\n",
+            color.red, color.green, color.blue, count
+        ));
+    }
+
+    if colors.len() <= 4 {
+        // gameboy color 0 is the lightest so sort from lightest to darkest
+        let mut sorted: Vec<&Color> = colors.keys().collect();
+        sorted.sort_by_key(|color| {
+            std::cmp::Reverse(
+                color.red as u32 * 299 + color.green as u32 * 587 + color.blue as u32 * 114,
+            )
+        });
+        message.push_str(&format!(
+            "The image only uses {} colors so this color map could be used:\n",
+            sorted.len()
+        ));
+        message.push_str("    let mut color_map = HashMap::new();\n");
+        for (i, color) in sorted.iter().enumerate() {
+            let gb_color = if sorted.len() > 1 {
+                (i * 3 + (sorted.len() - 1) / 2) / (sorted.len() - 1)
+            } else {
+                0
+            };
+            message.push_str(&format!(
+                "    color_map.insert(Color::new(0x{:x}, 0x{:x}, 0x{:x}), {});\n",
+                color.red, color.green, color.blue, gb_color
+            ));
+        }
+    }
+    message
+}
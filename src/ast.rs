@@ -1,11 +1,17 @@
 //! The AST produced by the parser.
 //!
 //! You can manually create the types below and give them to the RomBuilder via RomBuilder::add_instructions(instructions)
+//!
+//! Helpers such as [Instruction::jp] and [Instruction::db_str] validate their arguments when
+//! constructing the instruction, rather than when the rom is compiled.
 
 use std::collections::HashMap;
 
 use anyhow::{bail, Error};
+use arrayvec::ArrayVec;
 use byteorder::{ByteOrder, LittleEndian};
+#[cfg(feature = "serde-ast")]
+use serde::{Deserialize, Serialize};
 use thiserror::Error as ThisError;
 
 use crate::constants::*;
@@ -16,16 +22,24 @@ use crate::constants::*;
 /// The run method evaluates the constant expression.
 /// The get_2bytes, get_byte and get_bit_index evaluate the constant expression but also convert
 /// to a specific low level type needed by instructions.
+///
+/// Identifiers are Strings by default, the parser can also produce expressions that borrow them from the source,
+/// see [crate::parser::parse_asm_borrowed].
 #[derive(Clone, PartialEq, Debug)]
-pub enum Expr {
-    Ident(String),
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
+pub enum Expr<S = String> {
+    Ident(S),
     Const(i64),
-    Binary(Box<BinaryExpr>),
-    Unary(Box<UnaryExpr>),
+    Binary(Box<BinaryExpr<S>>),
+    Unary(Box<UnaryExpr<S>>),
+    /// A reference to an anonymous label, `:+` is 1 and refers to the next anonymous label,
+    /// `:--` is -2 and refers to the second previous anonymous label.
+    /// Resolved into [Expr::Ident] by the RomBuilder.
+    AnonymousLabelRef(i32),
 }
 
-impl Expr {
-    pub fn binary(left: Expr, operator: BinaryOperator, right: Expr) -> Expr {
+impl<S> Expr<S> {
+    pub fn binary(left: Expr<S>, operator: BinaryOperator, right: Expr<S>) -> Expr<S> {
         Expr::Binary(Box::new(BinaryExpr {
             left,
             operator,
@@ -33,10 +47,71 @@ impl Expr {
         }))
     }
 
-    pub fn unary(expr: Expr, operator: UnaryOperator) -> Expr {
+    pub fn unary(expr: Expr<S>, operator: UnaryOperator) -> Expr<S> {
         Expr::Unary(Box::new(UnaryExpr { expr, operator }))
     }
 
+    /// Converts each identifier with f, e.g. from borrowed to owned.
+    pub fn map_text<T>(self, f: &mut dyn FnMut(S) -> T) -> Expr<T> {
+        match self {
+            Expr::Ident(ident) => Expr::Ident(f(ident)),
+            Expr::Const(value) => Expr::Const(value),
+            Expr::Binary(binary) => {
+                let binary = *binary;
+                Expr::binary(
+                    binary.left.map_text(f),
+                    binary.operator,
+                    binary.right.map_text(f),
+                )
+            }
+            Expr::Unary(unary) => {
+                let unary = *unary;
+                Expr::unary(unary.expr.map_text(f), unary.operator)
+            }
+            Expr::AnonymousLabelRef(offset) => Expr::AnonymousLabelRef(offset),
+        }
+    }
+}
+
+impl<S> From<i64> for Expr<S> {
+    fn from(value: i64) -> Expr<S> {
+        Expr::Const(value)
+    }
+}
+
+impl<S> From<i32> for Expr<S> {
+    fn from(value: i32) -> Expr<S> {
+        Expr::Const(value as i64)
+    }
+}
+
+impl<S> From<u8> for Expr<S> {
+    fn from(value: u8) -> Expr<S> {
+        Expr::Const(value as i64)
+    }
+}
+
+impl<S> From<u16> for Expr<S> {
+    fn from(value: u16) -> Expr<S> {
+        Expr::Const(value as i64)
+    }
+}
+
+/// Converts an identifier, e.g. the name of a label.
+impl From<&str> for Expr {
+    fn from(ident: &str) -> Expr {
+        Expr::Ident(ident.to_string())
+    }
+}
+
+/// Converts an identifier, e.g. the name of a label.
+impl From<String> for Expr {
+    fn from(ident: String) -> Expr {
+        Expr::Ident(ident)
+    }
+}
+
+impl Expr {
     pub fn get_2bytes(&self, constants: &HashMap<String, i64>) -> Result<[u8; 2], ExprRunError> {
         let value = self.run(constants)?;
         if value > 0xFFFF {
@@ -63,6 +138,11 @@ impl Expr {
         }
     }
 
+    /// Evaluates the expression as a signed byte, e.g. the offset of `add sp, -2`.
+    pub fn get_signed_byte(&self, constants: &HashMap<String, i64>) -> Result<u8, ExprRunError> {
+        signed_byte(self.run(constants)?)
+    }
+
     pub fn get_bit_index(&self, constants: &HashMap<String, i64>) -> Result<u8, ExprRunError> {
         let value = self.run(constants)?;
         if value > 7 {
@@ -78,12 +158,21 @@ impl Expr {
     pub fn run(&self, constants: &HashMap<String, i64>) -> Result<i64, ExprRunError> {
         match self {
             Expr::Ident(ident) => match constants.get(ident) {
-                Some(address) => Ok(*address as i64),
+                Some(address) => Ok(*address),
                 None => Err(ExprRunError::MissingIdentifier(ident.clone())),
             },
             Expr::Const(value) => Ok(*value),
+            Expr::AnonymousLabelRef(_) => Err(ExprRunError::MissingIdentifier(
+                "an anonymous label reference".to_string(),
+            )),
             Expr::Binary(binary) => {
                 let left = binary.left.run(constants)?;
+                // logical operators only evaluate the right side when needed
+                match binary.operator {
+                    BinaryOperator::LogicalAnd if left == 0 => return Ok(0),
+                    BinaryOperator::LogicalOr if left != 0 => return Ok(1),
+                    _ => {}
+                }
                 let right = binary.right.run(constants)?;
                 match binary.operator {
                     BinaryOperator::Add => match left.checked_add(right) {
@@ -139,9 +228,35 @@ impl Expr {
                             }
                         }
                     }
+                    BinaryOperator::Shl | BinaryOperator::Shr => {
+                        let shifted = if (0..64).contains(&right) {
+                            match binary.operator {
+                                BinaryOperator::Shl => left.checked_shl(right as u32),
+                                _ => left.checked_shr(right as u32),
+                            }
+                        } else {
+                            None
+                        };
+                        match shifted {
+                            Some(value) => Ok(value),
+                            None => Err(ExprRunError::ArithmeticError(format!(
+                                "Shift amount must be between 0 and 63: {:?} {:?} {:?}",
+                                binary.left, binary.operator, binary.right
+                            ))),
+                        }
+                    }
                     BinaryOperator::And => Ok(left & right),
                     BinaryOperator::Or => Ok(left | right),
                     BinaryOperator::Xor => Ok(left ^ right),
+                    BinaryOperator::Equal => Ok((left == right) as i64),
+                    BinaryOperator::NotEqual => Ok((left != right) as i64),
+                    BinaryOperator::Less => Ok((left < right) as i64),
+                    BinaryOperator::Greater => Ok((left > right) as i64),
+                    BinaryOperator::LessEqual => Ok((left <= right) as i64),
+                    BinaryOperator::GreaterEqual => Ok((left >= right) as i64),
+                    BinaryOperator::LogicalAnd | BinaryOperator::LogicalOr => {
+                        Ok((right != 0) as i64)
+                    }
                 }
             }
             Expr::Unary(unary) => match unary.operator {
@@ -170,37 +285,93 @@ pub enum ExprRunError {
     ResultDoesntFit(String),
 }
 
+/// Converts a value from -128 to 127 into the byte it is encoded as.
+pub fn signed_byte(value: i64) -> Result<u8, ExprRunError> {
+    match i8::try_from(value) {
+        Ok(value) => Ok(value as u8),
+        Err(_) => Err(ExprRunError::ResultDoesntFit(format!(
+            "{} is invalid because the value needs to fit in a signed byte, from -128 to 127",
+            value
+        ))),
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
-pub struct BinaryExpr {
-    pub left: Expr,
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
+pub struct BinaryExpr<S = String> {
+    pub left: Expr<S>,
     pub operator: BinaryOperator,
-    pub right: Expr,
+    pub right: Expr<S>,
+}
+
+/// The memory region of a [Instruction::Section].
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
+pub enum SectionType {
+    /// The first rom bank at 0x0000-0x3FFF.
+    Rom0,
+    /// A switchable rom bank at 0x4000-0x7FFF.
+    Romx,
 }
 
+/// An item of a db instruction containing strings.
 #[derive(Clone, PartialEq, Debug)]
-pub struct UnaryExpr {
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
+pub enum DbItem<S = String> {
+    Expr(Expr<S>),
+    /// Each character is converted to a byte via the charmap, characters missing from the charmap are encoded as ASCII.
+    String(S),
+}
+
+impl<S> DbItem<S> {
+    /// Converts the string or the identifiers of the expression with f, e.g. from borrowed to owned.
+    pub fn map_text<T>(self, f: &mut dyn FnMut(S) -> T) -> DbItem<T> {
+        match self {
+            DbItem::Expr(expr) => DbItem::Expr(expr.map_text(f)),
+            DbItem::String(text) => DbItem::String(f(text)),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
+pub struct UnaryExpr<S = String> {
     pub operator: UnaryOperator,
-    pub expr: Expr,
+    pub expr: Expr<S>,
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
 pub enum BinaryOperator {
     Add,
     Sub,
     Mul,
     Div,
     Rem,
+    Shl,
+    Shr,
     And,
     Xor,
     Or,
+    /// comparison and logical operators evaluate to 1 when true and 0 when false
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    LogicalAnd,
+    LogicalOr,
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
 pub enum UnaryOperator {
     Minus,
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
 pub enum Reg8 {
     A,
     B,
@@ -212,6 +383,7 @@ pub enum Reg8 {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
 pub enum Reg16 {
     BC,
     DE,
@@ -220,6 +392,7 @@ pub enum Reg16 {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
 pub enum Reg16Push {
     BC,
     DE,
@@ -228,6 +401,7 @@ pub enum Reg16Push {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
 pub enum Flag {
     Always,
     Z,
@@ -236,6 +410,75 @@ pub enum Flag {
     NC,
 }
 
+/// How `debug_assert` compares `a` with an expression.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
+pub enum Comparison {
+    /// `==`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `<`
+    Less,
+    /// `>=`
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    /// Returns the flag that `cp` sets when the comparison is true.
+    pub fn flag(self) -> Flag {
+        match self {
+            Comparison::Equal => Flag::Z,
+            Comparison::NotEqual => Flag::NZ,
+            Comparison::Less => Flag::C,
+            Comparison::GreaterOrEqual => Flag::NC,
+        }
+    }
+}
+
+/// An assembler option set by an `opt` directive, e.g. `opt Werror, h, radix=16`.
+/// Options apply until the end of the asm file, included files start with the default options.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
+pub enum AsmOption {
+    /// `Werror` or `Wno-error`, whether `warn` fails the build.
+    WarningsAsErrors(bool),
+    /// `!h` or `h`, whether halt is followed by a nop, see [crate::RomBuilder::stop_halt_padding].
+    /// `h` disables the nop, the same as the RGBDS option.
+    HaltPadding(bool),
+    /// `radix=N`, the base of numbers without a prefix, from 2 to 16. Applied by the parser.
+    Radix(u32),
+}
+
+/// Where an instruction was written in asm text.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
+pub struct Span {
+    /// the asm file, None when the text was not read from a file
+    pub file: Option<String>,
+    /// 1 indexed line number
+    pub line: usize,
+    /// 1 indexed column of the first character of the instruction, counted in characters
+    pub column: usize,
+}
+
+impl Span {
+    /// Returns the line of the instruction at index i of instructions that have the spans.
+    /// Instructions that were not parsed from asm text have no spans, so their line is the index + 1.
+    pub(crate) fn line_of(spans: &[Span], i: usize) -> usize {
+        spans.get(i).map_or(i + 1, |x| x.line)
+    }
+}
+
+/// A value along with where it was written in asm text, e.g. an instruction returned by
+/// [crate::parser::parse_asm_spanned].
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
+pub struct Spanned<T> {
+    pub span: Span,
+    pub inner: T,
+}
+
 /// The main type in the AST, the parser creates an Instruction for each line in a *.asm
 ///
 /// Key:
@@ -250,19 +493,110 @@ pub enum Flag {
 /// *   I16  - immediate 16 bit value
 /// *   Bit  - an index to a bit
 #[derive(Clone, PartialEq, Debug)]
-pub enum Instruction {
+#[cfg_attr(feature = "serde-ast", derive(Serialize, Deserialize))]
+pub enum Instruction<S = String> {
     /// Keeping track of empty lines makes it easier to refer errors back to a line number
     EmptyLine, // TODO: Combine this and the Option returned by the parser into a new enum
-    /// the address within the current ROM bank
-    AdvanceAddress(u16),
-    Equ(String, Expr),
-    Label(String),
+    /// Skips to an address, filling the space in between with zeroes.
+    /// Without a bank the address is within the current ROM bank, with a bank it is the address seen by the cpu,
+    /// as in [crate::RomBuilder::advance_address], and the bank must be the current ROM bank.
+    AdvanceAddress(Expr<S>, Option<Expr<S>>),
+    Equ(S, Expr<S>),
+    /// A constant that can be reassigned, declared with `SET`, `=` or `REDEF`.
+    /// References use the value assigned by the nearest previous assignment.
+    /// Resolved by the RomBuilder before addresses are assigned.
+    Set(S, Expr<S>),
+    Label(S),
+    /// A label without a name, referred to by [Expr::AnonymousLabelRef].
+    /// Resolved into [Instruction::Label] by the RomBuilder.
+    AnonymousLabel,
     Db(Vec<u8>),
-    DbExpr8(Expr),
-    DbExpr16(Expr),
+    DbExpr8(Expr<S>),
+    DbExpr16(Expr<S>),
+    /// A byte for each expression.
+    DbExprList8(Vec<Expr<S>>),
+    /// Two little endian bytes for each expression.
+    DbExprList16(Vec<Expr<S>>),
+    /// Bytes from strings and expressions.
+    DbString(Vec<DbItem<S>>),
+    /// The second expression as a byte, repeated the number of times given by the first expression.
+    DbFill(Expr<S>, Expr<S>),
+    /// Maps a character to a byte in the strings of following [Instruction::DbString]s.
+    /// Charmaps are applied by the RomBuilder in the order they are added.
+    Charmap(char, Expr<S>),
+    /// Traps to the crash handler via `rst 0x38` when the flag is not set.
+    /// The RomBuilder removes it unless `DEBUG` is defined.
+    DebugAssert(Flag),
+    /// Compares `a` with the expression via `cp` and traps to the crash handler via `rst 0x38` when the comparison
+    /// is false, e.g. `debug_assert a < MaxLives` is `DebugAssertCp(Comparison::Less, MaxLives)`.
+    /// The RomBuilder removes it unless `DEBUG` is defined.
+    DebugAssertCp(Comparison, Expr<S>),
+    /// Displays the message in the BGB and Emulicious debug message windows.
+    /// Encoded as `ld d, d` followed by a jump over the message.
+    /// The RomBuilder removes it unless `DEBUG` is defined.
+    DebugPrint(S),
+    /// Triggers a breakpoint in BGB and Emulicious.
+    /// Encoded as `ld b, b`.
+    /// The RomBuilder removes it unless `DEBUG` is defined.
+    DebugBreak,
+    /// Fails the build when the expression evaluates to zero, displaying the message if provided.
+    Assert(Expr<S>, Option<S>),
+    /// Prints the strings and expressions to stdout when the rom is compiled.
+    /// Expressions are displayed in decimal.
+    Print(Vec<DbItem<S>>),
+    /// Displays the strings and expressions as a warning when the rom is compiled.
+    /// Warnings are printed to stderr and recorded in [crate::layout::Layout::warnings].
+    Warn(Vec<DbItem<S>>),
+    /// Fails the build, displaying the strings and expressions.
+    Fail(Vec<DbItem<S>>),
+    /// Starts a conditional block, included when the expression is non-zero.
+    /// Conditional blocks are resolved by the RomBuilder before addresses are assigned.
+    If(Expr<S>),
+    Elif(Expr<S>),
+    Else,
+    Endc,
+    /// Includes the instructions of another asm file in place of this instruction.
+    /// Includes are resolved by [crate::RomBuilder::add_asm_file].
+    Include(S),
+    /// Includes the bytes of a binary file, from the offset and for the length if provided.
+    /// Incbins are resolved into [Instruction::Db] by [crate::RomBuilder::add_asm_file].
+    Incbin(S, u32, Option<u32>),
+    /// Starts a section with a name, memory region, address and bank.
+    /// The following instructions are placed by [crate::RomBuilder::add_asm_file] according to the section.
+    Section(S, SectionType, Option<Expr<S>>, Option<Expr<S>>),
+    /// Sets assembler options for the rest of the asm file, see [AsmOption].
+    Opt(Vec<AsmOption>),
+    /// Saves the current options, to be restored by [Instruction::PopO].
+    PushO,
+    PopO,
+    /// Saves the current section, the instructions after the next [Instruction::PopS] continue where it left off.
+    /// Resolved by [crate::RomBuilder::add_asm_file].
+    PushS,
+    PopS,
+    /// Skips the asm file when it is included or added again.
+    PragmaOnce,
+    /// Makes labels of an asm file visible to other asm files and rust code.
+    /// Has no effect outside of asm files, where every label is visible.
+    Export(Vec<S>),
+    /// Declares the following labels and EQU constants within the namespace, until the matching
+    /// [Instruction::EndNamespace], e.g. `Init:` after `namespace Audio` declares `Audio.Init`.
+    /// Resolved by the RomBuilder, see [crate::RomBuilder::namespace].
+    Namespace(S),
+    EndNamespace,
+    /// Starts a union, each block separated by [Instruction::NextU] starts at the same address,
+    /// so different game states can reuse the same RAM with their own labels.
+    /// Unions are only valid in RAM, which the RomBuilder does not support placing yet.
+    Union,
+    NextU,
+    EndU,
     Nop,
     Stop,
+    /// The byte after stop is skipped by the cpu, so this pads it with a nop.
+    StopPadded,
+    /// Halt is followed by a nop to avoid the halt bug, see [Instruction::HaltUnpadded].
     Halt,
+    /// Halt without the nop, for when the following byte is known to be safe.
+    HaltUnpadded,
     Di,
     Ei,
     Rrca,
@@ -275,10 +609,12 @@ pub enum Instruction {
     Scf,
     Ret(Flag),
     Reti,
-    Call(Flag, Expr),
-    JpI16(Flag, Expr),
+    Call(Flag, Expr<S>),
+    /// Calls one of the eight restart vectors: 0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30 or 0x38
+    Rst(Expr<S>),
+    JpI16(Flag, Expr<S>),
     JpRhl,
-    Jr(Flag, Expr),
+    Jr(Flag, Expr<S>),
     IncR16(Reg16),
     IncR8(Reg8),
     IncMRhl,
@@ -287,52 +623,52 @@ pub enum Instruction {
     DecMRhl,
     AddR8(Reg8),
     AddMRhl,
-    AddI8(Expr),
+    AddI8(Expr<S>),
     AddRhlR16(Reg16),
-    AddRspI8(Expr),
+    AddRspI8(Expr<S>),
     SubR8(Reg8),
     SubMRhl,
-    SubI8(Expr),
+    SubI8(Expr<S>),
     AndR8(Reg8),
     AndMRhl,
-    AndI8(Expr),
+    AndI8(Expr<S>),
     OrR8(Reg8),
     OrMRhl,
-    OrI8(Expr),
+    OrI8(Expr<S>),
     AdcR8(Reg8),
     AdcMRhl,
-    AdcI8(Expr),
+    AdcI8(Expr<S>),
     SbcR8(Reg8),
     SbcMRhl,
-    SbcI8(Expr),
+    SbcI8(Expr<S>),
     XorR8(Reg8),
     XorMRhl,
-    XorI8(Expr),
+    XorI8(Expr<S>),
     CpR8(Reg8),
     CpMRhl,
-    CpI8(Expr),
-    LdR16I16(Reg16, Expr),
-    LdMI16Rsp(Expr),
+    CpI8(Expr<S>),
+    LdR16I16(Reg16, Expr<S>),
+    LdMI16Rsp(Expr<S>),
     LdMRbcRa,
     LdMRdeRa,
     LdRaMRbc,
     LdRaMRde,
     LdR8R8(Reg8, Reg8),
-    LdR8I8(Reg8, Expr),
+    LdR8I8(Reg8, Expr<S>),
     LdR8MRhl(Reg8),
     LdMRhlR8(Reg8),
-    LdMRhlI8(Expr),
-    LdMI16Ra(Expr),
-    LdRaMI16(Expr),
-    LdhRaMI8(Expr),
-    LdhMI8Ra(Expr),
+    LdMRhlI8(Expr<S>),
+    LdMI16Ra(Expr<S>),
+    LdRaMI16(Expr<S>),
+    LdhRaMI8(Expr<S>),
+    LdhMI8Ra(Expr<S>),
     LdhRaMRc,
     LdhMRcRa,
     LdiMRhlRa,
     LddMRhlRa,
     LdiRaMRhl,
     LddRaMRhl,
-    LdRhlRspI8(Expr),
+    LdRhlRspI8(Expr<S>),
     LdRspRhl,
     Push(Reg16Push),
     Pop(Reg16Push),
@@ -354,15 +690,442 @@ pub enum Instruction {
     SwapMRhl,
     SrlR8(Reg8),
     SrlMRhl,
-    BitBitR8(Expr, Reg8),
-    BitBitMRhl(Expr),
-    ResBitR8(Expr, Reg8),
-    ResBitMRhl(Expr),
-    SetBitR8(Expr, Reg8),
-    SetBitMRhl(Expr),
+    BitBitR8(Expr<S>, Reg8),
+    BitBitMRhl(Expr<S>),
+    ResBitR8(Expr<S>, Reg8),
+    ResBitMRhl(Expr<S>),
+    SetBitR8(Expr<S>, Reg8),
+    SetBitMRhl(Expr<S>),
+}
+
+impl<S> Instruction<S> {
+    /// Converts each identifier and string with f, e.g. from borrowed to owned.
+    pub fn map_text<T>(self, f: &mut dyn FnMut(S) -> T) -> Instruction<T> {
+        match self {
+            Instruction::EmptyLine => Instruction::EmptyLine,
+            Instruction::AdvanceAddress(address, bank) => {
+                Instruction::AdvanceAddress(address.map_text(f), bank.map(|x| x.map_text(f)))
+            }
+            Instruction::Equ(x0, x1) => Instruction::Equ(f(x0), x1.map_text(f)),
+            Instruction::Set(x0, x1) => Instruction::Set(f(x0), x1.map_text(f)),
+            Instruction::Label(x) => Instruction::Label(f(x)),
+            Instruction::AnonymousLabel => Instruction::AnonymousLabel,
+            Instruction::Db(x) => Instruction::Db(x),
+            Instruction::DbExpr8(x) => Instruction::DbExpr8(x.map_text(f)),
+            Instruction::DbExpr16(x) => Instruction::DbExpr16(x.map_text(f)),
+            Instruction::DbExprList8(x) => {
+                Instruction::DbExprList8(x.into_iter().map(|x| x.map_text(f)).collect())
+            }
+            Instruction::DbExprList16(x) => {
+                Instruction::DbExprList16(x.into_iter().map(|x| x.map_text(f)).collect())
+            }
+            Instruction::DbString(x) => {
+                Instruction::DbString(x.into_iter().map(|x| x.map_text(f)).collect())
+            }
+            Instruction::DbFill(count, value) => {
+                Instruction::DbFill(count.map_text(f), value.map_text(f))
+            }
+            Instruction::Charmap(x0, x1) => Instruction::Charmap(x0, x1.map_text(f)),
+            Instruction::DebugAssert(x) => Instruction::DebugAssert(x),
+            Instruction::DebugAssertCp(x0, x1) => Instruction::DebugAssertCp(x0, x1.map_text(f)),
+            Instruction::DebugPrint(x) => Instruction::DebugPrint(f(x)),
+            Instruction::DebugBreak => Instruction::DebugBreak,
+            Instruction::Assert(x0, x1) => Instruction::Assert(x0.map_text(f), x1.map(&mut *f)),
+            Instruction::Print(x) => {
+                Instruction::Print(x.into_iter().map(|x| x.map_text(f)).collect())
+            }
+            Instruction::Warn(x) => {
+                Instruction::Warn(x.into_iter().map(|x| x.map_text(f)).collect())
+            }
+            Instruction::Fail(x) => {
+                Instruction::Fail(x.into_iter().map(|x| x.map_text(f)).collect())
+            }
+            Instruction::If(x) => Instruction::If(x.map_text(f)),
+            Instruction::Elif(x) => Instruction::Elif(x.map_text(f)),
+            Instruction::Else => Instruction::Else,
+            Instruction::Endc => Instruction::Endc,
+            Instruction::Include(x) => Instruction::Include(f(x)),
+            Instruction::Incbin(x0, x1, x2) => Instruction::Incbin(f(x0), x1, x2),
+            Instruction::Section(x0, x1, x2, x3) => Instruction::Section(
+                f(x0),
+                x1,
+                x2.map(|x| x.map_text(f)),
+                x3.map(|x| x.map_text(f)),
+            ),
+            Instruction::Opt(x) => Instruction::Opt(x),
+            Instruction::PushO => Instruction::PushO,
+            Instruction::PopO => Instruction::PopO,
+            Instruction::PushS => Instruction::PushS,
+            Instruction::PopS => Instruction::PopS,
+            Instruction::PragmaOnce => Instruction::PragmaOnce,
+            Instruction::Export(x) => Instruction::Export(x.into_iter().map(&mut *f).collect()),
+            Instruction::Namespace(x) => Instruction::Namespace(f(x)),
+            Instruction::EndNamespace => Instruction::EndNamespace,
+            Instruction::Union => Instruction::Union,
+            Instruction::NextU => Instruction::NextU,
+            Instruction::EndU => Instruction::EndU,
+            Instruction::Nop => Instruction::Nop,
+            Instruction::Stop => Instruction::Stop,
+            Instruction::StopPadded => Instruction::StopPadded,
+            Instruction::Halt => Instruction::Halt,
+            Instruction::HaltUnpadded => Instruction::HaltUnpadded,
+            Instruction::Di => Instruction::Di,
+            Instruction::Ei => Instruction::Ei,
+            Instruction::Rrca => Instruction::Rrca,
+            Instruction::Rra => Instruction::Rra,
+            Instruction::Cpl => Instruction::Cpl,
+            Instruction::Ccf => Instruction::Ccf,
+            Instruction::Rlca => Instruction::Rlca,
+            Instruction::Rla => Instruction::Rla,
+            Instruction::Daa => Instruction::Daa,
+            Instruction::Scf => Instruction::Scf,
+            Instruction::Ret(x) => Instruction::Ret(x),
+            Instruction::Reti => Instruction::Reti,
+            Instruction::Call(x0, x1) => Instruction::Call(x0, x1.map_text(f)),
+            Instruction::Rst(x) => Instruction::Rst(x.map_text(f)),
+            Instruction::JpI16(x0, x1) => Instruction::JpI16(x0, x1.map_text(f)),
+            Instruction::JpRhl => Instruction::JpRhl,
+            Instruction::Jr(x0, x1) => Instruction::Jr(x0, x1.map_text(f)),
+            Instruction::IncR16(x) => Instruction::IncR16(x),
+            Instruction::IncR8(x) => Instruction::IncR8(x),
+            Instruction::IncMRhl => Instruction::IncMRhl,
+            Instruction::DecR16(x) => Instruction::DecR16(x),
+            Instruction::DecR8(x) => Instruction::DecR8(x),
+            Instruction::DecMRhl => Instruction::DecMRhl,
+            Instruction::AddR8(x) => Instruction::AddR8(x),
+            Instruction::AddMRhl => Instruction::AddMRhl,
+            Instruction::AddI8(x) => Instruction::AddI8(x.map_text(f)),
+            Instruction::AddRhlR16(x) => Instruction::AddRhlR16(x),
+            Instruction::AddRspI8(x) => Instruction::AddRspI8(x.map_text(f)),
+            Instruction::SubR8(x) => Instruction::SubR8(x),
+            Instruction::SubMRhl => Instruction::SubMRhl,
+            Instruction::SubI8(x) => Instruction::SubI8(x.map_text(f)),
+            Instruction::AndR8(x) => Instruction::AndR8(x),
+            Instruction::AndMRhl => Instruction::AndMRhl,
+            Instruction::AndI8(x) => Instruction::AndI8(x.map_text(f)),
+            Instruction::OrR8(x) => Instruction::OrR8(x),
+            Instruction::OrMRhl => Instruction::OrMRhl,
+            Instruction::OrI8(x) => Instruction::OrI8(x.map_text(f)),
+            Instruction::AdcR8(x) => Instruction::AdcR8(x),
+            Instruction::AdcMRhl => Instruction::AdcMRhl,
+            Instruction::AdcI8(x) => Instruction::AdcI8(x.map_text(f)),
+            Instruction::SbcR8(x) => Instruction::SbcR8(x),
+            Instruction::SbcMRhl => Instruction::SbcMRhl,
+            Instruction::SbcI8(x) => Instruction::SbcI8(x.map_text(f)),
+            Instruction::XorR8(x) => Instruction::XorR8(x),
+            Instruction::XorMRhl => Instruction::XorMRhl,
+            Instruction::XorI8(x) => Instruction::XorI8(x.map_text(f)),
+            Instruction::CpR8(x) => Instruction::CpR8(x),
+            Instruction::CpMRhl => Instruction::CpMRhl,
+            Instruction::CpI8(x) => Instruction::CpI8(x.map_text(f)),
+            Instruction::LdR16I16(x0, x1) => Instruction::LdR16I16(x0, x1.map_text(f)),
+            Instruction::LdMI16Rsp(x) => Instruction::LdMI16Rsp(x.map_text(f)),
+            Instruction::LdMRbcRa => Instruction::LdMRbcRa,
+            Instruction::LdMRdeRa => Instruction::LdMRdeRa,
+            Instruction::LdRaMRbc => Instruction::LdRaMRbc,
+            Instruction::LdRaMRde => Instruction::LdRaMRde,
+            Instruction::LdR8R8(x0, x1) => Instruction::LdR8R8(x0, x1),
+            Instruction::LdR8I8(x0, x1) => Instruction::LdR8I8(x0, x1.map_text(f)),
+            Instruction::LdR8MRhl(x) => Instruction::LdR8MRhl(x),
+            Instruction::LdMRhlR8(x) => Instruction::LdMRhlR8(x),
+            Instruction::LdMRhlI8(x) => Instruction::LdMRhlI8(x.map_text(f)),
+            Instruction::LdMI16Ra(x) => Instruction::LdMI16Ra(x.map_text(f)),
+            Instruction::LdRaMI16(x) => Instruction::LdRaMI16(x.map_text(f)),
+            Instruction::LdhRaMI8(x) => Instruction::LdhRaMI8(x.map_text(f)),
+            Instruction::LdhMI8Ra(x) => Instruction::LdhMI8Ra(x.map_text(f)),
+            Instruction::LdhRaMRc => Instruction::LdhRaMRc,
+            Instruction::LdhMRcRa => Instruction::LdhMRcRa,
+            Instruction::LdiMRhlRa => Instruction::LdiMRhlRa,
+            Instruction::LddMRhlRa => Instruction::LddMRhlRa,
+            Instruction::LdiRaMRhl => Instruction::LdiRaMRhl,
+            Instruction::LddRaMRhl => Instruction::LddRaMRhl,
+            Instruction::LdRhlRspI8(x) => Instruction::LdRhlRspI8(x.map_text(f)),
+            Instruction::LdRspRhl => Instruction::LdRspRhl,
+            Instruction::Push(x) => Instruction::Push(x),
+            Instruction::Pop(x) => Instruction::Pop(x),
+            Instruction::RlcR8(x) => Instruction::RlcR8(x),
+            Instruction::RlcMRhl => Instruction::RlcMRhl,
+            Instruction::RrcR8(x) => Instruction::RrcR8(x),
+            Instruction::RrcMRhl => Instruction::RrcMRhl,
+            Instruction::RlR8(x) => Instruction::RlR8(x),
+            Instruction::RlMRhl => Instruction::RlMRhl,
+            Instruction::RrR8(x) => Instruction::RrR8(x),
+            Instruction::RrMRhl => Instruction::RrMRhl,
+            Instruction::SlaR8(x) => Instruction::SlaR8(x),
+            Instruction::SlaMRhl => Instruction::SlaMRhl,
+            Instruction::SraR8(x) => Instruction::SraR8(x),
+            Instruction::SraMRhl => Instruction::SraMRhl,
+            Instruction::SwapR8(x) => Instruction::SwapR8(x),
+            Instruction::SwapMRhl => Instruction::SwapMRhl,
+            Instruction::SrlR8(x) => Instruction::SrlR8(x),
+            Instruction::SrlMRhl => Instruction::SrlMRhl,
+            Instruction::BitBitR8(x0, x1) => Instruction::BitBitR8(x0.map_text(f), x1),
+            Instruction::BitBitMRhl(x) => Instruction::BitBitMRhl(x.map_text(f)),
+            Instruction::ResBitR8(x0, x1) => Instruction::ResBitR8(x0.map_text(f), x1),
+            Instruction::ResBitMRhl(x) => Instruction::ResBitMRhl(x.map_text(f)),
+            Instruction::SetBitR8(x0, x1) => Instruction::SetBitR8(x0.map_text(f), x1),
+            Instruction::SetBitMRhl(x) => Instruction::SetBitMRhl(x.map_text(f)),
+        }
+    }
+}
+
+impl<S: AsRef<str>> Instruction<S> {
+    /// Converts borrowed identifiers and strings into Strings, so the instruction can be given to the RomBuilder.
+    pub fn into_owned(self) -> Instruction {
+        self.map_text(&mut |x| x.as_ref().to_string())
+    }
 }
 
 impl Instruction {
+    /// Returns the expression used by the instruction if it has one.
+    pub fn expr_mut(&mut self) -> Option<&mut Expr> {
+        match self {
+            Instruction::Equ(_, expr)
+            | Instruction::Set(_, expr)
+            | Instruction::DbExpr8(expr)
+            | Instruction::DbExpr16(expr)
+            | Instruction::Assert(expr, _)
+            | Instruction::Charmap(_, expr)
+            | Instruction::If(expr)
+            | Instruction::Elif(expr)
+            | Instruction::Call(_, expr)
+            | Instruction::Rst(expr)
+            | Instruction::DebugAssertCp(_, expr)
+            | Instruction::JpI16(_, expr)
+            | Instruction::Jr(_, expr)
+            | Instruction::AddI8(expr)
+            | Instruction::AddRspI8(expr)
+            | Instruction::SubI8(expr)
+            | Instruction::AndI8(expr)
+            | Instruction::OrI8(expr)
+            | Instruction::AdcI8(expr)
+            | Instruction::SbcI8(expr)
+            | Instruction::XorI8(expr)
+            | Instruction::CpI8(expr)
+            | Instruction::LdR16I16(_, expr)
+            | Instruction::LdMI16Rsp(expr)
+            | Instruction::LdR8I8(_, expr)
+            | Instruction::LdMRhlI8(expr)
+            | Instruction::LdMI16Ra(expr)
+            | Instruction::LdRaMI16(expr)
+            | Instruction::LdhRaMI8(expr)
+            | Instruction::LdhMI8Ra(expr)
+            | Instruction::LdRhlRspI8(expr)
+            | Instruction::BitBitR8(expr, _)
+            | Instruction::BitBitMRhl(expr)
+            | Instruction::ResBitR8(expr, _)
+            | Instruction::ResBitMRhl(expr)
+            | Instruction::SetBitR8(expr, _)
+            | Instruction::SetBitMRhl(expr) => Some(expr),
+            _ => None,
+        }
+    }
+
+    /// Creates a label, returns Err if the name is not a valid identifier.
+    pub fn label(name: &str) -> Result<Instruction, Error> {
+        validate_identifier(name)?;
+        Ok(Instruction::Label(name.to_string()))
+    }
+
+    /// Creates a constant, returns Err if the name is not a valid identifier.
+    pub fn equ(name: &str, value: i64) -> Result<Instruction, Error> {
+        validate_identifier(name)?;
+        Ok(Instruction::Equ(name.to_string(), Expr::Const(value)))
+    }
+
+    /// Creates bytes from a string, each character is converted to a byte via the charmap.
+    /// Returns Err if a character is missing from the charmap.
+    pub fn db_str(text: &str, charmap: &HashMap<char, u8>) -> Result<Instruction, Error> {
+        let mut bytes = Vec::with_capacity(text.len());
+        for c in text.chars() {
+            match charmap.get(&c) {
+                Some(byte) => bytes.push(*byte),
+                None => bail!("Character {:?} in {:?} is not in the charmap", c, text),
+            }
+        }
+        Ok(Instruction::Db(bytes))
+    }
+
+    /// Creates an unconditional jump to the label, returns Err if the label is not a valid identifier.
+    pub fn jp(label: &str) -> Result<Instruction, Error> {
+        Instruction::jp_flag(Flag::Always, label)
+    }
+
+    /// Creates a conditional jump to the label, returns Err if the label is not a valid identifier.
+    pub fn jp_flag(flag: Flag, label: &str) -> Result<Instruction, Error> {
+        validate_identifier(label)?;
+        Ok(Instruction::JpI16(flag, Expr::Ident(label.to_string())))
+    }
+
+    /// Creates an unconditional relative jump to the label, returns Err if the label is not a valid identifier.
+    pub fn jr(label: &str) -> Result<Instruction, Error> {
+        Instruction::jr_flag(Flag::Always, label)
+    }
+
+    /// Creates a conditional relative jump to the label, returns Err if the label is not a valid identifier.
+    pub fn jr_flag(flag: Flag, label: &str) -> Result<Instruction, Error> {
+        validate_identifier(label)?;
+        Ok(Instruction::Jr(flag, Expr::Ident(label.to_string())))
+    }
+
+    /// Creates an unconditional call to the label, returns Err if the label is not a valid identifier.
+    pub fn call(label: &str) -> Result<Instruction, Error> {
+        Instruction::call_flag(Flag::Always, label)
+    }
+
+    /// Creates a conditional call to the label, returns Err if the label is not a valid identifier.
+    pub fn call_flag(flag: Flag, label: &str) -> Result<Instruction, Error> {
+        validate_identifier(label)?;
+        Ok(Instruction::Call(flag, Expr::Ident(label.to_string())))
+    }
+
+    /// Creates an rst, returns Err if the vector is not one of the eight restart vectors.
+    pub fn rst(vector: u8) -> Result<Instruction, Error> {
+        if vector > 0x38 || !vector.is_multiple_of(8) {
+            bail!("0x{:x} is not a valid rst vector, it must be one of 0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30 or 0x38", vector);
+        }
+        Ok(Instruction::Rst(Expr::Const(vector as i64)))
+    }
+
+    /// Creates a load of the value into the register, returns Err if the value does not fit in a byte.
+    pub fn ld_r8_i8(reg: Reg8, value: i64) -> Result<Instruction, Error> {
+        if !(-128..=255).contains(&value) {
+            bail!("{} does not fit in a byte", value);
+        }
+        Ok(Instruction::LdR8I8(reg, Expr::Const(value)))
+    }
+
+    /// Creates a load of the value into the register, returns Err if the value does not fit in two bytes.
+    pub fn ld_r16_i16(reg: Reg16, value: i64) -> Result<Instruction, Error> {
+        if !(-32768..=65535).contains(&value) {
+            bail!("{} does not fit in two bytes", value);
+        }
+        Ok(Instruction::LdR16I16(reg, Expr::Const(value)))
+    }
+
+    /// Creates a bit test of the register, returns Err if the bit index is greater than 7.
+    pub fn bit(bit: u8, reg: Reg8) -> Result<Instruction, Error> {
+        if bit > 7 {
+            bail!("{} is not a valid bit index, it must be from 0 to 7", bit);
+        }
+        Ok(Instruction::BitBitR8(Expr::Const(bit as i64), reg))
+    }
+
+    /// Returns every expression used by the instruction.
+    pub fn exprs(&self) -> Vec<&Expr> {
+        match self {
+            Instruction::AdvanceAddress(address, bank) => {
+                std::iter::once(address).chain(bank.as_ref()).collect()
+            }
+            Instruction::Equ(_, expr)
+            | Instruction::Set(_, expr)
+            | Instruction::DbExpr8(expr)
+            | Instruction::DbExpr16(expr)
+            | Instruction::Assert(expr, _)
+            | Instruction::Charmap(_, expr)
+            | Instruction::If(expr)
+            | Instruction::Elif(expr)
+            | Instruction::DebugAssertCp(_, expr) => vec![expr],
+            Instruction::DbFill(count, value) => vec![count, value],
+            Instruction::DbExprList8(exprs) | Instruction::DbExprList16(exprs) => {
+                exprs.iter().collect()
+            }
+            Instruction::DbString(items)
+            | Instruction::Print(items)
+            | Instruction::Warn(items)
+            | Instruction::Fail(items) => items
+                .iter()
+                .filter_map(|item| match item {
+                    DbItem::Expr(expr) => Some(expr),
+                    DbItem::String(_) => None,
+                })
+                .collect(),
+            Instruction::Section(_, _, address, bank) => address.iter().chain(bank).collect(),
+            instruction => instruction
+                .encoding()
+                .and_then(|x| x.operand.expr())
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Returns every expression used by the instruction.
+    pub fn exprs_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Instruction::AdvanceAddress(address, bank) => {
+                std::iter::once(address).chain(bank.as_mut()).collect()
+            }
+            Instruction::Section(_, _, address, bank) => {
+                address.iter_mut().chain(bank.iter_mut()).collect()
+            }
+            Instruction::DbFill(count, value) => vec![count, value],
+            Instruction::DbExprList8(exprs) | Instruction::DbExprList16(exprs) => {
+                exprs.iter_mut().collect()
+            }
+            Instruction::DbString(items)
+            | Instruction::Print(items)
+            | Instruction::Warn(items)
+            | Instruction::Fail(items) => items
+                .iter_mut()
+                .filter_map(|item| match item {
+                    DbItem::Expr(expr) => Some(expr),
+                    DbItem::String(_) => None,
+                })
+                .collect(),
+            instruction => instruction.expr_mut().into_iter().collect(),
+        }
+    }
+
+    /// Returns the bytes of the instruction when it starts at current_address, an address within the entire rom.
+    ///
+    /// Unlike [Instruction::write_to_rom] no rom is needed, so tools such as emulators can encode a single instruction.
+    /// Returns an error for directives that write more than 3 bytes e.g. long `db` lines, use write_to_rom for them.
+    pub fn encode(
+        &self,
+        current_address: u32,
+        constants: &HashMap<String, i64>,
+    ) -> Result<ArrayVec<u8, 3>, Error> {
+        if let Some(encoding) = self.encoding() {
+            return encoding.encode(current_address, constants);
+        }
+        let mut bytes = vec![];
+        match self {
+            Instruction::AdvanceAddress(..) => {
+                let len = self.advance_address_len(current_address, constants)?;
+                bytes.resize(len as usize, 0x00);
+            }
+            instruction => instruction.write_to_rom(&mut bytes, constants)?,
+        }
+        match ArrayVec::try_from(bytes.as_slice()) {
+            Ok(encoded) => Ok(encoded),
+            Err(_) => bail!(
+                "`{}` is {} bytes, only instructions of up to 3 bytes can be encoded",
+                self,
+                bytes.len()
+            ),
+        }
+    }
+
+    /// Returns the number of zeroes written by advance_address when it starts at address within the entire rom.
+    pub(crate) fn advance_address_len(
+        &self,
+        address: u32,
+        constants: &HashMap<String, i64>,
+    ) -> Result<u16, Error> {
+        if let Instruction::AdvanceAddress(_, Some(bank)) = self {
+            let bank = bank.run(constants)?;
+            let current_bank = address / ROM_BANK_SIZE;
+            if bank != current_bank as i64 {
+                bail!(
+                    "advance_address is for bank {} but is in bank {}",
+                    bank,
+                    current_bank
+                );
+            }
+        }
+        self.bytes_len_with((address % ROM_BANK_SIZE) as u16, constants)
+    }
+
     /// Writes the instructions bytes to the passed rom.
     /// If an expr in the instruction uses an identifier than it looks up the value for it in constants.
     /// Will return Err if constants doesn't contain the required label.
@@ -370,630 +1133,276 @@ impl Instruction {
         &self,
         rom: &mut Vec<u8>,
         constants: &HashMap<String, i64>,
+    ) -> Result<(), Error> {
+        self.write_to_rom_with_charmap(rom, constants, &HashMap::new())
+    }
+
+    /// Writes the instructions bytes to the passed rom, converting the characters of strings via the charmap.
+    pub fn write_to_rom_with_charmap(
+        &self,
+        rom: &mut Vec<u8>,
+        constants: &HashMap<String, i64>,
+        charmap: &HashMap<char, u8>,
     ) -> Result<(), Error> {
         match self {
-            Instruction::AdvanceAddress(advance_address) => {
-                let address_bank = (rom.len() as u32 % ROM_BANK_SIZE) as u16;
-                assert!(
-                    *advance_address >= address_bank,
-                    "This should be handled by RomBuilder::add_instructions_inner"
-                );
-                for _ in 0..(advance_address - address_bank) {
-                    rom.push(0x00);
-                }
+            Instruction::AdvanceAddress(..) => {
+                let len = self.advance_address_len(rom.len() as u32, constants)?;
+                rom.resize(rom.len() + len as usize, 0x00);
             }
             Instruction::EmptyLine => {}
             Instruction::Equ(_, _) => {}
+            Instruction::Set(_, _) => {}
             Instruction::Label(_) => {}
+            Instruction::AnonymousLabel => {}
             Instruction::Db(bytes) => rom.extend(bytes.iter()),
             Instruction::DbExpr8(expr) => rom.push(expr.get_byte(constants)?),
             Instruction::DbExpr16(expr) => rom.extend(expr.get_2bytes(constants)?.iter()),
-            Instruction::Nop => rom.push(0x00),
-            Instruction::Stop => rom.push(0x10),
-            Instruction::Halt => rom.extend([0x76, 0x00].iter()),
-            Instruction::Di => rom.push(0xF3),
-            Instruction::Ei => rom.push(0xFB),
-            Instruction::Rrca => rom.push(0x0F),
-            Instruction::Rra => rom.push(0x1F),
-            Instruction::Cpl => rom.push(0x2F),
-            Instruction::Ccf => rom.push(0x3F),
-            Instruction::Rlca => rom.push(0x07),
-            Instruction::Rla => rom.push(0x17),
-            Instruction::Daa => rom.push(0x27),
-            Instruction::Scf => rom.push(0x37),
-            Instruction::Reti => rom.push(0xD9),
-            Instruction::Ret(flag) => match flag {
-                Flag::Always => rom.push(0xC9),
-                Flag::Z => rom.push(0xC8),
-                Flag::C => rom.push(0xC9),
-                Flag::NZ => rom.push(0xC0),
-                Flag::NC => rom.push(0xD0),
-            },
-            Instruction::Call(flag, expr) => {
-                match flag {
-                    Flag::Always => rom.push(0xCD),
-                    Flag::Z => rom.push(0xCC),
-                    Flag::C => rom.push(0xDC),
-                    Flag::NZ => rom.push(0xC4),
-                    Flag::NC => rom.push(0xD4),
-                }
-                rom.extend(expr.get_2bytes(constants)?.iter());
-            }
-            Instruction::JpI16(flag, expr) => {
-                match flag {
-                    Flag::Always => rom.push(0xC3),
-                    Flag::Z => rom.push(0xCA),
-                    Flag::C => rom.push(0xDA),
-                    Flag::NZ => rom.push(0xC2),
-                    Flag::NC => rom.push(0xD2),
-                }
-                rom.extend(expr.get_2bytes(constants)?.iter());
-            }
-            Instruction::JpRhl => rom.push(0xE9),
-            Instruction::Jr(flag, expr) => {
-                let abs_dest = expr.run(constants)?;
-                let rel_dest = abs_dest - rom.len() as i64 - 2; // 2 accounts for the 2 bytes that the make up the jr instruction
-                if rel_dest > 0x7F {
-                    bail!("0x{} > 0x7F This is invalid because the value needs to fit in a signed byte", rel_dest);
-                } else if rel_dest < -0x80 {
-                    bail!("0x{} < 0x80 This is invalid because the value needs to fit in a signed byte", rel_dest);
-                }
-                match flag {
-                    Flag::Always => rom.push(0x18),
-                    Flag::Z => rom.push(0x28),
-                    Flag::C => rom.push(0x38),
-                    Flag::NZ => rom.push(0x20),
-                    Flag::NC => rom.push(0x30),
+            Instruction::DbExprList8(exprs) => {
+                for expr in exprs {
+                    rom.push(expr.get_byte(constants)?);
                 }
-                rom.push(rel_dest as u8);
-            }
-            Instruction::IncR16(reg) => match reg {
-                Reg16::BC => rom.push(0x03),
-                Reg16::DE => rom.push(0x13),
-                Reg16::HL => rom.push(0x23),
-                Reg16::SP => rom.push(0x33),
-            },
-            Instruction::IncR8(reg) => match reg {
-                Reg8::A => rom.push(0x3C),
-                Reg8::B => rom.push(0x04),
-                Reg8::C => rom.push(0x0C),
-                Reg8::D => rom.push(0x14),
-                Reg8::E => rom.push(0x1C),
-                Reg8::H => rom.push(0x24),
-                Reg8::L => rom.push(0x2C),
-            },
-            Instruction::IncMRhl => rom.push(0x034),
-            Instruction::DecR16(reg) => match reg {
-                Reg16::BC => rom.push(0x0B),
-                Reg16::DE => rom.push(0x1B),
-                Reg16::HL => rom.push(0x2B),
-                Reg16::SP => rom.push(0x3B),
-            },
-            Instruction::DecR8(reg) => match reg {
-                Reg8::A => rom.push(0x3D),
-                Reg8::B => rom.push(0x05),
-                Reg8::C => rom.push(0x0D),
-                Reg8::D => rom.push(0x15),
-                Reg8::E => rom.push(0x1D),
-                Reg8::H => rom.push(0x25),
-                Reg8::L => rom.push(0x2D),
-            },
-            Instruction::DecMRhl => rom.push(0x035),
-            Instruction::AddR8(reg) => match reg {
-                Reg8::A => rom.push(0x87),
-                Reg8::B => rom.push(0x80),
-                Reg8::C => rom.push(0x81),
-                Reg8::D => rom.push(0x82),
-                Reg8::E => rom.push(0x83),
-                Reg8::H => rom.push(0x84),
-                Reg8::L => rom.push(0x85),
-            },
-            Instruction::AddMRhl => rom.push(0x86),
-            Instruction::AddI8(expr) => {
-                rom.push(0xC6);
-                rom.push(expr.get_byte(constants)?);
-            }
-            Instruction::AddRhlR16(reg) => match reg {
-                Reg16::BC => rom.push(0x09),
-                Reg16::DE => rom.push(0x19),
-                Reg16::HL => rom.push(0x29),
-                Reg16::SP => rom.push(0x39),
-            },
-            Instruction::AddRspI8(expr) => {
-                rom.push(0xE8);
-                rom.push(expr.get_byte(constants)?);
-            }
-            Instruction::SubR8(reg) => match reg {
-                Reg8::A => rom.push(0x97),
-                Reg8::B => rom.push(0x90),
-                Reg8::C => rom.push(0x91),
-                Reg8::D => rom.push(0x92),
-                Reg8::E => rom.push(0x93),
-                Reg8::H => rom.push(0x94),
-                Reg8::L => rom.push(0x95),
-            },
-            Instruction::SubMRhl => rom.push(0x96),
-            Instruction::SubI8(expr) => {
-                rom.push(0xD6);
-                rom.push(expr.get_byte(constants)?);
-            }
-            Instruction::AndR8(reg) => match reg {
-                Reg8::A => rom.push(0xA7),
-                Reg8::B => rom.push(0xA0),
-                Reg8::C => rom.push(0xA1),
-                Reg8::D => rom.push(0xA2),
-                Reg8::E => rom.push(0xA3),
-                Reg8::H => rom.push(0xA4),
-                Reg8::L => rom.push(0xA5),
-            },
-            Instruction::AndMRhl => rom.push(0xA6),
-            Instruction::AndI8(expr) => {
-                rom.push(0xE6);
-                rom.push(expr.get_byte(constants)?);
-            }
-            Instruction::OrR8(reg) => match reg {
-                Reg8::A => rom.push(0xB7),
-                Reg8::B => rom.push(0xB0),
-                Reg8::C => rom.push(0xB1),
-                Reg8::D => rom.push(0xB2),
-                Reg8::E => rom.push(0xB3),
-                Reg8::H => rom.push(0xB4),
-                Reg8::L => rom.push(0xB5),
-            },
-            Instruction::OrMRhl => rom.push(0xB6),
-            Instruction::OrI8(expr) => {
-                rom.push(0xF6);
-                rom.push(expr.get_byte(constants)?);
-            }
-            Instruction::AdcR8(reg) => match reg {
-                Reg8::A => rom.push(0x8F),
-                Reg8::B => rom.push(0x88),
-                Reg8::C => rom.push(0x89),
-                Reg8::D => rom.push(0x8A),
-                Reg8::E => rom.push(0x8B),
-                Reg8::H => rom.push(0x8C),
-                Reg8::L => rom.push(0x8D),
-            },
-            Instruction::AdcMRhl => rom.push(0x8E),
-            Instruction::AdcI8(expr) => {
-                rom.push(0xCE);
-                rom.push(expr.get_byte(constants)?);
             }
-            Instruction::SbcR8(reg) => match reg {
-                Reg8::A => rom.push(0x9F),
-                Reg8::B => rom.push(0x98),
-                Reg8::C => rom.push(0x99),
-                Reg8::D => rom.push(0x9A),
-                Reg8::E => rom.push(0x9B),
-                Reg8::H => rom.push(0x9C),
-                Reg8::L => rom.push(0x9D),
-            },
-            Instruction::SbcMRhl => rom.push(0x9E),
-            Instruction::SbcI8(expr) => {
-                rom.push(0xDE);
-                rom.push(expr.get_byte(constants)?);
-            }
-            Instruction::XorR8(reg) => match reg {
-                Reg8::A => rom.push(0xAF),
-                Reg8::B => rom.push(0xA8),
-                Reg8::C => rom.push(0xA9),
-                Reg8::D => rom.push(0xAA),
-                Reg8::E => rom.push(0xAB),
-                Reg8::H => rom.push(0xAC),
-                Reg8::L => rom.push(0xAD),
-            },
-            Instruction::XorMRhl => rom.push(0xAE),
-            Instruction::XorI8(expr) => {
-                rom.push(0xEE);
-                rom.push(expr.get_byte(constants)?);
-            }
-            Instruction::CpR8(reg) => match reg {
-                Reg8::A => rom.push(0xBF),
-                Reg8::B => rom.push(0xB8),
-                Reg8::C => rom.push(0xB9),
-                Reg8::D => rom.push(0xBA),
-                Reg8::E => rom.push(0xBB),
-                Reg8::H => rom.push(0xBC),
-                Reg8::L => rom.push(0xBD),
-            },
-            Instruction::CpMRhl => rom.push(0xBE),
-            Instruction::CpI8(expr) => {
-                rom.push(0xFE);
-                rom.push(expr.get_byte(constants)?);
-            }
-            Instruction::LdR16I16(reg, expr) => {
-                match reg {
-                    Reg16::BC => rom.push(0x01),
-                    Reg16::DE => rom.push(0x11),
-                    Reg16::HL => rom.push(0x21),
-                    Reg16::SP => rom.push(0x31),
+            Instruction::DbExprList16(exprs) => {
+                for expr in exprs {
+                    rom.extend(expr.get_2bytes(constants)?.iter());
                 }
-                rom.extend(expr.get_2bytes(constants)?.iter());
-            }
-            Instruction::LdMI16Rsp(expr) => {
-                rom.push(0x08);
-                rom.extend(expr.get_2bytes(constants)?.iter());
-            }
-            Instruction::LdR8I8(reg, expr) => {
-                match reg {
-                    Reg8::A => rom.push(0x3E),
-                    Reg8::B => rom.push(0x06),
-                    Reg8::C => rom.push(0x0E),
-                    Reg8::D => rom.push(0x16),
-                    Reg8::E => rom.push(0x1E),
-                    Reg8::H => rom.push(0x26),
-                    Reg8::L => rom.push(0x2E),
-                }
-                rom.push(expr.get_byte(constants)?);
             }
-            Instruction::LdR8R8(reg_in, reg_out) => {
-                let mut byte = 0;
-                // first 5 bits
-                byte |= match reg_in {
-                    Reg8::A => 0x78,
-                    Reg8::B => 0x40,
-                    Reg8::C => 0x48,
-                    Reg8::D => 0x50,
-                    Reg8::E => 0x58,
-                    Reg8::H => 0x60,
-                    Reg8::L => 0x68,
-                };
-
-                // last 3 bits
-                byte |= Instruction::reg8_to_bits(reg_out);
-
-                rom.push(byte);
-            }
-            Instruction::LdMRbcRa => rom.push(0x02),
-            Instruction::LdMRdeRa => rom.push(0x12),
-            Instruction::LdRaMRbc => rom.push(0x0A),
-            Instruction::LdRaMRde => rom.push(0x1A),
-            Instruction::LdR8MRhl(reg) => match reg {
-                Reg8::A => rom.push(0x7E),
-                Reg8::B => rom.push(0x46),
-                Reg8::C => rom.push(0x4E),
-                Reg8::D => rom.push(0x56),
-                Reg8::E => rom.push(0x5E),
-                Reg8::H => rom.push(0x66),
-                Reg8::L => rom.push(0x6E),
-            },
-            Instruction::LdMRhlR8(reg) => match reg {
-                Reg8::A => rom.push(0x77),
-                Reg8::B => rom.push(0x70),
-                Reg8::C => rom.push(0x71),
-                Reg8::D => rom.push(0x72),
-                Reg8::E => rom.push(0x73),
-                Reg8::H => rom.push(0x74),
-                Reg8::L => rom.push(0x75),
-            },
-            Instruction::LdMRhlI8(expr) => {
-                rom.push(0x36);
-                rom.push(expr.get_byte(constants)?);
-            }
-            Instruction::LdMI16Ra(expr) => {
-                rom.push(0xEA);
-                rom.extend(expr.get_2bytes(constants)?.iter());
-            }
-            Instruction::LdRaMI16(expr) => {
-                rom.push(0xFA);
-                rom.extend(expr.get_2bytes(constants)?.iter());
-            }
-            Instruction::LdhRaMI8(expr) => {
-                rom.push(0xF0);
-                rom.push(expr.get_byte(constants)?);
+            Instruction::DbString(items) => {
+                for item in items {
+                    match item {
+                        DbItem::Expr(expr) => rom.push(expr.get_byte(constants)?),
+                        DbItem::String(text) => {
+                            for c in text.chars() {
+                                match charmap.get(&c) {
+                                    Some(byte) => rom.push(*byte),
+                                    None if c.is_ascii() => rom.push(c as u8),
+                                    None => bail!(
+                                        "Character {:?} is not ASCII and is not in the charmap",
+                                        c
+                                    ),
+                                }
+                            }
+                        }
+                    }
+                }
             }
-            Instruction::LdhMI8Ra(expr) => {
-                rom.push(0xE0);
-                rom.push(expr.get_byte(constants)?);
+            Instruction::DbFill(_, value) => {
+                let byte = value.get_byte(constants)?;
+                for _ in 0..self.bytes_len_with(0, constants)? {
+                    rom.push(byte);
+                }
             }
-            Instruction::LdhRaMRc => rom.push(0xF2),
-            Instruction::LdhMRcRa => rom.push(0xE2),
-            Instruction::LdiMRhlRa => rom.push(0x22),
-            Instruction::LddMRhlRa => rom.push(0x32),
-            Instruction::LdiRaMRhl => rom.push(0x2A),
-            Instruction::LddRaMRhl => rom.push(0x3A),
-            Instruction::LdRspRhl => rom.push(0xF9),
-            Instruction::LdRhlRspI8(expr) => {
-                rom.push(0xF8);
+            Instruction::Charmap(_, _) => {}
+            Instruction::DebugAssert(flag) => write_debug_assert(rom, flag),
+            Instruction::DebugAssertCp(comparison, expr) => {
+                rom.push(0xFE);
                 rom.push(expr.get_byte(constants)?);
+                write_debug_assert(rom, &comparison.flag());
             }
-            Instruction::Push(reg) => match reg {
-                Reg16Push::BC => rom.push(0xC5),
-                Reg16Push::DE => rom.push(0xD5),
-                Reg16Push::HL => rom.push(0xE5),
-                Reg16Push::AF => rom.push(0xF5),
-            },
-            Instruction::Pop(reg) => match reg {
-                Reg16Push::BC => rom.push(0xC1),
-                Reg16Push::DE => rom.push(0xD1),
-                Reg16Push::HL => rom.push(0xE1),
-                Reg16Push::AF => rom.push(0xF1),
-            },
-            Instruction::RlcR8(reg) => {
-                rom.push(0xCB);
-                match reg {
-                    Reg8::A => rom.push(0x07),
-                    Reg8::B => rom.push(0x00),
-                    Reg8::C => rom.push(0x01),
-                    Reg8::D => rom.push(0x02),
-                    Reg8::E => rom.push(0x03),
-                    Reg8::H => rom.push(0x04),
-                    Reg8::L => rom.push(0x05),
+            Instruction::DebugPrint(message) => {
+                // The jump needs to skip over 4 bytes of header + the message
+                if message.len() > 0x7F - 4 {
+                    bail!(
+                        "debug_print message is {} bytes long, but cannot be longer than {} bytes",
+                        message.len(),
+                        0x7F - 4
+                    );
                 }
+                rom.push(0x52); // ld d, d
+                rom.push(0x18); // jr
+                rom.push(message.len() as u8 + 4);
+                rom.extend([0x64, 0x64, 0x00, 0x00].iter());
+                rom.extend(message.as_bytes());
             }
-            Instruction::RlcMRhl => {
-                rom.push(0xCB);
-                rom.push(0x06);
-            }
-            Instruction::RrcR8(reg) => {
-                rom.push(0xCB);
-                match reg {
-                    Reg8::A => rom.push(0x0f),
-                    Reg8::B => rom.push(0x08),
-                    Reg8::C => rom.push(0x09),
-                    Reg8::D => rom.push(0x0a),
-                    Reg8::E => rom.push(0x0b),
-                    Reg8::H => rom.push(0x0c),
-                    Reg8::L => rom.push(0x0d),
+            Instruction::DebugBreak => rom.push(0x40), // ld b, b
+            Instruction::Assert(expr, message) => {
+                if expr.run(constants)? == 0 {
+                    match message {
+                        Some(message) => bail!("Assertion failed: {}", message),
+                        None => bail!("Assertion failed: {:?}", expr),
+                    }
                 }
             }
-            Instruction::RrcMRhl => {
-                rom.push(0xCB);
-                rom.push(0x0E);
-            }
-            Instruction::RlR8(reg) => {
-                rom.push(0xCB);
-                match reg {
-                    Reg8::A => rom.push(0x17),
-                    Reg8::B => rom.push(0x10),
-                    Reg8::C => rom.push(0x11),
-                    Reg8::D => rom.push(0x12),
-                    Reg8::E => rom.push(0x13),
-                    Reg8::H => rom.push(0x14),
-                    Reg8::L => rom.push(0x15),
-                }
+            Instruction::Print(_) | Instruction::Warn(_) => {}
+            Instruction::Fail(items) => bail!("{}", format_message(items, constants)?),
+            Instruction::If(_) | Instruction::Elif(_) | Instruction::Else | Instruction::Endc => {
+                bail!("Conditional blocks need to be resolved before writing to the rom")
             }
-            Instruction::RlMRhl => {
-                rom.push(0xCB);
-                rom.push(0x16);
-            }
-            Instruction::RrR8(reg) => {
-                rom.push(0xCB);
-                match reg {
-                    Reg8::A => rom.push(0x1f),
-                    Reg8::B => rom.push(0x18),
-                    Reg8::C => rom.push(0x19),
-                    Reg8::D => rom.push(0x1a),
-                    Reg8::E => rom.push(0x1b),
-                    Reg8::H => rom.push(0x1c),
-                    Reg8::L => rom.push(0x1d),
-                }
+            Instruction::Include(_) => {
+                bail!("Includes need to be resolved before writing to the rom")
             }
-            Instruction::RrMRhl => {
-                rom.push(0xCB);
-                rom.push(0x1E);
-            }
-            Instruction::SlaR8(reg) => {
-                rom.push(0xCB);
-                match reg {
-                    Reg8::A => rom.push(0x27),
-                    Reg8::B => rom.push(0x20),
-                    Reg8::C => rom.push(0x21),
-                    Reg8::D => rom.push(0x22),
-                    Reg8::E => rom.push(0x23),
-                    Reg8::H => rom.push(0x24),
-                    Reg8::L => rom.push(0x25),
-                }
+            Instruction::Incbin(..) => {
+                bail!("Incbins need to be resolved before writing to the rom")
             }
-            Instruction::SlaMRhl => {
-                rom.push(0xCB);
-                rom.push(0x26);
-            }
-            Instruction::SraR8(reg) => {
-                rom.push(0xCB);
-                match reg {
-                    Reg8::A => rom.push(0x2f),
-                    Reg8::B => rom.push(0x28),
-                    Reg8::C => rom.push(0x29),
-                    Reg8::D => rom.push(0x2a),
-                    Reg8::E => rom.push(0x2b),
-                    Reg8::H => rom.push(0x2c),
-                    Reg8::L => rom.push(0x2d),
-                }
+            Instruction::Section(..) => {
+                bail!("Sections need to be resolved before writing to the rom")
             }
-            Instruction::SraMRhl => {
-                rom.push(0xCB);
-                rom.push(0x2E);
-            }
-            Instruction::SwapR8(reg) => {
-                rom.push(0xCB);
-                match reg {
-                    Reg8::A => rom.push(0x37),
-                    Reg8::B => rom.push(0x30),
-                    Reg8::C => rom.push(0x31),
-                    Reg8::D => rom.push(0x32),
-                    Reg8::E => rom.push(0x33),
-                    Reg8::H => rom.push(0x34),
-                    Reg8::L => rom.push(0x35),
-                }
+            Instruction::Opt(_) => {}
+            Instruction::PushO | Instruction::PopO | Instruction::PragmaOnce => {}
+            Instruction::PushS | Instruction::PopS => {
+                bail!("Section stacks need to be resolved before writing to the rom")
             }
-            Instruction::SwapMRhl => {
-                rom.push(0xCB);
-                rom.push(0x36);
-            }
-            Instruction::SrlR8(reg) => {
-                rom.push(0xCB);
-                match reg {
-                    Reg8::A => rom.push(0x3f),
-                    Reg8::B => rom.push(0x38),
-                    Reg8::C => rom.push(0x39),
-                    Reg8::D => rom.push(0x3a),
-                    Reg8::E => rom.push(0x3b),
-                    Reg8::H => rom.push(0x3c),
-                    Reg8::L => rom.push(0x3d),
-                }
+            Instruction::Export(_) => {}
+            Instruction::Namespace(_) | Instruction::EndNamespace => {
+                bail!("Namespaces need to be resolved before writing to the rom")
             }
-            Instruction::SrlMRhl => {
-                rom.push(0xCB);
-                rom.push(0x3E);
-            }
-            Instruction::BitBitR8(expr, reg) => {
-                rom.push(0xCB);
-                let byte = 0x40                                    // 0b11000000
-                         | (expr.get_bit_index(constants)? * 0x08) // 0b00111000
-                         | Instruction::reg8_to_bits(reg); // 0b00000111
-                rom.push(byte);
-            }
-            Instruction::BitBitMRhl(expr) => {
-                rom.push(0xCB);
-                rom.push(0x46 + expr.get_bit_index(constants)? * 0x08);
-            }
-            Instruction::ResBitR8(expr, reg) => {
-                rom.push(0xCB);
-                let byte = 0x80                                    // 0b11000000
-                         | (expr.get_bit_index(constants)? * 0x08) // 0b00111000
-                         | Instruction::reg8_to_bits(reg); // 0b00000111
-                rom.push(byte)
-            }
-            Instruction::ResBitMRhl(expr) => {
-                rom.push(0xCB);
-                rom.push(0x86 + expr.get_bit_index(constants)? * 0x08);
-            }
-            Instruction::SetBitR8(expr, reg) => {
-                rom.push(0xCB);
-                let byte = 0xC0                                    // 0b11000000
-                         | (expr.get_bit_index(constants)? * 0x08) // 0b00111000
-                         | Instruction::reg8_to_bits(reg); // 0b00000111
-                rom.push(byte)
-            }
-            Instruction::SetBitMRhl(expr) => {
-                rom.push(0xCB);
-                rom.push(0xC6 + expr.get_bit_index(constants)? * 0x08);
+            Instruction::Union | Instruction::NextU | Instruction::EndU => {
+                bail!("Unions can only be placed in RAM, not written to the rom")
             }
+            instruction => match instruction.encoding() {
+                Some(encoding) => encoding.write(rom, constants)?,
+                None => unreachable!("{:?} has no encoding", instruction),
+            },
         }
         Ok(())
     }
 
-    fn reg8_to_bits(reg: &Reg8) -> u8 {
-        match reg {
-            Reg8::A => 0x07,
-            Reg8::B => 0x00,
-            Reg8::C => 0x01,
-            Reg8::D => 0x02,
-            Reg8::E => 0x03,
-            Reg8::H => 0x04,
-            Reg8::L => 0x05,
-        }
+    /// Returns how many bytes the instruction takes up when it starts at start_address within the current ROM bank.
+    /// Returns Err if the instruction is an advance_address to before start_address.
+    pub fn bytes_len(&self, start_address: u16) -> Result<u16, Error> {
+        self.bytes_len_with(start_address, &HashMap::new())
     }
 
-    /// Returns how many bytes the instruction takes up
-    pub fn bytes_len(&self, start_address: u16) -> u16 {
-        match self {
-            Instruction::AdvanceAddress(advance_address) => advance_address - start_address,
+    /// Returns how many bytes the instruction takes up when it starts at start_address within the current ROM bank,
+    /// evaluating any expressions the size depends on with constants.
+    ///
+    /// Used by the RomBuilder when placing labels, see [crate::RomBuilder::add_instructions].
+    /// When a constant is missing, e.g. a label that has not been placed yet, the largest size is returned.
+    pub fn bytes_len_with(
+        &self,
+        start_address: u16,
+        constants: &HashMap<String, i64>,
+    ) -> Result<u16, Error> {
+        Ok(match self {
+            Instruction::AdvanceAddress(address, bank) => {
+                let advance_address = address.run(constants)?;
+                // the cpu sees every bank but bank 0 at 0x4000-0x7FFF
+                let offset = match bank {
+                    Some(bank) if bank.run(constants)? != 0 => {
+                        if !(0x4000..0x8000).contains(&advance_address) {
+                            bail!(
+                                "advance_address 0x{:x} is not in a switchable bank, which is mapped to 0x4000-0x7FFF",
+                                advance_address
+                            );
+                        }
+                        advance_address - ROM_BANK_SIZE as i64
+                    }
+                    _ => advance_address,
+                };
+                if offset < start_address as i64 {
+                    bail!(
+                        "advance_address 0x{:x} is before the current address 0x{:x}",
+                        advance_address,
+                        start_address
+                    );
+                }
+                if offset > ROM_BANK_SIZE as i64 {
+                    bail!(
+                        "advance_address 0x{:x} is past the end of the bank",
+                        advance_address
+                    );
+                }
+                (offset - start_address as i64) as u16
+            }
             Instruction::EmptyLine => 0,
             Instruction::Equ(_, _) => 0,
+            Instruction::Set(_, _) => 0,
             Instruction::Label(_) => 0,
+            Instruction::AnonymousLabel => 0,
             Instruction::Db(bytes) => bytes.len() as u16,
             Instruction::DbExpr8(_) => 1,
             Instruction::DbExpr16(_) => 2,
-            Instruction::Nop => 1,
-            Instruction::Stop => 1,
-            Instruction::Halt => 2,
-            Instruction::Di => 1,
-            Instruction::Ei => 1,
-            Instruction::Rrca => 1,
-            Instruction::Rra => 1,
-            Instruction::Cpl => 1,
-            Instruction::Ccf => 1,
-            Instruction::Rlca => 1,
-            Instruction::Rla => 1,
-            Instruction::Daa => 1,
-            Instruction::Scf => 1,
-            Instruction::Ret(_) => 1,
-            Instruction::Reti => 1,
-            Instruction::Call(_, _) => 3,
-            Instruction::JpI16(_, _) => 3,
-            Instruction::JpRhl => 1,
-            Instruction::Jr(_, _) => 2,
-            Instruction::IncR16(_) => 1,
-            Instruction::IncR8(_) => 1,
-            Instruction::IncMRhl => 1,
-            Instruction::DecR16(_) => 1,
-            Instruction::DecR8(_) => 1,
-            Instruction::DecMRhl => 1,
-            Instruction::AddR8(_) => 1,
-            Instruction::AddMRhl => 1,
-            Instruction::AddI8(_) => 2,
-            Instruction::AddRhlR16(_) => 1,
-            Instruction::AddRspI8(_) => 2,
-            Instruction::SubR8(_) => 1,
-            Instruction::SubMRhl => 1,
-            Instruction::SubI8(_) => 2,
-            Instruction::AndR8(_) => 1,
-            Instruction::AndMRhl => 1,
-            Instruction::AndI8(_) => 2,
-            Instruction::OrR8(_) => 1,
-            Instruction::OrMRhl => 1,
-            Instruction::OrI8(_) => 2,
-            Instruction::AdcR8(_) => 1,
-            Instruction::AdcMRhl => 1,
-            Instruction::AdcI8(_) => 2,
-            Instruction::SbcR8(_) => 1,
-            Instruction::SbcMRhl => 1,
-            Instruction::SbcI8(_) => 2,
-            Instruction::XorR8(_) => 1,
-            Instruction::XorMRhl => 1,
-            Instruction::XorI8(_) => 2,
-            Instruction::CpR8(_) => 1,
-            Instruction::CpMRhl => 1,
-            Instruction::CpI8(_) => 2,
-            Instruction::LdR16I16(_, _) => 3,
-            Instruction::LdMI16Rsp(_) => 3,
-            Instruction::LdR8I8(_, _) => 2,
-            Instruction::LdR8R8(_, _) => 1,
-            Instruction::LdMRbcRa => 1,
-            Instruction::LdMRdeRa => 1,
-            Instruction::LdRaMRbc => 1,
-            Instruction::LdRaMRde => 1,
-            Instruction::LdR8MRhl(_) => 1,
-            Instruction::LdMRhlR8(_) => 1,
-            Instruction::LdMRhlI8(_) => 2,
-            Instruction::LdMI16Ra(_) => 3,
-            Instruction::LdRaMI16(_) => 3,
-            Instruction::LdhRaMI8(_) => 2,
-            Instruction::LdhMI8Ra(_) => 2,
-            Instruction::LdhRaMRc => 1,
-            Instruction::LdhMRcRa => 1,
-            Instruction::LdiMRhlRa => 1,
-            Instruction::LddMRhlRa => 1,
-            Instruction::LdiRaMRhl => 1,
-            Instruction::LddRaMRhl => 1,
-            Instruction::LdRhlRspI8(_) => 2,
-            Instruction::LdRspRhl => 1,
-            Instruction::Push(_) => 1,
-            Instruction::Pop(_) => 1,
-            Instruction::BitBitR8(_, _) => 2,
-            Instruction::BitBitMRhl(_) => 2,
-            Instruction::ResBitR8(_, _) => 2,
-            Instruction::ResBitMRhl(_) => 2,
-            Instruction::SetBitR8(_, _) => 2,
-            Instruction::SetBitMRhl(_) => 2,
-            Instruction::RlcR8(_) => 2,
-            Instruction::RlcMRhl => 2,
-            Instruction::RrcR8(_) => 2,
-            Instruction::RrcMRhl => 2,
-            Instruction::RlR8(_) => 2,
-            Instruction::RlMRhl => 2,
-            Instruction::RrR8(_) => 2,
-            Instruction::RrMRhl => 2,
-            Instruction::SlaR8(_) => 2,
-            Instruction::SlaMRhl => 2,
-            Instruction::SraR8(_) => 2,
-            Instruction::SraMRhl => 2,
-            Instruction::SwapR8(_) => 2,
-            Instruction::SwapMRhl => 2,
-            Instruction::SrlR8(_) => 2,
-            Instruction::SrlMRhl => 2,
+            Instruction::DbExprList8(exprs) => exprs.len() as u16,
+            Instruction::DbExprList16(exprs) => exprs.len() as u16 * 2,
+            Instruction::DbString(items) => items
+                .iter()
+                .map(|item| match item {
+                    DbItem::Expr(_) => 1,
+                    DbItem::String(text) => text.chars().count() as u16,
+                })
+                .sum(),
+            Instruction::DbFill(count, _) => {
+                let count = count.run(constants)?;
+                if !(0..=ROM_BANK_SIZE as i64).contains(&count) {
+                    bail!(
+                        "dbfill count {} must be from 0 to 0x{:x}, the size of a rom bank",
+                        count,
+                        ROM_BANK_SIZE
+                    );
+                }
+                count as u16
+            }
+            Instruction::Charmap(_, _) => 0,
+            Instruction::DebugAssert(_) => 3,
+            Instruction::DebugAssertCp(..) => 5,
+            Instruction::DebugPrint(message) => 6 + message.len() as u16,
+            Instruction::DebugBreak => 1,
+            Instruction::Assert(_, _) => 0,
+            Instruction::Print(_) => 0,
+            Instruction::Warn(_) => 0,
+            Instruction::Fail(_) => 0,
+            Instruction::If(_) => 0,
+            Instruction::Elif(_) => 0,
+            Instruction::Else => 0,
+            Instruction::Endc => 0,
+            Instruction::Include(_) => 0,
+            Instruction::Incbin(..) => 0,
+            Instruction::Section(..) => 0,
+            Instruction::Opt(_) => 0,
+            Instruction::PushO => 0,
+            Instruction::PopO => 0,
+            Instruction::PushS => 0,
+            Instruction::PopS => 0,
+            Instruction::PragmaOnce => 0,
+            Instruction::Export(_) => 0,
+            Instruction::Namespace(_) => 0,
+            Instruction::EndNamespace => 0,
+            Instruction::Union => 0,
+            Instruction::NextU => 0,
+            Instruction::EndU => 0,
+            instruction => match instruction.encoding() {
+                Some(encoding) => encoding.bytes_len(),
+                None => unreachable!("{:?} has no encoding", instruction),
+            },
+        })
+    }
+}
+
+/// Joins the strings and evaluated expressions of a PRINT, WARN or FAIL, displaying expressions in decimal.
+pub fn format_message(items: &[DbItem], constants: &HashMap<String, i64>) -> Result<String, Error> {
+    let mut message = String::new();
+    for item in items {
+        match item {
+            DbItem::String(text) => message.push_str(text),
+            DbItem::Expr(expr) => message.push_str(&expr.run(constants)?.to_string()),
         }
     }
+    Ok(message)
+}
+
+/// Writes a `jr` over `rst 0x38` that is taken when the flag is set, followed by the `rst 0x38`.
+fn write_debug_assert(rom: &mut Vec<u8>, flag: &Flag) {
+    match flag {
+        Flag::Always => rom.push(0x18),
+        Flag::Z => rom.push(0x28),
+        Flag::C => rom.push(0x38),
+        Flag::NZ => rom.push(0x20),
+        Flag::NC => rom.push(0x30),
+    }
+    rom.push(0x01);
+    rom.push(0xFF);
+}
+
+/// Returns Err if the identifier could not be written in an asm file.
+fn validate_identifier(ident: &str) -> Result<(), Error> {
+    if ident.is_empty() {
+        bail!("Identifier is empty");
+    }
+    if let Some(c) = ident
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '_')
+    {
+        bail!("Identifier {:?} contains invalid character {:?}", ident, c);
+    }
+    Ok(())
 }
@@ -10,9 +10,57 @@
 use crate::ast::{Expr, Instruction};
 use anyhow::{bail, Error};
 
+/// Options applied to the entire song during data generation.
+#[derive(Debug, Clone, Default)]
+pub struct AudioOptions {
+    /// semitones to shift every note by, applied on top of any `transpose` commands
+    pub transpose: i8,
+    /// when set, a delayed copy of channel 2 is generated on channel 1
+    pub echo: Option<Echo>,
+    /// when set, generation fails if the player would take more than this many machine cycles for a single step
+    ///
+    /// Use [step_cycles] to calculate the cost of a step.
+    pub cycle_budget: Option<u32>,
+}
+
+/// Machine cycles taken by GGBASMAudioStep for a step, including the call and the final rest command.
+pub const PLAYER_STEP_CYCLES: u32 = 80;
+
+/// Machine cycles taken by GGBASMAudioStep for each register write command.
+pub const PLAYER_WRITE_CYCLES: u32 = 16;
+
+/// Returns the machine cycles the audio player takes to perform a step with the given number of register writes.
+pub fn step_cycles(register_writes: u32) -> u32 {
+    PLAYER_STEP_CYCLES + register_writes * PLAYER_WRITE_CYCLES
+}
+
+/// Generates a delayed, quieter copy of the channel 2 melody on channel 1, the classic gameboy echo effect.
+///
+/// A copy is only placed on rows that do not already use channel 1.
+/// Copies that would be placed after the last row are dropped.
+#[derive(Debug, Clone)]
+pub struct Echo {
+    /// number of rows the copy is delayed by
+    pub delay: usize,
+    /// subtracted from the envelope initial volume of the copy
+    pub attenuation: u8,
+}
+
 /// Processes `Vec<AudioLine>` into `Vec<Instruction>` that can be played by the audio player
 /// Despite returning Instruction, the only variants used are Db* and Label.
 pub fn generate_audio_data(lines: Vec<AudioLine>) -> Result<Vec<Instruction>, Error> {
+    generate_audio_data_with_options(lines, &AudioOptions::default())
+}
+
+/// Same as [generate_audio_data] but the provided options are applied to the generated data.
+pub fn generate_audio_data_with_options(
+    mut lines: Vec<AudioLine>,
+    options: &AudioOptions,
+) -> Result<Vec<Instruction>, Error> {
+    if let Some(echo) = &options.echo {
+        apply_echo(&mut lines, echo)?;
+    }
+
     // Bail if a clean exit is impossible
     let mut bad_label = None;
     let mut clean_exit = false;
@@ -35,8 +83,12 @@ pub fn generate_audio_data(lines: Vec<AudioLine>) -> Result<Vec<Instruction>, Er
         }
     }
 
+    let mut transpose = 0;
+    let mut label = None;
+    let mut step = 0;
     let mut result = vec![];
     for line in lines {
+        let semitones = options.transpose as i32 + transpose;
         match line {
             AudioLine::SetRegisters { rest, ch1, ch2, .. } => {
                 let mut bytes = vec![];
@@ -63,7 +115,9 @@ pub fn generate_audio_data(lines: Vec<AudioLine>) -> Result<Vec<Instruction>, Er
                     // TODO: Validate ff10 inputs
 
                     // generate register values
-                    let frequency = note_to_frequency(state.octave, &state.note, state.sharp)?;
+                    let (note, sharp, octave) =
+                        transpose_note(state.octave, &state.note, state.sharp, semitones)?;
+                    let frequency = note_to_frequency(octave, &note, sharp)?;
                     let length = 0x3f - state.length; // make length start at 0 and higher values mean longer length.
 
                     let ff10 = 0;
@@ -114,7 +168,9 @@ pub fn generate_audio_data(lines: Vec<AudioLine>) -> Result<Vec<Instruction>, Er
                     }
 
                     // generate register values
-                    let frequency = note_to_frequency(state.octave, &state.note, state.sharp)?;
+                    let (note, sharp, octave) =
+                        transpose_note(state.octave, &state.note, state.sharp, semitones)?;
+                    let frequency = note_to_frequency(octave, &note, sharp)?;
                     let length = 0x3f - state.length; // make length start at 0 and higher values mean longer length.
 
                     let ff16 = (state.duty << 6 & 0b11000000) | length & 0b00111111;
@@ -140,24 +196,89 @@ pub fn generate_audio_data(lines: Vec<AudioLine>) -> Result<Vec<Instruction>, Er
                     bytes.push(ff19);
                 }
 
+                step += 1;
+                if let Some(budget) = options.cycle_budget {
+                    let cycles = step_cycles(bytes.len() as u32 / 2);
+                    if cycles > budget {
+                        bail!(
+                            "Step {} after label {} takes {} machine cycles which exceeds the cycle budget of {}",
+                            step,
+                            label.as_deref().unwrap_or("<none>"),
+                            cycles,
+                            budget
+                        );
+                    }
+                }
+
                 bytes.push(0xFF);
                 bytes.push(rest);
 
                 result.push(Instruction::Db(bytes));
             }
-            AudioLine::Rest(rest) => result.push(Instruction::Db(vec![0xFF, rest])),
+            AudioLine::Rest(rest) => {
+                step += 1;
+                result.push(Instruction::Db(vec![0xFF, rest]))
+            }
             AudioLine::Disable => result.push(Instruction::Db(vec![0xFC])),
             AudioLine::PlayFrom(label) => {
                 result.push(Instruction::Db(vec![0xFE]));
                 result.push(Instruction::DbExpr16(Expr::Ident(label)));
             }
-            AudioLine::Label(label) => result.push(Instruction::Label(label)),
+            AudioLine::Label(new_label) => {
+                label = Some(new_label.clone());
+                step = 0;
+                result.push(Instruction::Label(new_label));
+            }
+            AudioLine::Transpose(semitones) => transpose = semitones as i32,
         }
     }
 
     Ok(result)
 }
 
+/// Copies the channel 2 state of each row onto channel 1 of the row `echo.delay` rows later.
+fn apply_echo(lines: &mut [AudioLine], echo: &Echo) -> Result<(), Error> {
+    if echo.delay == 0 {
+        bail!("Echo delay must be at least 1 row");
+    }
+
+    let mut copies = vec![];
+    for line in lines.iter() {
+        if let AudioLine::SetRegisters { ch2, .. } = line {
+            copies.push(ch2.as_ref().map(|state| {
+                Channel1State {
+                    note: state.note.clone(),
+                    sharp: state.sharp,
+                    octave: state.octave,
+                    duty: state.duty,
+                    length: state.length,
+                    envelope_initial_volume: state
+                        .envelope_initial_volume
+                        .saturating_sub(echo.attenuation),
+                    envelope_argument: state.envelope_argument,
+                    envelope_increase: state.envelope_increase,
+                    enable_length: state.enable_length,
+                    initial: state.initial,
+                    sweep_time: 0,
+                    sweep_increase: true,
+                    sweep_number: 0,
+                }
+            }));
+        }
+    }
+
+    let mut row = 0;
+    for line in lines.iter_mut() {
+        if let AudioLine::SetRegisters { ch1, .. } = line {
+            if ch1.is_none() && row >= echo.delay {
+                *ch1 = copies[row - echo.delay].take();
+            }
+            row += 1;
+        }
+    }
+    Ok(())
+}
+
 /// Parses `&str` into `Vec<AudioLine>`
 /// Returns `Err` if the text does not conform to the audio text format.
 ///
@@ -209,6 +330,18 @@ fn parse_audio_line(line: &str) -> Result<AudioLine, Error> {
                 tokens.len()
             );
         }
+    } else if tokens[0].to_lowercase() == "transpose" {
+        if tokens.len() == 2 {
+            match tokens[1].trim_start_matches('+').parse() {
+                Ok(value) => Ok(AudioLine::Transpose(value)),
+                Err(_) => bail!("transpose instruction argument is not an integer"),
+            }
+        } else {
+            bail!(
+                "Expected 1 argument for transpose, however there is {} arguments",
+                tokens.len()
+            );
+        }
     } else if tokens[0].to_lowercase() == "disable" {
         Ok(AudioLine::Disable)
     } else {
@@ -381,10 +514,12 @@ pub enum AudioLine {
     PlayFrom(String),
     Rest(u8),
     Disable,
+    /// shift the notes of all following lines by this many semitones
+    Transpose(i8),
 }
 
 /// Represents a Note to be played by a channel
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Note {
     A,
     B,
@@ -432,6 +567,50 @@ pub struct Channel3State {}
 /// Represents the state of channel 4
 pub struct Channel4State {}
 
+/// Shifts a note by the provided number of semitones.
+/// return (Note, sharp, octave)
+fn transpose_note(
+    octave: u8,
+    note: &Note,
+    sharp: bool,
+    semitones: i32,
+) -> Result<(Note, bool, u8), Error> {
+    let natural = match note {
+        Note::C => 0,
+        Note::D => 2,
+        Note::E => 4,
+        Note::F => 5,
+        Note::G => 7,
+        Note::A => 9,
+        Note::B => 11,
+    };
+    let index = octave as i32 * 12 + natural + if sharp { 1 } else { 0 } + semitones;
+    if index < 0 {
+        bail!(
+            "Transposing {}{} by {} semitones is too low",
+            format!("{:?}", note).to_uppercase(),
+            octave,
+            semitones
+        );
+    }
+
+    let (note, sharp) = match index % 12 {
+        0 => (Note::C, false),
+        1 => (Note::C, true),
+        2 => (Note::D, false),
+        3 => (Note::D, true),
+        4 => (Note::E, false),
+        5 => (Note::F, false),
+        6 => (Note::F, true),
+        7 => (Note::G, false),
+        8 => (Note::G, true),
+        9 => (Note::A, false),
+        10 => (Note::A, true),
+        _ => (Note::B, false),
+    };
+    Ok((note, sharp, (index / 12) as u8))
+}
+
 /// Converts an octave, note and sharp into the 16 bit value the gameboy uses for frequency.
 #[rustfmt::skip]
 fn note_to_frequency(octave: u8, note: &Note, sharp: bool) -> Result<u16, Error> {
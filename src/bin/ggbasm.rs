@@ -0,0 +1,52 @@
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use anyhow::{bail, Error};
+
+use ggbasm::scaffold::{self, Template};
+
+const USAGE: &str =
+    "Usage: ggbasm new <path> [--template <blank|sprite-demo|platformer|music-demo>]";
+
+fn main() {
+    if let Err(err) = run(env::args().skip(1).collect()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), Error> {
+    let mut args = args.into_iter();
+    match args.next().as_deref() {
+        Some("new") => {}
+        _ => bail!(USAGE),
+    }
+
+    let mut path = None;
+    let mut template = Template::Blank;
+    while let Some(arg) = args.next() {
+        if arg == "--template" {
+            match args.next() {
+                Some(name) => template = name.parse()?,
+                None => bail!(USAGE),
+            }
+        } else if path.is_none() {
+            path = Some(PathBuf::from(arg));
+        } else {
+            bail!(USAGE);
+        }
+    }
+    let path = match path {
+        Some(path) => path,
+        None => bail!(USAGE),
+    };
+
+    scaffold::new_project(&path, template)?;
+    println!(
+        "Created {} project at {}, build the rom with `cargo run`",
+        template,
+        path.display()
+    );
+    Ok(())
+}
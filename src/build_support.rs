@@ -0,0 +1,89 @@
+//! Compiles the ROM from a `build.rs` build script, so `cargo build` keeps the ROM up to date.
+//!
+//!```no_run
+//! // build.rs
+//! # fn main() -> Result<(), anyhow::Error> {
+//! let builder = ggbasm::RomBuilder::new()?
+//!     .add_basic_interrupts_and_jumps()?
+//!     .add_asm_file("main.asm")?;
+//! ggbasm::build_support::build_rom(builder, "game.gb")?;
+//! # Ok(())
+//! # }
+//!```
+//!
+//! The crate can then refer to the ROM via the environment variables set for it, derived from the file name:
+//!
+//!```ignore
+//! const ROM: &[u8] = include_bytes!(env!("GGBASM_GAME_PATH"));
+//! const ROM_SHA256: &str = env!("GGBASM_GAME_SHA256");
+//!```
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Error};
+use sha2::{Digest, Sha256};
+
+use crate::RomBuilder;
+
+/// A ROM compiled by [build_rom].
+pub struct BuiltRom {
+    /// Where the ROM was written, within `OUT_DIR`.
+    pub path: PathBuf,
+    /// The SHA-256 hash of the ROM as lowercase hex.
+    pub sha256: String,
+}
+
+/// Compiles the ROM and writes it to `OUT_DIR` with the provided file name.
+///
+/// Prints the cargo instructions to:
+/// *   rerun the build script when `build.rs` or the gbasm, graphics or audio directories change.
+/// *   set `GGBASM_<NAME>_PATH` and `GGBASM_<NAME>_SHA256` when compiling the crate, where `<NAME>` is the
+///     file name without its extension, uppercased with non alphanumeric characters replaced by `_`.
+/// *   display each of the [crate::layout::Layout::warnings] as a cargo warning.
+///
+/// Must be called from a build script.
+pub fn build_rom(builder: RomBuilder, name: &str) -> Result<BuiltRom, Error> {
+    let out_dir = env::var_os("OUT_DIR").ok_or_else(|| {
+        anyhow!("OUT_DIR is not set, build_rom must be called from a build script")
+    })?;
+
+    println!("cargo:rerun-if-changed=build.rs");
+    for dir in ["gbasm", "graphics", "audio"] {
+        // cargo always reruns the build script when a path does not exist
+        if builder.root_dir().join(dir).exists() {
+            println!("cargo:rerun-if-changed={}", dir);
+        }
+    }
+
+    let (rom, layout) = builder.compile_with_layout()?;
+    for warning in &layout.warnings {
+        println!("cargo:warning={}", warning);
+    }
+    let path = PathBuf::from(out_dir).join(name);
+    fs::write(&path, &rom)?;
+    let sha256: String = Sha256::digest(&rom)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    let stem = path
+        .file_stem()
+        .map(|x| x.to_string_lossy())
+        .unwrap_or_default();
+    let var: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    println!("cargo:rustc-env=GGBASM_{}_PATH={}", var, path.display());
+    println!("cargo:rustc-env=GGBASM_{}_SHA256={}", var, sha256);
+
+    Ok(BuiltRom { path, sha256 })
+}
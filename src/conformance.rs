@@ -0,0 +1,167 @@
+//! Runs a corpus of asm fixtures and compares the assembled bytes against expected binaries.
+//!
+//! Each `foo.asm` in the fixture directory is assembled from address 0x0000 without a header and
+//! compared against `foo.bin` in the same directory.
+//! Mismatches are reported as a hexdump of the differing rows.
+//!
+//! Setting the environment variable `GGBASM_BLESS=1` writes the assembled bytes to the `.bin`
+//! files instead of comparing them, use this when adding new fixtures.
+//! Make sure to check the blessed output is actually correct!
+//!
+//! Downstream projects can use this for their own asm:
+//! ```no_run
+//! #[test]
+//! fn conformance() {
+//!     ggbasm::conformance::run("tests/asm_fixtures").unwrap();
+//! }
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Error};
+
+use crate::parser;
+use crate::RomBuilder;
+
+/// The maximum number of differing rows displayed per fixture.
+const MAX_DIFF_ROWS: usize = 16;
+
+/// Assembles the provided asm text from address 0x0000 without a header.
+pub fn assemble(text: &str) -> Result<Vec<u8>, Error> {
+    let mut instructions = vec![];
+    for (i, instruction) in parser::parse_asm(text)?.into_iter().enumerate() {
+        match instruction {
+            Ok(instruction) => instructions.push(instruction),
+            Err(diagnostic) => bail!("Invalid instruction on line {}: {}", i + 1, diagnostic),
+        }
+    }
+    RomBuilder::new()?
+        .add_instructions(instructions)?
+        .compile_raw()
+}
+
+/// Assembles every `.asm` file in the directory and compares it against the `.bin` file of the same name.
+///
+/// Returns an error describing every fixture that failed.
+/// If `GGBASM_BLESS=1` is set, the `.bin` files are written instead.
+pub fn run<P: AsRef<Path>>(dir: P) -> Result<(), Error> {
+    let dir = dir.as_ref();
+    let bless = env::var("GGBASM_BLESS").map(|x| x == "1").unwrap_or(false);
+
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map(|x| x == "asm").unwrap_or(false) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    if paths.is_empty() {
+        bail!("No .asm fixtures found in {}", dir.display());
+    }
+
+    let mut failures = String::new();
+    for asm_path in paths {
+        let bin_path = asm_path.with_extension("bin");
+        let name = asm_path.file_name().unwrap().to_string_lossy();
+
+        let bytes = fs::read(&asm_path)?;
+        let actual = match parser::decode_asm(&bytes).and_then(assemble) {
+            Ok(actual) => actual,
+            Err(err) => {
+                failures.push_str(&format!("{} failed to assemble: {}\n\n", name, err));
+                continue;
+            }
+        };
+
+        if bless {
+            fs::write(&bin_path, &actual)?;
+            continue;
+        }
+
+        match fs::read(&bin_path) {
+            Ok(expected) => {
+                if expected != actual {
+                    failures.push_str(&format!(
+                        "{} does not match {}:\n{}\n",
+                        name,
+                        bin_path.file_name().unwrap().to_string_lossy(),
+                        hexdump_diff(&expected, &actual)
+                    ));
+                }
+            }
+            Err(err) => failures.push_str(&format!(
+                "Cannot read {} because: {}\nRun with GGBASM_BLESS=1 to create it.\n\n",
+                bin_path.display(),
+                err
+            )),
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        bail!(failures)
+    }
+}
+
+/// Displays the rows of 16 bytes that differ between expected and actual.
+pub fn hexdump_diff(expected: &[u8], actual: &[u8]) -> String {
+    let mut result = String::new();
+    if expected.len() != actual.len() {
+        result.push_str(&format!(
+            "expected {} bytes but was {} bytes\n",
+            expected.len(),
+            actual.len()
+        ));
+    }
+
+    let rows = expected.len().max(actual.len()).div_ceil(16);
+    let mut differing_rows = 0;
+    for row in 0..rows {
+        let start = row * 16;
+        let expected_row = row_bytes(expected, start);
+        let actual_row = row_bytes(actual, start);
+        if expected_row == actual_row {
+            continue;
+        }
+
+        differing_rows += 1;
+        if differing_rows > MAX_DIFF_ROWS {
+            continue;
+        }
+
+        let mut markers = String::new();
+        for i in 0..16 {
+            if expected_row.get(i) == actual_row.get(i) {
+                markers.push_str("   ");
+            } else {
+                markers.push_str(" ^^");
+            }
+        }
+        result.push_str(&format!("-0x{:04x}:{}\n", start, hex_row(expected_row)));
+        result.push_str(&format!("+0x{:04x}:{}\n", start, hex_row(actual_row)));
+        result.push_str(&format!("        {}\n", markers.trim_end()));
+    }
+    if differing_rows > MAX_DIFF_ROWS {
+        result.push_str(&format!(
+            "... and {} more differing rows\n",
+            differing_rows - MAX_DIFF_ROWS
+        ));
+    }
+    result
+}
+
+fn row_bytes(bytes: &[u8], start: usize) -> &[u8] {
+    if start >= bytes.len() {
+        &[]
+    } else {
+        &bytes[start..bytes.len().min(start + 16)]
+    }
+}
+
+fn hex_row(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!(" {:02x}", x)).collect()
+}
@@ -1,4 +1,8 @@
 //! Miscellaneous gameboy hardware constants.
+//!
+//! Every constant is also listed in [CONSTANTS] so it can be made available to assembly code via
+//! [crate::RomBuilder::add_constants].
+//! For the register and flag names used by the community hardware.inc file see [crate::hardware].
 
 pub const CPU_SPEED_HZ: u32 = 4_194_304;
 pub const ROM_BANK_SIZE: u32 = 0x4000;
@@ -6,3 +10,200 @@ pub const RAM_BANK_SIZE: u32 = 0x2000;
 pub const SCREEN_WIDTH: u32 = 160;
 pub const SCREEN_HEIGHT: u32 = 144;
 pub const SCREEN_PIXELS: u32 = SCREEN_WIDTH * SCREEN_HEIGHT;
+
+// timing, measured in clock cycles at CPU_SPEED_HZ, a machine cycle is 4 clock cycles
+pub const CYCLES_PER_LINE: u32 = 456;
+pub const LINES_PER_FRAME: u32 = 154;
+pub const VBLANK_LINES: u32 = LINES_PER_FRAME - SCREEN_HEIGHT;
+pub const CYCLES_PER_FRAME: u32 = CYCLES_PER_LINE * LINES_PER_FRAME;
+pub const CYCLES_PER_DIV_TICK: u32 = 256;
+
+// interrupt and entry point addresses
+pub const RST_VECTORS: [u16; 8] = [0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38];
+pub const VBLANK_VECTOR: u16 = 0x40;
+pub const STAT_VECTOR: u16 = 0x48;
+pub const TIMER_VECTOR: u16 = 0x50;
+pub const SERIAL_VECTOR: u16 = 0x58;
+pub const JOYPAD_VECTOR: u16 = 0x60;
+pub const ENTRY_POINT: u16 = 0x100;
+pub const HEADER_START: u16 = 0x104;
+pub const HEADER_END: u16 = 0x150;
+
+// memory regions, each START is the first address and SIZE is the number of bytes
+pub const ROM0_START: u16 = 0x0000;
+pub const ROM0_SIZE: u16 = 0x4000;
+pub const ROMX_START: u16 = 0x4000;
+pub const ROMX_SIZE: u16 = 0x4000;
+pub const VRAM_START: u16 = 0x8000;
+pub const VRAM_SIZE: u16 = 0x2000;
+pub const SRAM_START: u16 = 0xA000;
+pub const SRAM_SIZE: u16 = 0x2000;
+pub const WRAM0_START: u16 = 0xC000;
+pub const WRAM0_SIZE: u16 = 0x1000;
+pub const WRAMX_START: u16 = 0xD000;
+pub const WRAMX_SIZE: u16 = 0x1000;
+pub const ECHO_START: u16 = 0xE000;
+pub const ECHO_SIZE: u16 = 0x1E00;
+pub const OAM_START: u16 = 0xFE00;
+pub const OAM_SIZE: u16 = 0xA0;
+pub const IO_START: u16 = 0xFF00;
+pub const IO_SIZE: u16 = 0x80;
+pub const HRAM_START: u16 = 0xFF80;
+pub const HRAM_SIZE: u16 = 0x7F;
+
+// io registers
+pub const REG_P1: u16 = 0xFF00;
+pub const REG_SB: u16 = 0xFF01;
+pub const REG_SC: u16 = 0xFF02;
+pub const REG_DIV: u16 = 0xFF04;
+pub const REG_TIMA: u16 = 0xFF05;
+pub const REG_TMA: u16 = 0xFF06;
+pub const REG_TAC: u16 = 0xFF07;
+pub const REG_IF: u16 = 0xFF0F;
+pub const REG_NR10: u16 = 0xFF10;
+pub const REG_NR11: u16 = 0xFF11;
+pub const REG_NR12: u16 = 0xFF12;
+pub const REG_NR13: u16 = 0xFF13;
+pub const REG_NR14: u16 = 0xFF14;
+pub const REG_NR21: u16 = 0xFF16;
+pub const REG_NR22: u16 = 0xFF17;
+pub const REG_NR23: u16 = 0xFF18;
+pub const REG_NR24: u16 = 0xFF19;
+pub const REG_NR30: u16 = 0xFF1A;
+pub const REG_NR31: u16 = 0xFF1B;
+pub const REG_NR32: u16 = 0xFF1C;
+pub const REG_NR33: u16 = 0xFF1D;
+pub const REG_NR34: u16 = 0xFF1E;
+pub const REG_NR41: u16 = 0xFF20;
+pub const REG_NR42: u16 = 0xFF21;
+pub const REG_NR43: u16 = 0xFF22;
+pub const REG_NR44: u16 = 0xFF23;
+pub const REG_NR50: u16 = 0xFF24;
+pub const REG_NR51: u16 = 0xFF25;
+pub const REG_NR52: u16 = 0xFF26;
+pub const REG_WAVE_RAM: u16 = 0xFF30;
+pub const REG_LCDC: u16 = 0xFF40;
+pub const REG_STAT: u16 = 0xFF41;
+pub const REG_SCY: u16 = 0xFF42;
+pub const REG_SCX: u16 = 0xFF43;
+pub const REG_LY: u16 = 0xFF44;
+pub const REG_LYC: u16 = 0xFF45;
+pub const REG_DMA: u16 = 0xFF46;
+pub const REG_BGP: u16 = 0xFF47;
+pub const REG_OBP0: u16 = 0xFF48;
+pub const REG_OBP1: u16 = 0xFF49;
+pub const REG_WY: u16 = 0xFF4A;
+pub const REG_WX: u16 = 0xFF4B;
+pub const REG_KEY1: u16 = 0xFF4D;
+pub const REG_VBK: u16 = 0xFF4F;
+pub const REG_HDMA1: u16 = 0xFF51;
+pub const REG_HDMA2: u16 = 0xFF52;
+pub const REG_HDMA3: u16 = 0xFF53;
+pub const REG_HDMA4: u16 = 0xFF54;
+pub const REG_HDMA5: u16 = 0xFF55;
+pub const REG_RP: u16 = 0xFF56;
+pub const REG_BCPS: u16 = 0xFF68;
+pub const REG_BCPD: u16 = 0xFF69;
+pub const REG_OCPS: u16 = 0xFF6A;
+pub const REG_OCPD: u16 = 0xFF6B;
+pub const REG_SVBK: u16 = 0xFF70;
+pub const REG_IE: u16 = 0xFFFF;
+
+/// Every constant in this module except [RST_VECTORS] as (identifier, value) pairs, named the same as in rust.
+pub static CONSTANTS: &[(&str, i64)] = &[
+    ("CPU_SPEED_HZ", CPU_SPEED_HZ as i64),
+    ("ROM_BANK_SIZE", ROM_BANK_SIZE as i64),
+    ("RAM_BANK_SIZE", RAM_BANK_SIZE as i64),
+    ("SCREEN_WIDTH", SCREEN_WIDTH as i64),
+    ("SCREEN_HEIGHT", SCREEN_HEIGHT as i64),
+    ("SCREEN_PIXELS", SCREEN_PIXELS as i64),
+    ("CYCLES_PER_LINE", CYCLES_PER_LINE as i64),
+    ("LINES_PER_FRAME", LINES_PER_FRAME as i64),
+    ("VBLANK_LINES", VBLANK_LINES as i64),
+    ("CYCLES_PER_FRAME", CYCLES_PER_FRAME as i64),
+    ("CYCLES_PER_DIV_TICK", CYCLES_PER_DIV_TICK as i64),
+    ("VBLANK_VECTOR", VBLANK_VECTOR as i64),
+    ("STAT_VECTOR", STAT_VECTOR as i64),
+    ("TIMER_VECTOR", TIMER_VECTOR as i64),
+    ("SERIAL_VECTOR", SERIAL_VECTOR as i64),
+    ("JOYPAD_VECTOR", JOYPAD_VECTOR as i64),
+    ("ENTRY_POINT", ENTRY_POINT as i64),
+    ("HEADER_START", HEADER_START as i64),
+    ("HEADER_END", HEADER_END as i64),
+    ("ROM0_START", ROM0_START as i64),
+    ("ROM0_SIZE", ROM0_SIZE as i64),
+    ("ROMX_START", ROMX_START as i64),
+    ("ROMX_SIZE", ROMX_SIZE as i64),
+    ("VRAM_START", VRAM_START as i64),
+    ("VRAM_SIZE", VRAM_SIZE as i64),
+    ("SRAM_START", SRAM_START as i64),
+    ("SRAM_SIZE", SRAM_SIZE as i64),
+    ("WRAM0_START", WRAM0_START as i64),
+    ("WRAM0_SIZE", WRAM0_SIZE as i64),
+    ("WRAMX_START", WRAMX_START as i64),
+    ("WRAMX_SIZE", WRAMX_SIZE as i64),
+    ("ECHO_START", ECHO_START as i64),
+    ("ECHO_SIZE", ECHO_SIZE as i64),
+    ("OAM_START", OAM_START as i64),
+    ("OAM_SIZE", OAM_SIZE as i64),
+    ("IO_START", IO_START as i64),
+    ("IO_SIZE", IO_SIZE as i64),
+    ("HRAM_START", HRAM_START as i64),
+    ("HRAM_SIZE", HRAM_SIZE as i64),
+    ("REG_P1", REG_P1 as i64),
+    ("REG_SB", REG_SB as i64),
+    ("REG_SC", REG_SC as i64),
+    ("REG_DIV", REG_DIV as i64),
+    ("REG_TIMA", REG_TIMA as i64),
+    ("REG_TMA", REG_TMA as i64),
+    ("REG_TAC", REG_TAC as i64),
+    ("REG_IF", REG_IF as i64),
+    ("REG_NR10", REG_NR10 as i64),
+    ("REG_NR11", REG_NR11 as i64),
+    ("REG_NR12", REG_NR12 as i64),
+    ("REG_NR13", REG_NR13 as i64),
+    ("REG_NR14", REG_NR14 as i64),
+    ("REG_NR21", REG_NR21 as i64),
+    ("REG_NR22", REG_NR22 as i64),
+    ("REG_NR23", REG_NR23 as i64),
+    ("REG_NR24", REG_NR24 as i64),
+    ("REG_NR30", REG_NR30 as i64),
+    ("REG_NR31", REG_NR31 as i64),
+    ("REG_NR32", REG_NR32 as i64),
+    ("REG_NR33", REG_NR33 as i64),
+    ("REG_NR34", REG_NR34 as i64),
+    ("REG_NR41", REG_NR41 as i64),
+    ("REG_NR42", REG_NR42 as i64),
+    ("REG_NR43", REG_NR43 as i64),
+    ("REG_NR44", REG_NR44 as i64),
+    ("REG_NR50", REG_NR50 as i64),
+    ("REG_NR51", REG_NR51 as i64),
+    ("REG_NR52", REG_NR52 as i64),
+    ("REG_WAVE_RAM", REG_WAVE_RAM as i64),
+    ("REG_LCDC", REG_LCDC as i64),
+    ("REG_STAT", REG_STAT as i64),
+    ("REG_SCY", REG_SCY as i64),
+    ("REG_SCX", REG_SCX as i64),
+    ("REG_LY", REG_LY as i64),
+    ("REG_LYC", REG_LYC as i64),
+    ("REG_DMA", REG_DMA as i64),
+    ("REG_BGP", REG_BGP as i64),
+    ("REG_OBP0", REG_OBP0 as i64),
+    ("REG_OBP1", REG_OBP1 as i64),
+    ("REG_WY", REG_WY as i64),
+    ("REG_WX", REG_WX as i64),
+    ("REG_KEY1", REG_KEY1 as i64),
+    ("REG_VBK", REG_VBK as i64),
+    ("REG_HDMA1", REG_HDMA1 as i64),
+    ("REG_HDMA2", REG_HDMA2 as i64),
+    ("REG_HDMA3", REG_HDMA3 as i64),
+    ("REG_HDMA4", REG_HDMA4 as i64),
+    ("REG_HDMA5", REG_HDMA5 as i64),
+    ("REG_RP", REG_RP as i64),
+    ("REG_BCPS", REG_BCPS as i64),
+    ("REG_BCPD", REG_BCPD as i64),
+    ("REG_OCPS", REG_OCPS as i64),
+    ("REG_OCPD", REG_OCPD as i64),
+    ("REG_SVBK", REG_SVBK as i64),
+    ("REG_IE", REG_IE as i64),
+];
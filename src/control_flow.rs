@@ -0,0 +1,367 @@
+//! Builds the control flow graph and call graph of a compiled rom, e.g. to find dead code, plan which routines
+//! go in which bank or document a larger game.
+//!
+//! The code is decoded by following `call`, `rst`, `jp`, `jr` and `ret` from the entry point, the interrupt vectors
+//! and every label in a code region. Jumps to a computed address, e.g. `jp hl`, cannot be followed.
+//! Both graphs can be rendered to graphviz DOT, [crate::stack] analyzes the stack usage of the routines.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use anyhow::Error;
+
+use crate::address::GlobalAddr;
+use crate::ast::{Expr, Flag, Instruction};
+use crate::constants::ROM_BANK_SIZE;
+use crate::disassembler::{disassemble_instruction, label_names};
+use crate::layout::{Layout, RegionKind};
+
+/// The address of the entry point, executed after the boot rom.
+pub(crate) const ENTRY_POINT: u32 = 0x100;
+
+/// The addresses of the vblank, stat, timer, serial and joypad interrupts.
+pub(crate) const INTERRUPT_VECTORS: [u32; 5] = [0x40, 0x48, 0x50, 0x58, 0x60];
+
+/// The basic blocks and routines of a compiled rom, generated by [control_flow_graph].
+/// All addresses are within the entire rom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlFlowGraph {
+    /// ordered by address
+    pub blocks: Vec<BasicBlock>,
+    /// the entry point, interrupt vectors and every address that is called, ordered by address
+    pub routines: Vec<Routine>,
+}
+
+/// Instructions that are always executed in order, only the last instruction can jump or return.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub address: u32,
+    /// the address and decoded instruction of each instruction in the block
+    pub instructions: Vec<(u32, Instruction)>,
+    /// the blocks that can be executed after this block, via a jump or by continuing to the next instruction
+    pub successors: Vec<u32>,
+    /// the routines called by the block via `call` or `rst`, in the order they are called
+    pub calls: Vec<u32>,
+    /// true if the block can return from the routine via `ret` or `reti`
+    pub returns: bool,
+    /// true if the block ends with `jp hl`, its destination is unknown
+    pub indirect_jump: bool,
+}
+
+/// Code that is called, along with the blocks executed before it returns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Routine {
+    pub address: u32,
+    /// the label at the address, if any
+    pub name: Option<String>,
+    /// the blocks reachable from the start of the routine without following calls, ordered by address
+    pub blocks: Vec<u32>,
+    /// the routines called by any of the blocks, ordered by address
+    pub calls: Vec<u32>,
+}
+
+impl Routine {
+    /// Returns the name of the routine, or its address when there is no label at it.
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("0x{:04X}", self.address),
+        }
+    }
+}
+
+impl ControlFlowGraph {
+    /// Returns the block starting at the address.
+    pub fn block(&self, address: u32) -> Option<&BasicBlock> {
+        self.blocks
+            .binary_search_by_key(&address, |x| x.address)
+            .ok()
+            .map(|i| &self.blocks[i])
+    }
+
+    /// Returns the routine starting at the address.
+    pub fn routine(&self, address: u32) -> Option<&Routine> {
+        self.routines
+            .binary_search_by_key(&address, |x| x.address)
+            .ok()
+            .map(|i| &self.routines[i])
+    }
+
+    /// Returns the routine starting at the label.
+    pub fn routine_by_name(&self, name: &str) -> Option<&Routine> {
+        self.routines
+            .iter()
+            .find(|x| x.name.as_deref() == Some(name))
+    }
+
+    /// Renders the call graph to graphviz DOT, with a node for each routine and an edge for each call.
+    pub fn call_graph_dot(&self) -> String {
+        let mut dot = String::from("digraph calls {\n");
+        for routine in &self.routines {
+            writeln!(
+                dot,
+                "    \"{:04X}\" [label=\"{}\"];",
+                routine.address,
+                escape(&routine.display_name())
+            )
+            .unwrap();
+        }
+        for routine in &self.routines {
+            for call in &routine.calls {
+                writeln!(dot, "    \"{:04X}\" -> \"{:04X}\";", routine.address, call).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the basic blocks to graphviz DOT, with a node listing the instructions of each block
+    /// and an edge to each of its successors.
+    /// Calls are drawn as dashed edges to the block starting the called routine.
+    pub fn cfg_dot(&self) -> String {
+        let names: HashMap<u32, String> = self
+            .routines
+            .iter()
+            .map(|x| (x.address, x.display_name()))
+            .collect();
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        for block in &self.blocks {
+            let mut label = match names.get(&block.address) {
+                Some(name) => format!("{}:\\l", escape(name)),
+                None => format!("0x{:04X}:\\l", block.address),
+            };
+            for (_, instruction) in &block.instructions {
+                write!(label, "    {}\\l", escape(&instruction.to_string())).unwrap();
+            }
+            writeln!(dot, "    \"{:04X}\" [label=\"{}\"];", block.address, label).unwrap();
+        }
+        for block in &self.blocks {
+            for successor in &block.successors {
+                writeln!(
+                    dot,
+                    "    \"{:04X}\" -> \"{:04X}\";",
+                    block.address, successor
+                )
+                .unwrap();
+            }
+            for call in &block.calls {
+                writeln!(
+                    dot,
+                    "    \"{:04X}\" -> \"{:04X}\" [style=dashed];",
+                    block.address, call
+                )
+                .unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Escapes text for use within a quoted DOT string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// How an instruction affects the flow of execution.
+struct Flow {
+    /// the addresses it can jump to
+    jumps: Vec<u32>,
+    /// the address it calls
+    call: Option<u32>,
+    /// true if execution can continue to the next instruction
+    continues: bool,
+    returns: bool,
+    indirect_jump: bool,
+}
+
+/// Returns the address within the entire rom that a jump or call from the instruction at address goes to,
+/// when it goes to rom.
+///
+/// Addresses from 0x4000-0x7FFF refer to the bank the instruction is in, bank 0 is mapped in with bank 1 by default.
+pub(crate) fn destination(address: u32, target: &Expr) -> Option<u32> {
+    let target = match target {
+        Expr::Const(target) => *target,
+        _ => return None,
+    };
+    match target {
+        0x0000..=0x3FFF => Some(target as u32),
+        0x4000..=0x7FFF => {
+            let bank = GlobalAddr(address).bank().0.max(1) as u32;
+            Some(target as u32 + (bank - 1) * ROM_BANK_SIZE)
+        }
+        _ => None,
+    }
+}
+
+/// Returns how the decoded instruction at address affects the flow of execution.
+fn flow(address: u32, instruction: &Instruction, rom_len: usize) -> Flow {
+    let destination = |target| destination(address, target).filter(|x| (*x as usize) < rom_len);
+    let mut flow = Flow {
+        jumps: vec![],
+        call: None,
+        continues: true,
+        returns: false,
+        indirect_jump: false,
+    };
+    match instruction {
+        // jr destinations are decoded into the address within the entire rom
+        Instruction::Jr(flag, Expr::Const(target)) => {
+            flow.jumps.push(*target as u32);
+            flow.continues = *flag != Flag::Always;
+        }
+        Instruction::JpI16(flag, target) => {
+            flow.jumps.extend(destination(target));
+            flow.continues = *flag != Flag::Always;
+        }
+        Instruction::Call(_, target) | Instruction::Rst(target) => {
+            flow.call = destination(target);
+        }
+        Instruction::Ret(flag) => {
+            flow.returns = true;
+            flow.continues = *flag != Flag::Always;
+        }
+        Instruction::Reti => {
+            flow.returns = true;
+            flow.continues = false;
+        }
+        Instruction::JpRhl => {
+            flow.indirect_jump = true;
+            flow.continues = false;
+        }
+        _ => {}
+    }
+    flow
+}
+
+/// Builds the control flow graph and call graph of a rom compiled by [crate::RomBuilder::compile_with_layout].
+///
+/// Only the code regions and the interrupts and jumps generated by the RomBuilder are decoded,
+/// jumps and calls to any other address are not followed.
+/// Returns an error if the code does not contain valid instructions, i.e. the rom does not match the layout.
+pub fn control_flow_graph(rom: &[u8], layout: &Layout) -> Result<ControlFlowGraph, Error> {
+    let code_end = |address: u32| {
+        layout
+            .regions
+            .iter()
+            .find(|x| x.address <= address && address < x.address + x.size)
+            .filter(|x| matches!(x.kind, RegionKind::Code | RegionKind::InterruptsAndJumps))
+            .map(|x| x.address + x.size)
+    };
+    let names = label_names(layout);
+
+    // decode every instruction reachable from the roots
+    let mut routine_addresses: BTreeSet<u32> = BTreeSet::new();
+    let mut leaders: BTreeSet<u32> = BTreeSet::new();
+    let mut pending = vec![];
+    for address in INTERRUPT_VECTORS.iter().chain([ENTRY_POINT].iter()) {
+        if code_end(*address).is_some() {
+            routine_addresses.insert(*address);
+            pending.push(*address);
+        }
+    }
+    let mut labels: Vec<_> = names.keys().map(|x| *x as u32).collect();
+    labels.sort();
+    pending.extend(labels.into_iter().filter(|x| code_end(*x).is_some()));
+
+    let mut decoded: BTreeMap<u32, (Instruction, u32, Flow)> = BTreeMap::new();
+    while let Some(start) = pending.pop() {
+        leaders.insert(start);
+        let mut address = start;
+        while let Some(end) = code_end(address) {
+            if decoded.contains_key(&address) {
+                break;
+            }
+            let (instruction, len) =
+                disassemble_instruction(&rom[address as usize..end as usize], address as usize)?;
+            let flow = flow(address, &instruction, rom.len());
+            let next = address + len as u32;
+            pending.extend(flow.jumps.iter().cloned());
+            if let Some(call) = flow.call {
+                routine_addresses.insert(call);
+                pending.push(call);
+            }
+            let ends_block = !flow.jumps.is_empty() || flow.returns || !flow.continues;
+            let continues = flow.continues;
+            decoded.insert(address, (instruction, len as u32, flow));
+            if !continues {
+                break;
+            }
+            if ends_block {
+                pending.push(next);
+                break;
+            }
+            address = next;
+        }
+    }
+    // jumps and calls into data are not followed
+    leaders.retain(|x| decoded.contains_key(x));
+    routine_addresses.retain(|x| decoded.contains_key(x));
+
+    // split the instructions into blocks at each leader and after each jump or return
+    let mut blocks: Vec<BasicBlock> = vec![];
+    let mut open = false;
+    let mut next_address = None;
+    for (address, (instruction, len, flow)) in decoded {
+        if !open || leaders.contains(&address) || next_address != Some(address) {
+            if open {
+                // continues into the next leader
+                let block = blocks.last_mut().unwrap();
+                if next_address == Some(address) {
+                    block.successors.push(address);
+                }
+            }
+            blocks.push(BasicBlock {
+                address,
+                instructions: vec![],
+                successors: vec![],
+                calls: vec![],
+                returns: false,
+                indirect_jump: false,
+            });
+        }
+        let block = blocks.last_mut().unwrap();
+        block.instructions.push((address, instruction));
+        block.calls.extend(flow.call);
+        block.returns |= flow.returns;
+        block.indirect_jump |= flow.indirect_jump;
+        next_address = Some(address + len);
+        open = flow.continues && flow.jumps.is_empty() && !flow.returns;
+        if !open {
+            block.successors.extend(flow.jumps);
+            if flow.continues {
+                block.successors.push(address + len);
+            }
+        }
+    }
+    let starts: BTreeSet<u32> = blocks.iter().map(|x| x.address).collect();
+    for block in &mut blocks {
+        block.successors.retain(|x| starts.contains(x));
+        block.successors.dedup();
+    }
+
+    let mut graph = ControlFlowGraph {
+        blocks,
+        routines: vec![],
+    };
+    for address in routine_addresses {
+        let mut reached = BTreeSet::new();
+        let mut pending = vec![address];
+        while let Some(address) = pending.pop() {
+            if reached.insert(address) {
+                pending.extend(graph.block(address).unwrap().successors.iter().cloned());
+            }
+        }
+        let calls: BTreeSet<u32> = reached
+            .iter()
+            .flat_map(|x| graph.block(*x).unwrap().calls.iter().cloned())
+            .collect();
+        graph.routines.push(Routine {
+            address,
+            name: names.get(&(address as i64)).cloned(),
+            blocks: reached.into_iter().collect(),
+            calls: calls.into_iter().collect(),
+        });
+    }
+    Ok(graph)
+}
@@ -0,0 +1,111 @@
+; crash handler, jumped to from rst 0x38
+; executing 0xFF bytes (e.g. uninitialized memory or a bad jump) runs rst 0x38
+;
+; the crash record written to SRAM at GGBASMCrashRecord is 6 little endian words:
+; SP AF BC DE HL PC
+; SP is the stack pointer before the rst 0x38 instruction
+; PC is the address following the rst 0x38 instruction
+;
+; the same words are drawn to the screen, one per line, in the same order.
+
+GGBASMCrashHandler:
+    di
+    push hl
+    push de
+    push bc
+    push af
+    ld hl, sp+10
+    push hl
+    ; the stack now contains SP AF BC DE HL PC
+
+    ; enable SRAM and select the first SRAM bank
+    ld a, 0x0A
+    ld [0x0000], a
+    xor a; ld a 0
+    ld [0x4000], a
+
+    ld hl, sp+0
+    ld de, GGBASMCrashRecord
+    ld b, 12
+GGBASMCrashCopyRecord:
+    ldi a, [hl]
+    ld [de], a
+    inc de
+    dec b
+    jr nz, GGBASMCrashCopyRecord
+
+    ; disable SRAM to protect the record
+    xor a; ld a 0
+    ld [0x0000], a
+
+    ; turn off the LCD, waiting for VBlank if it is on
+    ld a, [0xFF00+0x40]
+    bit 7, a
+    jr z, GGBASMCrashLCDOff
+GGBASMCrashWaitVBlank:
+    ld a, [0xFF00+0x44]
+    cp 144
+    jr c, GGBASMCrashWaitVBlank
+    xor a; ld a 0
+    ld [0xFF00+0x40], a
+GGBASMCrashLCDOff:
+
+    ; copy the 1bpp font into both bitplanes of the tiles
+    ld hl, GGBASMHexFont
+    ld de, 0x8EF0
+    ld b, 17 * 8
+GGBASMCrashCopyFont:
+    ldi a, [hl]
+    ld [de], a
+    inc de
+    ld [de], a
+    inc de
+    dec b
+    jr nz, GGBASMCrashCopyFont
+
+    ; clear the background tile map
+    ld hl, 0x9800
+    ld bc, 0x400
+GGBASMCrashClear:
+    ld a, 0xEF
+    ldi [hl], a
+    dec bc
+    ld a, b
+    or c
+    jr nz, GGBASMCrashClear
+
+    ; draw each word on the stack, high byte first
+    ld hl, sp+0
+    ld d, h
+    ld e, l
+    ld hl, 0x9821
+    ld c, 6
+GGBASMCrashDrawWord:
+    inc de
+    ld a, [de]
+    call GGBASMDrawHex
+    dec de
+    ld a, [de]
+    call GGBASMDrawHex
+    inc de
+    inc de
+    ; move to the start of the next line
+    push de
+    ld de, 32 - 4
+    add hl, de
+    pop de
+    dec c
+    jr nz, GGBASMCrashDrawWord
+
+    ; reset scrolling and the palette then display the background
+    ; TODO: set a CGB palette
+    xor a; ld a 0
+    ld [0xFF00+0x42], a
+    ld [0xFF00+0x43], a
+    ld a, 0xE4
+    ld [0xFF00+0x47], a
+    ld a, 0x81
+    ld [0xFF00+0x40], a
+
+GGBASMCrashLoop:
+    jr GGBASMCrashLoop
@@ -0,0 +1,107 @@
+//! Generates instructions for debugging games on real hardware and emulators.
+
+use anyhow::{bail, Error};
+
+use crate::ast::{Expr, Flag, Instruction, Reg16};
+use crate::parser;
+
+/// 1bpp 8x8 glyphs for a blank tile followed by the hex digits 0-F.
+pub static HEX_FONT: [[u8; 8]; 17] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x7C, 0xC6, 0xCE, 0xD6, 0xE6, 0xC6, 0x7C, 0x00],
+    [0x18, 0x38, 0x18, 0x18, 0x18, 0x18, 0x7E, 0x00],
+    [0x7C, 0xC6, 0x06, 0x1C, 0x70, 0xC0, 0xFE, 0x00],
+    [0x7C, 0xC6, 0x06, 0x3C, 0x06, 0xC6, 0x7C, 0x00],
+    [0x1C, 0x3C, 0x6C, 0xCC, 0xFE, 0x0C, 0x0C, 0x00],
+    [0xFE, 0xC0, 0xFC, 0x06, 0x06, 0xC6, 0x7C, 0x00],
+    [0x3C, 0x60, 0xC0, 0xFC, 0xC6, 0xC6, 0x7C, 0x00],
+    [0xFE, 0xC6, 0x0C, 0x18, 0x30, 0x30, 0x30, 0x00],
+    [0x7C, 0xC6, 0xC6, 0x7C, 0xC6, 0xC6, 0x7C, 0x00],
+    [0x7C, 0xC6, 0xC6, 0x7E, 0x06, 0x0C, 0x78, 0x00],
+    [0x38, 0x6C, 0xC6, 0xC6, 0xFE, 0xC6, 0xC6, 0x00],
+    [0xFC, 0xC6, 0xC6, 0xFC, 0xC6, 0xC6, 0xFC, 0x00],
+    [0x7C, 0xC6, 0xC0, 0xC0, 0xC0, 0xC6, 0x7C, 0x00],
+    [0xF8, 0xCC, 0xC6, 0xC6, 0xC6, 0xCC, 0xF8, 0x00],
+    [0xFE, 0xC0, 0xC0, 0xFC, 0xC0, 0xC0, 0xFE, 0x00],
+    [0xFE, 0xC0, 0xC0, 0xFC, 0xC0, 0xC0, 0xC0, 0x00],
+];
+
+/// The maximum number of RAM bytes that fit on the debug overlay.
+pub const DEBUG_OVERLAY_MAX_WATCHED: usize = 4;
+
+/// Generates the hex font data under the label GGBASMHexFont and the GGBASMDrawHex routine.
+///
+/// These are shared by the debug overlay and the crash handler.
+pub fn generate_hex_support() -> Vec<Instruction> {
+    let mut instructions = vec![
+        Instruction::Label(String::from("GGBASMHexFont")),
+        Instruction::Db(HEX_FONT.iter().flatten().cloned().collect()),
+    ];
+    instructions.extend(parse_builtin(
+        include_str!("debug_hex.asm"),
+        "debug_hex.asm",
+    ));
+    instructions
+}
+
+/// Generates the debug overlay routines.
+///
+/// The watched RAM bytes are drawn after the frame counter and free VBlank cycles.
+/// Returns an error if there are more watched bytes than fit on the screen.
+pub fn generate_debug_overlay(watched_addresses: &[u16]) -> Result<Vec<Instruction>, Error> {
+    if watched_addresses.len() > DEBUG_OVERLAY_MAX_WATCHED {
+        bail!(
+            "Debug overlay can only display {} RAM bytes but {} were given",
+            DEBUG_OVERLAY_MAX_WATCHED,
+            watched_addresses.len()
+        );
+    }
+
+    let mut instructions = parse_builtin(include_str!("debug_overlay.asm"), "debug_overlay.asm");
+    for (i, address) in watched_addresses.iter().enumerate() {
+        let tile_address = 0x9C08 + i as i64 * 3;
+        instructions.push(Instruction::LdRaMI16(Expr::Const(*address as i64)));
+        instructions.push(Instruction::LdR16I16(Reg16::HL, Expr::Const(tile_address)));
+        instructions.push(Instruction::Call(
+            Flag::Always,
+            Expr::Ident(String::from("GGBASMDrawHex")),
+        ));
+    }
+    instructions.push(Instruction::Ret(Flag::Always));
+
+    Ok(instructions)
+}
+
+/// Generates labels for the debug overlay routines that return immediately.
+///
+/// Used in release builds so that calls to the overlay dont need to be removed.
+pub fn generate_debug_overlay_stub() -> Vec<Instruction> {
+    vec![
+        Instruction::Label(String::from("GGBASMDebugOverlayInit")),
+        Instruction::Label(String::from("GGBASMDebugOverlayStep")),
+        Instruction::Ret(Flag::Always),
+    ]
+}
+
+/// Generates the crash handler routine.
+pub fn generate_crash_handler() -> Vec<Instruction> {
+    parse_builtin(include_str!("crash_handler.asm"), "crash_handler.asm")
+}
+
+fn parse_builtin(text: &str, file_name: &str) -> Vec<Instruction> {
+    parser::parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .enumerate()
+        .map(|(i, x)| {
+            x.unwrap_or_else(|err| {
+                panic!(
+                    "Invalid instruction on line {} of {}: {}",
+                    i + 1,
+                    file_name,
+                    err
+                )
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,13 @@
+; draws a as two hex digits at hl using the tiles copied from GGBASMHexFont
+; hl is advanced past the digits, b is clobbered
+GGBASMDrawHex:
+    ld b, a
+    swap a
+    and 0x0F
+    or 0xF0
+    ldi [hl], a
+    ld a, b
+    and 0x0F
+    or 0xF0
+    ldi [hl], a
+    ret
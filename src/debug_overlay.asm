@@ -0,0 +1,86 @@
+; debug overlay drawn on the first row of the window layer:
+; FF CCCC AA BB CC DD
+; FF   - frame counter
+; CCCC - machine cycles left in VBlank when GGBASMDebugOverlayStep was called
+; AA.. - the watched RAM bytes
+;
+; the font is stored at tiles 0xEF (blank) and 0xF0-0xFF (hex digits 0-F)
+; these tiles are at the same location in both the 0x8000 and 0x8800 addressing modes.
+
+GGBASMDebugOverlayInit:
+    ; copy the 1bpp font into both bitplanes of the tiles
+    ld hl, GGBASMHexFont
+    ld de, 0x8EF0
+    ld b, 17 * 8
+GGBASMDebugInitCopy:
+    ldi a, [hl]
+    ld [de], a
+    inc de
+    ld [de], a
+    inc de
+    dec b
+    jr nz, GGBASMDebugInitCopy
+
+    ; clear the first row of the window tile map
+    ld hl, 0x9C00
+    ld b, 20
+    ld a, 0xEF
+GGBASMDebugInitClear:
+    ldi [hl], a
+    dec b
+    jr nz, GGBASMDebugInitClear
+
+    ; only show the first row of the window, at the bottom of the screen
+    ld a, 136
+    ld [0xFF00+0x4A], a
+    ld a, 7
+    ld [0xFF00+0x4B], a
+
+    ; enable the window and use the 0x9C00 tile map for it
+    ld a, [0xFF00+0x40]
+    or 0x60
+    ld [0xFF00+0x40], a
+
+    xor a; ld a 0
+    ld [GGBASMDebugFrameCounter], a
+    ret
+
+GGBASMDebugOverlayStep:
+    ; measure the free VBlank lines first, before we use any of them
+    ld a, [0xFF00+0x44]
+    cp 144
+    jr c, GGBASMDebugNotVBlank
+    ld b, a
+    ld a, 153
+    sub b
+    jr GGBASMDebugCountCycles
+GGBASMDebugNotVBlank:
+    xor a; ld a 0
+
+GGBASMDebugCountCycles:
+    ; each line is 114 machine cycles
+    ld hl, 0
+    ld de, 114
+    and a ; cp 0
+    jr z, GGBASMDebugDrawCycles
+GGBASMDebugCyclesLoop:
+    add hl, de
+    dec a
+    jr nz, GGBASMDebugCyclesLoop
+
+GGBASMDebugDrawCycles:
+    ld a, h
+    ld c, l
+    ld hl, 0x9C03
+    call GGBASMDrawHex
+    ld a, c
+    call GGBASMDrawHex
+
+    ; frame counter
+    ld hl, GGBASMDebugFrameCounter
+    inc [hl]
+    ld a, [hl]
+    ld hl, 0x9C00
+    call GGBASMDrawHex
+
+    ; the watched RAM bytes are drawn by generated instructions following this
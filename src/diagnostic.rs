@@ -0,0 +1,294 @@
+//! Describes why a line of asm failed to parse, returned by [crate::parser::parse_asm].
+
+use std::fmt;
+use std::ops::Range;
+
+/// A line that failed to parse.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// 1 indexed line number
+    pub line: usize,
+    /// byte range of the offending text within the line
+    pub columns: Range<usize>,
+    /// the offending text
+    pub text: String,
+    /// the entire line
+    pub line_text: String,
+    /// the forms that would have been accepted, e.g. `ld r8, n8`
+    pub expected: Vec<String>,
+    /// describes errors that are not about the syntax of the line, e.g. a macro that is defined twice
+    pub message: Option<String>,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic for a line that failed to parse.
+    ///
+    /// When the line starts with a known mnemonic or directive its operands are reported along with every
+    /// form it accepts, otherwise the first word is reported.
+    pub fn new(line_text: &str, line: usize) -> Diagnostic {
+        let code = line_text.split(';').next().unwrap().trim_end();
+        let words = word_ranges(code);
+        let word = |i: usize| words.get(i).map(|x| &code[x.clone()]).unwrap_or("");
+
+        // constant definitions start with the name of the constant
+        let definition = DEFINITIONS
+            .iter()
+            .find(|(keyword, _)| word(1).eq_ignore_ascii_case(keyword));
+        let (keyword, forms) = match (definition, words.first()) {
+            (Some((keyword, form)), _) => {
+                let form = format!("NAME {} {}", keyword.to_ascii_uppercase(), form);
+                (words[1].clone(), Some(vec![form]))
+            }
+            (None, Some(range)) => {
+                let forms = FORMS
+                    .iter()
+                    .find(|(name, _)| word(0).eq_ignore_ascii_case(name))
+                    .map(|(name, forms)| {
+                        forms
+                            .iter()
+                            .map(|form| format!("{} {}", name, form).trim_end().to_string())
+                            .collect()
+                    });
+                (range.clone(), forms)
+            }
+            (None, None) => (0..code.len(), None),
+        };
+
+        let (columns, expected) = match forms {
+            Some(forms) => {
+                let operands = &code[keyword.end..];
+                let columns = if operands.trim().is_empty() {
+                    keyword
+                } else {
+                    code.len() - operands.trim_start().len()..code.len()
+                };
+                (columns, forms)
+            }
+            None => (
+                keyword,
+                vec![String::from(
+                    "an instruction, directive, label or constant definition",
+                )],
+            ),
+        };
+
+        Diagnostic {
+            line,
+            text: code[columns.clone()].to_string(),
+            columns,
+            line_text: line_text.to_string(),
+            expected,
+            message: None,
+        }
+    }
+
+    /// Creates a diagnostic for an error that is not about the syntax of the line, the entire line is reported.
+    pub fn error(line_text: &str, line: usize, message: String) -> Diagnostic {
+        let text = line_text.trim();
+        let start = line_text.len() - line_text.trim_start().len();
+        Diagnostic {
+            line,
+            columns: start..start + text.len(),
+            text: text.to_string(),
+            line_text: line_text.to_string(),
+            expected: vec![],
+            message: Some(message),
+        }
+    }
+
+    /// Creates a diagnostic for an error about the text at columns, e.g. a number that is out of range.
+    pub fn error_at(
+        line_text: &str,
+        line: usize,
+        columns: Range<usize>,
+        message: String,
+    ) -> Diagnostic {
+        Diagnostic {
+            line,
+            text: line_text[columns.clone()].to_string(),
+            columns,
+            line_text: line_text.to_string(),
+            expected: vec![],
+            message: Some(message),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(message) = &self.message {
+            writeln!(f, "{}", message)?;
+        } else {
+            let expected: Vec<_> = self.expected.iter().map(|x| format!("`{}`", x)).collect();
+            writeln!(
+                f,
+                "expected one of {}, found `{}`",
+                expected.join(", "),
+                self.text
+            )?;
+        }
+        writeln!(f, "{}", self.line_text)?;
+        // keep tabs so the underline lines up with the text
+        let indent: String = self.line_text[..self.columns.start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let len = self.text.chars().count().max(1);
+        write!(f, "{}{}", indent, "^".repeat(len))
+    }
+}
+
+/// Returns the byte range of each word, split on whitespace and commas.
+fn word_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut words = vec![];
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        let separator = c.is_whitespace() || c == ',';
+        match (start, separator) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                words.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push(s..text.len());
+    }
+    words
+}
+
+/// The operands accepted by each mnemonic and directive, using the RGBDS notation:
+/// *   r8 - an 8 bit register: a, b, c, d, e, h or l
+/// *   r16 - a 16 bit register: bc, de, hl or sp, af instead of sp for push and pop
+/// *   n8, n16 - an 8 or 16 bit expression
+/// *   e8 - a signed 8 bit expression
+/// *   u3 - a bit index from 0 to 7
+/// *   cc - a condition: z, nz, c or nc
+/// *   vec - a restart vector: 0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30 or 0x38
+const FORMS: &[(&str, &[&str])] = &[
+    ("nop", &[""]),
+    ("stop", &[""]),
+    ("halt", &[""]),
+    ("di", &[""]),
+    ("ei", &[""]),
+    ("reti", &[""]),
+    ("rrca", &[""]),
+    ("rra", &[""]),
+    ("cpl", &[""]),
+    ("ccf", &[""]),
+    ("rlca", &[""]),
+    ("rla", &[""]),
+    ("daa", &[""]),
+    ("scf", &[""]),
+    ("ret", &["", "cc"]),
+    ("call", &["n16", "cc, n16"]),
+    ("jp", &["n16", "cc, n16", "hl"]),
+    ("jr", &["n16", "cc, n16"]),
+    ("rst", &["vec"]),
+    ("inc", &["r8", "r16", "[hl]"]),
+    ("dec", &["r8", "r16", "[hl]"]),
+    ("add", &["a, r8", "a, [hl]", "a, n8", "hl, r16", "sp, e8"]),
+    ("sub", &["a, r8", "a, [hl]", "a, n8"]),
+    ("and", &["a, r8", "a, [hl]", "a, n8"]),
+    ("or", &["a, r8", "a, [hl]", "a, n8"]),
+    ("adc", &["a, r8", "a, [hl]", "a, n8"]),
+    ("sbc", &["a, r8", "a, [hl]", "a, n8"]),
+    ("xor", &["a, r8", "a, [hl]", "a, n8"]),
+    ("cp", &["a, r8", "a, [hl]", "a, n8"]),
+    (
+        "ld",
+        &[
+            "r8, r8",
+            "r8, n8",
+            "r16, n16",
+            "r8, [hl]",
+            "[hl], r8",
+            "[hl], n8",
+            "[r16], a",
+            "a, [r16]",
+            "[n16], a",
+            "a, [n16]",
+            "[n16], sp",
+            "[0xFF00 + n8], a",
+            "a, [0xFF00 + n8]",
+            "[0xFF00 + c], a",
+            "a, [0xFF00 + c]",
+            "[hl+], a",
+            "[hl-], a",
+            "a, [hl+]",
+            "a, [hl-]",
+            "hl, sp + e8",
+            "sp, hl",
+        ],
+    ),
+    ("ldh", &["[n16], a", "a, [n16]", "[c], a", "a, [c]"]),
+    ("ldi", &["[hl], a", "a, [hl]"]),
+    ("ldd", &["[hl], a", "a, [hl]"]),
+    ("push", &["r16"]),
+    ("pop", &["r16"]),
+    ("rlc", &["r8", "[hl]"]),
+    ("rrc", &["r8", "[hl]"]),
+    ("rl", &["r8", "[hl]"]),
+    ("rr", &["r8", "[hl]"]),
+    ("sla", &["r8", "[hl]"]),
+    ("sra", &["r8", "[hl]"]),
+    ("swap", &["r8", "[hl]"]),
+    ("srl", &["r8", "[hl]"]),
+    ("bit", &["u3, r8", "u3, [hl]"]),
+    ("res", &["u3, r8", "u3, [hl]"]),
+    ("set", &["u3, r8", "u3, [hl]"]),
+    ("db", &["n8, \"string\", ..."]),
+    ("dw", &["n16, ..."]),
+    ("dbfill", &["n16, n8"]),
+    ("advance_address", &["n16", "bank, n16"]),
+    (
+        "debug_assert",
+        &["cc", "a == n8", "a != n8", "a < n8", "a >= n8"],
+    ),
+    ("debug_print", &["\"message\""]),
+    ("debug_break", &[""]),
+    ("if", &["n16"]),
+    ("elif", &["n16"]),
+    ("else", &[""]),
+    ("endc", &[""]),
+    ("include", &["\"file\""]),
+    (
+        "incbin",
+        &["\"file\"", "\"file\", n16", "\"file\", n16, n16"],
+    ),
+    ("assert", &["n16", "n16, \"message\""]),
+    ("print", &["\"message\", n16, ..."]),
+    ("warn", &["\"message\", n16, ..."]),
+    ("fail", &["\"message\", n16, ..."]),
+    ("charmap", &["\"c\", n8"]),
+    (
+        "section",
+        &["\"name\", ROM0[n16]", "\"name\", ROMX[n16], BANK[n16]"],
+    ),
+    ("export", &["label, ..."]),
+    ("global", &["label, ..."]),
+    ("opt", &["option, ..."]),
+    ("pusho", &[""]),
+    ("popo", &[""]),
+    ("pushs", &[""]),
+    ("pops", &[""]),
+    ("pragma", &["once"]),
+    ("union", &[""]),
+    ("nextu", &[""]),
+    ("endu", &[""]),
+    (
+        "redef",
+        &["NAME EQU n16", "NAME EQUS \"text\"", "NAME = n16"],
+    ),
+    ("def", &["NAME EQU n16", "NAME EQUS \"text\"", "NAME = n16"]),
+];
+
+/// The value accepted by each kind of constant definition, e.g. `NAME EQU n16`.
+const DEFINITIONS: &[(&str, &str)] = &[
+    ("equ", "n16"),
+    ("equs", "\"text\""),
+    ("set", "n16"),
+    ("=", "n16"),
+];
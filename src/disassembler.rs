@@ -0,0 +1,137 @@
+//! Decode machine code back into [Instruction]s.
+//!
+//! Immediate values are decoded into [Expr::Const], jr destinations are decoded into the absolute address.
+//! [disassemble_with_symbols] also replaces addresses with the names of symbols, e.g. the labels of a compiled rom.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Error};
+
+use crate::address::GlobalAddr;
+use crate::ast::*;
+use crate::constants::ROM_BANK_SIZE;
+use crate::layout::Layout;
+use crate::opcodes::{decode_table, Operand};
+
+/// Decodes every instruction in bytes, where address is the address of the first byte.
+pub fn disassemble(bytes: &[u8], address: usize) -> Result<Vec<Instruction>, Error> {
+    let mut result = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let (instruction, len) = disassemble_instruction(&bytes[offset..], address + offset)?;
+        result.push(instruction);
+        offset += len;
+    }
+    Ok(result)
+}
+
+/// Decodes every instruction in bytes like [disassemble], replacing addresses with the name of the symbol at them.
+///
+/// symbols maps values to names, where rom addresses are within the entire rom, see [label_names].
+/// The destinations of jumps and calls, 16 bit operands and the addresses of ldh are replaced,
+/// 16 bit operands from 0x4000-0x7FFF refer to the bank the instruction is in.
+pub fn disassemble_with_symbols(
+    bytes: &[u8],
+    address: usize,
+    symbols: &HashMap<i64, String>,
+) -> Result<Vec<Instruction>, Error> {
+    let mut result = disassemble(bytes, address)?;
+    let mut offset = 0;
+    for instruction in &mut result {
+        replace_symbols(instruction, address + offset, symbols);
+        offset += instruction.bytes_len(0)? as usize;
+    }
+    Ok(result)
+}
+
+/// Returns the names of the labels of a compiled rom, for use with [disassemble_with_symbols].
+/// When labels share an address the first one is used.
+pub fn label_names(layout: &Layout) -> HashMap<i64, String> {
+    let mut names = HashMap::new();
+    for symbol in layout.symbols.iter().filter(|x| x.label) {
+        names
+            .entry(symbol.value)
+            .or_insert_with(|| symbol.name.clone());
+    }
+    names
+}
+
+/// Replaces the address used by the instruction at address with the name of the symbol at it.
+fn replace_symbols(instruction: &mut Instruction, address: usize, symbols: &HashMap<i64, String>) {
+    let ldh = matches!(
+        instruction,
+        Instruction::LdhRaMI8(_) | Instruction::LdhMI8Ra(_)
+    );
+    let symbol = match instruction.encoding().map(|x| x.operand) {
+        // jr destinations are already an address within the entire rom
+        Some(Operand::Rel8(Expr::Const(value))) => symbols.get(value),
+        Some(Operand::I16(Expr::Const(value))) => {
+            // bank 0 is mapped in with bank 1 by default
+            let bank = GlobalAddr(address as u32).bank().0.max(1) as i64;
+            match value {
+                0x4000..=0x7FFF => symbols.get(&(value + (bank - 1) * ROM_BANK_SIZE as i64)),
+                value => symbols.get(value),
+            }
+        }
+        Some(Operand::I8(Expr::Const(value))) if ldh => symbols.get(&(value + 0xFF00)),
+        _ => None,
+    };
+    let name = match symbol {
+        Some(name) => name.clone(),
+        None => return,
+    };
+    *instruction.expr_mut().unwrap() = if ldh {
+        Expr::binary(Expr::Ident(name), BinaryOperator::Sub, Expr::Const(0xFF00))
+    } else {
+        Expr::Ident(name)
+    };
+}
+
+/// Decodes the instruction at the start of bytes, where address is the address of the first byte.
+/// Returns the instruction and how many bytes it takes up.
+///
+/// `halt` is followed by a `nop` to work around the halt bug, the `nop` is included in the halt instruction.
+pub fn disassemble_instruction(
+    bytes: &[u8],
+    address: usize,
+) -> Result<(Instruction, usize), Error> {
+    let table = decode_table();
+    let (opcode, instruction) = match bytes {
+        [] => bail!("There are no bytes to disassemble at 0x{:x}", address),
+        [0xCB] => bail!("Opcode 0xcb at 0x{:x} is missing its operands", address),
+        [0xCB, opcode, ..] => (*opcode, &table.prefixed[*opcode as usize]),
+        [opcode, ..] => (*opcode, &table.unprefixed[*opcode as usize]),
+    };
+    let mut instruction = match instruction {
+        Some(instruction) => instruction.clone(),
+        None => bail!("0x{:x} at 0x{:x} is not a valid opcode", opcode, address),
+    };
+
+    let encoding = instruction.encoding().unwrap();
+    let mut len = encoding.bytes_len() as usize;
+    let operand = match encoding.operand {
+        Operand::I8(_) => bytes.get(1).map(|x| *x as i64),
+        Operand::S8(_) => bytes.get(1).map(|x| *x as i8 as i64),
+        Operand::Rel8(_) => bytes.get(1).map(|x| address as i64 + 2 + *x as i8 as i64),
+        Operand::I16(_) => match (bytes.get(1), bytes.get(2)) {
+            (Some(low), Some(high)) => Some(*low as i64 | (*high as i64) << 8),
+            _ => None,
+        },
+        Operand::Nop => {
+            if bytes.get(1) != Some(&0x00) {
+                len = 1;
+            }
+            return Ok((instruction, len));
+        }
+        Operand::None | Operand::Bit(_) | Operand::Rst(_) => return Ok((instruction, len)),
+    };
+    match operand {
+        Some(operand) => *instruction.expr_mut().unwrap() = Expr::Const(operand),
+        None => bail!(
+            "Opcode 0x{:x} at 0x{:x} is missing its operands",
+            opcode,
+            address
+        ),
+    }
+    Ok((instruction, len))
+}
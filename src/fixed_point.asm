@@ -0,0 +1,100 @@
+; 8.8 fixed point values store the integer part in the high byte and the fraction in the low byte.
+; e.g. 0x0180 is 1.5 and 0xFF80 is -0.5
+
+; sets de to the sine of a as signed 8.8 fixed point, where 256 is a full turn
+; hl is clobbered
+GGBASMSinLookup:
+    ld l, a
+    ld h, 0
+    add hl, hl
+    ld de, GGBASMSineTable
+    add hl, de
+    ld e, [hl]
+    inc hl
+    ld d, [hl]
+    ret
+
+; sets de to the cosine of a as signed 8.8 fixed point, where 256 is a full turn
+; a and hl are clobbered
+GGBASMCosLookup:
+    add a, 64
+    jr GGBASMSinLookup
+
+; sets hl to de * bc where all three are signed 8.8 fixed point
+; the result is rounded towards zero and wraps when it doesnt fit, af, bc and de are clobbered
+GGBASMMulFixed:
+    ld a, d
+    xor b
+    push af ; bit 7 of a is the sign of the result
+
+    bit 7, d
+    jr z, GGBASMMulFixedDePositive
+    xor a
+    sub e
+    ld e, a
+    ld a, 0
+    sbc a, d
+    ld d, a
+GGBASMMulFixedDePositive:
+
+    bit 7, b
+    jr z, GGBASMMulFixedBcPositive
+    xor a
+    sub c
+    ld c, a
+    ld a, 0
+    sbc a, b
+    ld b, a
+GGBASMMulFixedBcPositive:
+
+    call GGBASMMulFixedUnsigned
+    pop af
+    bit 7, a
+    ret z
+    xor a
+    sub l
+    ld l, a
+    ld a, 0
+    sbc a, h
+    ld h, a
+    ret
+
+; sets hl to de * bc where all three are unsigned 8.8 fixed point
+; the result wraps when it doesnt fit, af and bc are clobbered
+;
+; Shifts the 32 bit product right through h, l and a while adding de to hl for each set bit of bc.
+; Once all 16 bits are done l and a hold the middle 16 bits of the product, which is the 8.8 result.
+GGBASMMulFixedUnsigned:
+    ld hl, 0
+    ld a, b
+    push af ; the high byte is multiplied once the low byte is done
+    xor a
+    ld b, 8
+GGBASMMulFixedLowByte:
+    srl c
+    jr nc, GGBASMMulFixedLowByteSkip
+    add hl, de
+GGBASMMulFixedLowByteSkip:
+    rr h
+    rr l
+    rra
+    dec b
+    jr nz, GGBASMMulFixedLowByte
+
+    pop bc
+    ld c, b
+    ld b, 8
+GGBASMMulFixedHighByte:
+    srl c
+    jr nc, GGBASMMulFixedHighByteSkip
+    add hl, de
+GGBASMMulFixedHighByteSkip:
+    rr h
+    rr l
+    rra
+    dec b
+    jr nz, GGBASMMulFixedHighByte
+
+    ld h, l
+    ld l, a
+    ret
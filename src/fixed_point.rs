@@ -0,0 +1,80 @@
+//! Generates 8.8 fixed point lookup tables and the routines that use them.
+//!
+//! 8.8 fixed point values store the integer part in the high byte and the fraction in the low byte,
+//! e.g. 0x0180 is 1.5 and 0xFF80 is -0.5.
+//! Tables are stored as little endian words, use [table_bytes] to add one with [crate::RomBuilder::add_bytes].
+
+use std::f64::consts::PI;
+
+use crate::ast::Instruction;
+use crate::parser;
+
+/// Converts value to signed 8.8 fixed point, rounding to the nearest value.
+/// Values that dont fit are clamped.
+pub fn to_fixed(value: f64) -> i16 {
+    (value * 256.0)
+        .round()
+        .clamp(i16::MIN as f64, i16::MAX as f64) as i16
+}
+
+/// Returns the sine of N angles evenly spread over a full turn.
+pub fn sine_table<const N: usize>() -> [i16; N] {
+    std::array::from_fn(|i| to_fixed((i as f64 * 2.0 * PI / N as f64).sin()))
+}
+
+/// Returns the cosine of N angles evenly spread over a full turn.
+pub fn cosine_table<const N: usize>() -> [i16; N] {
+    std::array::from_fn(|i| to_fixed((i as f64 * 2.0 * PI / N as f64).cos()))
+}
+
+/// Returns 1 / i for i in 0..N.
+/// The entry for 0 is the largest value that fits.
+pub fn reciprocal_table<const N: usize>() -> [i16; N] {
+    std::array::from_fn(|i| {
+        if i == 0 {
+            i16::MAX
+        } else {
+            to_fixed(1.0 / i as f64)
+        }
+    })
+}
+
+/// Returns i * factor for i in 0..N.
+/// Use a factor of `1.0 / divisor` for a table that divides by a constant.
+pub fn multiply_table<const N: usize>(factor: f64) -> [i16; N] {
+    std::array::from_fn(|i| to_fixed(i as f64 * factor))
+}
+
+/// Returns the table as little endian words.
+pub fn table_bytes(table: &[i16]) -> Vec<u8> {
+    table.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+/// Generates a 256 entry sine table under the label GGBASMSineTable and the routines:
+/// *   GGBASMSinLookup - sets de to the sine of a, where 256 is a full turn
+/// *   GGBASMCosLookup - sets de to the cosine of a, where 256 is a full turn
+/// *   GGBASMMulFixed - sets hl to de * bc, all signed
+/// *   GGBASMMulFixedUnsigned - sets hl to de * bc, all unsigned
+pub fn generate_routines() -> Vec<Instruction> {
+    let mut instructions = vec![
+        Instruction::Label(String::from("GGBASMSineTable")),
+        Instruction::Db(table_bytes(&sine_table::<256>())),
+    ];
+    let text = include_str!("fixed_point.asm");
+    instructions.extend(
+        parser::parse_asm(text)
+            .unwrap()
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| {
+                x.unwrap_or_else(|err| {
+                    panic!(
+                        "Invalid instruction on line {} of fixed_point.asm: {}",
+                        i + 1,
+                        err
+                    )
+                })
+            }),
+    );
+    instructions
+}
@@ -0,0 +1,261 @@
+//! Hardware register and flag constants using the names from the community hardware.inc file.
+//!
+//! These can be made available to assembly code via [crate::RomBuilder::add_hardware_constants].
+//! Alternatively an existing hardware.inc file can be used via [crate::RomBuilder::add_hardware_inc].
+
+use std::collections::HashSet;
+
+use crate::ast::Instruction;
+use crate::parser;
+
+/// Every hardware constant as (identifier, value) pairs.
+pub static HARDWARE_CONSTANTS: &[(&str, i64)] = &[
+    // memory map
+    ("_VRAM", 0x8000),
+    ("_VRAM8000", 0x8000),
+    ("_VRAM8800", 0x8800),
+    ("_VRAM9000", 0x9000),
+    ("_SCRN0", 0x9800),
+    ("_SCRN1", 0x9C00),
+    ("_SRAM", 0xA000),
+    ("_RAM", 0xC000),
+    ("_RAMBANK", 0xD000),
+    ("_OAMRAM", 0xFE00),
+    ("_IO", 0xFF00),
+    ("_AUD3WAVERAM", 0xFF30),
+    ("_HRAM", 0xFF80),
+    // joypad
+    ("rP1", 0xFF00),
+    ("P1F_5", 0x20),
+    ("P1F_4", 0x10),
+    ("P1F_3", 0x08),
+    ("P1F_2", 0x04),
+    ("P1F_1", 0x02),
+    ("P1F_0", 0x01),
+    ("P1F_GET_DPAD", 0x20),
+    ("P1F_GET_BTN", 0x10),
+    ("P1F_GET_NONE", 0x30),
+    // serial
+    ("rSB", 0xFF01),
+    ("rSC", 0xFF02),
+    ("SCF_START", 0x80),
+    ("SCF_SPEED", 0x02),
+    ("SCF_SOURCE", 0x01),
+    // timer
+    ("rDIV", 0xFF04),
+    ("rTIMA", 0xFF05),
+    ("rTMA", 0xFF06),
+    ("rTAC", 0xFF07),
+    ("TACF_START", 0x04),
+    ("TACF_STOP", 0x00),
+    ("TACF_4KHZ", 0x00),
+    ("TACF_16KHZ", 0x03),
+    ("TACF_65KHZ", 0x02),
+    ("TACF_262KHZ", 0x01),
+    // interrupts
+    ("rIF", 0xFF0F),
+    ("rIE", 0xFFFF),
+    ("IEF_HILO", 0x10),
+    ("IEF_SERIAL", 0x08),
+    ("IEF_TIMER", 0x04),
+    ("IEF_STAT", 0x02),
+    ("IEF_VBLANK", 0x01),
+    // audio
+    ("rNR10", 0xFF10),
+    ("rNR11", 0xFF11),
+    ("rNR12", 0xFF12),
+    ("rNR13", 0xFF13),
+    ("rNR14", 0xFF14),
+    ("rNR21", 0xFF16),
+    ("rNR22", 0xFF17),
+    ("rNR23", 0xFF18),
+    ("rNR24", 0xFF19),
+    ("rNR30", 0xFF1A),
+    ("rNR31", 0xFF1B),
+    ("rNR32", 0xFF1C),
+    ("rNR33", 0xFF1D),
+    ("rNR34", 0xFF1E),
+    ("rNR41", 0xFF20),
+    ("rNR42", 0xFF21),
+    ("rNR43", 0xFF22),
+    ("rNR44", 0xFF23),
+    ("rNR50", 0xFF24),
+    ("rNR51", 0xFF25),
+    ("rNR52", 0xFF26),
+    ("AUDENA_ON", 0x80),
+    ("AUDENA_OFF", 0x00),
+    // lcd
+    ("rLCDC", 0xFF40),
+    ("LCDCF_OFF", 0x00),
+    ("LCDCF_ON", 0x80),
+    ("LCDCF_WIN9800", 0x00),
+    ("LCDCF_WIN9C00", 0x40),
+    ("LCDCF_WINOFF", 0x00),
+    ("LCDCF_WINON", 0x20),
+    ("LCDCF_BG8800", 0x00),
+    ("LCDCF_BG8000", 0x10),
+    ("LCDCF_BG9800", 0x00),
+    ("LCDCF_BG9C00", 0x08),
+    ("LCDCF_OBJ8", 0x00),
+    ("LCDCF_OBJ16", 0x04),
+    ("LCDCF_OBJOFF", 0x00),
+    ("LCDCF_OBJON", 0x02),
+    ("LCDCF_BGOFF", 0x00),
+    ("LCDCF_BGON", 0x01),
+    ("rSTAT", 0xFF41),
+    ("STATF_LYC", 0x40),
+    ("STATF_MODE10", 0x20),
+    ("STATF_MODE01", 0x10),
+    ("STATF_MODE00", 0x08),
+    ("STATF_LYCF", 0x04),
+    ("STATF_HBL", 0x00),
+    ("STATF_VBL", 0x01),
+    ("STATF_OAM", 0x02),
+    ("STATF_LCD", 0x03),
+    ("STATF_BUSY", 0x02),
+    ("rSCY", 0xFF42),
+    ("rSCX", 0xFF43),
+    ("rLY", 0xFF44),
+    ("rLYC", 0xFF45),
+    ("rDMA", 0xFF46),
+    ("rBGP", 0xFF47),
+    ("rOBP0", 0xFF48),
+    ("rOBP1", 0xFF49),
+    ("rWY", 0xFF4A),
+    ("rWX", 0xFF4B),
+    // color gameboy
+    ("rKEY1", 0xFF4D),
+    ("rSPD", 0xFF4D),
+    ("KEY1F_DBLSPEED", 0x80),
+    ("KEY1F_PREPARE", 0x01),
+    ("rVBK", 0xFF4F),
+    ("rHDMA1", 0xFF51),
+    ("rHDMA2", 0xFF52),
+    ("rHDMA3", 0xFF53),
+    ("rHDMA4", 0xFF54),
+    ("rHDMA5", 0xFF55),
+    ("HDMA5F_MODE_GP", 0x00),
+    ("HDMA5F_MODE_HBL", 0x80),
+    ("HDMA5F_BUSY", 0x80),
+    ("rRP", 0xFF56),
+    ("rBCPS", 0xFF68),
+    ("rBGPI", 0xFF68),
+    ("rBCPD", 0xFF69),
+    ("rBGPD", 0xFF69),
+    ("rOCPS", 0xFF6A),
+    ("rOBPI", 0xFF6A),
+    ("rOCPD", 0xFF6B),
+    ("rOBPD", 0xFF6B),
+    ("BCPSF_AUTOINC", 0x80),
+    ("OCPSF_AUTOINC", 0x80),
+    ("rSVBK", 0xFF70),
+    ("rSMBK", 0xFF70),
+    // object attributes
+    ("OAMF_PRI", 0x80),
+    ("OAMF_YFLIP", 0x40),
+    ("OAMF_XFLIP", 0x20),
+    ("OAMF_PAL0", 0x00),
+    ("OAMF_PAL1", 0x10),
+    ("OAMF_BANK0", 0x00),
+    ("OAMF_BANK1", 0x08),
+    ("OAMF_PALMASK", 0x07),
+    // joypad buttons, as read after combining the dpad and buttons into one byte
+    ("PADF_DOWN", 0x80),
+    ("PADF_UP", 0x40),
+    ("PADF_LEFT", 0x20),
+    ("PADF_RIGHT", 0x10),
+    ("PADF_START", 0x08),
+    ("PADF_SELECT", 0x04),
+    ("PADF_B", 0x02),
+    ("PADF_A", 0x01),
+    ("PADB_DOWN", 0x7),
+    ("PADB_UP", 0x6),
+    ("PADB_LEFT", 0x5),
+    ("PADB_RIGHT", 0x4),
+    ("PADB_START", 0x3),
+    ("PADB_SELECT", 0x2),
+    ("PADB_B", 0x1),
+    ("PADB_A", 0x0),
+    // screen
+    ("SCRN_X", 160),
+    ("SCRN_Y", 144),
+    ("SCRN_X_B", 20),
+    ("SCRN_Y_B", 18),
+    ("SCRN_VX", 256),
+    ("SCRN_VY", 256),
+    ("SCRN_VX_B", 32),
+    ("SCRN_VY_B", 32),
+];
+
+/// Returns the value of the hardware constant with the given identifier.
+pub fn hardware_constant(identifier: &str) -> Option<i64> {
+    HARDWARE_CONSTANTS
+        .iter()
+        .find(|(name, _)| *name == identifier)
+        .map(|(_, value)| *value)
+}
+
+/// Extracts the constants from the text of a community hardware.inc file.
+///
+/// Returns one instruction per line so that line numbers are kept intact.
+/// Only EQU definitions, with or without `DEF`, are extracted as [Instruction::Equ].
+/// Everything else, e.g. macros and conditional blocks, becomes [Instruction::EmptyLine].
+/// The RGBDS `$` hex and `%` binary literals are supported.
+/// Definitions using syntax that ggbasm does not support are skipped.
+/// If a constant is defined multiple times only the first definition is used.
+pub fn parse_hardware_inc(text: &str) -> Vec<Instruction> {
+    let mut defined = HashSet::new();
+    let mut in_macro = false;
+    let mut result = vec![];
+    for line in parser::SourceLines::new(text) {
+        let line = line.split(';').next().unwrap().trim();
+        let upper = line.to_uppercase();
+        if upper.starts_with("MACRO") || upper.ends_with(": MACRO") {
+            in_macro = true;
+        }
+        if in_macro {
+            if upper == "ENDM" {
+                in_macro = false;
+            }
+            result.push(Instruction::EmptyLine);
+            continue;
+        }
+
+        let line = match line.strip_prefix("DEF ") {
+            Some(line) => line.trim_start(),
+            None => line,
+        };
+        let equ = match parser::parse_asm(&rgbds_literals(line)) {
+            Ok(instructions) => match instructions.into_iter().next() {
+                Some(Ok(Instruction::Equ(ident, expr))) => {
+                    if defined.insert(ident.clone()) {
+                        Some(Instruction::Equ(ident, expr))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            },
+            Err(_) => None,
+        };
+        result.push(equ.unwrap_or(Instruction::EmptyLine));
+    }
+    result
+}
+
+/// Converts RGBDS `$` hex literals into ggbasm literals.
+///
+/// `%` binary literals are already supported by the parser.
+fn rgbds_literals(line: &str) -> String {
+    let mut result = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' if chars.peek().is_some_and(|x| x.is_ascii_hexdigit()) => {
+                result.push_str("0x");
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
@@ -2,6 +2,9 @@
 //!
 //! The Header struct is the main type, create one with the values needed for your rom.
 //! Then pass it the RomBuilder via add_header.
+//! Alternatively declare the entire prologue, the first 0x150 bytes of the rom, via a [Prologue].
+
+use crate::mapper::Mapper;
 
 pub enum ColorSupport {
     Unsupported,
@@ -118,6 +121,11 @@ impl CartridgeType {
             a => CartridgeType::Unknown(a),
         }
     }
+
+    /// Returns the memory bank controller that receives writes to ROM.
+    pub fn mapper(&self) -> Mapper {
+        Mapper::from_cartridge_type(self)
+    }
 }
 
 pub enum RamType {
@@ -140,6 +148,20 @@ impl RamType {
     }
 }
 
+/// Declares the parts of the first 0x150 bytes of the rom added by [crate::RomBuilder::add_prologue].
+///
+/// Each part is placed at its fixed address, parts that are not declared can be supplied from asm.
+#[derive(Default)]
+pub struct Prologue {
+    /// Adds the restart and interrupt vectors from 0x0000 to 0x00FF, see [crate::RomBuilder::add_basic_interrupts_and_jumps].
+    pub interrupts_and_jumps: bool,
+    /// Adds an entry point from 0x0100 to 0x0103 that jumps to this identifier.
+    /// When None and interrupts_and_jumps is set, the entry point jumps to 0x0150.
+    pub entry: Option<String>,
+    /// Adds the header from 0x0104 to 0x014F, see [crate::RomBuilder::add_header].
+    pub header: Option<Header>,
+}
+
 pub struct Header {
     /// 11 bytes
     pub title: String,
@@ -166,7 +188,7 @@ impl Header {
         }
 
         rom.extend(self.licence.as_bytes());
-        for _ in 0..0x2 - self.licence.as_bytes().len() {
+        for _ in 0..0x2 - self.licence.len() {
             rom.push(0x00);
         }
         rom.push(if self.sgb_support { 0x03 } else { 0x00 });
@@ -177,12 +199,7 @@ impl Header {
         rom.push(0x33); // we are using the new licence, so set old licence accordingly
         rom.push(self.version_number);
 
-        let mut checksum: u8 = 0;
-        for byte in &rom[0x0134..0x014D] {
-            checksum = checksum.wrapping_sub(*byte);
-            checksum = checksum.wrapping_sub(1);
-        }
-        rom.push(checksum);
+        rom.push(checksum(rom));
 
         // Global checksum, gameboy doesnt care about these
         rom.push(0x00);
@@ -190,7 +207,18 @@ impl Header {
     }
 }
 
-static LOGO: [u8; 0x30] = [
+/// Calculates the header checksum stored at 0x014D from the bytes at 0x0134 to 0x014C.
+pub(crate) fn checksum(rom: &[u8]) -> u8 {
+    let mut checksum: u8 = 0;
+    for byte in &rom[0x0134..0x014D] {
+        checksum = checksum.wrapping_sub(*byte);
+        checksum = checksum.wrapping_sub(1);
+    }
+    checksum
+}
+
+/// The Nintendo logo at 0x0104, the gameboy does not boot roms without it.
+pub(crate) static LOGO: [u8; 0x30] = [
     0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
     0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
     0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
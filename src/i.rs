@@ -0,0 +1,247 @@
+//! Short constructors for [Instruction], so building instructions in rust reads close to the asm it replaces.
+//!
+//! Operands take anything that converts into an [Expr], i.e. numbers, the name of a label or constant, or an Expr.
+//! Unlike the constructors on [Instruction] the arguments are not validated,
+//! invalid operands are reported when the rom is compiled.
+//!
+//! ```
+//! use ggbasm::ast::{Flag, Reg8};
+//! use ggbasm::i;
+//!
+//! let instructions = vec![
+//!     i::label("Loop"),
+//!     i::ld_a(1),
+//!     i::ld_mem_a("PlayerX"),
+//!     i::dec(Reg8::B),
+//!     i::jr_flag(Flag::NZ, "Loop"),
+//!     i::call("Main"),
+//!     i::ret(),
+//! ];
+//! ```
+
+use crate::ast::{Expr, Flag, Instruction, Reg16, Reg16Push, Reg8};
+
+/// `Name:`
+pub fn label(name: impl Into<String>) -> Instruction {
+    Instruction::Label(name.into())
+}
+
+/// `Name EQU value`
+pub fn equ(name: impl Into<String>, value: impl Into<Expr>) -> Instruction {
+    Instruction::Equ(name.into(), value.into())
+}
+
+/// `db bytes`
+pub fn db(bytes: &[u8]) -> Instruction {
+    Instruction::Db(bytes.to_vec())
+}
+
+/// `dw value`
+pub fn dw(value: impl Into<Expr>) -> Instruction {
+    Instruction::DbExpr16(value.into())
+}
+
+/// `nop`
+pub fn nop() -> Instruction {
+    Instruction::Nop
+}
+
+/// `halt`
+pub fn halt() -> Instruction {
+    Instruction::Halt
+}
+
+/// `stop`
+pub fn stop() -> Instruction {
+    Instruction::Stop
+}
+
+/// `di`
+pub fn di() -> Instruction {
+    Instruction::Di
+}
+
+/// `ei`
+pub fn ei() -> Instruction {
+    Instruction::Ei
+}
+
+/// `jp target`
+pub fn jp(target: impl Into<Expr>) -> Instruction {
+    Instruction::JpI16(Flag::Always, target.into())
+}
+
+/// `jp flag, target`
+pub fn jp_flag(flag: Flag, target: impl Into<Expr>) -> Instruction {
+    Instruction::JpI16(flag, target.into())
+}
+
+/// `jr target`
+pub fn jr(target: impl Into<Expr>) -> Instruction {
+    Instruction::Jr(Flag::Always, target.into())
+}
+
+/// `jr flag, target`
+pub fn jr_flag(flag: Flag, target: impl Into<Expr>) -> Instruction {
+    Instruction::Jr(flag, target.into())
+}
+
+/// `call target`
+pub fn call(target: impl Into<Expr>) -> Instruction {
+    Instruction::Call(Flag::Always, target.into())
+}
+
+/// `call flag, target`
+pub fn call_flag(flag: Flag, target: impl Into<Expr>) -> Instruction {
+    Instruction::Call(flag, target.into())
+}
+
+/// `ret`
+pub fn ret() -> Instruction {
+    Instruction::Ret(Flag::Always)
+}
+
+/// `ret flag`
+pub fn ret_flag(flag: Flag) -> Instruction {
+    Instruction::Ret(flag)
+}
+
+/// `reti`
+pub fn reti() -> Instruction {
+    Instruction::Reti
+}
+
+/// `rst vector`
+pub fn rst(vector: impl Into<Expr>) -> Instruction {
+    Instruction::Rst(vector.into())
+}
+
+/// `ld dst, src`
+pub fn ld(dst: Reg8, src: Reg8) -> Instruction {
+    Instruction::LdR8R8(dst, src)
+}
+
+/// `ld a, value`
+pub fn ld_a(value: impl Into<Expr>) -> Instruction {
+    Instruction::LdR8I8(Reg8::A, value.into())
+}
+
+/// `ld reg, value`
+pub fn ld_r8(reg: Reg8, value: impl Into<Expr>) -> Instruction {
+    Instruction::LdR8I8(reg, value.into())
+}
+
+/// `ld reg, value`
+pub fn ld_r16(reg: Reg16, value: impl Into<Expr>) -> Instruction {
+    Instruction::LdR16I16(reg, value.into())
+}
+
+/// `ld a, [address]`
+pub fn ld_a_mem(address: impl Into<Expr>) -> Instruction {
+    Instruction::LdRaMI16(address.into())
+}
+
+/// `ld [address], a`
+pub fn ld_mem_a(address: impl Into<Expr>) -> Instruction {
+    Instruction::LdMI16Ra(address.into())
+}
+
+/// `ld a, [$FF00 + offset]`
+pub fn ldh_a(offset: impl Into<Expr>) -> Instruction {
+    Instruction::LdhRaMI8(offset.into())
+}
+
+/// `ld [$FF00 + offset], a`
+pub fn ldh_mem_a(offset: impl Into<Expr>) -> Instruction {
+    Instruction::LdhMI8Ra(offset.into())
+}
+
+/// `inc reg`
+pub fn inc(reg: Reg8) -> Instruction {
+    Instruction::IncR8(reg)
+}
+
+/// `dec reg`
+pub fn dec(reg: Reg8) -> Instruction {
+    Instruction::DecR8(reg)
+}
+
+/// `inc reg`
+pub fn inc16(reg: Reg16) -> Instruction {
+    Instruction::IncR16(reg)
+}
+
+/// `dec reg`
+pub fn dec16(reg: Reg16) -> Instruction {
+    Instruction::DecR16(reg)
+}
+
+/// `add a, value`
+pub fn add(value: impl Into<Expr>) -> Instruction {
+    Instruction::AddI8(value.into())
+}
+
+/// `sub a, value`
+pub fn sub(value: impl Into<Expr>) -> Instruction {
+    Instruction::SubI8(value.into())
+}
+
+/// `and a, value`
+pub fn and(value: impl Into<Expr>) -> Instruction {
+    Instruction::AndI8(value.into())
+}
+
+/// `or a, value`
+pub fn or(value: impl Into<Expr>) -> Instruction {
+    Instruction::OrI8(value.into())
+}
+
+/// `xor a, value`
+pub fn xor(value: impl Into<Expr>) -> Instruction {
+    Instruction::XorI8(value.into())
+}
+
+/// `cp a, value`
+pub fn cp(value: impl Into<Expr>) -> Instruction {
+    Instruction::CpI8(value.into())
+}
+
+/// `add a, reg`
+pub fn add_r8(reg: Reg8) -> Instruction {
+    Instruction::AddR8(reg)
+}
+
+/// `sub a, reg`
+pub fn sub_r8(reg: Reg8) -> Instruction {
+    Instruction::SubR8(reg)
+}
+
+/// `and a, reg`
+pub fn and_r8(reg: Reg8) -> Instruction {
+    Instruction::AndR8(reg)
+}
+
+/// `or a, reg`
+pub fn or_r8(reg: Reg8) -> Instruction {
+    Instruction::OrR8(reg)
+}
+
+/// `xor a, reg`
+pub fn xor_r8(reg: Reg8) -> Instruction {
+    Instruction::XorR8(reg)
+}
+
+/// `cp a, reg`
+pub fn cp_r8(reg: Reg8) -> Instruction {
+    Instruction::CpR8(reg)
+}
+
+/// `push reg`
+pub fn push(reg: Reg16Push) -> Instruction {
+    Instruction::Push(reg)
+}
+
+/// `pop reg`
+pub fn pop(reg: Reg16Push) -> Instruction {
+    Instruction::Pop(reg)
+}
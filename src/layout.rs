@@ -0,0 +1,324 @@
+//! Describes the structure of a compiled rom for use by external tools.
+//!
+//! A [Layout] is generated by [crate::RomBuilder::compile_with_layout] and can be exported as JSON.
+//! A [SizeEstimate] is generated by [crate::RomBuilder::estimate] without compiling the rom.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::profile::BuildProfile;
+use crate::symbols::SymbolKind;
+
+/// The structure of a compiled rom.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    /// size of the rom in bytes
+    pub rom_size: u32,
+    /// every block of data added to the RomBuilder, ordered by address
+    pub sections: Vec<Section>,
+    /// every identifier and its value, ordered by value
+    pub symbols: Vec<Symbol>,
+    /// classifies every byte of the rom, adjacent bytes of the same kind are merged
+    pub regions: Vec<Region>,
+    /// tweakable data registered via [crate::RomBuilder::register_data_region], ordered by address
+    pub data_regions: Vec<DataRegion>,
+    /// symbols that changed since the previous build, see [crate::RomBuilder::symbol_snapshot]
+    #[serde(default)]
+    pub symbol_drift: Vec<SymbolDrift>,
+    /// data stored once instead of duplicated, see [crate::RomBuilder::deduplicate_data]
+    #[serde(default)]
+    pub deduplicated: Vec<DeduplicatedData>,
+    /// warnings from WARN directives, lints, writes to mapper registers and unused symbols
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// messages from PRINT directives, in the order they occur in the rom
+    #[serde(default)]
+    pub messages: Vec<String>,
+    /// the time spent in each phase of the build, see [crate::RomBuilder::profile_build]
+    #[serde(default)]
+    pub profile: Option<BuildProfile>,
+}
+
+impl Layout {
+    /// Serializes the layout to JSON.
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Deserializes a layout from JSON.
+    pub fn from_json(json: &str) -> Result<Layout, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Returns the data region with the given name.
+    pub fn data_region(&self, name: &str) -> Option<&DataRegion> {
+        self.data_regions.iter().find(|x| x.name == name)
+    }
+
+    /// Rewrites the bytes backing a constant in an already compiled rom.
+    ///
+    /// The constant must be registered as a data region containing a single element,
+    /// e.g. `register_data_region("StartingLives", 1, 1)` for a label followed by `db 3`.
+    /// The value is written as little endian and must fit in the element size.
+    pub fn patch_constant(&self, rom: &mut [u8], name: &str, value: i64) -> Result<(), Error> {
+        let region = match self.data_region(name) {
+            Some(region) => region,
+            None => bail!("{} is not a registered data region", name),
+        };
+        if region.count != 1 {
+            bail!(
+                "{} is a data region with {} elements, constants must have exactly 1 element",
+                name,
+                region.count
+            );
+        }
+        if rom.len() as u32 != self.rom_size {
+            bail!(
+                "The rom is {} bytes but the layout is for a rom of {} bytes",
+                rom.len(),
+                self.rom_size
+            );
+        }
+
+        let size = region.element_size;
+        if size == 0 {
+            bail!("Data region {} has an element size of 0", name);
+        }
+        if size > 8 {
+            bail!(
+                "{} is {} bytes, constants can be at most 8 bytes",
+                name,
+                size
+            );
+        }
+        if size < 8 {
+            let bits = size * 8;
+            let min = -(1i64 << (bits - 1));
+            let max = (1i64 << bits) - 1;
+            if value < min || value > max {
+                bail!("{} does not fit in the {} bytes of {}", value, size, name);
+            }
+        }
+
+        let start = region.address as usize;
+        let bytes = value.to_le_bytes();
+        rom[start..start + size as usize].copy_from_slice(&bytes[..size as usize]);
+        Ok(())
+    }
+
+    /// Rewrites the bytes of a data region in an already compiled rom.
+    ///
+    /// Used to hot reload assets, e.g. with the bytes of a re-imported image from
+    /// [crate::asset::ConvertedAsset::to_bytes].
+    /// Only the rom passed in is patched, ggbasm has no emulator harness to update a running machine,
+    /// so the caller must load the patched rom into its emulator itself, e.g. by copying it over the emulator's
+    /// rom between frames or by resetting it.
+    /// The bytes must fit in the data region, any remaining bytes of the region are left unchanged.
+    pub fn patch_data_region(&self, rom: &mut [u8], name: &str, bytes: &[u8]) -> Result<(), Error> {
+        let region = match self.data_region(name) {
+            Some(region) => region,
+            None => bail!("{} is not a registered data region", name),
+        };
+        if rom.len() as u32 != self.rom_size {
+            bail!(
+                "The rom is {} bytes but the layout is for a rom of {} bytes",
+                rom.len(),
+                self.rom_size
+            );
+        }
+        let size = region.element_size as usize * region.count as usize;
+        if bytes.len() > size {
+            bail!(
+                "{} bytes do not fit in the {} bytes of {}",
+                bytes.len(),
+                size,
+                name
+            );
+        }
+
+        let start = region.address as usize;
+        rom[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Returns the number of bytes saved by [crate::RomBuilder::deduplicate_data].
+    pub fn deduplicated_bytes(&self) -> u32 {
+        self.deduplicated.iter().map(|x| x.size).sum()
+    }
+
+    /// Returns the value of the symbol with the given name.
+    pub fn symbol(&self, name: &str) -> Option<i64> {
+        self.symbols
+            .iter()
+            .find(|x| x.name == name)
+            .map(|x| x.value)
+    }
+
+    /// Adds a region of the given kind, merging it into the previous region if possible.
+    pub(crate) fn push_region(&mut self, address: u32, size: u32, kind: RegionKind) {
+        if size == 0 {
+            return;
+        }
+        if let Some(last) = self.regions.last_mut() {
+            if last.kind == kind && last.address + last.size == address {
+                last.size += size;
+                return;
+            }
+        }
+        self.regions.push(Region {
+            address,
+            size,
+            kind,
+        });
+    }
+}
+
+/// A block of data added to the RomBuilder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Section {
+    /// address within the entire rom
+    pub address: u32,
+    pub size: u32,
+    /// describes where the data came from
+    pub source: String,
+}
+
+/// An identifier from assembly, rust code or the built-in ggbasm routines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: String,
+    /// labels are addresses within the entire rom, other identifiers can be any value
+    pub value: i64,
+    /// true for labels and the names of data added by rust code
+    #[serde(default)]
+    pub label: bool,
+    /// what declared the symbol, None in layouts from older versions
+    #[serde(default)]
+    pub kind: Option<SymbolKind>,
+    /// the number of bytes the symbol refers to, when known
+    #[serde(default)]
+    pub size: Option<u32>,
+    /// the asm file a label is private to, None when it is exported or declared elsewhere
+    #[serde(default)]
+    pub private_to: Option<String>,
+}
+
+/// Data identical to previously added data, its identifier refers to the previous copy instead.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeduplicatedData {
+    pub name: String,
+    /// address within the entire rom of the previous copy
+    pub address: u32,
+    /// number of bytes saved
+    pub size: u32,
+}
+
+/// A symbol whose value changed since the previous build.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SymbolDrift {
+    pub name: String,
+    pub previous: i64,
+    /// None when the symbol no longer exists
+    pub current: Option<i64>,
+}
+
+impl fmt::Display for SymbolDrift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.current {
+            Some(current) => write!(
+                f,
+                "{} moved from 0x{:x} to 0x{:x}",
+                self.name, self.previous, current
+            ),
+            None => write!(f, "{} at 0x{:x} was removed", self.name, self.previous),
+        }
+    }
+}
+
+/// Compares the symbols of a previous build to the current build, ordered by name.
+pub fn symbol_drift(previous: &[Symbol], current: &[Symbol]) -> Vec<SymbolDrift> {
+    // private labels of different files can share a name
+    let current: HashMap<(&str, Option<&str>), i64> = current
+        .iter()
+        .map(|x| ((x.name.as_str(), x.private_to.as_deref()), x.value))
+        .collect();
+    let mut drift: Vec<_> = previous
+        .iter()
+        .filter_map(|symbol| {
+            let key = (symbol.name.as_str(), symbol.private_to.as_deref());
+            let value = current.get(&key).copied();
+            (value != Some(symbol.value)).then(|| SymbolDrift {
+                name: symbol.name.clone(),
+                previous: symbol.value,
+                current: value,
+            })
+        })
+        .collect();
+    drift.sort_by(|a, b| a.name.cmp(&b.name));
+    drift
+}
+
+/// A run of bytes with the same classification.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    /// address within the entire rom
+    pub address: u32,
+    pub size: u32,
+    pub kind: RegionKind,
+}
+
+/// A named table of fixed size elements that external tools can safely modify, e.g. item tables or enemy stats.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DataRegion {
+    pub name: String,
+    /// address within the entire rom
+    pub address: u32,
+    /// size of each element in bytes
+    pub element_size: u32,
+    /// number of elements
+    pub count: u32,
+}
+
+impl DataRegion {
+    /// Returns the address within the entire rom of the element at the given index.
+    pub fn element_address(&self, index: u32) -> Option<u32> {
+        if index < self.count {
+            Some(self.address + index * self.element_size)
+        } else {
+            None
+        }
+    }
+}
+
+/// What a byte in the rom is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegionKind {
+    /// the RST and interrupt vectors and entry point generated by the RomBuilder
+    InterruptsAndJumps,
+    Header,
+    Code,
+    /// raw bytes, graphics, audio or db/dw data
+    Data,
+    /// zeroes filling unused space
+    Padding,
+}
+
+/// The projected size of a rom, generated without compiling it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SizeEstimate {
+    /// size of the rom in bytes after padding to a valid rom size
+    pub rom_size: u32,
+    /// every bank in the rom, ordered by bank
+    pub banks: Vec<BankUsage>,
+}
+
+/// How many bytes of a rom bank are used.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BankUsage {
+    pub bank: u32,
+    pub used: u32,
+    pub free: u32,
+}
@@ -17,6 +17,7 @@
 //! #     version_number: 0,
 //! # };
 //!
+//! use ggbasm::address::{Bank, BankAddr};
 //! use ggbasm::RomBuilder;
 //!
 //! RomBuilder::new()?
@@ -31,7 +32,7 @@
 //!    .add_asm_file("main.asm")?
 //!
 //!    // Add an image to the second rom bank
-//!    .advance_address(1, 0)?
+//!    .advance_address(Bank(1), BankAddr(0x4000))?
 //!    .add_image("tiles.png", "Tileset", &colors_map)?
 //!
 //!    // Consume the RomBuilder and write the rom to disk
@@ -48,16 +49,42 @@
 //! ## Parser
 //!
 //! If you are after a lower level api, the [parser] and [ast] modules can be used without the RomBuilder.
-//! You can also construct the ast types yourself and give them to the RomBuilder.
+//! You can also construct the ast types yourself and give them to the RomBuilder, the [i] module has short
+//! constructors for them.
+//! The [printer] module renders the ast types back into asm text.
 
 #![recursion_limit = "1024"] // Used for large nom parsers
 
+pub mod address;
+pub mod asset;
 pub mod ast;
 pub mod audio;
+pub mod build_support;
+pub mod conformance;
 pub mod constants;
+pub mod control_flow;
+pub mod debug;
+pub mod diagnostic;
+pub mod disassembler;
+pub mod fixed_point;
+pub mod hardware;
 pub mod header;
+pub mod i;
+pub mod layout;
+pub mod lint;
+pub mod mapper;
+pub mod opcodes;
+pub mod output;
 pub mod parser;
+pub mod printer;
+pub mod profile;
+pub mod scaffold;
+pub mod stack;
+pub mod stats;
+pub mod symbols;
+pub mod visit;
 
 mod rom_builder;
 pub use self::rom_builder::Color;
 pub use self::rom_builder::RomBuilder;
+pub use self::rom_builder::VariableType;
@@ -0,0 +1,281 @@
+//! Lints for constructs known to behave differently on emulators and real hardware.
+//!
+//! Enabled via [crate::RomBuilder::lint_profile], lints are reported as warnings in [crate::rom_builder::Layout::warnings]
+//! when the rom is compiled.
+//!
+//! Accesses to addresses that are never valid are always reported as errors, regardless of the lint profile.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Instruction, Reg16, Reg8, Span};
+use crate::mapper::Mapper;
+
+/// Selects which lints are run when the rom is compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LintProfile {
+    /// No lints are run.
+    #[default]
+    Default,
+    /// Flags constructs that work on emulators but can break on real hardware:
+    /// *   Reading RAM that is never written, RAM is uninitialized on real hardware but zeroed by many emulators.
+    ///     Stores through `bc`, `de` or `hl` count as writing the WRAM or HRAM region the pointer was loaded with,
+    ///     or all of RAM when the pointer is unknown.
+    /// *   Writing to rSTAT, which can trigger a spurious STAT interrupt on DMG hardware.
+    HardwareStrict,
+}
+
+/// A lint found in an instruction, line is 1 indexed.
+pub(crate) struct LintError {
+    pub source: String,
+    pub line: usize,
+    pub message: String,
+    /// true if the instruction is valid but likely a mistake
+    pub warning: bool,
+}
+
+#[derive(PartialEq)]
+enum Access {
+    Read,
+    Write,
+}
+
+/// Returns the address accessed by an instruction when the address is known at compile time.
+fn memory_access(
+    instruction: &Instruction,
+    constants: &HashMap<String, i64>,
+) -> Option<(Access, u16)> {
+    let (access, address) = match instruction {
+        Instruction::LdMI16Ra(expr) | Instruction::LdMI16Rsp(expr) => {
+            (Access::Write, expr.run(constants).ok()?)
+        }
+        Instruction::LdRaMI16(expr) => (Access::Read, expr.run(constants).ok()?),
+        Instruction::LdhMI8Ra(expr) => (Access::Write, 0xFF00 | (expr.run(constants).ok()? & 0xFF)),
+        Instruction::LdhRaMI8(expr) => (Access::Read, 0xFF00 | (expr.run(constants).ok()? & 0xFF)),
+        _ => return None,
+    };
+    u16::try_from(address).ok().map(|address| (access, address))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum RamRegion {
+    Wram,
+    Hram,
+}
+
+fn ram_region(address: u16) -> Option<RamRegion> {
+    match address {
+        0xC000..=0xDFFF => Some(RamRegion::Wram),
+        0xFF80..=0xFFFE => Some(RamRegion::Hram),
+        _ => None,
+    }
+}
+
+/// Returns the index into the tracked pointers of bc, de and hl
+fn pointer_index(reg: &Reg16) -> Option<usize> {
+    match reg {
+        Reg16::BC => Some(0),
+        Reg16::DE => Some(1),
+        Reg16::HL => Some(2),
+        Reg16::SP => None,
+    }
+}
+
+fn pointer_index_of_half(reg: &Reg8) -> Option<usize> {
+    match reg {
+        Reg8::B | Reg8::C => Some(0),
+        Reg8::D | Reg8::E => Some(1),
+        Reg8::H | Reg8::L => Some(2),
+        Reg8::A => None,
+    }
+}
+
+/// Returns the RAM regions written by stores through a pointer.
+/// The pointer is known when it was last loaded with a constant by `ld r16, n16`, incrementing or decrementing it is
+/// assumed to stay within the region, e.g. when clearing RAM in a loop.
+/// When the pointer is unknown it could point anywhere, so every region is considered written.
+fn pointer_writes(
+    sources: &[(String, &[Instruction], &[Span])],
+    constants: &HashMap<String, i64>,
+) -> HashSet<RamRegion> {
+    let mut regions = HashSet::new();
+    for (_, instructions, _) in sources {
+        let mut pointers: [Option<u16>; 3] = [None; 3];
+        for instruction in instructions.iter() {
+            let store = match instruction {
+                Instruction::LdMRbcRa => Some(pointers[0]),
+                Instruction::LdMRdeRa => Some(pointers[1]),
+                Instruction::LdMRhlR8(_)
+                | Instruction::LdMRhlI8(_)
+                | Instruction::LdiMRhlRa
+                | Instruction::LddMRhlRa => Some(pointers[2]),
+                Instruction::LdhMRcRa => Some(Some(0xFF80)),
+                _ => None,
+            };
+            match store {
+                Some(Some(address)) => regions.extend(ram_region(address)),
+                Some(None) => {
+                    regions.insert(RamRegion::Wram);
+                    regions.insert(RamRegion::Hram);
+                }
+                None => {}
+            }
+
+            match instruction {
+                Instruction::LdR16I16(reg, expr) => {
+                    if let Some(i) = pointer_index(reg) {
+                        pointers[i] = expr
+                            .run(constants)
+                            .ok()
+                            .and_then(|value| u16::try_from(value).ok());
+                    }
+                }
+                Instruction::LdR8R8(reg, _)
+                | Instruction::LdR8I8(reg, _)
+                | Instruction::LdR8MRhl(reg) => {
+                    if let Some(i) = pointer_index_of_half(reg) {
+                        pointers[i] = None;
+                    }
+                }
+                Instruction::AddRhlR16(_) | Instruction::LdRhlRspI8(_) => pointers[2] = None,
+                Instruction::Call(..) | Instruction::Rst(_) | Instruction::Pop(_) => {
+                    pointers = [None; 3]
+                }
+                _ => {}
+            }
+        }
+    }
+    regions
+}
+
+/// Returns the name and range of the memory region if accessing the address is never valid.
+fn illegal_region(address: u16) -> Option<&'static str> {
+    if (0xE000..=0xFDFF).contains(&address) {
+        Some("echo RAM (0xE000-0xFDFF)")
+    } else if (0xFEA0..=0xFEFF).contains(&address) {
+        Some("the unusable region (0xFEA0-0xFEFF)")
+    } else {
+        None
+    }
+}
+
+/// Checks every absolute address accessed against the memory map, given as (source description, instructions, spans).
+/// Writes to ROM are checked against the registers of the mapper, the value written is validated when it was
+/// loaded into `a` as a constant by the previous instructions.
+pub(crate) fn validate_addresses(
+    sources: &[(String, &[Instruction], &[Span])],
+    constants: &HashMap<String, i64>,
+    mapper: Mapper,
+) -> Vec<LintError> {
+    let mut errors = vec![];
+    for (source, instructions, spans) in sources {
+        let mut a = None;
+        for (i, instruction) in instructions.iter().enumerate() {
+            let access = memory_access(instruction, constants);
+            let known_a = a;
+            a = match instruction {
+                Instruction::LdR8I8(Reg8::A, expr) => expr
+                    .run(constants)
+                    .ok()
+                    .and_then(|value| u8::try_from(value).ok()),
+                Instruction::XorR8(Reg8::A) => Some(0),
+                Instruction::LdMI16Ra(_)
+                | Instruction::LdhMI8Ra(_)
+                | Instruction::EmptyLine
+                | Instruction::Equ(..) => a,
+                _ => None,
+            };
+
+            let (access, address) = match access {
+                Some(access) => access,
+                None => continue,
+            };
+            let (message, warning) = if let Some(region) = illegal_region(address) {
+                let message = format!(
+                    "0x{:04X} is in {}, it must not be accessed",
+                    address, region
+                );
+                (message, false)
+            } else if access == Access::Write && address <= 0x7FFF {
+                let result = match (known_a, mapper.register(address)) {
+                    (Some(value), _) => mapper
+                        .validate_write(address, value)
+                        .map(|()| mapper.write_warning(address, value)),
+                    (None, Some(_)) => Ok(None),
+                    // fails regardless of the value as there is no register
+                    (None, None) => mapper.validate_write(address, 0).map(|()| None),
+                };
+                match result {
+                    Ok(None) => continue,
+                    Ok(Some(warning)) => (
+                        format!("Write to 0x{:04X} in ROM: {}", address, warning),
+                        true,
+                    ),
+                    Err(err) => (
+                        format!("Invalid write to 0x{:04X} in ROM: {}", address, err),
+                        false,
+                    ),
+                }
+            } else {
+                continue;
+            };
+            errors.push(LintError {
+                source: source.clone(),
+                line: Span::line_of(spans, i),
+                message,
+                warning,
+            });
+        }
+    }
+    errors
+}
+
+/// Runs the lints of the profile over every set of instructions, given as (source description, instructions, spans).
+pub(crate) fn lint(
+    profile: LintProfile,
+    sources: &[(String, &[Instruction], &[Span])],
+    constants: &HashMap<String, i64>,
+) -> Vec<LintError> {
+    let mut errors = vec![];
+    if profile == LintProfile::Default {
+        return errors;
+    }
+
+    let written: HashSet<u16> = sources
+        .iter()
+        .flat_map(|(_, instructions, _)| instructions.iter())
+        .filter_map(|instruction| memory_access(instruction, constants))
+        .filter(|(access, _)| *access == Access::Write)
+        .map(|(_, address)| address)
+        .collect();
+    let written_regions = pointer_writes(sources, constants);
+
+    for (source, instructions, spans) in sources {
+        for (i, instruction) in instructions.iter().enumerate() {
+            let (access, address) = match memory_access(instruction, constants) {
+                Some(access) => access,
+                None => continue,
+            };
+            let message = if access == Access::Write && address == 0xFF41 {
+                "Writing to rSTAT (0xFF41) can trigger a spurious STAT interrupt on DMG hardware"
+                    .to_string()
+            } else if access == Access::Read
+                && !written.contains(&address)
+                && ram_region(address).is_some_and(|region| !written_regions.contains(&region))
+            {
+                format!(
+                    "0x{:04X} is read but never written, RAM is uninitialized on real hardware",
+                    address
+                )
+            } else {
+                continue;
+            };
+            errors.push(LintError {
+                source: source.clone(),
+                line: Span::line_of(spans, i),
+                message,
+                warning: true,
+            });
+        }
+    }
+    errors
+}
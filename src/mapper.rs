@@ -0,0 +1,153 @@
+//! Memory bank controllers, the hardware on a cartridge that receives writes to ROM addresses.
+
+use anyhow::{bail, Error};
+
+use crate::header::CartridgeType;
+
+/// The memory bank controller of a cartridge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mapper {
+    /// No memory bank controller, writes to ROM have no effect.
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    /// A memory bank controller whose registers are not validated.
+    Other,
+}
+
+impl Mapper {
+    /// Returns the mapper used by the cartridge type.
+    pub fn from_cartridge_type(cartridge_type: &CartridgeType) -> Mapper {
+        match cartridge_type {
+            CartridgeType::RomOnly | CartridgeType::RomRam | CartridgeType::RomRamBattery => {
+                Mapper::None
+            }
+            CartridgeType::Mbc1 | CartridgeType::Mbc1Ram | CartridgeType::Mbc1RamBattery => {
+                Mapper::Mbc1
+            }
+            CartridgeType::Mbc2 | CartridgeType::Mbc2Battery => Mapper::Mbc2,
+            CartridgeType::Mbc3TimerBattery
+            | CartridgeType::Mbc3TimerRamBattery
+            | CartridgeType::Mbc3
+            | CartridgeType::Mbc3Ram
+            | CartridgeType::Mbc3RamBattery => Mapper::Mbc3,
+            CartridgeType::Mbc5
+            | CartridgeType::Mbc5Ram
+            | CartridgeType::Mbc5RamBattery
+            | CartridgeType::Mbc5Rumble
+            | CartridgeType::Mbc5RumbleRam
+            | CartridgeType::Mbc5RumbleRamBattery => Mapper::Mbc5,
+            _ => Mapper::Other,
+        }
+    }
+
+    /// Returns the register written to by a write to the ROM address.
+    pub fn register(&self, address: u16) -> Option<Register> {
+        let register = match (self, address) {
+            (Mapper::None, _) | (_, 0x8000..=0xFFFF) => return None,
+            (Mapper::Other, _) => Register::Unknown,
+            (Mapper::Mbc2, 0x4000..=0x7FFF) => return None,
+            (Mapper::Mbc2, _) if address & 0x0100 == 0 => Register::RamEnable,
+            (Mapper::Mbc2, _) => Register::RomBank,
+            (_, 0x0000..=0x1FFF) => Register::RamEnable,
+            (Mapper::Mbc5, 0x2000..=0x2FFF) => Register::RomBankLow,
+            (Mapper::Mbc5, 0x3000..=0x3FFF) => Register::RomBankHigh,
+            (_, 0x2000..=0x3FFF) => Register::RomBank,
+            (_, 0x4000..=0x5FFF) => Register::RamBank,
+            (Mapper::Mbc1, _) => Register::BankingMode,
+            (Mapper::Mbc3, _) => Register::ClockLatch,
+            (Mapper::Mbc5, _) => return None,
+        };
+        Some(register)
+    }
+
+    /// Checks the value written to the ROM address is valid for the register at that address.
+    /// Values that are valid but likely a mistake are reported by [Mapper::write_warning] instead.
+    pub fn validate_write(&self, address: u16, value: u8) -> Result<(), Error> {
+        let register = match self.register(address) {
+            Some(register) => register,
+            None if *self == Mapper::None => bail!("there is no MBC to receive the write"),
+            None => bail!("there is no {:?} register at this address", self),
+        };
+        let max = match (self, register) {
+            (Mapper::Mbc1, Register::RomBank) => 0x1F,
+            (Mapper::Mbc1, Register::RamBank) => 0x03,
+            (Mapper::Mbc1, Register::BankingMode) => 0x01,
+            (Mapper::Mbc2, Register::RomBank) => 0x0F,
+            (Mapper::Mbc3, Register::RomBank) => 0x7F,
+            (Mapper::Mbc3, Register::RamBank) => {
+                if value <= 0x03 || (0x08..=0x0C).contains(&value) {
+                    return Ok(());
+                }
+                bail!(
+                    "0x{:02X} is not a valid value for the Mbc3 RAM bank register, must be 0x00-0x03 or an RTC register 0x08-0x0C",
+                    value
+                );
+            }
+            (Mapper::Mbc3, Register::ClockLatch) => 0x01,
+            (Mapper::Mbc5, Register::RomBankHigh) => 0x01,
+            (Mapper::Mbc5, Register::RamBank) => 0x0F,
+            _ => return Ok(()),
+        };
+        if value > max {
+            bail!(
+                "0x{:02X} is too large for the {:?} {}, the maximum is 0x{:02X}",
+                value,
+                self,
+                register.name(),
+                max
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns a warning when a valid write to the ROM address likely does not do what was intended,
+    /// i.e. writing bank 0 to a ROM bank register that selects bank 1 instead.
+    /// Writing 0 is a common way to select bank 1, so it is not an error.
+    pub fn write_warning(&self, address: u16, value: u8) -> Option<String> {
+        match (self, self.register(address)?) {
+            (Mapper::Mbc1 | Mapper::Mbc2 | Mapper::Mbc3, Register::RomBank) if value == 0 => {
+                Some(format!(
+                    "writing bank 0 to the {:?} {} selects bank 1",
+                    self,
+                    Register::RomBank.name()
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A memory bank controller register, selected by the address written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    RamEnable,
+    RomBank,
+    /// The lower 8 bits of the MBC5 ROM bank.
+    RomBankLow,
+    /// The 9th bit of the MBC5 ROM bank.
+    RomBankHigh,
+    /// Also selects the upper ROM bank bits on MBC1 and the RTC register on MBC3.
+    RamBank,
+    BankingMode,
+    ClockLatch,
+    /// A register of a mapper that is not validated.
+    Unknown,
+}
+
+impl Register {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Register::RamEnable => "RAM enable register",
+            Register::RomBank => "ROM bank register",
+            Register::RomBankLow => "ROM bank low register",
+            Register::RomBankHigh => "ROM bank high register",
+            Register::RamBank => "RAM bank register",
+            Register::BankingMode => "banking mode register",
+            Register::ClockLatch => "clock latch register",
+            Register::Unknown => "MBC register",
+        }
+    }
+}
@@ -0,0 +1,591 @@
+//! The opcode table shared by the encoder, the disassembler and instruction lengths.
+//!
+//! Every cpu instruction is described by an [Encoding]: the opcode, whether it uses the 0xCB prefix and
+//! how its operand is encoded.
+//! Adding or fixing an instruction only requires changing [Instruction::encoding].
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use anyhow::{bail, Error};
+use arrayvec::ArrayVec;
+
+use crate::address::GlobalAddr;
+use crate::ast::*;
+
+/// How the operand of an instruction is encoded.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Operand<'a, S = String> {
+    None,
+    /// A byte following the opcode.
+    I8(&'a Expr<S>),
+    /// A signed byte following the opcode, from -128 to 127.
+    S8(&'a Expr<S>),
+    /// Two little endian bytes following the opcode.
+    I16(&'a Expr<S>),
+    /// A signed byte following the opcode, relative to the address after the instruction.
+    Rel8(&'a Expr<S>),
+    /// A bit index stored in bits 3-5 of the opcode.
+    Bit(&'a Expr<S>),
+    /// A restart vector stored in bits 3-5 of the opcode.
+    Rst(&'a Expr<S>),
+    /// A nop following the opcode, used by halt to avoid the halt bug and to pad stop.
+    Nop,
+}
+
+impl<'a, S> Operand<'a, S> {
+    /// Returns the expression of the operand if it has one.
+    pub fn expr(&self) -> Option<&'a Expr<S>> {
+        match self {
+            Operand::I8(expr)
+            | Operand::S8(expr)
+            | Operand::I16(expr)
+            | Operand::Rel8(expr)
+            | Operand::Bit(expr)
+            | Operand::Rst(expr) => Some(expr),
+            Operand::None | Operand::Nop => None,
+        }
+    }
+}
+
+/// The machine code encoding of a cpu instruction.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Encoding<'a, S = String> {
+    /// The instruction is prefixed by 0xCB
+    pub prefixed: bool,
+    /// The opcode before any bit index or restart vector is applied
+    pub opcode: u8,
+    pub operand: Operand<'a, S>,
+}
+
+impl<'a, S> Encoding<'a, S> {
+    fn new(opcode: u8, operand: Operand<'a, S>) -> Encoding<'a, S> {
+        Encoding {
+            prefixed: false,
+            opcode,
+            operand,
+        }
+    }
+
+    fn prefixed(opcode: u8, operand: Operand<'a, S>) -> Encoding<'a, S> {
+        Encoding {
+            prefixed: true,
+            opcode,
+            operand,
+        }
+    }
+
+    /// Returns how many bytes the instruction takes up
+    pub fn bytes_len(&self) -> u16 {
+        let operand = match self.operand {
+            Operand::None | Operand::Bit(_) | Operand::Rst(_) => 0,
+            Operand::I8(_) | Operand::S8(_) | Operand::Rel8(_) | Operand::Nop => 1,
+            Operand::I16(_) => 2,
+        };
+        1 + self.prefixed as u16 + operand
+    }
+}
+
+impl Encoding<'_> {
+    /// Returns the opcode with the bit index or restart vector applied.
+    pub fn opcode(&self, constants: &HashMap<String, i64>) -> Result<u8, Error> {
+        Ok(match self.operand {
+            Operand::Bit(expr) => self.opcode | expr.get_bit_index(constants)? << 3,
+            Operand::Rst(expr) => {
+                let vector = expr.run(constants)?;
+                if !(0..=0x38).contains(&vector) || vector % 8 != 0 {
+                    bail!("0x{:x} is not a valid rst vector, it must be one of 0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30 or 0x38", vector);
+                }
+                self.opcode | vector as u8
+            }
+            _ => self.opcode,
+        })
+    }
+
+    /// Writes the encoded instruction to the end of the rom.
+    pub fn write(&self, rom: &mut Vec<u8>, constants: &HashMap<String, i64>) -> Result<(), Error> {
+        rom.extend(self.encode(rom.len() as u32, constants)?);
+        Ok(())
+    }
+
+    /// Returns the bytes of the instruction when it starts at address, an address within the entire rom.
+    pub fn encode(
+        &self,
+        address: u32,
+        constants: &HashMap<String, i64>,
+    ) -> Result<ArrayVec<u8, 3>, Error> {
+        let mut bytes = ArrayVec::new();
+        let opcode = self.opcode(constants)?;
+        if self.prefixed {
+            bytes.push(0xCB);
+        }
+        bytes.push(opcode);
+        match self.operand {
+            Operand::None | Operand::Bit(_) | Operand::Rst(_) => {}
+            Operand::I8(expr) => bytes.push(expr.get_byte(constants)?),
+            Operand::S8(expr) => bytes.push(expr.get_signed_byte(constants)?),
+            Operand::I16(expr) => bytes.extend(expr.get_2bytes(constants)?),
+            Operand::Rel8(expr) => {
+                let abs_dest = expr.run(constants)?;
+                // only the operand byte of the instruction remains after the opcode,
+                // the destination is an address as seen by the cpu so the operand is compared as one too
+                let operand = GlobalAddr(address + bytes.len() as u32).bank_addr();
+                let rel_dest = abs_dest - operand.0 as i64 - 1;
+                match signed_byte(rel_dest) {
+                    Ok(byte) => bytes.push(byte),
+                    Err(_) => bail!(
+                        "jr cannot reach 0x{:x}, it is {} bytes away but jr can only jump from -128 to 127 bytes, use jp instead",
+                        abs_dest,
+                        rel_dest
+                    ),
+                }
+            }
+            Operand::Nop => bytes.push(0x00),
+        }
+        Ok(bytes)
+    }
+}
+
+impl<S> Instruction<S> {
+    /// Returns the machine code encoding of the instruction.
+    /// Returns None for directives and pseudo instructions which are not a single cpu instruction.
+    pub fn encoding(&self) -> Option<Encoding<'_, S>> {
+        use Operand::*;
+        let op = Encoding::new;
+        let cb = Encoding::prefixed;
+        Some(match self {
+            Instruction::Nop => op(0x00, None),
+            Instruction::Stop => op(0x10, None),
+            Instruction::StopPadded => op(0x10, Nop),
+            Instruction::Halt => op(0x76, Nop),
+            Instruction::HaltUnpadded => op(0x76, None),
+            Instruction::Di => op(0xF3, None),
+            Instruction::Ei => op(0xFB, None),
+            Instruction::Rrca => op(0x0F, None),
+            Instruction::Rra => op(0x1F, None),
+            Instruction::Cpl => op(0x2F, None),
+            Instruction::Ccf => op(0x3F, None),
+            Instruction::Rlca => op(0x07, None),
+            Instruction::Rla => op(0x17, None),
+            Instruction::Daa => op(0x27, None),
+            Instruction::Scf => op(0x37, None),
+            Instruction::Ret(Flag::Always) => op(0xC9, None),
+            Instruction::Ret(flag) => op(0xC0 | condition_bits(flag), None),
+            Instruction::Reti => op(0xD9, None),
+            Instruction::Call(Flag::Always, expr) => op(0xCD, I16(expr)),
+            Instruction::Call(flag, expr) => op(0xC4 | condition_bits(flag), I16(expr)),
+            Instruction::Rst(expr) => op(0xC7, Rst(expr)),
+            Instruction::JpI16(Flag::Always, expr) => op(0xC3, I16(expr)),
+            Instruction::JpI16(flag, expr) => op(0xC2 | condition_bits(flag), I16(expr)),
+            Instruction::JpRhl => op(0xE9, None),
+            Instruction::Jr(Flag::Always, expr) => op(0x18, Rel8(expr)),
+            Instruction::Jr(flag, expr) => op(0x20 | condition_bits(flag), Rel8(expr)),
+            Instruction::IncR16(reg) => op(0x03 | reg16_bits(reg), None),
+            Instruction::IncR8(reg) => op(0x04 | reg8_bits(reg) << 3, None),
+            Instruction::IncMRhl => op(0x34, None),
+            Instruction::DecR16(reg) => op(0x0B | reg16_bits(reg), None),
+            Instruction::DecR8(reg) => op(0x05 | reg8_bits(reg) << 3, None),
+            Instruction::DecMRhl => op(0x35, None),
+            Instruction::AddR8(reg) => op(0x80 | reg8_bits(reg), None),
+            Instruction::AddMRhl => op(0x86, None),
+            Instruction::AddI8(expr) => op(0xC6, I8(expr)),
+            Instruction::AddRhlR16(reg) => op(0x09 | reg16_bits(reg), None),
+            Instruction::AddRspI8(expr) => op(0xE8, S8(expr)),
+            Instruction::AdcR8(reg) => op(0x88 | reg8_bits(reg), None),
+            Instruction::AdcMRhl => op(0x8E, None),
+            Instruction::AdcI8(expr) => op(0xCE, I8(expr)),
+            Instruction::SubR8(reg) => op(0x90 | reg8_bits(reg), None),
+            Instruction::SubMRhl => op(0x96, None),
+            Instruction::SubI8(expr) => op(0xD6, I8(expr)),
+            Instruction::SbcR8(reg) => op(0x98 | reg8_bits(reg), None),
+            Instruction::SbcMRhl => op(0x9E, None),
+            Instruction::SbcI8(expr) => op(0xDE, I8(expr)),
+            Instruction::AndR8(reg) => op(0xA0 | reg8_bits(reg), None),
+            Instruction::AndMRhl => op(0xA6, None),
+            Instruction::AndI8(expr) => op(0xE6, I8(expr)),
+            Instruction::XorR8(reg) => op(0xA8 | reg8_bits(reg), None),
+            Instruction::XorMRhl => op(0xAE, None),
+            Instruction::XorI8(expr) => op(0xEE, I8(expr)),
+            Instruction::OrR8(reg) => op(0xB0 | reg8_bits(reg), None),
+            Instruction::OrMRhl => op(0xB6, None),
+            Instruction::OrI8(expr) => op(0xF6, I8(expr)),
+            Instruction::CpR8(reg) => op(0xB8 | reg8_bits(reg), None),
+            Instruction::CpMRhl => op(0xBE, None),
+            Instruction::CpI8(expr) => op(0xFE, I8(expr)),
+            Instruction::LdR16I16(reg, expr) => op(0x01 | reg16_bits(reg), I16(expr)),
+            Instruction::LdMI16Rsp(expr) => op(0x08, I16(expr)),
+            Instruction::LdMRbcRa => op(0x02, None),
+            Instruction::LdMRdeRa => op(0x12, None),
+            Instruction::LdRaMRbc => op(0x0A, None),
+            Instruction::LdRaMRde => op(0x1A, None),
+            Instruction::LdR8R8(reg_in, reg_out) => {
+                op(0x40 | reg8_bits(reg_in) << 3 | reg8_bits(reg_out), None)
+            }
+            Instruction::LdR8I8(reg, expr) => op(0x06 | reg8_bits(reg) << 3, I8(expr)),
+            Instruction::LdR8MRhl(reg) => op(0x46 | reg8_bits(reg) << 3, None),
+            Instruction::LdMRhlR8(reg) => op(0x70 | reg8_bits(reg), None),
+            Instruction::LdMRhlI8(expr) => op(0x36, I8(expr)),
+            Instruction::LdMI16Ra(expr) => op(0xEA, I16(expr)),
+            Instruction::LdRaMI16(expr) => op(0xFA, I16(expr)),
+            Instruction::LdhRaMI8(expr) => op(0xF0, I8(expr)),
+            Instruction::LdhMI8Ra(expr) => op(0xE0, I8(expr)),
+            Instruction::LdhRaMRc => op(0xF2, None),
+            Instruction::LdhMRcRa => op(0xE2, None),
+            Instruction::LdiMRhlRa => op(0x22, None),
+            Instruction::LddMRhlRa => op(0x32, None),
+            Instruction::LdiRaMRhl => op(0x2A, None),
+            Instruction::LddRaMRhl => op(0x3A, None),
+            Instruction::LdRhlRspI8(expr) => op(0xF8, S8(expr)),
+            Instruction::LdRspRhl => op(0xF9, None),
+            Instruction::Push(reg) => op(0xC5 | reg16_push_bits(reg), None),
+            Instruction::Pop(reg) => op(0xC1 | reg16_push_bits(reg), None),
+            Instruction::RlcR8(reg) => cb(reg8_bits(reg), None),
+            Instruction::RlcMRhl => cb(0x06, None),
+            Instruction::RrcR8(reg) => cb(0x08 | reg8_bits(reg), None),
+            Instruction::RrcMRhl => cb(0x0E, None),
+            Instruction::RlR8(reg) => cb(0x10 | reg8_bits(reg), None),
+            Instruction::RlMRhl => cb(0x16, None),
+            Instruction::RrR8(reg) => cb(0x18 | reg8_bits(reg), None),
+            Instruction::RrMRhl => cb(0x1E, None),
+            Instruction::SlaR8(reg) => cb(0x20 | reg8_bits(reg), None),
+            Instruction::SlaMRhl => cb(0x26, None),
+            Instruction::SraR8(reg) => cb(0x28 | reg8_bits(reg), None),
+            Instruction::SraMRhl => cb(0x2E, None),
+            Instruction::SwapR8(reg) => cb(0x30 | reg8_bits(reg), None),
+            Instruction::SwapMRhl => cb(0x36, None),
+            Instruction::SrlR8(reg) => cb(0x38 | reg8_bits(reg), None),
+            Instruction::SrlMRhl => cb(0x3E, None),
+            Instruction::BitBitR8(bit, reg) => cb(0x40 | reg8_bits(reg), Bit(bit)),
+            Instruction::BitBitMRhl(bit) => cb(0x46, Bit(bit)),
+            Instruction::ResBitR8(bit, reg) => cb(0x80 | reg8_bits(reg), Bit(bit)),
+            Instruction::ResBitMRhl(bit) => cb(0x86, Bit(bit)),
+            Instruction::SetBitR8(bit, reg) => cb(0xC0 | reg8_bits(reg), Bit(bit)),
+            Instruction::SetBitMRhl(bit) => cb(0xC6, Bit(bit)),
+            _ => return Option::None,
+        })
+    }
+
+    /// Returns the mnemonic of the instruction e.g. `ld` or `jr`.
+    /// Returns None for directives and pseudo instructions which are not a single cpu instruction.
+    pub fn mnemonic(&self) -> Option<&'static str> {
+        Some(match self {
+            Instruction::Nop => "nop",
+            Instruction::Stop | Instruction::StopPadded => "stop",
+            Instruction::Halt | Instruction::HaltUnpadded => "halt",
+            Instruction::Di => "di",
+            Instruction::Ei => "ei",
+            Instruction::Rrca => "rrca",
+            Instruction::Rra => "rra",
+            Instruction::Cpl => "cpl",
+            Instruction::Ccf => "ccf",
+            Instruction::Rlca => "rlca",
+            Instruction::Rla => "rla",
+            Instruction::Daa => "daa",
+            Instruction::Scf => "scf",
+            Instruction::Ret(_) => "ret",
+            Instruction::Reti => "reti",
+            Instruction::Call(..) => "call",
+            Instruction::Rst(_) => "rst",
+            Instruction::JpI16(..) | Instruction::JpRhl => "jp",
+            Instruction::Jr(..) => "jr",
+            Instruction::IncR16(_) | Instruction::IncR8(_) | Instruction::IncMRhl => "inc",
+            Instruction::DecR16(_) | Instruction::DecR8(_) | Instruction::DecMRhl => "dec",
+            Instruction::AddR8(_)
+            | Instruction::AddMRhl
+            | Instruction::AddI8(_)
+            | Instruction::AddRhlR16(_)
+            | Instruction::AddRspI8(_) => "add",
+            Instruction::AdcR8(_) | Instruction::AdcMRhl | Instruction::AdcI8(_) => "adc",
+            Instruction::SubR8(_) | Instruction::SubMRhl | Instruction::SubI8(_) => "sub",
+            Instruction::SbcR8(_) | Instruction::SbcMRhl | Instruction::SbcI8(_) => "sbc",
+            Instruction::AndR8(_) | Instruction::AndMRhl | Instruction::AndI8(_) => "and",
+            Instruction::XorR8(_) | Instruction::XorMRhl | Instruction::XorI8(_) => "xor",
+            Instruction::OrR8(_) | Instruction::OrMRhl | Instruction::OrI8(_) => "or",
+            Instruction::CpR8(_) | Instruction::CpMRhl | Instruction::CpI8(_) => "cp",
+            Instruction::LdR16I16(..)
+            | Instruction::LdMI16Rsp(_)
+            | Instruction::LdMRbcRa
+            | Instruction::LdMRdeRa
+            | Instruction::LdRaMRbc
+            | Instruction::LdRaMRde
+            | Instruction::LdR8R8(..)
+            | Instruction::LdR8I8(..)
+            | Instruction::LdR8MRhl(_)
+            | Instruction::LdMRhlR8(_)
+            | Instruction::LdMRhlI8(_)
+            | Instruction::LdMI16Ra(_)
+            | Instruction::LdRaMI16(_)
+            | Instruction::LdRhlRspI8(_)
+            | Instruction::LdRspRhl => "ld",
+            Instruction::LdhRaMI8(_)
+            | Instruction::LdhMI8Ra(_)
+            | Instruction::LdhRaMRc
+            | Instruction::LdhMRcRa => "ldh",
+            Instruction::LdiMRhlRa | Instruction::LdiRaMRhl => "ldi",
+            Instruction::LddMRhlRa | Instruction::LddRaMRhl => "ldd",
+            Instruction::Push(_) => "push",
+            Instruction::Pop(_) => "pop",
+            Instruction::RlcR8(_) | Instruction::RlcMRhl => "rlc",
+            Instruction::RrcR8(_) | Instruction::RrcMRhl => "rrc",
+            Instruction::RlR8(_) | Instruction::RlMRhl => "rl",
+            Instruction::RrR8(_) | Instruction::RrMRhl => "rr",
+            Instruction::SlaR8(_) | Instruction::SlaMRhl => "sla",
+            Instruction::SraR8(_) | Instruction::SraMRhl => "sra",
+            Instruction::SwapR8(_) | Instruction::SwapMRhl => "swap",
+            Instruction::SrlR8(_) | Instruction::SrlMRhl => "srl",
+            Instruction::BitBitR8(..) | Instruction::BitBitMRhl(_) => "bit",
+            Instruction::ResBitR8(..) | Instruction::ResBitMRhl(_) => "res",
+            Instruction::SetBitR8(..) | Instruction::SetBitMRhl(_) => "set",
+            _ => return Option::None,
+        })
+    }
+}
+
+/// How many machine cycles a cpu instruction takes, each machine cycle is 4 clock cycles.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Cycles {
+    /// when the condition of a jump, call or return is met, otherwise the cycles of the instruction
+    pub taken: u8,
+    /// when the condition of a jump, call or return is not met, otherwise the cycles of the instruction
+    pub not_taken: u8,
+}
+
+impl Cycles {
+    fn new(cycles: u8) -> Cycles {
+        Cycles {
+            taken: cycles,
+            not_taken: cycles,
+        }
+    }
+
+    /// Returns the cycles of a jump, call or return whose flag is Always or a condition.
+    fn branch(flag: &Flag, taken: u8, not_taken: u8) -> Cycles {
+        match flag {
+            Flag::Always => Cycles::new(taken),
+            _ => Cycles { taken, not_taken },
+        }
+    }
+}
+
+impl<S> Instruction<S> {
+    /// Returns how many machine cycles the instruction takes.
+    /// Halt and stop include the nop they may be padded with, halt is counted until the cpu stops.
+    /// Returns None for directives and pseudo instructions which are not a single cpu instruction.
+    pub fn cycles(&self) -> Option<Cycles> {
+        let cycles = match self {
+            Instruction::Ret(Flag::Always) => Cycles::new(4),
+            Instruction::Ret(_) => Cycles {
+                taken: 5,
+                not_taken: 2,
+            },
+            Instruction::Reti | Instruction::Rst(_) => Cycles::new(4),
+            Instruction::Call(flag, _) => Cycles::branch(flag, 6, 3),
+            Instruction::JpI16(flag, _) => Cycles::branch(flag, 4, 3),
+            Instruction::Jr(flag, _) => Cycles::branch(flag, 3, 2),
+            Instruction::JpRhl => Cycles::new(1),
+            Instruction::IncR16(_) | Instruction::DecR16(_) => Cycles::new(2),
+            Instruction::IncMRhl | Instruction::DecMRhl => Cycles::new(3),
+            Instruction::AddRhlR16(_) => Cycles::new(2),
+            Instruction::AddRspI8(_) => Cycles::new(4),
+            Instruction::LdR16I16(..) => Cycles::new(3),
+            Instruction::LdMI16Rsp(_) => Cycles::new(5),
+            Instruction::LdMRhlI8(_) => Cycles::new(3),
+            Instruction::LdMI16Ra(_) | Instruction::LdRaMI16(_) => Cycles::new(4),
+            Instruction::LdhRaMI8(_) | Instruction::LdhMI8Ra(_) => Cycles::new(3),
+            Instruction::LdRhlRspI8(_) => Cycles::new(3),
+            Instruction::LdRspRhl => Cycles::new(2),
+            Instruction::Push(_) => Cycles::new(4),
+            Instruction::Pop(_) => Cycles::new(3),
+            Instruction::BitBitMRhl(_) => Cycles::new(3),
+            instruction => {
+                let encoding = instruction.encoding()?;
+                // the remaining instructions take a cycle per byte, apart from the nop padding which is its own
+                // instruction, and a cycle for each access to [hl] or [r16] or when writing back to [hl]
+                let memory = match instruction {
+                    Instruction::RlcMRhl
+                    | Instruction::RrcMRhl
+                    | Instruction::RlMRhl
+                    | Instruction::RrMRhl
+                    | Instruction::SlaMRhl
+                    | Instruction::SraMRhl
+                    | Instruction::SwapMRhl
+                    | Instruction::SrlMRhl
+                    | Instruction::ResBitMRhl(_)
+                    | Instruction::SetBitMRhl(_) => 2,
+                    Instruction::AddMRhl
+                    | Instruction::SubMRhl
+                    | Instruction::AndMRhl
+                    | Instruction::OrMRhl
+                    | Instruction::AdcMRhl
+                    | Instruction::SbcMRhl
+                    | Instruction::XorMRhl
+                    | Instruction::CpMRhl
+                    | Instruction::LdMRbcRa
+                    | Instruction::LdMRdeRa
+                    | Instruction::LdRaMRbc
+                    | Instruction::LdRaMRde
+                    | Instruction::LdR8MRhl(_)
+                    | Instruction::LdMRhlR8(_)
+                    | Instruction::LdhRaMRc
+                    | Instruction::LdhMRcRa
+                    | Instruction::LdiMRhlRa
+                    | Instruction::LddMRhlRa
+                    | Instruction::LdiRaMRhl
+                    | Instruction::LddRaMRhl => 1,
+                    _ => 0,
+                };
+                Cycles::new(encoding.bytes_len() as u8 + memory)
+            }
+        };
+        Some(cycles)
+    }
+}
+
+/// The instruction for each opcode, as looked up by the disassembler.
+/// Operands are [Expr::Const] set to 0 except for bit indexes and restart vectors which are part of the opcode.
+pub struct DecodeTable {
+    pub unprefixed: Vec<Option<Instruction>>,
+    pub prefixed: Vec<Option<Instruction>>,
+}
+
+/// Returns the decode table, built from [Instruction::encoding] on first use.
+pub fn decode_table() -> &'static DecodeTable {
+    static TABLE: OnceLock<DecodeTable> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = DecodeTable {
+            unprefixed: vec![None; 0x100],
+            prefixed: vec![None; 0x100],
+        };
+        let constants = HashMap::new();
+        for instruction in every_instruction() {
+            let encoding = instruction.encoding().unwrap();
+            let opcode = encoding.opcode(&constants).unwrap() as usize;
+            let entry = if encoding.prefixed {
+                &mut table.prefixed[opcode]
+            } else {
+                &mut table.unprefixed[opcode]
+            };
+            assert!(entry.is_none(), "opcode 0x{:x} is used twice", opcode);
+            *entry = Some(instruction);
+        }
+        table
+    })
+}
+
+/// Returns one instance of every distinct opcode.
+fn every_instruction() -> Vec<Instruction> {
+    use Instruction::*;
+    let zero = || Expr::Const(0);
+    let reg8s = [
+        Reg8::A,
+        Reg8::B,
+        Reg8::C,
+        Reg8::D,
+        Reg8::E,
+        Reg8::H,
+        Reg8::L,
+    ];
+    let reg16s = [Reg16::BC, Reg16::DE, Reg16::HL, Reg16::SP];
+    let reg16_pushes = [Reg16Push::BC, Reg16Push::DE, Reg16Push::HL, Reg16Push::AF];
+    let flags = [Flag::Always, Flag::Z, Flag::NZ, Flag::C, Flag::NC];
+
+    let mut result = vec![
+        Nop, Stop, Halt, Di, Ei, Rrca, Rra, Cpl, Ccf, Rlca, Rla, Daa, Scf, Reti, JpRhl, IncMRhl,
+        DecMRhl, AddMRhl, AdcMRhl, SubMRhl, SbcMRhl, AndMRhl, XorMRhl, OrMRhl, CpMRhl, LdMRbcRa,
+        LdMRdeRa, LdRaMRbc, LdRaMRde, LdhRaMRc, LdhMRcRa, LdiMRhlRa, LddMRhlRa, LdiRaMRhl,
+        LddRaMRhl, LdRspRhl, RlcMRhl, RrcMRhl, RlMRhl, RrMRhl, SlaMRhl, SraMRhl, SwapMRhl, SrlMRhl,
+    ];
+    for expr in [
+        AddI8, AddRspI8, AdcI8, SubI8, SbcI8, AndI8, XorI8, OrI8, CpI8, LdMI16Rsp, LdMRhlI8,
+        LdMI16Ra, LdRaMI16, LdhRaMI8, LdhMI8Ra, LdRhlRspI8,
+    ] {
+        result.push(expr(zero()));
+    }
+    for flag in flags {
+        result.push(Ret(flag.clone()));
+        result.push(Call(flag.clone(), zero()));
+        result.push(JpI16(flag.clone(), zero()));
+        result.push(Jr(flag, zero()));
+    }
+    for vector in 0..8 {
+        result.push(Rst(Expr::Const(vector * 8)));
+    }
+    for reg in reg16s {
+        result.push(IncR16(reg.clone()));
+        result.push(DecR16(reg.clone()));
+        result.push(AddRhlR16(reg.clone()));
+        result.push(LdR16I16(reg, zero()));
+    }
+    for reg in reg16_pushes {
+        result.push(Push(reg.clone()));
+        result.push(Pop(reg));
+    }
+    for reg in reg8s.clone() {
+        for r8 in [
+            IncR8, DecR8, AddR8, AdcR8, SubR8, SbcR8, AndR8, XorR8, OrR8, CpR8, LdR8MRhl, LdMRhlR8,
+            RlcR8, RrcR8, RlR8, RrR8, SlaR8, SraR8, SwapR8, SrlR8,
+        ] {
+            result.push(r8(reg.clone()));
+        }
+        result.push(LdR8I8(reg.clone(), zero()));
+        for reg_out in reg8s.clone() {
+            result.push(LdR8R8(reg.clone(), reg_out));
+        }
+    }
+    for bit in 0..8 {
+        let bit = || Expr::Const(bit);
+        result.push(BitBitMRhl(bit()));
+        result.push(ResBitMRhl(bit()));
+        result.push(SetBitMRhl(bit()));
+        for reg in reg8s.clone() {
+            result.push(BitBitR8(bit(), reg.clone()));
+            result.push(ResBitR8(bit(), reg.clone()));
+            result.push(SetBitR8(bit(), reg));
+        }
+    }
+    result
+}
+
+/// The bits 3-4 of a conditional opcode, must not be called with Flag::Always.
+fn condition_bits(flag: &Flag) -> u8 {
+    match flag {
+        Flag::NZ => 0x00,
+        Flag::Z => 0x08,
+        Flag::NC => 0x10,
+        Flag::C => 0x18,
+        Flag::Always => unreachable!("unconditional instructions have their own opcode"),
+    }
+}
+
+/// The bits 0-2 of an opcode using an 8 bit register, 0x06 is used for [hl].
+pub(crate) fn reg8_bits(reg: &Reg8) -> u8 {
+    match reg {
+        Reg8::B => 0x00,
+        Reg8::C => 0x01,
+        Reg8::D => 0x02,
+        Reg8::E => 0x03,
+        Reg8::H => 0x04,
+        Reg8::L => 0x05,
+        Reg8::A => 0x07,
+    }
+}
+
+/// The bits 4-5 of an opcode using a 16 bit register.
+fn reg16_bits(reg: &Reg16) -> u8 {
+    match reg {
+        Reg16::BC => 0x00,
+        Reg16::DE => 0x10,
+        Reg16::HL => 0x20,
+        Reg16::SP => 0x30,
+    }
+}
+
+/// The bits 4-5 of a push or pop opcode.
+fn reg16_push_bits(reg: &Reg16Push) -> u8 {
+    match reg {
+        Reg16Push::BC => 0x00,
+        Reg16Push::DE => 0x10,
+        Reg16Push::HL => 0x20,
+        Reg16Push::AF => 0x30,
+    }
+}
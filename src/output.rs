@@ -0,0 +1,162 @@
+//! Writes the files produced by a build, see [crate::RomBuilder::add_output].
+//!
+//! The built-in writers produce the rom, a symbol file, a map file and an html report.
+//! Implement [OutputWriter] for custom packaging steps e.g. zipping the rom with its manual.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Error};
+
+use crate::address::GlobalAddr;
+use crate::constants::ROM_BANK_SIZE;
+use crate::layout::{Layout, RegionKind};
+use crate::symbols::SymbolKind;
+
+/// Receives the result of a build and writes it somewhere.
+pub trait OutputWriter {
+    /// Writes the output, dir is the root of the project.
+    /// Every symbol is listed in [Layout::symbols].
+    fn write(&self, dir: &Path, rom: &[u8], layout: &Layout) -> Result<(), Error>;
+}
+
+/// Writes the rom.
+pub struct GbFile(pub String);
+
+/// Writes the labels and RAM variables in the `BB:AAAA Name` format read by emulators such as BGB and SameBoy.
+pub struct SymFile(pub String);
+
+/// Writes a text description of what is in each rom bank and how much space is free.
+pub struct MapFile(pub String);
+
+/// Writes an html page with a table of the sections and symbols.
+pub struct HtmlFile(pub String);
+
+impl OutputWriter for GbFile {
+    fn write(&self, dir: &Path, rom: &[u8], _layout: &Layout) -> Result<(), Error> {
+        write_file(dir, &self.0, rom)
+    }
+}
+
+impl OutputWriter for SymFile {
+    fn write(&self, dir: &Path, _rom: &[u8], layout: &Layout) -> Result<(), Error> {
+        write_file(dir, &self.0, sym_file(layout).as_bytes())
+    }
+}
+
+impl OutputWriter for MapFile {
+    fn write(&self, dir: &Path, _rom: &[u8], layout: &Layout) -> Result<(), Error> {
+        write_file(dir, &self.0, map_file(layout).as_bytes())
+    }
+}
+
+impl OutputWriter for HtmlFile {
+    fn write(&self, dir: &Path, _rom: &[u8], layout: &Layout) -> Result<(), Error> {
+        write_file(dir, &self.0, html_file(layout).as_bytes())
+    }
+}
+
+fn write_file(dir: &Path, name: &str, contents: &[u8]) -> Result<(), Error> {
+    match fs::write(dir.join(name), contents) {
+        Ok(()) => Ok(()),
+        Err(err) => bail!("Cannot write {} because: {}", name, err),
+    }
+}
+
+/// Returns the contents of the file written by [SymFile].
+pub fn sym_file(layout: &Layout) -> String {
+    let mut sym = String::from("; File generated by ggbasm\n");
+    for symbol in &layout.symbols {
+        let (bank, address) = if symbol.label {
+            let address = GlobalAddr(symbol.value as u32);
+            (address.bank().0, address.bank_addr().0)
+        } else if symbol.kind == Some(SymbolKind::RamVariable) {
+            (0, symbol.value as u16)
+        } else {
+            continue;
+        };
+        writeln!(sym, "{:02X}:{:04X} {}", bank, address, symbol.name).unwrap();
+    }
+    sym
+}
+
+/// Returns the contents of the file written by [MapFile].
+pub fn map_file(layout: &Layout) -> String {
+    let mut map = String::new();
+    for bank in 0..layout.rom_size / ROM_BANK_SIZE {
+        let start = bank * ROM_BANK_SIZE;
+        let end = start + ROM_BANK_SIZE;
+        writeln!(map, "ROM Bank #{}:", bank).unwrap();
+        for section in &layout.sections {
+            if section.address >= start && section.address < end {
+                let address = GlobalAddr(section.address);
+                writeln!(
+                    map,
+                    "  ${:04X}-${:04X} (${:04X} bytes) {}",
+                    address.bank_addr().0,
+                    address.bank_addr().0 as u32 + section.size.max(1) - 1,
+                    section.size,
+                    section.source
+                )
+                .unwrap();
+            }
+        }
+        let free: u32 = layout
+            .regions
+            .iter()
+            .filter(|x| x.kind == RegionKind::Padding)
+            .map(|x| {
+                (x.address + x.size)
+                    .min(end)
+                    .saturating_sub(x.address.max(start))
+            })
+            .sum();
+        writeln!(map, "  Free: ${:04X} bytes", free).unwrap();
+        map.push('\n');
+    }
+    map
+}
+
+/// Returns the contents of the file written by [HtmlFile].
+pub fn html_file(layout: &Layout) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>ROM layout</title></head>\n<body>\n",
+    );
+    writeln!(html, "<h1>ROM layout, {} bytes</h1>", layout.rom_size).unwrap();
+
+    html.push_str(
+        "<h2>Sections</h2>\n<table>\n<tr><th>Address</th><th>Size</th><th>Source</th></tr>\n",
+    );
+    for section in &layout.sections {
+        writeln!(
+            html,
+            "<tr><td>0x{:X}</td><td>{}</td><td>{}</td></tr>",
+            section.address,
+            section.size,
+            escape(&section.source)
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Symbols</h2>\n<table>\n<tr><th>Name</th><th>Value</th></tr>\n");
+    for symbol in &layout.symbols {
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>0x{:X}</td></tr>",
+            escape(&symbol.name),
+            symbol.value
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
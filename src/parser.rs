@@ -1,17 +1,26 @@
 //! Parse asm files into an AST.
 
-use anyhow::{bail, Error};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, bail, Error};
 use byteorder::{LittleEndian, WriteBytesExt};
 use nom::branch::alt;
-use nom::bytes::complete::{is_a, is_not, tag, tag_no_case, take_while_m_n};
-use nom::character::complete::{char, line_ending};
-use nom::combinator::{map, opt, peek, value};
+use nom::bytes::complete::{is_a, is_not, tag, tag_no_case, take_while1};
+use nom::character::complete::{char, none_of};
+use nom::combinator::{eof, map, map_res, opt, peek, recognize, value, verify};
 use nom::error::VerboseError;
 use nom::multi::{many0, separated_list1};
-use nom::sequence::{delimited, terminated};
+use nom::sequence::{delimited, pair, preceded, terminated};
 use nom::IResult;
 
 use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::opcodes::Operand;
 
 static IDENT: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz1234567890_";
 static HEX: &str = "1234567890ABCDEFabcdef";
@@ -26,57 +35,63 @@ fn is_dec(input: char) -> bool {
     DEC.contains(input)
 }
 
-fn parse_u8_hex(i: &str) -> IResult<&str, u8, VerboseError<&str>> {
-    let (i, _) = tag("0x")(i)?;
-    let (i, value) = take_while_m_n(1, 2, is_hex)(i)?;
-    let value = u8::from_str_radix(value, 16).unwrap();
-    Ok((i, value))
+fn is_bin(input: char) -> bool {
+    input == '0' || input == '1'
 }
 
-fn parse_u8_dec(i: &str) -> IResult<&str, u8, VerboseError<&str>> {
-    let (i, value) = take_while_m_n(1, 3, is_dec)(i)?;
-    let value = value.parse().unwrap(); // TODO: Handle 255 < x < 1000
-    Ok((i, value))
+/// The prefix of a hexadecimal number, `0x` or the RGBDS style `$`
+fn hex_prefix(i: &str) -> IResult<&str, &str, VerboseError<&str>> {
+    alt((tag("0x"), tag("$")))(i)
 }
 
-// TODO: Replace with parse_constant in db and dw, advance_address
-fn parse_u8(i: &str) -> IResult<&str, u8, VerboseError<&str>> {
-    alt((parse_u8_hex, parse_u8_dec))(i)
-}
-
-fn parse_u16_hex(i: &str) -> IResult<&str, u16, VerboseError<&str>> {
-    let (i, _) = tag("0x")(i)?;
-    let (i, value) = take_while_m_n(1, 4, is_hex)(i)?;
-    let value = u16::from_str_radix(value, 16).unwrap();
-    Ok((i, value))
-}
-
-fn parse_u16_dec(i: &str) -> IResult<&str, u16, VerboseError<&str>> {
-    let (i, value) = take_while_m_n(1, 5, is_dec)(i)?;
-    let value = value.parse().unwrap(); // TODO: Handle 65535 < x < 100000
-    Ok((i, value))
-}
-
-// TODO: Replace with parse_constant in db and dw, advance_address
-fn parse_u16(i: &str) -> IResult<&str, u16, VerboseError<&str>> {
-    alt((parse_u16_hex, parse_u16_dec))(i)
-}
+// Numbers that do not fit in 64 bits fail to parse, the diagnostic reports them as out of range.
+// Hexadecimal and binary numbers can use all 64 bits, e.g. `$FFFFFFFFFFFFFFFF` is -1.
 
 fn parse_constant_hex(i: &str) -> IResult<&str, i64, VerboseError<&str>> {
-    let (i, _) = tag("0x")(i)?;
-    let (i, value) = take_while_m_n(1, 16, is_hex)(i)?; // TODO: Make this endless, we should really handle all the num to big to parse errors in one case
-    let value = i64::from_str_radix(value, 16).unwrap();
-    Ok((i, value))
+    let (i, _) = hex_prefix(i)?;
+    map_res(take_while1(is_hex), |value| {
+        u64::from_str_radix(value, 16).map(|x| x as i64)
+    })(i)
 }
 
 fn parse_constant_dec(i: &str) -> IResult<&str, i64, VerboseError<&str>> {
-    let (i, value) = take_while_m_n(1, 20, is_dec)(i)?; // TODO: Make this endless, we should really handle all the num to big to parse errors in one case
-    let value = value.parse().unwrap(); // TODO: Handle 65535 < x < 100000
-    Ok((i, value))
+    map_res(take_while1(is_dec), str::parse)(i)
+}
+
+fn parse_constant_bin(i: &str) -> IResult<&str, i64, VerboseError<&str>> {
+    let (i, _) = alt((tag("0b"), tag("%")))(i)?;
+    map_res(take_while1(is_bin), |value| {
+        u64::from_str_radix(value, 2).map(|x| x as i64)
+    })(i)
+}
+
+/// A fixed point literal e.g. `12.5q8`, evaluates to the value scaled by 2 to the power of the number after the q.
+fn parse_constant_fixed(i: &str) -> IResult<&str, i64, VerboseError<&str>> {
+    let (i, integer) = take_while1(is_dec)(i)?;
+    let (i, _) = char('.')(i)?;
+    let (i, fraction) = take_while1(is_dec)(i)?;
+    let (i, _) = alt((char('q'), char('Q')))(i)?;
+    map_res(take_while1(is_dec), move |bits: &str| {
+        let bits: u32 = bits.parse().unwrap_or(u32::MAX);
+        if bits > 32 {
+            return Err("fixed point literals can have at most 32 fractional bits");
+        }
+        let value: f64 = format!("{}.{}", integer, fraction).parse().unwrap();
+        let scaled = (value * (1u64 << bits) as f64).round();
+        if scaled >= i64::MAX as f64 {
+            return Err("fixed point literal is too big");
+        }
+        Ok(scaled as i64)
+    })(i)
 }
 
 fn parse_constant(i: &str) -> IResult<&str, i64, VerboseError<&str>> {
-    alt((parse_constant_hex, parse_constant_dec))(i)
+    alt((
+        parse_constant_hex,
+        parse_constant_bin,
+        parse_constant_fixed,
+        parse_constant_dec,
+    ))(i)
 }
 
 fn u16_to_vec(input: u16) -> Vec<u8> {
@@ -85,128 +100,102 @@ fn u16_to_vec(input: u16) -> Vec<u8> {
     result
 }
 
-fn primary_expr(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+fn anonymous_label_ref(i: &str) -> IResult<&str, Expr<&str>, VerboseError<&str>> {
+    let (i, _) = char(':')(i)?;
+    alt((
+        map(is_a("+"), |x: &str| Expr::AnonymousLabelRef(x.len() as i32)),
+        map(is_a("-"), |x: &str| {
+            Expr::AnonymousLabelRef(-(x.len() as i32))
+        }),
+    ))(i)
+}
+
+fn primary_expr(i: &str) -> IResult<&str, Expr<&str>, VerboseError<&str>> {
     alt((
         delimited(char('('), parse_expr, char(')')),
+        anonymous_label_ref,
         map(parse_constant, Expr::Const),
-        map(is_a(IDENT), |ident: &str| Expr::Ident(ident.to_string())),
+        // numbers too big to parse are not identifiers
+        map(
+            verify(identifier, |x: &str| !is_number(x)),
+            |ident: &str| Expr::Ident(ident),
+        ),
     ))(i)
 }
 
-fn unary_expr_inner(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+fn unary_expr_inner(i: &str) -> IResult<&str, Expr<&str>, VerboseError<&str>> {
     let (i, op) = value(UnaryOperator::Minus, char('-'))(i)?;
     let (i, expr) = unary_expr(i)?;
     Ok((i, Expr::unary(expr, op)))
 }
 
-fn unary_expr(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+fn unary_expr(i: &str) -> IResult<&str, Expr<&str>, VerboseError<&str>> {
     alt((unary_expr_inner, primary_expr))(i)
 }
 
-fn mult_expr_inner(i: &str) -> IResult<&str, (BinaryOperator, Expr), VerboseError<&str>> {
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, op) = alt((
-        value(BinaryOperator::Mul, char('*')),
-        value(BinaryOperator::Div, char('/')),
-        value(BinaryOperator::Rem, char('%')),
-    ))(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, right) = mult_expr(i)?;
-    Ok((i, (op, right)))
-}
-
-fn mult_expr(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, left) = unary_expr(i)?;
-    let left2 = left.clone();
+/// Parses a binary operator, returning it with its precedence, higher precedences bind tighter.
+fn binary_operator(i: &str) -> IResult<&str, (BinaryOperator, u8), VerboseError<&str>> {
+    // longer operators come first so `&&` isnt parsed as `&`
     alt((
-        map(mult_expr_inner, move |(op, right)| {
-            Expr::binary(left2.clone(), op, right)
-        }),
-        move |i| Ok((i, left.clone())),
+        value((BinaryOperator::LogicalOr, 1), tag("||")),
+        value((BinaryOperator::LogicalAnd, 2), tag("&&")),
+        value((BinaryOperator::Or, 3), char('|')),
+        value((BinaryOperator::Xor, 4), char('^')),
+        value((BinaryOperator::And, 5), char('&')),
+        value((BinaryOperator::Equal, 6), tag("==")),
+        value((BinaryOperator::NotEqual, 6), tag("!=")),
+        value((BinaryOperator::Shl, 8), tag("<<")),
+        value((BinaryOperator::Shr, 8), tag(">>")),
+        value((BinaryOperator::LessEqual, 7), tag("<=")),
+        value((BinaryOperator::GreaterEqual, 7), tag(">=")),
+        value((BinaryOperator::Less, 7), char('<')),
+        value((BinaryOperator::Greater, 7), char('>')),
+        value((BinaryOperator::Add, 9), char('+')),
+        value((BinaryOperator::Sub, 9), char('-')),
+        value((BinaryOperator::Mul, 10), char('*')),
+        value((BinaryOperator::Div, 10), char('/')),
+        value((BinaryOperator::Rem, 10), char('%')),
     ))(i)
 }
 
-fn add_expr_inner(i: &str) -> IResult<&str, (BinaryOperator, Expr), VerboseError<&str>> {
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, op) = alt((
-        value(BinaryOperator::Add, char('+')),
-        value(BinaryOperator::Sub, char('-')),
-    ))(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, right) = add_expr(i)?;
-    Ok((i, (op, right)))
-}
-
-fn add_expr(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, left) = mult_expr(i)?;
-    let left2 = left.clone();
-    alt((
-        map(add_expr_inner, move |(op, right)| {
-            Expr::binary(left2.clone(), op, right)
-        }),
-        move |i| Ok((i, left.clone())),
-    ))(i)
-}
-
-fn bit_and_expr_inner(i: &str) -> IResult<&str, (BinaryOperator, Expr), VerboseError<&str>> {
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, op) = value(BinaryOperator::And, char('&'))(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, right) = bit_and_expr(i)?;
-    Ok((i, (op, right)))
-}
-
-fn bit_and_expr(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, left) = add_expr(i)?;
-    let left2 = left.clone();
-    alt((
-        map(bit_and_expr_inner, move |(op, right)| {
-            Expr::binary(left2.clone(), op, right)
-        }),
-        move |i| Ok((i, left.clone())),
-    ))(i)
-}
-
-fn bit_xor_expr_inner(i: &str) -> IResult<&str, (BinaryOperator, Expr), VerboseError<&str>> {
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, op) = value(BinaryOperator::Xor, char('^'))(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, right) = bit_xor_expr(i)?;
-    Ok((i, (op, right)))
-}
-
-fn bit_xor_expr(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, left) = bit_and_expr(i)?;
-    let left2 = left.clone();
-    alt((
-        map(bit_xor_expr_inner, move |(op, right)| {
-            Expr::binary(left2.clone(), op, right)
-        }),
-        move |i| Ok((i, left.clone())),
-    ))(i)
-}
-
-fn bit_or_expr_inner(i: &str) -> IResult<&str, (BinaryOperator, Expr), VerboseError<&str>> {
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, op) = value(BinaryOperator::Or, char('|'))(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, right) = bit_or_expr(i)?;
-    Ok((i, (op, right)))
-}
-
-fn bit_or_expr(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, left) = bit_xor_expr(i)?;
-    let left2 = left.clone();
-    alt((
-        map(bit_or_expr_inner, move |(op, right)| {
-            Expr::binary(left2.clone(), op, right)
-        }),
-        move |i| Ok((i, left.clone())),
-    ))(i)
+/// Parses an expression whose binary operators all have at least min_precedence, using precedence climbing.
+///
+/// Operators of the same precedence are left associative, so `a - b - c` is `(a - b) - c`.
+/// When an operator is not followed by an operand the expression ends before the operator, e.g. `hl+]`
+/// is the expression `hl` followed by `+]`.
+fn binary_expr(i: &str, min_precedence: u8) -> IResult<&str, Expr<&str>, VerboseError<&str>> {
+    let (i, left) = unary_expr(i)?;
+    binary_expr_rest(i, left, min_precedence)
+}
+
+/// Parses the binary operators following the already parsed left operand, see [binary_expr].
+fn binary_expr_rest<'a>(
+    mut i: &'a str,
+    mut left: Expr<&'a str>,
+    min_precedence: u8,
+) -> IResult<&'a str, Expr<&'a str>, VerboseError<&'a str>> {
+    loop {
+        let (rest, _) = opt(is_a(WHITESPACE))(i)?;
+        let (rest, (operator, precedence)) = match binary_operator(rest) {
+            Ok((rest, (operator, precedence))) if precedence >= min_precedence => {
+                (rest, (operator, precedence))
+            }
+            _ => return Ok((i, left)),
+        };
+        let (rest, _) = opt(is_a(WHITESPACE))(rest)?;
+        match binary_expr(rest, precedence + 1) {
+            Ok((rest, right)) => {
+                left = Expr::binary(left, operator, right);
+                i = rest;
+            }
+            Err(nom::Err::Error(_)) => return Ok((i, left)),
+            Err(err) => return Err(err),
+        }
+    }
 }
 
-fn parse_expr(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    bit_or_expr(i)
+fn parse_expr(i: &str) -> IResult<&str, Expr<&str>, VerboseError<&str>> {
+    binary_expr(i, 0)
 }
 
 fn parse_reg_u8(i: &str) -> IResult<&str, Reg8, VerboseError<&str>> {
@@ -271,7 +260,7 @@ fn end_line(i: &str) -> IResult<&str, (), VerboseError<&str>> {
     let (i, _) = opt(comment)(i)?;
 
     // does the line truely end?
-    peek(is_a("\r\n"))(i)?;
+    alt((peek(is_a("\r\n")), eof))(i)?;
 
     Ok((i, ()))
 }
@@ -310,62 +299,387 @@ fn deref_hl(i: &str) -> IResult<&str, (), VerboseError<&str>> {
     Ok((i, ()))
 }
 
-fn parse_string(i: &str) -> IResult<&str, Vec<u8>, VerboseError<&str>> {
-    delimited(
-        char('"'),
-        map(is_not("\r\n\""), |value: &str| value.as_bytes().to_vec()),
-        char('"'),
-    )(i)
+fn parse_string(i: &str) -> IResult<&str, &str, VerboseError<&str>> {
+    delimited(char('"'), is_not("\r\n\""), char('"'))(i)
 }
 
-fn label(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, label) = is_a(IDENT)(i)?;
+/// Matches an identifier, `.` separates the namespaces of a qualified identifier e.g. `Audio.Init`.
+fn identifier(i: &str) -> IResult<&str, &str, VerboseError<&str>> {
+    recognize(pair(is_a(IDENT), many0(pair(char('.'), is_a(IDENT)))))(i)
+}
+
+fn label(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, label) = identifier(i)?;
     let (i, _) = char(':')(i)?;
     let (i, _) = end_line(i)?;
-    Ok((i, Instruction::Label(label.to_string())))
+    Ok((i, Instruction::Label(label)))
 }
 
-fn equ(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, label) = is_a(IDENT)(i)?;
+fn anonymous_label(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = char(':')(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::AnonymousLabel))
+}
+
+fn equ(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, label) = identifier(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("EQU")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, expr) = parse_expr(i)?;
     let (i, _) = end_line(i)?;
-    Ok((i, Instruction::Equ(label.to_string(), expr)))
+    Ok((i, Instruction::Equ(label, expr)))
+}
+
+/// Matches `SET` or `=`, `=` does not require surrounding whitespace.
+fn set_operator(i: &str) -> IResult<&str, &str, VerboseError<&str>> {
+    alt((
+        delimited(is_a(WHITESPACE), tag_no_case("SET"), is_a(WHITESPACE)),
+        delimited(opt(is_a(WHITESPACE)), tag("="), opt(is_a(WHITESPACE))),
+    ))(i)
+}
+
+fn set(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, label) = is_a(IDENT)(i)?;
+    let (i, _) = set_operator(i)?;
+    let (i, expr) = parse_expr(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::Set(label, expr)))
+}
+
+fn redef(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("REDEF")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, label) = is_a(IDENT)(i)?;
+    let (i, _) = alt((
+        delimited(is_a(WHITESPACE), tag_no_case("EQU"), is_a(WHITESPACE)),
+        set_operator,
+    ))(i)?;
+    let (i, expr) = parse_expr(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::Set(label, expr)))
+}
+
+/// A definition in the modern RGBDS form e.g. `DEF Lives EQU 3` or `DEF Counter = 0`.
+fn def(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("DEF")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    alt((equ, set))(i)
 }
 
-fn direct_bytes(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+/// A comma separated list of strings and expressions.
+fn db_items(i: &str) -> IResult<&str, Vec<DbItem<&str>>, VerboseError<&str>> {
+    separated_list1(
+        comma_sep,
+        alt((
+            map(parse_string, DbItem::String),
+            map(parse_expr, DbItem::Expr),
+        )),
+    )(i)
+}
+
+fn direct_bytes(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("db")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
-    let (i, value) = separated_list1(
+    let (i, mut items) = db_items(i)?;
+    let (i, _) = end_line(i)?;
+
+    // Strings are kept so the RomBuilder can apply charmaps
+    if items.iter().any(|item| matches!(item, DbItem::String(_))) {
+        return Ok((i, Instruction::DbString(items)));
+    }
+
+    // Use the simplest instruction that can represent the bytes
+    let bytes: Option<Vec<u8>> = items
+        .iter()
+        .map(|item| match item {
+            DbItem::Expr(Expr::Const(value)) => u8::try_from(*value).ok(),
+            _ => None,
+        })
+        .collect();
+    let mut exprs: Vec<Expr<&str>> = items
+        .drain(..)
+        .filter_map(|item| match item {
+            DbItem::Expr(expr) => Some(expr),
+            DbItem::String(_) => None,
+        })
+        .collect();
+    let instruction = match bytes {
+        Some(bytes) => Instruction::Db(bytes),
+        None if exprs.len() == 1 => Instruction::DbExpr8(exprs.remove(0)),
+        None => Instruction::DbExprList8(exprs),
+    };
+    Ok((i, instruction))
+}
+
+fn direct_fill(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("dbfill")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, count) = parse_expr(i)?;
+    let (i, _) = comma_sep(i)?;
+    let (i, value) = parse_expr(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::DbFill(count, value)))
+}
+
+fn charmap(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("charmap")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, c) = delimited(char('"'), none_of("\r\n\""), char('"'))(i)?;
+    let (i, _) = comma_sep(i)?;
+    let (i, expr) = parse_expr(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::Charmap(c, expr)))
+}
+
+fn direct_words(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("dw")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, mut exprs) = separated_list1(comma_sep, parse_expr)(i)?;
+    let (i, _) = end_line(i)?;
+
+    // Use the simplest instruction that can represent the words
+    let words: Option<Vec<u16>> = exprs
+        .iter()
+        .map(|expr| match expr {
+            Expr::Const(value) => u16::try_from(*value).ok(),
+            _ => None,
+        })
+        .collect();
+    let instruction = match words {
+        Some(words) => Instruction::Db(words.into_iter().flat_map(u16_to_vec).collect()),
+        None if exprs.len() == 1 => Instruction::DbExpr16(exprs.remove(0)),
+        None => Instruction::DbExprList16(exprs),
+    };
+    Ok((i, instruction))
+}
+
+fn advance_address(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("advance_address")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, first) = parse_expr(i)?;
+    let (i, second) = opt(preceded(comma_sep, parse_expr))(i)?;
+    let (i, _) = end_line(i)?;
+    let instruction = match second {
+        Some(address) => Instruction::AdvanceAddress(address, Some(first)),
+        None => Instruction::AdvanceAddress(first, None),
+    };
+    Ok((i, instruction))
+}
+
+fn debug_assert(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("debug_assert")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, instruction) = alt((
+        map(parse_flag, Instruction::DebugAssert),
+        map(debug_assert_comparison, |(comparison, expr)| {
+            Instruction::DebugAssertCp(comparison, expr)
+        }),
+    ))(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, instruction))
+}
+
+/// Parses a comparison of `a` with an expression, returning the flag `cp` sets when the comparison holds.
+fn debug_assert_comparison(i: &str) -> IResult<&str, (Comparison, Expr<&str>), VerboseError<&str>> {
+    let (i, _) = tag_no_case("a")(i)?;
+    let (i, _) = opt(is_a(WHITESPACE))(i)?;
+    let (i, comparison) = alt((
+        value(Comparison::Equal, tag("==")),
+        value(Comparison::NotEqual, tag("!=")),
+        value(Comparison::GreaterOrEqual, tag(">=")),
+        value(Comparison::Less, tag("<")),
+    ))(i)?;
+    let (i, _) = opt(is_a(WHITESPACE))(i)?;
+    let (i, expr) = parse_expr(i)?;
+    Ok((i, (comparison, expr)))
+}
+
+fn debug_print(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("debug_print")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, message) = parse_string(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::DebugPrint(message)))
+}
+
+fn assert(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("assert")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, expr) = parse_expr(i)?;
+    let (i, message) = opt(preceded(comma_sep, parse_string))(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::Assert(expr, message)))
+}
+
+fn message(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, directive) = alt((
+        tag_no_case("print"),
+        tag_no_case("warn"),
+        tag_no_case("fail"),
+    ))(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, items) = db_items(i)?;
+    let (i, _) = end_line(i)?;
+    let instruction = match directive.to_ascii_lowercase().as_str() {
+        "print" => Instruction::Print(items),
+        "warn" => Instruction::Warn(items),
+        _ => Instruction::Fail(items),
+    };
+    Ok((i, instruction))
+}
+
+fn include(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("include")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, file_name) = parse_string(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::Include(file_name)))
+}
+
+/// An expression within square brackets, e.g. the address of `ROMX[0x4100]`.
+fn bracketed_expr(i: &str) -> IResult<&str, Expr<&str>, VerboseError<&str>> {
+    let (i, _) = char('[')(i)?;
+    let (i, _) = opt(is_a(WHITESPACE))(i)?;
+    let (i, expr) = parse_expr(i)?;
+    let (i, _) = opt(is_a(WHITESPACE))(i)?;
+    let (i, _) = char(']')(i)?;
+    Ok((i, expr))
+}
+
+fn section(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("section")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, name) = parse_string(i)?;
+    let (i, _) = comma_sep(i)?;
+    let (i, section_type) = alt((
+        value(SectionType::Rom0, tag_no_case("rom0")),
+        value(SectionType::Romx, tag_no_case("romx")),
+    ))(i)?;
+    let (i, address) = opt(bracketed_expr)(i)?;
+    let (i, bank) = opt(preceded(
         comma_sep,
-        alt((parse_string, map(parse_u8, |value| vec![value]))),
-    )(i)?;
+        preceded(tag_no_case("bank"), bracketed_expr),
+    ))(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::Section(name, section_type, address, bank)))
+}
+
+fn export(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = alt((tag_no_case("export"), tag_no_case("global")))(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, idents) = separated_list1(comma_sep, identifier)(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::Export(idents)))
+}
+
+fn namespace(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, instruction) = alt((
+        map(
+            preceded(
+                terminated(tag_no_case("namespace"), is_a(WHITESPACE)),
+                identifier,
+            ),
+            Instruction::Namespace,
+        ),
+        value(Instruction::EndNamespace, tag_no_case("endnamespace")),
+    ))(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, instruction))
+}
+
+fn union(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, instruction) = alt((
+        value(Instruction::Union, tag_no_case("union")),
+        value(Instruction::NextU, tag_no_case("nextu")),
+        value(Instruction::EndU, tag_no_case("endu")),
+    ))(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, instruction))
+}
+
+fn state_stack(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, instruction) = alt((
+        value(Instruction::PushO, tag_no_case("pusho")),
+        value(Instruction::PopO, tag_no_case("popo")),
+        value(Instruction::PushS, tag_no_case("pushs")),
+        value(Instruction::PopS, tag_no_case("pops")),
+        value(
+            Instruction::PragmaOnce,
+            preceded(
+                terminated(tag_no_case("pragma"), is_a(WHITESPACE)),
+                tag_no_case("once"),
+            ),
+        ),
+    ))(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, instruction))
+}
+
+fn asm_option(i: &str) -> IResult<&str, AsmOption, VerboseError<&str>> {
+    alt((
+        value(AsmOption::WarningsAsErrors(true), tag("Werror")),
+        value(AsmOption::WarningsAsErrors(false), tag("Wno-error")),
+        value(AsmOption::HaltPadding(true), tag("!h")),
+        value(AsmOption::HaltPadding(false), tag("h")),
+        map(
+            preceded(
+                tag("radix="),
+                map_res(parse_constant_dec, |radix| match radix {
+                    2..=16 => Ok(radix as u32),
+                    _ => Err("radix must be from 2 to 16"),
+                }),
+            ),
+            AsmOption::Radix,
+        ),
+    ))(i)
+}
+
+fn asm_options(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("opt")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, options) = separated_list1(comma_sep, asm_option)(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::Opt(options)))
+}
+
+fn parse_u32(i: &str) -> IResult<&str, u32, VerboseError<&str>> {
+    map_res(parse_constant, u32::try_from)(i)
+}
+
+fn incbin(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("incbin")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, file_name) = parse_string(i)?;
+    let (i, offset) = opt(preceded(comma_sep, parse_u32))(i)?;
+    let (i, length) = match offset {
+        Some(_) => opt(preceded(comma_sep, parse_u32))(i)?,
+        None => (i, None),
+    };
     let (i, _) = end_line(i)?;
     Ok((
         i,
-        Instruction::Db(value.iter().flatten().cloned().collect()),
+        Instruction::Incbin(file_name, offset.unwrap_or(0), length),
     ))
 }
 
-fn direct_words(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, _) = tag_no_case("dw")(i)?;
+fn conditional_if(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("if")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
-    let (i, value) = parse_u16(i)?;
+    let (i, expr) = parse_expr(i)?;
     let (i, _) = end_line(i)?;
-    Ok((i, Instruction::Db(u16_to_vec(value))))
+    Ok((i, Instruction::If(expr)))
 }
 
-fn advance_address(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, _) = tag_no_case("advance_address")(i)?;
+fn conditional_elif(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("elif")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
-    let (i, value) = parse_u16(i)?;
+    let (i, expr) = parse_expr(i)?;
     let (i, _) = end_line(i)?;
-    Ok((i, Instruction::AdvanceAddress(value)))
+    Ok((i, Instruction::Elif(expr)))
 }
 
-fn instruction_ret(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ret(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ret")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, flag) = parse_flag(i)?;
@@ -373,7 +687,7 @@ fn instruction_ret(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
     Ok((i, Instruction::Ret(flag)))
 }
 
-fn instruction_call_flag(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_call_flag(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("call")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, flag) = parse_flag(i)?;
@@ -383,7 +697,7 @@ fn instruction_call_flag(i: &str) -> IResult<&str, Instruction, VerboseError<&st
     Ok((i, Instruction::Call(flag, expr)))
 }
 
-fn instruction_call_always(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_call_always(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("call")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -391,7 +705,15 @@ fn instruction_call_always(i: &str) -> IResult<&str, Instruction, VerboseError<&
     Ok((i, Instruction::Call(Flag::Always, expr)))
 }
 
-fn instruction_jprhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_rst(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = tag_no_case("rst")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, expr) = parse_expr(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::Rst(expr)))
+}
+
+fn instruction_jprhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("jp")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("hl")(i)?;
@@ -399,7 +721,7 @@ fn instruction_jprhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::JpRhl))
 }
 
-fn instruction_jpi16_always(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_jpi16_always(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("jp")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -407,7 +729,7 @@ fn instruction_jpi16_always(i: &str) -> IResult<&str, Instruction, VerboseError<
     Ok((i, Instruction::JpI16(Flag::Always, expr)))
 }
 
-fn instruction_jpi16_flag(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_jpi16_flag(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("jp")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, flag) = parse_flag(i)?;
@@ -417,7 +739,7 @@ fn instruction_jpi16_flag(i: &str) -> IResult<&str, Instruction, VerboseError<&s
     Ok((i, Instruction::JpI16(flag, expr)))
 }
 
-fn instruction_jr_always(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_jr_always(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("jr")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -425,7 +747,7 @@ fn instruction_jr_always(i: &str) -> IResult<&str, Instruction, VerboseError<&st
     Ok((i, Instruction::Jr(Flag::Always, expr)))
 }
 
-fn instruction_jr_flag(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_jr_flag(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("jr")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, flag) = parse_flag(i)?;
@@ -435,7 +757,7 @@ fn instruction_jr_flag(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::Jr(flag, expr)))
 }
 
-fn instruction_inc(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_inc(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("inc")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, instruction) = alt((
@@ -447,7 +769,7 @@ fn instruction_inc(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
     Ok((i, instruction))
 }
 
-fn instruction_dec(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_dec(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("dec")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, instruction) = alt((
@@ -459,7 +781,7 @@ fn instruction_dec(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
     Ok((i, instruction))
 }
 
-fn instruction_addr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_addr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("add")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = reg_a_u8(i)?;
@@ -467,7 +789,7 @@ fn instruction_addr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::AddR8(reg)))
 }
 
-fn instruction_addmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_addmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("add")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, _) = deref_hl(i)?;
@@ -475,7 +797,7 @@ fn instruction_addmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::AddMRhl))
 }
 
-fn instruction_addi8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_addi8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("add")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -483,7 +805,7 @@ fn instruction_addi8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::AddI8(expr)))
 }
 
-fn instruction_addrhlr16(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_addrhlr16(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("add")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("hl")(i)?;
@@ -493,7 +815,7 @@ fn instruction_addrhlr16(i: &str) -> IResult<&str, Instruction, VerboseError<&st
     Ok((i, Instruction::AddRhlR16(reg)))
 }
 
-fn instruction_addrspi8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_addrspi8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("add")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("sp")(i)?;
@@ -503,7 +825,7 @@ fn instruction_addrspi8(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::AddRspI8(expr)))
 }
 
-fn instruction_subr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_subr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("sub")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = reg_a_u8(i)?;
@@ -511,7 +833,7 @@ fn instruction_subr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::SubR8(reg)))
 }
 
-fn instruction_submrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_submrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("sub")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, _) = deref_hl(i)?;
@@ -519,7 +841,7 @@ fn instruction_submrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::SubMRhl))
 }
 
-fn instruction_subi8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_subi8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("sub")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -527,7 +849,7 @@ fn instruction_subi8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::SubI8(expr)))
 }
 
-fn instruction_andr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_andr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("and")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = reg_a_u8(i)?;
@@ -535,7 +857,7 @@ fn instruction_andr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::AndR8(reg)))
 }
 
-fn instruction_andmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_andmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("and")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, _) = deref_hl(i)?;
@@ -543,7 +865,7 @@ fn instruction_andmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::AndMRhl))
 }
 
-fn instruction_andi8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_andi8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("and")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -551,7 +873,7 @@ fn instruction_andi8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::AndI8(expr)))
 }
 
-fn instruction_orr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_orr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("or")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = reg_a_u8(i)?;
@@ -559,7 +881,7 @@ fn instruction_orr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
     Ok((i, Instruction::OrR8(reg)))
 }
 
-fn instruction_ormrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ormrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("or")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, _) = deref_hl(i)?;
@@ -567,7 +889,7 @@ fn instruction_ormrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::OrMRhl))
 }
 
-fn instruction_ori8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ori8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("or")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -575,7 +897,7 @@ fn instruction_ori8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
     Ok((i, Instruction::OrI8(expr)))
 }
 
-fn instruction_adcr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_adcr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("adc")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = reg_a_u8(i)?;
@@ -583,7 +905,7 @@ fn instruction_adcr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::AdcR8(reg)))
 }
 
-fn instruction_adcmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_adcmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("adc")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, _) = deref_hl(i)?;
@@ -591,7 +913,7 @@ fn instruction_adcmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::AdcMRhl))
 }
 
-fn instruction_adci8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_adci8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("adc")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -599,7 +921,7 @@ fn instruction_adci8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::AdcI8(expr)))
 }
 
-fn instruction_sbcr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_sbcr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("sbc")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = reg_a_u8(i)?;
@@ -607,7 +929,7 @@ fn instruction_sbcr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::SbcR8(reg)))
 }
 
-fn instruction_sbcmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_sbcmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("sbc")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, _) = deref_hl(i)?;
@@ -615,7 +937,7 @@ fn instruction_sbcmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::SbcMRhl))
 }
 
-fn instruction_sbci8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_sbci8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("sbc")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -623,7 +945,7 @@ fn instruction_sbci8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::SbcI8(expr)))
 }
 
-fn instruction_xorr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_xorr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("xor")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = reg_a_u8(i)?;
@@ -631,7 +953,7 @@ fn instruction_xorr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::XorR8(reg)))
 }
 
-fn instruction_xormrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_xormrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("xor")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, _) = deref_hl(i)?;
@@ -639,7 +961,7 @@ fn instruction_xormrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::XorMRhl))
 }
 
-fn instruction_xori8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_xori8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("xor")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -647,7 +969,7 @@ fn instruction_xori8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::XorI8(expr)))
 }
 
-fn instruction_cpr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_cpr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("cp")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = reg_a_u8(i)?;
@@ -655,7 +977,7 @@ fn instruction_cpr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
     Ok((i, Instruction::CpR8(reg)))
 }
 
-fn instruction_cpmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_cpmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("cp")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, _) = deref_hl(i)?;
@@ -663,7 +985,7 @@ fn instruction_cpmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::CpMRhl))
 }
 
-fn instruction_cpi8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_cpi8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("cp")(i)?;
     let (i, _) = opt_reg_a(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -671,7 +993,7 @@ fn instruction_cpi8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
     Ok((i, Instruction::CpI8(expr)))
 }
 
-fn instruction_ldr8r8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ldr8r8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg1) = parse_reg_u8(i)?;
@@ -681,7 +1003,7 @@ fn instruction_ldr8r8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::LdR8R8(reg1, reg2)))
 }
 
-fn instruction_ldr8i8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ldr8i8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u8(i)?;
@@ -691,7 +1013,7 @@ fn instruction_ldr8i8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::LdR8I8(reg, expr)))
 }
 
-fn instruction_ldrsprhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ldrsprhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("sp")(i)?;
@@ -701,7 +1023,7 @@ fn instruction_ldrsprhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::LdRspRhl))
 }
 
-fn instruction_ldmi16rsp(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ldmi16rsp(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = char('[')(i)?;
@@ -715,7 +1037,7 @@ fn instruction_ldmi16rsp(i: &str) -> IResult<&str, Instruction, VerboseError<&st
     Ok((i, Instruction::LdMI16Rsp(expr)))
 }
 
-fn instruction_ldmr16ra(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ldmr16ra(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, instruction) = alt((
@@ -728,7 +1050,7 @@ fn instruction_ldmr16ra(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, instruction))
 }
 
-fn instruction_ldramr16(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ldramr16(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("a")(i)?;
@@ -741,47 +1063,83 @@ fn instruction_ldramr16(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, instruction))
 }
 
-fn instruction_ldimrhlra(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, _) = tag_no_case("ldi")(i)?;
+fn instruction_ldimrhlra(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, inc) = ldi_mnemonic(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
-    let (i, _) = deref_hl(i)?;
+    let (i, _) = deref_hl_step(inc, '+')(i)?;
     let (i, _) = comma_sep(i)?;
     let (i, _) = tag_no_case("a")(i)?;
     let (i, _) = end_line(i)?;
     Ok((i, Instruction::LdiMRhlRa))
 }
 
-fn instruction_lddmrhlra(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, _) = tag_no_case("ldd")(i)?;
+fn instruction_lddmrhlra(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, dec) = ldd_mnemonic(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
-    let (i, _) = deref_hl(i)?;
+    let (i, _) = deref_hl_step(dec, '-')(i)?;
     let (i, _) = comma_sep(i)?;
     let (i, _) = tag_no_case("a")(i)?;
     let (i, _) = end_line(i)?;
     Ok((i, Instruction::LddMRhlRa))
 }
 
-fn instruction_ldiramrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, _) = tag_no_case("ldi")(i)?;
+fn instruction_ldiramrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, inc) = ldi_mnemonic(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("a")(i)?;
     let (i, _) = comma_sep(i)?;
-    let (i, _) = deref_hl(i)?;
+    let (i, _) = deref_hl_step(inc, '+')(i)?;
     let (i, _) = end_line(i)?;
     Ok((i, Instruction::LdiRaMRhl))
 }
 
-fn instruction_lddramrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, _) = tag_no_case("ldd")(i)?;
+fn instruction_lddramrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, dec) = ldd_mnemonic(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("a")(i)?;
     let (i, _) = comma_sep(i)?;
-    let (i, _) = deref_hl(i)?;
+    let (i, _) = deref_hl_step(dec, '-')(i)?;
     let (i, _) = end_line(i)?;
     Ok((i, Instruction::LddRaMRhl))
 }
 
-fn instruction_ldmrhlr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+/// Returns false for `ldi` and true for `ld`, which must be followed by `[hl+]`
+fn ldi_mnemonic(i: &str) -> IResult<&str, bool, VerboseError<&str>> {
+    alt((
+        value(false, tag_no_case("ldi")),
+        value(true, tag_no_case("ld")),
+    ))(i)
+}
+
+/// Returns false for `ldd` and true for `ld`, which must be followed by `[hl-]`
+fn ldd_mnemonic(i: &str) -> IResult<&str, bool, VerboseError<&str>> {
+    alt((
+        value(false, tag_no_case("ldd")),
+        value(true, tag_no_case("ld")),
+    ))(i)
+}
+
+/// Parses `[hl]` or when step is true, the RGBDS style `[hl+]`/`[hl-]`
+fn deref_hl_step(
+    step: bool,
+    sign: char,
+) -> impl FnMut(&str) -> IResult<&str, (), VerboseError<&str>> {
+    move |i| {
+        if !step {
+            return deref_hl(i);
+        }
+        let (i, _) = char('[')(i)?;
+        let (i, _) = opt(is_a(WHITESPACE))(i)?;
+        let (i, _) = tag_no_case("hl")(i)?;
+        let (i, _) = opt(is_a(WHITESPACE))(i)?;
+        let (i, _) = char(sign)(i)?;
+        let (i, _) = opt(is_a(WHITESPACE))(i)?;
+        let (i, _) = char(']')(i)?;
+        Ok((i, ()))
+    }
+}
+
+fn instruction_ldmrhlr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = deref_hl(i)?;
@@ -791,7 +1149,7 @@ fn instruction_ldmrhlr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::LdMRhlR8(reg)))
 }
 
-fn instruction_ldmrhli8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ldmrhli8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = deref_hl(i)?;
@@ -801,7 +1159,7 @@ fn instruction_ldmrhli8(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::LdMRhlI8(expr)))
 }
 
-fn instruction_ldr8mrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ldr8mrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u8(i)?;
@@ -811,93 +1169,123 @@ fn instruction_ldr8mrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::LdR8MRhl(reg)))
 }
 
-fn instruction_ldhramrc(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, _) = tag_no_case("ld")(i)?;
+fn instruction_ldhramrc(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = ldh_mnemonic(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("a")(i)?;
     let (i, _) = comma_sep(i)?;
-    let (i, _) = char('[')(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = tag_no_case("0xFF00")(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = tag_no_case("+")(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = tag_no_case("c")(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = char(']')(i)?;
+    let (i, _) = deref_high_c(i)?;
     let (i, _) = end_line(i)?;
     Ok((i, Instruction::LdhRaMRc))
 }
 
-fn instruction_ldhmrcra(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, _) = tag_no_case("ld")(i)?;
+fn instruction_ldhmrcra(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, _) = ldh_mnemonic(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
-    let (i, _) = char('[')(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = tag_no_case("0xFF00")(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = tag_no_case("+")(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = tag_no_case("c")(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = char(']')(i)?;
+    let (i, _) = deref_high_c(i)?;
     let (i, _) = comma_sep(i)?;
     let (i, _) = tag_no_case("a")(i)?;
     let (i, _) = end_line(i)?;
     Ok((i, Instruction::LdhMRcRa))
 }
 
-fn instruction_ldhmi8ra(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, _) = tag_no_case("ld")(i)?;
+fn instruction_ldhmi8ra(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, ldh) = ldh_mnemonic(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
-    let (i, _) = char('[')(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = tag_no_case("0xFF00")(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = tag_no_case("+")(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, expr) = parse_expr(i)?;
-    let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = char(']')(i)?;
+    let (i, expr) = deref_high(ldh)(i)?;
     let (i, _) = comma_sep(i)?;
     let (i, _) = tag_no_case("a")(i)?;
     let (i, _) = end_line(i)?;
     Ok((i, Instruction::LdhMI8Ra(expr)))
 }
 
-fn instruction_ldhrami8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
-    let (i, _) = tag_no_case("ld")(i)?;
+fn instruction_ldhrami8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    let (i, ldh) = ldh_mnemonic(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("a")(i)?;
     let (i, _) = comma_sep(i)?;
+    let (i, expr) = deref_high(ldh)(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, Instruction::LdhRaMI8(expr)))
+}
+
+/// Returns true for `ldh` and false for `ld`
+fn ldh_mnemonic(i: &str) -> IResult<&str, bool, VerboseError<&str>> {
+    alt((
+        value(true, tag_no_case("ldh")),
+        value(false, tag_no_case("ld")),
+    ))(i)
+}
+
+/// Parses `[0xFF00 + c]` or the RGBDS style `[c]`
+fn deref_high_c(i: &str) -> IResult<&str, (), VerboseError<&str>> {
     let (i, _) = char('[')(i)?;
     let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = tag_no_case("0xFF00")(i)?;
+    let (i, _) = opt(high_offset_prefix)(i)?;
+    let (i, _) = tag_no_case("c")(i)?;
     let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = tag_no_case("+")(i)?;
+    let (i, _) = char(']')(i)?;
+    Ok((i, ()))
+}
+
+/// Parses the `0xFF00 +` that starts the operand of a high ram `ld`
+fn high_offset_prefix(i: &str) -> IResult<&str, (), VerboseError<&str>> {
+    let (i, _) = preceded(hex_prefix, tag_no_case("FF00"))(i)?;
     let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, expr) = parse_expr(i)?;
+    let (i, _) = tag_no_case("+")(i)?;
     let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = char(']')(i)?;
-    let (i, _) = end_line(i)?;
-    Ok((i, Instruction::LdhRaMI8(expr)))
+    Ok((i, ()))
 }
 
-fn instruction_ldrhlrspi8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+/// Parses the operand of a high ram load and returns the offset from 0xFF00.
+///
+/// `ld` requires `[0xFF00 + n8]` while `ldh` takes `[n16]`, where n16 is either an address from 0xFF00 to 0xFFFF or an offset.
+fn deref_high(ldh: bool) -> impl FnMut(&str) -> IResult<&str, Expr<&str>, VerboseError<&str>> {
+    move |i| {
+        let (i, _) = char('[')(i)?;
+        let (i, _) = opt(is_a(WHITESPACE))(i)?;
+        let (i, expr) = if ldh {
+            let (i, address) = parse_expr(i)?;
+            // offset = address - 0xFF00 * (address >= 0xFF00)
+            let in_high_ram = Expr::binary(
+                address.clone(),
+                BinaryOperator::GreaterEqual,
+                Expr::Const(0xFF00),
+            );
+            let base = Expr::binary(Expr::Const(0xFF00), BinaryOperator::Mul, in_high_ram);
+            (i, Expr::binary(address, BinaryOperator::Sub, base))
+        } else {
+            let (i, _) = high_offset_prefix(i)?;
+            parse_expr(i)?
+        };
+        let (i, _) = opt(is_a(WHITESPACE))(i)?;
+        let (i, _) = char(']')(i)?;
+        Ok((i, expr))
+    }
+}
+
+fn instruction_ldrhlrspi8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("hl")(i)?;
     let (i, _) = comma_sep(i)?;
     let (i, _) = tag_no_case("sp")(i)?;
     let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, _) = tag_no_case("+")(i)?;
+    let (i, sign) = alt((char('+'), char('-')))(i)?;
     let (i, _) = opt(is_a(WHITESPACE))(i)?;
-    let (i, expr) = parse_expr(i)?;
+    let (i, expr) = match sign {
+        // `sp - 4 + 1` is `sp + (-4 + 1)`
+        '-' => {
+            let (i, first) = unary_expr(i)?;
+            binary_expr_rest(i, Expr::unary(first, UnaryOperator::Minus), 0)?
+        }
+        _ => parse_expr(i)?,
+    };
     let (i, _) = end_line(i)?;
     Ok((i, Instruction::LdRhlRspI8(expr)))
 }
 
-fn instruction_ldmi16ra(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ldmi16ra(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = char('[')(i)?;
@@ -911,7 +1299,7 @@ fn instruction_ldmi16ra(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::LdMI16Ra(expr)))
 }
 
-fn instruction_ldrami16(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ldrami16(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = tag_no_case("a")(i)?;
@@ -925,7 +1313,7 @@ fn instruction_ldrami16(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::LdRaMI16(expr)))
 }
 
-fn instruction_ldr16i16(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_ldr16i16(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("ld")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u16(i)?;
@@ -935,7 +1323,7 @@ fn instruction_ldr16i16(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::LdR16I16(reg, expr)))
 }
 
-fn instruction_push(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_push(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("push")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u16_push(i)?;
@@ -943,7 +1331,7 @@ fn instruction_push(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
     Ok((i, Instruction::Push(reg)))
 }
 
-fn instruction_pop(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_pop(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("pop")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u16_push(i)?;
@@ -951,7 +1339,7 @@ fn instruction_pop(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
     Ok((i, Instruction::Pop(reg)))
 }
 
-fn instruction_rlcr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_rlcr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("rlc")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u8(i)?;
@@ -959,7 +1347,7 @@ fn instruction_rlcr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::RlcR8(reg)))
 }
 
-fn instruction_rlcmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_rlcmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("rlc")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = deref_hl(i)?;
@@ -967,7 +1355,7 @@ fn instruction_rlcmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::RlcMRhl))
 }
 
-fn instruction_rrcr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_rrcr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("rrc")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u8(i)?;
@@ -975,7 +1363,7 @@ fn instruction_rrcr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::RrcR8(reg)))
 }
 
-fn instruction_rrcmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_rrcmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("rrc")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = deref_hl(i)?;
@@ -983,7 +1371,7 @@ fn instruction_rrcmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::RrcMRhl))
 }
 
-fn instruction_rlr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_rlr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("rl")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u8(i)?;
@@ -991,7 +1379,7 @@ fn instruction_rlr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
     Ok((i, Instruction::RlR8(reg)))
 }
 
-fn instruction_rlmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_rlmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("rl")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = deref_hl(i)?;
@@ -999,7 +1387,7 @@ fn instruction_rlmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::RlMRhl))
 }
 
-fn instruction_rrr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_rrr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("rr")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u8(i)?;
@@ -1007,7 +1395,7 @@ fn instruction_rrr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
     Ok((i, Instruction::RrR8(reg)))
 }
 
-fn instruction_rrmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_rrmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("rr")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = deref_hl(i)?;
@@ -1015,7 +1403,7 @@ fn instruction_rrmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::RrMRhl))
 }
 
-fn instruction_slar8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_slar8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("sla")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u8(i)?;
@@ -1023,7 +1411,7 @@ fn instruction_slar8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::SlaR8(reg)))
 }
 
-fn instruction_slamrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_slamrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("sla")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = deref_hl(i)?;
@@ -1031,7 +1419,7 @@ fn instruction_slamrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::SlaMRhl))
 }
 
-fn instruction_srar8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_srar8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("sra")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u8(i)?;
@@ -1039,7 +1427,7 @@ fn instruction_srar8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::SraR8(reg)))
 }
 
-fn instruction_sramrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_sramrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("sra")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = deref_hl(i)?;
@@ -1047,7 +1435,7 @@ fn instruction_sramrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::SraMRhl))
 }
 
-fn instruction_swapr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_swapr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("swap")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u8(i)?;
@@ -1055,7 +1443,7 @@ fn instruction_swapr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::SwapR8(reg)))
 }
 
-fn instruction_swapmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_swapmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("swap")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = deref_hl(i)?;
@@ -1063,7 +1451,7 @@ fn instruction_swapmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::SwapMRhl))
 }
 
-fn instruction_srlr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_srlr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("srl")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, reg) = parse_reg_u8(i)?;
@@ -1071,7 +1459,7 @@ fn instruction_srlr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>>
     Ok((i, Instruction::SrlR8(reg)))
 }
 
-fn instruction_srlmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_srlmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("srl")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, _) = deref_hl(i)?;
@@ -1079,7 +1467,7 @@ fn instruction_srlmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>
     Ok((i, Instruction::SrlMRhl))
 }
 
-fn instruction_bitbitr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_bitbitr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("bit")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -1089,7 +1477,7 @@ fn instruction_bitbitr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::BitBitR8(expr, reg)))
 }
 
-fn instruction_bitbitmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_bitbitmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("bit")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -1099,7 +1487,7 @@ fn instruction_bitbitmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&s
     Ok((i, Instruction::BitBitMRhl(expr)))
 }
 
-fn instruction_resbitr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_resbitr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("res")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -1109,7 +1497,7 @@ fn instruction_resbitr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::ResBitR8(expr, reg)))
 }
 
-fn instruction_resbitmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_resbitmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("res")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -1119,7 +1507,7 @@ fn instruction_resbitmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&s
     Ok((i, Instruction::ResBitMRhl(expr)))
 }
 
-fn instruction_setbitr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_setbitr8(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("set")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -1129,7 +1517,7 @@ fn instruction_setbitr8(i: &str) -> IResult<&str, Instruction, VerboseError<&str
     Ok((i, Instruction::SetBitR8(expr, reg)))
 }
 
-fn instruction_setbitmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+fn instruction_setbitmrhl(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
     let (i, _) = tag_no_case("set")(i)?;
     let (i, _) = is_a(WHITESPACE)(i)?;
     let (i, expr) = parse_expr(i)?;
@@ -1139,131 +1527,254 @@ fn instruction_setbitmrhl(i: &str) -> IResult<&str, Instruction, VerboseError<&s
     Ok((i, Instruction::SetBitMRhl(expr)))
 }
 
-fn instruction(i: &str) -> IResult<&str, Instruction, VerboseError<&str>> {
+/// Every mnemonic, register and flag must be followed by whitespace, a separator or the end of the line,
+/// so identifiers that start with one, e.g. `callback` or `hl_target`, are parsed as identifiers.
+type KeywordParser = fn(&str) -> IResult<&str, Instruction<&str>, VerboseError<&str>>;
+
+/// Returns the parser for every line starting with a keyword, keyed on the lowercase keyword.
+fn keyword_table() -> &'static HashMap<&'static str, KeywordParser> {
+    static TABLE: OnceLock<HashMap<&'static str, KeywordParser>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let keywords: &[(&'static str, KeywordParser)] = &[
+            // directives
+            ("redef", redef),
+            ("def", def),
+            ("db", direct_bytes),
+            ("dbfill", direct_fill),
+            ("dw", direct_words),
+            ("advance_address", advance_address),
+            ("debug_assert", debug_assert),
+            ("debug_print", debug_print),
+            ("debug_break", |i| {
+                terminated(
+                    value(Instruction::DebugBreak, tag_no_case("debug_break")),
+                    end_line,
+                )(i)
+            }),
+            ("if", conditional_if),
+            ("elif", conditional_elif),
+            ("else", |i| {
+                terminated(value(Instruction::Else, tag_no_case("else")), end_line)(i)
+            }),
+            ("endc", |i| {
+                terminated(value(Instruction::Endc, tag_no_case("endc")), end_line)(i)
+            }),
+            ("include", include),
+            ("incbin", incbin),
+            ("assert", assert),
+            ("print", message),
+            ("warn", message),
+            ("fail", message),
+            ("charmap", charmap),
+            ("section", section),
+            ("export", export),
+            ("global", export),
+            ("namespace", namespace),
+            ("endnamespace", namespace),
+            ("union", union),
+            ("nextu", union),
+            ("endu", union),
+            ("opt", asm_options),
+            ("pusho", state_stack),
+            ("popo", state_stack),
+            ("pushs", state_stack),
+            ("pops", state_stack),
+            ("pragma", state_stack),
+            // instructions
+            ("stop", |i| {
+                terminated(value(Instruction::Stop, tag_no_case("stop")), end_line)(i)
+            }),
+            ("nop", |i| {
+                terminated(value(Instruction::Nop, tag_no_case("nop")), end_line)(i)
+            }),
+            ("halt", |i| {
+                terminated(value(Instruction::Halt, tag_no_case("halt")), end_line)(i)
+            }),
+            ("di", |i| {
+                terminated(value(Instruction::Di, tag_no_case("di")), end_line)(i)
+            }),
+            ("ei", |i| {
+                terminated(value(Instruction::Ei, tag_no_case("ei")), end_line)(i)
+            }),
+            ("reti", |i| {
+                terminated(value(Instruction::Reti, tag_no_case("reti")), end_line)(i)
+            }),
+            ("rrca", |i| {
+                terminated(value(Instruction::Rrca, tag_no_case("rrca")), end_line)(i)
+            }),
+            ("rra", |i| {
+                terminated(value(Instruction::Rra, tag_no_case("rra")), end_line)(i)
+            }),
+            ("cpl", |i| {
+                terminated(value(Instruction::Cpl, tag_no_case("cpl")), end_line)(i)
+            }),
+            ("ccf", |i| {
+                terminated(value(Instruction::Ccf, tag_no_case("ccf")), end_line)(i)
+            }),
+            ("rlca", |i| {
+                terminated(value(Instruction::Rlca, tag_no_case("rlca")), end_line)(i)
+            }),
+            ("rla", |i| {
+                terminated(value(Instruction::Rla, tag_no_case("rla")), end_line)(i)
+            }),
+            ("daa", |i| {
+                terminated(value(Instruction::Daa, tag_no_case("daa")), end_line)(i)
+            }),
+            ("scf", |i| {
+                terminated(value(Instruction::Scf, tag_no_case("scf")), end_line)(i)
+            }),
+            ("ret", |i| {
+                alt((
+                    terminated(
+                        value(Instruction::Ret(Flag::Always), tag_no_case("ret")),
+                        end_line,
+                    ),
+                    instruction_ret,
+                ))(i)
+            }),
+            ("call", |i| {
+                alt((instruction_call_flag, instruction_call_always))(i)
+            }),
+            ("jp", |i| {
+                alt((
+                    instruction_jprhl,
+                    instruction_jpi16_always,
+                    instruction_jpi16_flag,
+                ))(i)
+            }),
+            ("jr", |i| {
+                alt((instruction_jr_always, instruction_jr_flag))(i)
+            }),
+            ("inc", instruction_inc),
+            ("dec", instruction_dec),
+            ("add", |i| {
+                alt((
+                    instruction_addr8,
+                    instruction_addmrhl,
+                    instruction_addi8,
+                    instruction_addrhlr16,
+                    instruction_addrspi8,
+                ))(i)
+            }),
+            ("sub", |i| {
+                alt((instruction_subr8, instruction_submrhl, instruction_subi8))(i)
+            }),
+            ("and", |i| {
+                alt((instruction_andr8, instruction_andmrhl, instruction_andi8))(i)
+            }),
+            ("or", |i| {
+                alt((instruction_orr8, instruction_ormrhl, instruction_ori8))(i)
+            }),
+            ("adc", |i| {
+                alt((instruction_adcr8, instruction_adcmrhl, instruction_adci8))(i)
+            }),
+            ("sbc", |i| {
+                alt((instruction_sbcr8, instruction_sbcmrhl, instruction_sbci8))(i)
+            }),
+            ("xor", |i| {
+                alt((instruction_xorr8, instruction_xormrhl, instruction_xori8))(i)
+            }),
+            ("cp", |i| {
+                alt((instruction_cpr8, instruction_cpmrhl, instruction_cpi8))(i)
+            }),
+            ("rst", instruction_rst),
+            ("ld", |i| {
+                alt((
+                    instruction_ldr8r8,
+                    instruction_ldr8i8,
+                    instruction_ldrsprhl,
+                    instruction_ldmi16rsp,
+                    instruction_ldmr16ra,
+                    instruction_ldramr16,
+                    instruction_ldimrhlra,
+                    instruction_lddmrhlra,
+                    instruction_ldiramrhl,
+                    instruction_lddramrhl,
+                    instruction_ldmrhlr8,
+                    instruction_ldmrhli8,
+                    instruction_ldr8mrhl,
+                    instruction_ldhramrc,
+                    instruction_ldhmrcra,
+                    instruction_ldhmi8ra,
+                    instruction_ldhrami8,
+                    instruction_ldrhlrspi8,
+                    instruction_ldmi16ra,
+                    instruction_ldrami16,
+                    instruction_ldr16i16,
+                ))(i)
+            }),
+            ("ldi", |i| {
+                alt((instruction_ldimrhlra, instruction_ldiramrhl))(i)
+            }),
+            ("ldd", |i| {
+                alt((instruction_lddmrhlra, instruction_lddramrhl))(i)
+            }),
+            ("ldh", |i| {
+                alt((
+                    instruction_ldhramrc,
+                    instruction_ldhmrcra,
+                    instruction_ldhmi8ra,
+                    instruction_ldhrami8,
+                ))(i)
+            }),
+            ("push", instruction_push),
+            ("pop", instruction_pop),
+            ("rlc", |i| alt((instruction_rlcr8, instruction_rlcmrhl))(i)),
+            ("rrc", |i| alt((instruction_rrcr8, instruction_rrcmrhl))(i)),
+            ("rl", |i| alt((instruction_rlr8, instruction_rlmrhl))(i)),
+            ("rr", |i| alt((instruction_rrr8, instruction_rrmrhl))(i)),
+            ("sla", |i| alt((instruction_slar8, instruction_slamrhl))(i)),
+            ("sra", |i| alt((instruction_srar8, instruction_sramrhl))(i)),
+            ("swap", |i| {
+                alt((instruction_swapr8, instruction_swapmrhl))(i)
+            }),
+            ("srl", |i| alt((instruction_srlr8, instruction_srlmrhl))(i)),
+            ("bit", |i| {
+                alt((instruction_bitbitr8, instruction_bitbitmrhl))(i)
+            }),
+            ("res", |i| {
+                alt((instruction_resbitr8, instruction_resbitmrhl))(i)
+            }),
+            ("set", |i| {
+                alt((instruction_setbitr8, instruction_setbitmrhl))(i)
+            }),
+        ];
+        keywords.iter().copied().collect()
+    })
+}
+
+/// Returns the first word of the line in lowercase, or None if it is too long to be a keyword.
+fn keyword<'a>(i: &str, buffer: &'a mut [u8; 16]) -> Option<&'a str> {
+    let len = i
+        .find(|x: char| !(x.is_ascii_alphanumeric() || x == '_'))
+        .unwrap_or(i.len());
+    let word = buffer.get_mut(..len)?;
+    word.copy_from_slice(&i.as_bytes()[..len]);
+    word.make_ascii_lowercase();
+    std::str::from_utf8(word).ok()
+}
+
+fn instruction(i: &str) -> IResult<&str, Instruction<&str>, VerboseError<&str>> {
+    // Only the parser for the first word is tried, instead of every parser.
+    // When it fails the line can still be a label or constant named after a keyword e.g. `Stop:`
+    let mut buffer = [0; 16];
+    if let Some(parser) = keyword(i, &mut buffer).and_then(|x| keyword_table().get(x)) {
+        if let Ok(result) = parser(i) {
+            return Ok(result);
+        }
+    }
+
     alt((
         label,
+        anonymous_label,
         equ,
-        direct_bytes,
-        direct_words,
-        advance_address,
-        // instructions
-        alt((
-            terminated(value(Instruction::Stop, tag_no_case("stop")), end_line),
-            terminated(value(Instruction::Nop, tag_no_case("nop")), end_line),
-            terminated(value(Instruction::Halt, tag_no_case("halt")), end_line),
-            terminated(value(Instruction::Di, tag_no_case("di")), end_line),
-            terminated(value(Instruction::Ei, tag_no_case("ei")), end_line),
-            terminated(value(Instruction::Reti, tag_no_case("reti")), end_line),
-            terminated(value(Instruction::Rrca, tag_no_case("rrca")), end_line),
-            terminated(value(Instruction::Rra, tag_no_case("rra")), end_line),
-            terminated(value(Instruction::Cpl, tag_no_case("cpl")), end_line),
-            terminated(value(Instruction::Ccf, tag_no_case("ccf")), end_line),
-            terminated(value(Instruction::Rlca, tag_no_case("rlca")), end_line),
-            terminated(value(Instruction::Rla, tag_no_case("rla")), end_line),
-            terminated(value(Instruction::Daa, tag_no_case("daa")), end_line),
-            terminated(value(Instruction::Scf, tag_no_case("scf")), end_line),
-            terminated(
-                value(Instruction::Ret(Flag::Always), tag_no_case("ret")),
-                end_line,
-            ),
-        )),
-        alt((
-            instruction_ret,
-            instruction_call_flag,
-            instruction_call_always,
-            instruction_jprhl,
-            instruction_jpi16_always,
-            instruction_jpi16_flag,
-            instruction_jr_always,
-            instruction_jr_flag,
-            instruction_inc,
-            instruction_dec,
-            instruction_addr8,
-            instruction_addmrhl,
-            instruction_addi8,
-            instruction_addrhlr16,
-            instruction_addrspi8,
-            instruction_subr8,
-            instruction_submrhl,
-            instruction_subi8,
-            instruction_andr8,
-            instruction_andmrhl,
-            instruction_andi8,
-        )),
-        alt((
-            instruction_orr8,
-            instruction_ormrhl,
-            instruction_ori8,
-            instruction_adcr8,
-            instruction_adcmrhl,
-            instruction_adci8,
-            instruction_sbcr8,
-            instruction_sbcmrhl,
-            instruction_sbci8,
-            instruction_xorr8,
-            instruction_xormrhl,
-            instruction_xori8,
-            instruction_cpr8,
-            instruction_cpmrhl,
-            instruction_cpi8,
-        )),
-        alt((
-            instruction_ldr8r8,
-            instruction_ldr8i8,
-            instruction_ldrsprhl,
-            instruction_ldmi16rsp,
-            instruction_ldmr16ra,
-            instruction_ldramr16,
-            instruction_ldimrhlra,
-            instruction_lddmrhlra,
-            instruction_ldiramrhl,
-            instruction_lddramrhl,
-            instruction_ldmrhlr8,
-            instruction_ldmrhli8,
-            instruction_ldr8mrhl,
-            instruction_ldhramrc,
-            instruction_ldhmrcra,
-            instruction_ldhmi8ra,
-            instruction_ldhrami8,
-            instruction_ldrhlrspi8,
-            instruction_ldmi16ra,
-            instruction_ldrami16,
-            instruction_ldr16i16,
-        )),
-        alt((
-            instruction_push,
-            instruction_pop,
-            instruction_rlcr8,
-            instruction_rlcmrhl,
-            instruction_rrcr8,
-            instruction_rrcmrhl,
-            instruction_rlr8,
-            instruction_rlmrhl,
-            instruction_rrr8,
-            instruction_rrmrhl,
-            instruction_slar8,
-            instruction_slamrhl,
-            instruction_srar8,
-            instruction_sramrhl,
-            instruction_swapr8,
-            instruction_swapmrhl,
-            instruction_srlr8,
-            instruction_srlmrhl,
-        )),
-        alt((
-            instruction_bitbitr8,
-            instruction_bitbitmrhl,
-            instruction_resbitr8,
-            instruction_resbitmrhl,
-            instruction_setbitr8,
-            instruction_setbitmrhl,
-        )),
+        set,
         // line containing only whitespace/empty
         value(Instruction::EmptyLine, end_line),
     ))(i)
 }
 
-fn instruction_option(i: &str) -> IResult<&str, Option<Instruction>, VerboseError<&str>> {
+fn instruction_option(i: &str) -> IResult<&str, Option<Instruction<&str>>, VerboseError<&str>> {
     // ignore preceding whitespace
     let (i, _) = opt(is_a(WHITESPACE))(i)?;
 
@@ -1275,21 +1786,1219 @@ fn instruction_option(i: &str) -> IResult<&str, Option<Instruction>, VerboseErro
     Ok((i, instruction))
 }
 
-fn instructions(i: &str) -> IResult<&str, Vec<Option<Instruction>>, VerboseError<&str>> {
-    many0(terminated(instruction_option, line_ending))(i)
+/// Removes the comment and surrounding whitespace from a line
+fn strip_comment(line: &str) -> &str {
+    line.split(';').next().unwrap().trim()
+}
+
+/// Returns the line before a trailing `\`, when the line continues onto the next line.
+fn continuation(line: &str) -> Option<&str> {
+    line.split(';')
+        .next()
+        .unwrap()
+        .trim_end()
+        .strip_suffix('\\')
+}
+
+/// Converts the bytes of an asm file into text, removing the UTF-8 byte order mark if there is one.
+///
+/// Returns an error describing where the first invalid UTF-8 is.
+pub fn decode_asm(bytes: &[u8]) -> Result<&str, Error> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Ok(text.strip_prefix('\u{FEFF}').unwrap_or(text)),
+        Err(err) => {
+            let offset = err.valid_up_to();
+            let line = SourceLines::new(&String::from_utf8_lossy(&bytes[..offset])).count();
+            bail!(
+                "Invalid UTF-8 at byte offset {} on line {}, asm files must be UTF-8",
+                offset,
+                line.max(1)
+            )
+        }
+    }
+}
+
+/// Splits text into lines ending with `\n`, `\r\n` or `\r`, ignoring a UTF-8 byte order mark.
+/// Like [str::lines] the last line can end without a line ending.
+pub(crate) struct SourceLines<'a> {
+    rest: &'a str,
+}
+
+impl<'a> SourceLines<'a> {
+    pub(crate) fn new(text: &'a str) -> SourceLines<'a> {
+        SourceLines {
+            rest: text.strip_prefix('\u{FEFF}').unwrap_or(text),
+        }
+    }
+}
+
+impl<'a> SourceLines<'a> {
+    /// Returns the next line and its line ending, which is empty when the text ends without one.
+    fn next_with_ending(&mut self) -> Option<(&'a str, &'a str)> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let (line, ending) = match self.rest.find(['\n', '\r']) {
+            Some(end) if self.rest[end..].starts_with("\r\n") => {
+                (&self.rest[..end], &self.rest[end..end + 2])
+            }
+            Some(end) => (&self.rest[..end], &self.rest[end..end + 1]),
+            None => (self.rest, ""),
+        };
+        self.rest = &self.rest[line.len() + ending.len()..];
+        Some((line, ending))
+    }
+}
+
+impl<'a> Iterator for SourceLines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.next_with_ending().map(|(line, _)| line)
+    }
+}
+
+/// Splits text into lines, joining each line that ends with `\` to the next line.
+/// Also returns how many lines were joined onto each line, so line numbers can be kept intact.
+struct LogicalLines<'a> {
+    lines: SourceLines<'a>,
+}
+
+impl<'a> LogicalLines<'a> {
+    fn new(text: &'a str) -> LogicalLines<'a> {
+        LogicalLines {
+            lines: SourceLines::new(text),
+        }
+    }
 }
 
-/// Parses the text in the provided &str into a Vec<Option<Instruction>>
-/// Instructions are None when that line fails to parse.
-pub fn parse_asm(text: &str) -> Result<Vec<Option<Instruction>>, Error> {
-    // Ensure a trailing \n is included TODO: Avoid this copy, should be able to handle this in the parser combinator
-    let mut text = String::from(text);
-    if text.chars().last().map(|x| x != '\n').unwrap_or(false) {
-        text.push('\n');
+impl<'a> Iterator for LogicalLines<'a> {
+    type Item = (Cow<'a, str>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.lines.next()?;
+        Some(join_continued(
+            Cow::Borrowed(line),
+            self.lines.by_ref().map(Cow::Borrowed),
+        ))
     }
+}
 
-    match instructions(&text) {
-        Ok(instructions) => Ok(instructions.1),
-        Err(err) => bail!("{:?}", err), // Convert error to text immediately to avoid lifetime issues
+/// Joins the lines following the line onto it, while they end with `\`.
+/// Returns the joined line and how many lines were joined onto it.
+fn join_continued<'a>(
+    line: Cow<'a, str>,
+    lines: impl Iterator<Item = Cow<'a, str>>,
+) -> (Cow<'a, str>, usize) {
+    let mut joined = match continuation(&line) {
+        Some(start) => start.to_string(),
+        None => return (line, 0),
+    };
+    let mut count = 0;
+    for line in lines {
+        count += 1;
+        match continuation(&line) {
+            Some(part) => joined.push_str(part),
+            None => {
+                joined.push_str(&line);
+                break;
+            }
+        }
+    }
+    (Cow::Owned(joined), count)
+}
+
+/// Reads lines from a reader, split the same as [SourceLines].
+/// Reading stops at the first io error or invalid UTF-8, which is stored in error.
+struct ReaderLines<R> {
+    reader: R,
+    /// lines from the last read that are yet to be returned, more than one when split by a `\r`
+    pending: std::vec::IntoIter<String>,
+    /// the number of lines read so far
+    lines: usize,
+    /// the number of bytes read so far
+    offset: usize,
+    error: Option<Error>,
+}
+
+impl<R: BufRead> Iterator for ReaderLines<R> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if let Some(line) = self.pending.next() {
+                return Some(line);
+            }
+            if self.error.is_some() {
+                return None;
+            }
+
+            let mut bytes = vec![];
+            match self.reader.read_until(b'\n', &mut bytes) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(err) => {
+                    self.error = Some(anyhow!("Cannot read asm because: {}", err));
+                    return None;
+                }
+            }
+            let text = match std::str::from_utf8(&bytes) {
+                Ok(text) => text,
+                Err(err) => {
+                    let valid = String::from_utf8_lossy(&bytes[..err.valid_up_to()]);
+                    self.error = Some(anyhow!(
+                        "Invalid UTF-8 at byte offset {} on line {}, asm files must be UTF-8",
+                        self.offset + err.valid_up_to(),
+                        self.lines + valid.matches('\r').count() + 1
+                    ));
+                    return None;
+                }
+            };
+            let mut text = text.strip_suffix('\n').unwrap_or(text);
+            text = text.strip_suffix('\r').unwrap_or(text);
+            if self.offset == 0 {
+                text = text.strip_prefix('\u{FEFF}').unwrap_or(text);
+            }
+            let lines: Vec<String> = text.split('\r').map(String::from).collect();
+            self.lines += lines.len();
+            self.offset += bytes.len();
+            self.pending = lines.into_iter();
+        }
+    }
+}
+
+/// Returns the name of the macro if the line is a macro definition e.g. `MyMacro: MACRO`
+fn macro_definition(line: &str) -> Option<&str> {
+    let line = strip_comment(line);
+    let (name, rest) = line.split_once(':')?;
+    if !name.is_empty()
+        && name.chars().all(|x| IDENT.contains(x))
+        && rest.trim().eq_ignore_ascii_case("macro")
+    {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// Parses a string constant definition e.g. `Name EQUS "player_x"` or `DEF Name EQUS "player_x"`,
+/// returning if it is a REDEF, the name and the text.
+fn string_constant_definition(i: &str) -> IResult<&str, (bool, &str, &str), VerboseError<&str>> {
+    let (i, _) = opt(is_a(WHITESPACE))(i)?;
+    let (i, keyword) = opt(terminated(
+        alt((tag_no_case("REDEF"), tag_no_case("DEF"))),
+        is_a(WHITESPACE),
+    ))(i)?;
+    let redef = keyword.filter(|x| x.eq_ignore_ascii_case("REDEF"));
+    let (i, name) = is_a(IDENT)(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, _) = tag_no_case("EQUS")(i)?;
+    let (i, _) = is_a(WHITESPACE)(i)?;
+    let (i, text) = parse_string(i)?;
+    let (i, _) = end_line(i)?;
+    Ok((i, (redef.is_some(), name, text)))
+}
+
+/// Rewrites the numbers without a prefix in the line from the radix to decimal, outside of strings and comments.
+/// Numbers with a prefix, fixed point literals and `opt` directives are left as is.
+fn apply_radix(line: &str, radix: u32) -> Cow<'_, str> {
+    let code = strip_comment(line);
+    let is_opt = code.get(..3).is_some_and(|x| x.eq_ignore_ascii_case("opt"))
+        && code[3..].starts_with(|x| WHITESPACE.contains(x));
+    if radix == 10 || is_opt {
+        return Cow::Borrowed(line);
+    }
+
+    let mut result = String::new();
+    let mut in_string = false;
+    let mut previous = None;
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        if !in_string && c == ';' {
+            result.push_str(rest);
+            break;
+        }
+        if !in_string && IDENT.contains(c) {
+            let len = rest.find(|x| !IDENT.contains(x)).unwrap_or(rest.len());
+            let word = &rest[..len];
+            rest = &rest[len..];
+            let prefixed = word.starts_with("0x") || word.starts_with("0b");
+            let fixed = previous == Some('.') || rest.starts_with('.');
+            let after_prefix = matches!(previous, Some('$') | Some('%'));
+            match i64::from_str_radix(word, radix) {
+                Ok(value) if c.is_ascii_digit() && !prefixed && !fixed && !after_prefix => {
+                    result.push_str(&value.to_string())
+                }
+                _ => result.push_str(word),
+            }
+            previous = word.chars().last();
+            continue;
+        }
+        if c == '"' {
+            in_string = !in_string;
+        }
+        result.push(c);
+        previous = Some(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    Cow::Owned(result)
+}
+
+/// Splits macro arguments on commas that are not within strings or parentheses.
+fn macro_arguments(text: &str) -> Vec<String> {
+    let mut arguments = vec![];
+    let mut current = String::new();
+    let mut in_string = false;
+    let mut depth = 0;
+    for c in text.chars() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                arguments.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !arguments.is_empty() {
+        arguments.push(current.trim().to_string());
+    }
+    arguments
+}
+
+/// Replaces calls to the string functions with their result, outside of strings and comments:
+/// * `STRLEN("text")` becomes the number of characters in the text
+/// * `STRCAT("text", "more")` becomes the joined strings
+/// * `STRSUB("text", position, length)` becomes the part of the text starting at the 1-based position,
+///   a negative position counts from the end and without a length the rest of the text is used
+///
+/// Calls can be nested and the numeric arguments can be any expression of constants.
+fn expand_string_functions(line: &str) -> Result<Cow<'_, str>, Error> {
+    let mut result = String::new();
+    let mut in_string = false;
+    let mut rest = line;
+    let mut replaced = false;
+    while let Some(c) = rest.chars().next() {
+        if !in_string && c == ';' {
+            result.push_str(rest);
+            break;
+        }
+        if !in_string && IDENT.contains(c) {
+            let len = rest.find(|x| !IDENT.contains(x)).unwrap_or(rest.len());
+            let word = &rest[..len];
+            rest = &rest[len..];
+            let is_function = ["STRLEN", "STRCAT", "STRSUB"]
+                .iter()
+                .any(|x| x.eq_ignore_ascii_case(word));
+            match rest.strip_prefix('(') {
+                Some(arguments) if is_function => {
+                    let end = match closing_paren(arguments) {
+                        Some(end) => end,
+                        None => bail!("{} is missing a closing parenthesis", word),
+                    };
+                    let inner = expand_string_functions(&arguments[..end])?;
+                    result.push_str(&string_function(word, &macro_arguments(&inner))?);
+                    rest = &arguments[end + 1..];
+                    replaced = true;
+                }
+                _ => result.push_str(word),
+            }
+            continue;
+        }
+        if c == '"' {
+            in_string = !in_string;
+        }
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    if replaced {
+        Ok(Cow::Owned(result))
+    } else {
+        Ok(Cow::Borrowed(line))
+    }
+}
+
+/// Returns the index of the `)` that closes the text, skipping over strings and nested parentheses.
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string && depth == 0 => return Some(i),
+            ')' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the result of the string function with the arguments, as asm text.
+fn string_function(name: &str, arguments: &[String]) -> Result<String, Error> {
+    let name = name.to_uppercase();
+    let string = |index: usize| -> Result<&str, Error> {
+        let argument = arguments.get(index).map(|x| x.as_str()).unwrap_or("");
+        match argument.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+            Some(text) if !text.contains('"') => Ok(text),
+            _ => bail!(
+                "{} argument {} must be a string but was: {}",
+                name,
+                index + 1,
+                argument
+            ),
+        }
+    };
+    let number = |index: usize| -> Result<i64, Error> {
+        let argument = arguments.get(index).map(|x| x.as_str()).unwrap_or("");
+        let value = match parse_expr(argument) {
+            Ok(("", expr)) => expr
+                .map_text(&mut |x| x.to_string())
+                .run(&HashMap::new())
+                .ok(),
+            _ => None,
+        };
+        match value {
+            Some(value) => Ok(value),
+            None => bail!(
+                "{} argument {} must be a constant number but was: {}",
+                name,
+                index + 1,
+                argument
+            ),
+        }
+    };
+    let (min, max) = match name.as_str() {
+        "STRLEN" => (1, 1),
+        "STRCAT" => (1, usize::MAX),
+        _ => (2, 3),
+    };
+    if arguments.len() < min || arguments.len() > max {
+        bail!("{} cannot take {} arguments", name, arguments.len());
+    }
+
+    match name.as_str() {
+        "STRLEN" => Ok(string(0)?.chars().count().to_string()),
+        "STRCAT" => {
+            let mut text = String::new();
+            for i in 0..arguments.len() {
+                text.push_str(string(i)?);
+            }
+            Ok(format!("\"{}\"", text))
+        }
+        _ => {
+            let text = string(0)?;
+            let chars = text.chars().count() as i64;
+            let position = number(1)?;
+            let start = match position {
+                p if p > 0 && p <= chars + 1 => p - 1,
+                p if p < 0 && p >= -chars => chars + p,
+                p => bail!(
+                    "STRSUB position {} is outside of the {} characters of \"{}\"",
+                    p,
+                    chars,
+                    text
+                ),
+            };
+            let len = match arguments.len() {
+                3 => number(2)?,
+                _ => chars - start,
+            };
+            if len < 0 || len > chars - start {
+                bail!(
+                    "STRSUB length {} from position {} is outside of the {} characters of \"{}\"",
+                    len,
+                    position,
+                    chars,
+                    text
+                );
+            }
+            let sub: String = text
+                .chars()
+                .skip(start as usize)
+                .take(len as usize)
+                .collect();
+            Ok(format!("\"{}\"", sub))
+        }
+    }
+}
+
+/// Parses asm one line at a time, expanding macros as they are encountered.
+struct LineParser {
+    macros: HashMap<String, Vec<String>>,
+    /// string constants declared with EQUS
+    strings: HashMap<String, String>,
+    /// used to generate unique labels via \@
+    invocations: usize,
+    /// the name and body of the macro currently being defined
+    defining: Option<(String, Vec<String>)>,
+    /// the number of instructions output by previous lines
+    emitted: usize,
+    /// the base of numbers without a prefix, set by `opt radix=N`
+    radix: u32,
+    /// radixes saved by `pusho`
+    pushed_radixes: Vec<u32>,
+}
+
+impl LineParser {
+    fn new() -> LineParser {
+        LineParser {
+            macros: HashMap::new(),
+            strings: HashMap::new(),
+            invocations: 0,
+            defining: None,
+            emitted: 0,
+            radix: 10,
+            pushed_radixes: vec![],
+        }
+    }
+
+    /// Parses a single line, without its line ending, pushing the resulting instructions to output.
+    ///
+    /// Macro definitions become empty lines.
+    /// Invocations are replaced by the lines of the macro with the arguments substituted.
+    ///
+    /// Invocations can expand to multiple lines, so the index of the instructions after an invocation no longer
+    /// matches the source file, [parse_asm_spanned] keeps track of the source lines.
+    fn parse_line(
+        &mut self,
+        line: &str,
+        output: &mut Vec<Result<Instruction, Diagnostic>>,
+    ) -> Result<(), Error> {
+        let first = output.len();
+        if let Some((name, body)) = &mut self.defining {
+            output.push(Ok(Instruction::EmptyLine));
+            if strip_comment(line).eq_ignore_ascii_case("endm") {
+                let name = name.clone();
+                let body = std::mem::take(body);
+                self.defining = None;
+                if self.macros.insert(name.clone(), body).is_some() {
+                    bail!("Macro {} is defined twice", name);
+                }
+            } else {
+                body.push(line.to_string());
+            }
+        } else if let Some(name) = macro_definition(line) {
+            output.push(Ok(Instruction::EmptyLine));
+            self.defining = Some((name.to_string(), vec![]));
+        } else {
+            let emitted = self.emitted;
+            let radix = self.radix;
+            self.expand_line(line, 0, &mut |line| {
+                let number = emitted + output.len() - first + 1;
+                let line = apply_radix(line, radix);
+                output.push(parse_checked(&line, number).map(Instruction::into_owned));
+            })?;
+            for instruction in &output[first..] {
+                match instruction {
+                    Ok(Instruction::Opt(options)) => {
+                        for option in options {
+                            if let AsmOption::Radix(radix) = option {
+                                self.radix = *radix;
+                            }
+                        }
+                    }
+                    Ok(Instruction::PushO) => self.pushed_radixes.push(self.radix),
+                    Ok(Instruction::PopO) => match self.pushed_radixes.pop() {
+                        Some(radix) => self.radix = radix,
+                        None => bail!("POPO has no matching PUSHO"),
+                    },
+                    _ => {}
+                }
+            }
+        }
+        self.emitted += output.len() - first;
+        Ok(())
+    }
+
+    /// Outputs an empty line for each line that was joined onto the previous line by a `\`.
+    fn joined_lines(&mut self, count: usize, output: &mut Vec<Result<Instruction, Diagnostic>>) {
+        output.extend((0..count).map(|_| Ok(Instruction::EmptyLine)));
+        self.emitted += count;
+    }
+
+    /// Returns true if the line is parsed as written, it doesnt define or invoke a macro, use a string constant
+    /// or change the options tracked by [LineParser::parse_line].
+    fn is_plain(&self, line: &str) -> bool {
+        if self.defining.is_some()
+            || self.radix != 10
+            || macro_definition(line).is_some()
+            || string_constant_definition(line).is_ok()
+            || !matches!(expand_string_functions(line), Ok(Cow::Borrowed(_)))
+        {
+            return false;
+        }
+        let name = strip_comment(line)
+            .split(|x: char| WHITESPACE.contains(x))
+            .next()
+            .unwrap();
+        !self.macros.contains_key(name)
+            && !["opt", "pusho", "popo"]
+                .iter()
+                .any(|x| name.eq_ignore_ascii_case(x))
+            && (self.strings.is_empty() || self.expand_strings(line).is_ok_and(|x| x == line))
+    }
+
+    /// Returns an error if the text ended in an invalid state.
+    fn finish(self) -> Result<(), Error> {
+        match self.defining {
+            Some((name, _)) => bail!("Macro {} is missing ENDM", name),
+            None => Ok(()),
+        }
+    }
+
+    fn expand_line(
+        &mut self,
+        line: &str,
+        depth: usize,
+        emit: &mut dyn FnMut(&str),
+    ) -> Result<(), Error> {
+        let line = &expand_string_functions(line)?;
+        if let Ok((_, (redef, name, text))) = string_constant_definition(line) {
+            if self
+                .strings
+                .insert(name.to_string(), text.to_string())
+                .is_some()
+                && !redef
+            {
+                bail!("String constant {} is defined twice", name);
+            }
+            emit("");
+            return Ok(());
+        }
+        let line = &self.expand_strings(line)?;
+        let line = &expand_string_functions(line)?;
+
+        let stripped = strip_comment(line);
+        let name = stripped
+            .split(|x: char| WHITESPACE.contains(x))
+            .next()
+            .unwrap();
+        let body = match self.macros.get(name) {
+            Some(body) => body.clone(),
+            None => {
+                emit(line);
+                return Ok(());
+            }
+        };
+        if depth > 64 {
+            bail!("Macro {} is nested too deeply, is it recursive?", name);
+        }
+
+        let arguments = macro_arguments(&stripped[name.len()..]);
+        self.invocations += 1;
+        let unique = format!("_{}", self.invocations);
+        for body_line in body {
+            let mut expanded = String::new();
+            let mut chars = body_line.chars().peekable();
+            while let Some(c) = chars.next() {
+                if c != '\\' {
+                    expanded.push(c);
+                    continue;
+                }
+                match chars.peek().cloned() {
+                    Some('@') => {
+                        chars.next();
+                        expanded.push_str(&unique);
+                    }
+                    Some(digit @ '1'..='9') => {
+                        chars.next();
+                        let index = digit.to_digit(10).unwrap() as usize - 1;
+                        match arguments.get(index) {
+                            Some(argument) => expanded.push_str(argument),
+                            None => bail!(
+                                "Macro {} uses argument \\{} but was only given {} arguments",
+                                name,
+                                index + 1,
+                                arguments.len()
+                            ),
+                        }
+                    }
+                    _ => expanded.push(c),
+                }
+            }
+            self.expand_line(&expanded, depth + 1, emit)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the identifiers that are string constants with their text, outside of strings and comments.
+    /// The text is expanded again, so string constants can refer to other string constants.
+    fn expand_strings(&self, line: &str) -> Result<String, Error> {
+        if self.strings.is_empty() {
+            return Ok(line.to_string());
+        }
+
+        let mut line = line.to_string();
+        for _ in 0..64 {
+            let mut expanded = String::new();
+            let mut replaced = false;
+            let mut in_string = false;
+            let mut rest = line.as_str();
+            while let Some(c) = rest.chars().next() {
+                if !in_string && c == ';' {
+                    expanded.push_str(rest);
+                    break;
+                }
+                if !in_string && IDENT.contains(c) {
+                    let len = rest.find(|x| !IDENT.contains(x)).unwrap_or(rest.len());
+                    let word = &rest[..len];
+                    match self.strings.get(word) {
+                        Some(text) => {
+                            expanded.push_str(text);
+                            replaced = true;
+                        }
+                        None => expanded.push_str(word),
+                    }
+                    rest = &rest[len..];
+                    continue;
+                }
+                if c == '"' {
+                    in_string = !in_string;
+                }
+                expanded.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+            if !replaced {
+                return Ok(expanded);
+            }
+            line = expanded;
+        }
+        bail!(
+            "String constants are nested too deeply, is one recursive? {}",
+            line
+        )
+    }
+}
+
+/// Parses the text one line at a time, the instructions are the same as returned by [parse_asm].
+///
+/// Nothing is collected into a Vec, so huge files can be processed incrementally and processing can stop early.
+/// Errors that are not about the syntax of a line, e.g. a macro that is defined twice or is missing ENDM,
+/// are returned as a [Diagnostic] with a message, after which the iterator ends.
+pub fn parse_asm_iter(text: &str) -> impl Iterator<Item = Result<Instruction, Diagnostic>> + '_ {
+    ParseAsmIter {
+        lines: LogicalLines::new(text),
+        parser: Some(LineParser::new()),
+        pending: vec![].into_iter(),
+    }
+}
+
+struct ParseAsmIter<'a> {
+    lines: LogicalLines<'a>,
+    /// None once the text is finished or an error ended the iteration
+    parser: Option<LineParser>,
+    /// instructions from the current line that are yet to be returned
+    pending: std::vec::IntoIter<Result<Instruction, Diagnostic>>,
+}
+
+impl Iterator for ParseAsmIter<'_> {
+    type Item = Result<Instruction, Diagnostic>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.next() {
+                return Some(item);
+            }
+            let parser = self.parser.as_mut()?;
+            let (line, joined) = match self.lines.next() {
+                Some(line) => line,
+                None => {
+                    let parser = self.parser.take().unwrap();
+                    let number = parser.emitted + 1;
+                    return parser
+                        .finish()
+                        .err()
+                        .map(|err| Err(Diagnostic::error("", number, err.to_string())));
+                }
+            };
+            let mut output = vec![];
+            match parser.parse_line(&line, &mut output) {
+                Ok(()) => parser.joined_lines(joined, &mut output),
+                Err(err) => {
+                    let number = parser.emitted + output.len() + 1;
+                    output.push(Err(Diagnostic::error(&line, number, err.to_string())));
+                    self.parser = None;
+                }
+            }
+            self.pending = output.into_iter();
+        }
+    }
+}
+
+/// Parses a single line without macro expansion.
+/// Returns None when the line fails to parse.
+fn parse_line(line: &str) -> Option<Instruction<&str>> {
+    match instruction_option(line) {
+        Ok((_, instruction)) => instruction,
+        Err(_) => None,
+    }
+}
+
+/// Parses a single line, returning a diagnostic when it fails to parse or a constant operand does not fit.
+fn parse_checked(line: &str, number: usize) -> Result<Instruction<&str>, Diagnostic> {
+    let instruction = match parse_line(line) {
+        Some(instruction) => instruction,
+        None => {
+            let overflowing = number_ranges(line).into_iter().find(|x| {
+                is_number(&line[x.clone()])
+                    && !matches!(parse_constant(&line[x.clone()]), Ok(("", _)))
+            });
+            return Err(match overflowing {
+                Some(columns) => {
+                    let message = format!(
+                        "Value {} is out of range, numbers must fit in 64 bits",
+                        &line[columns.clone()]
+                    );
+                    Diagnostic::error_at(line, number, columns, message)
+                }
+                None => Diagnostic::new(line, number),
+            });
+        }
+    };
+
+    for (expr, min, max, operand) in constant_operands(&instruction) {
+        let (literal, value) = match expr {
+            Expr::Const(value) => (*value, *value),
+            Expr::Unary(unary) => match (&unary.expr, &unary.operator) {
+                (Expr::Const(value), UnaryOperator::Minus) => (*value, value.wrapping_neg()),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        if (min..=max).contains(&value) {
+            continue;
+        }
+        let message = format!(
+            "Value {} is out of range for {}, which must be from {} to {}",
+            value, operand, min, max
+        );
+        let columns = number_ranges(line).into_iter().find(
+            |x| matches!(parse_constant(&line[x.clone()]), Ok(("", parsed)) if parsed == literal),
+        );
+        return Err(match columns {
+            Some(columns) => Diagnostic::error_at(line, number, columns, message),
+            None => Diagnostic::error(line, number, message),
+        });
+    }
+    Ok(instruction)
+}
+
+/// Returns the expressions of the instruction that are stored in a fixed number of bits,
+/// with the smallest and largest value that fits and a description of the operand.
+fn constant_operands<'a, S>(
+    instruction: &'a Instruction<S>,
+) -> Vec<(&'a Expr<S>, i64, i64, &'static str)> {
+    const BYTE: (i64, i64, &str) = (-0x80, 0xFF, "an 8 bit operand");
+    const WORD: (i64, i64, &str) = (-0x8000, 0xFFFF, "a 16 bit operand");
+    let with = |exprs: Vec<&'a Expr<S>>, (min, max, operand): (i64, i64, &'static str)| {
+        exprs
+            .into_iter()
+            .map(|expr| (expr, min, max, operand))
+            .collect()
+    };
+    match instruction {
+        Instruction::DbExpr8(expr) => with(vec![expr], BYTE),
+        Instruction::DbExprList8(exprs) => with(exprs.iter().collect(), BYTE),
+        Instruction::DbString(items) => with(
+            items
+                .iter()
+                .filter_map(|x| match x {
+                    DbItem::Expr(expr) => Some(expr),
+                    DbItem::String(_) => None,
+                })
+                .collect(),
+            BYTE,
+        ),
+        Instruction::DbFill(_, value) => with(vec![value], BYTE),
+        Instruction::DbExpr16(expr) => with(vec![expr], WORD),
+        Instruction::DbExprList16(exprs) => with(exprs.iter().collect(), WORD),
+        instruction => match instruction.encoding().map(|x| x.operand) {
+            Some(Operand::I8(expr)) => with(vec![expr], BYTE),
+            Some(Operand::I16(expr)) => with(vec![expr], WORD),
+            Some(Operand::S8(expr)) => with(vec![expr], (-0x80, 0x7F, "a signed 8 bit operand")),
+            Some(Operand::Bit(expr)) => with(vec![expr], (0, 7, "a bit index")),
+            _ => vec![],
+        },
+    }
+}
+
+/// Returns the byte range of each number in the line, outside of strings and comments.
+fn number_ranges(line: &str) -> Vec<Range<usize>> {
+    let bytes = line.as_bytes();
+    let is_ident = |i: usize| bytes.get(i).is_some_and(|x| IDENT.contains(*x as char));
+    let mut ranges = vec![];
+    let mut in_string = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let prefixed =
+            (c == b'$' || c == b'%') && bytes.get(i + 1).is_some_and(u8::is_ascii_hexdigit);
+        if c == b'"' {
+            in_string = !in_string;
+        } else if !in_string && c == b';' {
+            break;
+        } else if !in_string && (c.is_ascii_digit() || prefixed) && (i == 0 || !is_ident(i - 1)) {
+            let start = i;
+            i += 1;
+            while is_ident(i) || bytes.get(i) == Some(&b'.') {
+                i += 1;
+            }
+            ranges.push(start..i);
+            continue;
+        }
+        i += 1;
+    }
+    ranges
+}
+
+/// Returns true if the text is a number without a fractional part, regardless of whether it fits in 64 bits.
+fn is_number(text: &str) -> bool {
+    let (digits, is_digit): (&str, fn(char) -> bool) =
+        if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+            (digits, is_hex)
+        } else if let Some(digits) = text.strip_prefix("0b").or_else(|| text.strip_prefix('%')) {
+            (digits, is_bin)
+        } else {
+            (text, is_dec)
+        };
+    !digits.is_empty() && digits.chars().all(is_digit)
+}
+
+/// Parses the text in the provided &str into a Vec<Result<Instruction, Diagnostic>>
+/// Instructions are a [Diagnostic] describing the problem when that line fails to parse.
+/// Numbers that do not fit in their operand, e.g. `ld a, 256`, are also a [Diagnostic] pointing at the number.
+///
+/// Macros are expanded before parsing:
+/// ```asm
+/// LoadByte: MACRO
+///     ld a, \2
+///     ld [\1], a
+/// ENDM
+///
+///     LoadByte 0xC000, 0x12
+/// ```
+/// `\1` to `\9` are replaced by the arguments and `\@` is replaced by a value unique to each
+/// invocation, for use in labels.
+///
+/// String constants are also expanded before parsing, each reference is replaced by the text of the constant:
+/// ```asm
+/// PlayerX EQUS "[0xC000]"
+///     ld a, PlayerX
+/// ```
+/// They can be reassigned with `REDEF PlayerX EQUS "[0xC001]"`.
+///
+/// The string functions `STRLEN("text")`, `STRCAT("text", "more")` and `STRSUB("text", position, length)`
+/// are replaced by their result, so they can be used in expressions, strings and string constants:
+/// ```asm
+/// Name EQUS STRCAT("player", "_x")
+///     db STRLEN("Hello"), STRSUB("Hello", 2, 3)
+/// ```
+///
+/// Lines can end with `\n`, `\r\n` or `\r` and a UTF-8 byte order mark at the start of the text is ignored.
+///
+/// A line ending with `\` continues onto the next line, e.g. to wrap long `db` tables or macro invocations.
+/// The instruction is returned for the first line and an empty line for each continued line.
+pub fn parse_asm(text: &str) -> Result<Vec<Result<Instruction, Diagnostic>>, Error> {
+    let mut parser = LineParser::new();
+    let mut result = vec![];
+    for (line, joined) in LogicalLines::new(text) {
+        parser.parse_line(&line, &mut result)?;
+        parser.joined_lines(joined, &mut result);
+    }
+    parser.finish()?;
+    Ok(result)
+}
+
+/// Parses asm read from the reader, the instructions are the same as returned by [parse_asm] for the same text.
+///
+/// The text is read a line at a time, so it never needs to be in memory all at once.
+/// Returns an error if the reader fails or the text is not valid UTF-8.
+pub fn parse_asm_reader<R: BufRead>(
+    reader: R,
+) -> Result<Vec<Result<Instruction, Diagnostic>>, Error> {
+    let mut lines = ReaderLines {
+        reader,
+        pending: vec![].into_iter(),
+        lines: 0,
+        offset: 0,
+        error: None,
+    };
+    let mut parser = LineParser::new();
+    let mut result = vec![];
+    while let Some(line) = lines.next() {
+        let (line, joined) = join_continued(Cow::Owned(line), lines.by_ref().map(Cow::Owned));
+        parser.parse_line(&line, &mut result)?;
+        parser.joined_lines(joined, &mut result);
+    }
+    if let Some(err) = lines.error {
+        return Err(err);
+    }
+    parser.finish()?;
+    Ok(result)
+}
+
+/// Parses the text the same as [parse_asm], along with the line and column each instruction was written on.
+///
+/// Instructions expanded from a macro invocation have the span of the invocation.
+/// Unlike the index of the instruction, the line stays correct after macros that expand to multiple lines,
+/// so the line of each [Diagnostic] is also taken from the span.
+/// The file of each span is None, set it when the text was read from a file.
+pub fn parse_asm_spanned(
+    text: &str,
+) -> Result<Vec<Result<Spanned<Instruction>, Diagnostic>>, Error> {
+    let mut parser = SpannedParser::new(text);
+    let mut strings = HashMap::new();
+    let mut result = vec![];
+    while let Some(instructions) = parser.next_line(&mut strings)? {
+        result.extend(instructions);
+    }
+    parser.finish()?;
+    Ok(result)
+}
+
+/// The instructions parsed from a line by [SpannedParser::next_line].
+pub type SpannedLine = Vec<Result<Spanned<Instruction>, Diagnostic>>;
+
+/// Parses the text a line at a time like [parse_asm_spanned], so the caller can act on each line before the next
+/// line is parsed.
+///
+/// String constants are kept in a table owned by the caller, so the constants declared by one text can be used by
+/// another, e.g. the file including a file of shared definitions.
+pub struct SpannedParser<'a> {
+    lines: LogicalLines<'a>,
+    parser: LineParser,
+    /// the line number of the next line
+    number: usize,
+}
+
+impl<'a> SpannedParser<'a> {
+    pub fn new(text: &'a str) -> SpannedParser<'a> {
+        SpannedParser {
+            lines: LogicalLines::new(text),
+            parser: LineParser::new(),
+            number: 1,
+        }
+    }
+
+    /// Parses the next line using and updating the string constants, returns None once every line is parsed.
+    ///
+    /// Returns the instructions of the line followed by an empty line for each line continued onto it.
+    pub fn next_line(
+        &mut self,
+        strings: &mut HashMap<String, String>,
+    ) -> Result<Option<SpannedLine>, Error> {
+        let (line, joined) = match self.lines.next() {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let indent = line.chars().take_while(|x| WHITESPACE.contains(*x)).count();
+        let column = if line.trim().is_empty() {
+            1
+        } else {
+            indent + 1
+        };
+        let mut output = vec![];
+        std::mem::swap(&mut self.parser.strings, strings);
+        let parsed = self.parser.parse_line(&line, &mut output);
+        std::mem::swap(&mut self.parser.strings, strings);
+        parsed?;
+        // continued lines are output as empty lines, each has the span of its own line
+        self.parser.joined_lines(joined, &mut output);
+        let number = self.number;
+        let expanded = output.len() - joined;
+        let spans = (0..output.len()).map(|i| match i.checked_sub(expanded) {
+            Some(continued) => Span {
+                file: None,
+                line: number + continued + 1,
+                column: 1,
+            },
+            None => Span {
+                file: None,
+                line: number,
+                column,
+            },
+        });
+        let result = output
+            .into_iter()
+            .zip(spans)
+            .map(|(instruction, span)| match instruction {
+                Ok(inner) => Ok(Spanned { span, inner }),
+                Err(diagnostic) => Err(Diagnostic {
+                    line: span.line,
+                    ..diagnostic
+                }),
+            })
+            .collect();
+        self.number += joined + 1;
+        Ok(Some(result))
+    }
+
+    /// Returns an error if the text ended in an invalid state, e.g. a macro is missing ENDM.
+    pub fn finish(self) -> Result<(), Error> {
+        self.parser.finish()
+    }
+}
+
+/// Asm parsed by [parse_asm_lossless], displaying it returns the exact text that was parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LosslessAsm {
+    /// true when the text starts with a UTF-8 byte order mark
+    pub byte_order_mark: bool,
+    pub lines: Vec<LosslessLine>,
+}
+
+/// A line of asm, along with the instructions parsed from it.
+///
+/// The text of the line is `indent + code + trailing + comment + line_ending`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LosslessLine {
+    /// Usually a single instruction, but a macro invocation can produce many.
+    /// Each line continued onto by a `\` has the instruction [Instruction::EmptyLine].
+    pub instructions: Vec<Result<Instruction, Diagnostic>>,
+    /// the whitespace before the code
+    pub indent: String,
+    /// the code as written, without surrounding whitespace or the comment
+    pub code: String,
+    /// the whitespace after the code, before the comment or line ending
+    pub trailing: String,
+    /// the comment including its `;`, None when the line has no comment
+    pub comment: Option<String>,
+    /// `\n`, `\r\n`, `\r` or empty when the text ends without a line ending
+    pub line_ending: String,
+}
+
+impl LosslessLine {
+    fn new(
+        line: &str,
+        line_ending: &str,
+        instructions: Vec<Result<Instruction, Diagnostic>>,
+    ) -> LosslessLine {
+        let (code, comment) = match comment_start(line) {
+            Some(start) => (&line[..start], Some(line[start..].to_string())),
+            None => (line, None),
+        };
+        let trimmed = code.trim_start_matches(|x| WHITESPACE.contains(x));
+        let indent = &code[..code.len() - trimmed.len()];
+        let code = trimmed.trim_end_matches(|x| WHITESPACE.contains(x));
+        LosslessLine {
+            instructions,
+            indent: indent.to_string(),
+            code: code.to_string(),
+            trailing: trimmed[code.len()..].to_string(),
+            comment,
+            line_ending: line_ending.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LosslessAsm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.byte_order_mark {
+            f.write_str("\u{FEFF}")?;
+        }
+        for line in &self.lines {
+            write!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for LosslessLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.indent)?;
+        f.write_str(&self.code)?;
+        f.write_str(&self.trailing)?;
+        if let Some(comment) = &self.comment {
+            f.write_str(comment)?;
+        }
+        f.write_str(&self.line_ending)
+    }
+}
+
+/// Returns the index of the `;` starting the comment of the line, ignoring any `;` within a string.
+fn comment_start(line: &str) -> Option<usize> {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parses the text the same as [parse_asm], but keeps the comments, whitespace and line endings of every line.
+///
+/// This allows tools such as formatters to modify the asm and write it back out without losing anything.
+/// Each line holds the instructions parsed from it and `parse_asm_lossless(text)?.to_string() == text`.
+pub fn parse_asm_lossless(text: &str) -> Result<LosslessAsm, Error> {
+    let mut source = SourceLines::new(text);
+    let mut parser = LineParser::new();
+    let mut lines = vec![];
+    while let Some((line, line_ending)) = source.next_with_ending() {
+        let mut parts = vec![];
+        let (logical, joined) = join_continued(
+            Cow::Borrowed(line),
+            std::iter::from_fn(|| source.next_with_ending()).map(|(line, ending)| {
+                parts.push((line, ending));
+                Cow::Borrowed(line)
+            }),
+        );
+        let mut instructions = vec![];
+        parser.parse_line(&logical, &mut instructions)?;
+        lines.push(LosslessLine::new(line, line_ending, instructions));
+
+        let mut continued = vec![];
+        parser.joined_lines(joined, &mut continued);
+        for ((line, line_ending), instruction) in parts.into_iter().zip(continued) {
+            lines.push(LosslessLine::new(line, line_ending, vec![instruction]));
+        }
+    }
+    parser.finish()?;
+    Ok(LosslessAsm {
+        byte_order_mark: text.starts_with('\u{FEFF}'),
+        lines,
+    })
+}
+
+/// An instruction whose identifiers and strings may borrow from the parsed text.
+pub type BorrowedInstruction<'a> = Instruction<Cow<'a, str>>;
+
+/// Parses the text the same as [parse_asm], but identifiers and strings borrow from the text where possible.
+///
+/// Lines that dont involve macros or string constants are parsed without allocating for their identifiers,
+/// the remaining lines are expanded and their identifiers are owned.
+/// Use [Instruction::into_owned] to convert an instruction for use with the [crate::RomBuilder].
+pub fn parse_asm_borrowed(
+    text: &str,
+) -> Result<Vec<Result<BorrowedInstruction<'_>, Diagnostic>>, Error> {
+    let mut parser = LineParser::new();
+    let mut result = vec![];
+    let mut expanded = vec![];
+    for (line, joined) in LogicalLines::new(text) {
+        match line {
+            Cow::Borrowed(line) if parser.is_plain(line) => {
+                parser.emitted += 1;
+                let number = parser.emitted;
+                result.push(parse_checked(line, number).map(|x| x.map_text(&mut Cow::Borrowed)));
+            }
+            line => {
+                parser.parse_line(&line, &mut expanded)?;
+                parser.joined_lines(joined, &mut expanded);
+                result.extend(
+                    expanded
+                        .drain(..)
+                        .map(|x| x.map(|x| x.map_text(&mut Cow::Owned))),
+                );
+            }
+        }
     }
+    parser.finish()?;
+    Ok(result)
 }
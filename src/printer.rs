@@ -0,0 +1,439 @@
+//! Renders the AST back into asm text, which can be parsed again by [crate::parser].
+//!
+//! Every [Instruction] and [Expr] implements Display, e.g. `ld a, [$C000 + 1]`, and [to_asm_string] renders a
+//! whole program with labels at the start of the line and everything else indented.
+//!
+//! Parsing the text gives back the same instructions, except:
+//! *   negative constants are parsed as a negated [Expr::Unary]
+//! *   [Instruction::StopPadded] and [Instruction::HaltUnpadded] are displayed as `stop` and `halt`,
+//!     whose padding is then chosen by the RomBuilder
+//! *   db and dw directives are parsed into the simplest instruction that represents them e.g. [Instruction::Db]
+
+use std::fmt;
+
+use crate::ast::*;
+
+/// Renders the instructions as an asm file, one instruction per line.
+///
+/// Labels and constants start at the beginning of the line and everything else is indented by 4 spaces.
+pub fn to_asm_string<S: fmt::Display>(instructions: &[Instruction<S>]) -> String {
+    let mut text = String::new();
+    for instruction in instructions {
+        match instruction {
+            Instruction::EmptyLine => {}
+            Instruction::Label(_)
+            | Instruction::AnonymousLabel
+            | Instruction::Equ(..)
+            | Instruction::Set(..)
+            | Instruction::Section(..) => text.push_str(&instruction.to_string()),
+            instruction => {
+                text.push_str("    ");
+                text.push_str(&instruction.to_string());
+            }
+        }
+        text.push('\n');
+    }
+    text
+}
+
+impl BinaryOperator {
+    /// Returns the operator as written in asm e.g. `<<`.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Sub => "-",
+            BinaryOperator::Mul => "*",
+            BinaryOperator::Div => "/",
+            BinaryOperator::Rem => "%",
+            BinaryOperator::Shl => "<<",
+            BinaryOperator::Shr => ">>",
+            BinaryOperator::And => "&",
+            BinaryOperator::Xor => "^",
+            BinaryOperator::Or => "|",
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::Less => "<",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::LogicalAnd => "&&",
+            BinaryOperator::LogicalOr => "||",
+        }
+    }
+
+    /// Returns the precedence of the operator as used by the parser, higher precedences bind tighter.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::LogicalOr => 1,
+            BinaryOperator::LogicalAnd => 2,
+            BinaryOperator::Or => 3,
+            BinaryOperator::Xor => 4,
+            BinaryOperator::And => 5,
+            BinaryOperator::Equal | BinaryOperator::NotEqual => 6,
+            BinaryOperator::Less
+            | BinaryOperator::Greater
+            | BinaryOperator::LessEqual
+            | BinaryOperator::GreaterEqual => 7,
+            BinaryOperator::Shl | BinaryOperator::Shr => 8,
+            BinaryOperator::Add | BinaryOperator::Sub => 9,
+            BinaryOperator::Mul | BinaryOperator::Div | BinaryOperator::Rem => 10,
+        }
+    }
+}
+
+impl<S: fmt::Display> fmt::Display for Expr<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Ident(ident) => write!(f, "{}", ident),
+            Expr::Const(value) => {
+                if *value < 0 {
+                    f.write_str("-")?;
+                }
+                match value.unsigned_abs() {
+                    value if value < 10 => write!(f, "{}", value),
+                    value => write!(f, "${:X}", value),
+                }
+            }
+            Expr::Binary(binary) => {
+                let precedence = binary.operator.precedence();
+                // operators of the same precedence are left associative
+                write_operand(f, &binary.left, precedence)?;
+                write!(f, " {} ", binary.operator.symbol())?;
+                write_operand(f, &binary.right, precedence + 1)
+            }
+            Expr::Unary(unary) => match unary.operator {
+                UnaryOperator::Minus => {
+                    f.write_str("-")?;
+                    write_operand(f, &unary.expr, u8::MAX)
+                }
+            },
+            Expr::AnonymousLabelRef(offset) => {
+                let direction = if *offset < 0 { "-" } else { "+" };
+                write!(f, ":{}", direction.repeat(offset.unsigned_abs() as usize))
+            }
+        }
+    }
+}
+
+/// Writes the operand of an operator, in parentheses if it binds looser than min_precedence.
+fn write_operand<S: fmt::Display>(
+    f: &mut fmt::Formatter,
+    expr: &Expr<S>,
+    min_precedence: u8,
+) -> fmt::Result {
+    match expr {
+        Expr::Binary(binary) if binary.operator.precedence() < min_precedence => {
+            write!(f, "({})", expr)
+        }
+        Expr::Const(value) if *value < 0 && min_precedence == u8::MAX => write!(f, "({})", expr),
+        expr => write!(f, "{}", expr),
+    }
+}
+
+impl fmt::Display for Reg8 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Reg8::A => "a",
+            Reg8::B => "b",
+            Reg8::C => "c",
+            Reg8::D => "d",
+            Reg8::E => "e",
+            Reg8::H => "h",
+            Reg8::L => "l",
+        })
+    }
+}
+
+impl fmt::Display for Reg16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Reg16::BC => "bc",
+            Reg16::DE => "de",
+            Reg16::HL => "hl",
+            Reg16::SP => "sp",
+        })
+    }
+}
+
+impl fmt::Display for Reg16Push {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Reg16Push::BC => "bc",
+            Reg16Push::DE => "de",
+            Reg16Push::HL => "hl",
+            Reg16Push::AF => "af",
+        })
+    }
+}
+
+impl fmt::Display for AsmOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmOption::WarningsAsErrors(true) => f.write_str("Werror"),
+            AsmOption::WarningsAsErrors(false) => f.write_str("Wno-error"),
+            AsmOption::HaltPadding(true) => f.write_str("!h"),
+            AsmOption::HaltPadding(false) => f.write_str("h"),
+            AsmOption::Radix(radix) => write!(f, "radix={}", radix),
+        }
+    }
+}
+
+/// Returns the condition of a flag as written in asm, None for [Flag::Always] which is not written.
+fn condition(flag: &Flag) -> Option<&'static str> {
+    match flag {
+        Flag::Always => None,
+        Flag::Z => Some("z"),
+        Flag::NZ => Some("nz"),
+        Flag::C => Some("c"),
+        Flag::NC => Some("nc"),
+    }
+}
+
+/// Writes the mnemonic followed by the condition of the flag, if it has one, and the operand.
+fn write_conditional<S: fmt::Display>(
+    f: &mut fmt::Formatter,
+    mnemonic: &str,
+    flag: &Flag,
+    operand: Option<&Expr<S>>,
+) -> fmt::Result {
+    f.write_str(mnemonic)?;
+    let mut separator = " ";
+    if let Some(condition) = condition(flag) {
+        write!(f, " {}", condition)?;
+        separator = ", ";
+    }
+    match operand {
+        Some(operand) => write!(f, "{}{}", separator, operand),
+        None => Ok(()),
+    }
+}
+
+/// Writes the items separated by commas, strings are surrounded by quotes.
+fn write_items<S: fmt::Display>(f: &mut fmt::Formatter, items: &[DbItem<S>]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        match item {
+            DbItem::Expr(expr) => write!(f, "{}", expr)?,
+            DbItem::String(text) => write!(f, "\"{}\"", text)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes the values separated by commas.
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter, values: &[T]) -> fmt::Result {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", value)?;
+    }
+    Ok(())
+}
+
+impl<S: fmt::Display> fmt::Display for Instruction<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::EmptyLine => Ok(()),
+            Instruction::AdvanceAddress(address, None) => write!(f, "advance_address {}", address),
+            Instruction::AdvanceAddress(address, Some(bank)) => {
+                write!(f, "advance_address {}, {}", bank, address)
+            }
+            Instruction::Equ(name, expr) => write!(f, "{} EQU {}", name, expr),
+            Instruction::Set(name, expr) => write!(f, "{} = {}", name, expr),
+            Instruction::Label(name) => write!(f, "{}:", name),
+            Instruction::AnonymousLabel => f.write_str(":"),
+            Instruction::Db(bytes) => {
+                f.write_str("db ")?;
+                let exprs: Vec<Expr<S>> = bytes.iter().map(|x| Expr::Const(*x as i64)).collect();
+                write_list(f, &exprs)
+            }
+            Instruction::DbExpr8(expr) => write!(f, "db {}", expr),
+            Instruction::DbExpr16(expr) => write!(f, "dw {}", expr),
+            Instruction::DbExprList8(exprs) => {
+                f.write_str("db ")?;
+                write_list(f, exprs)
+            }
+            Instruction::DbExprList16(exprs) => {
+                f.write_str("dw ")?;
+                write_list(f, exprs)
+            }
+            Instruction::DbString(items) => {
+                f.write_str("db ")?;
+                write_items(f, items)
+            }
+            Instruction::DbFill(count, value) => write!(f, "dbfill {}, {}", count, value),
+            Instruction::Charmap(c, expr) => write!(f, "charmap \"{}\", {}", c, expr),
+            Instruction::DebugAssert(flag) => write_conditional::<S>(f, "debug_assert", flag, None),
+            Instruction::DebugAssertCp(comparison, expr) => {
+                let operator = match comparison {
+                    Comparison::Equal => "==",
+                    Comparison::NotEqual => "!=",
+                    Comparison::Less => "<",
+                    Comparison::GreaterOrEqual => ">=",
+                };
+                write!(f, "debug_assert a {} {}", operator, expr)
+            }
+            Instruction::DebugPrint(message) => write!(f, "debug_print \"{}\"", message),
+            Instruction::DebugBreak => f.write_str("debug_break"),
+            Instruction::Assert(expr, None) => write!(f, "assert {}", expr),
+            Instruction::Assert(expr, Some(message)) => {
+                write!(f, "assert {}, \"{}\"", expr, message)
+            }
+            Instruction::Print(items) => {
+                f.write_str("print ")?;
+                write_items(f, items)
+            }
+            Instruction::Warn(items) => {
+                f.write_str("warn ")?;
+                write_items(f, items)
+            }
+            Instruction::Fail(items) => {
+                f.write_str("fail ")?;
+                write_items(f, items)
+            }
+            Instruction::If(expr) => write!(f, "if {}", expr),
+            Instruction::Elif(expr) => write!(f, "elif {}", expr),
+            Instruction::Else => f.write_str("else"),
+            Instruction::Endc => f.write_str("endc"),
+            Instruction::Include(file_name) => write!(f, "include \"{}\"", file_name),
+            Instruction::Incbin(file_name, offset, length) => {
+                write!(f, "incbin \"{}\"", file_name)?;
+                if *offset != 0 || length.is_some() {
+                    write!(f, ", {}", offset)?;
+                }
+                match length {
+                    Some(length) => write!(f, ", {}", length),
+                    None => Ok(()),
+                }
+            }
+            Instruction::Section(name, section_type, address, bank) => {
+                let section_type = match section_type {
+                    SectionType::Rom0 => "rom0",
+                    SectionType::Romx => "romx",
+                };
+                write!(f, "section \"{}\", {}", name, section_type)?;
+                if let Some(address) = address {
+                    write!(f, "[{}]", address)?;
+                }
+                match bank {
+                    Some(bank) => write!(f, ", bank[{}]", bank),
+                    None => Ok(()),
+                }
+            }
+            Instruction::Opt(options) => {
+                f.write_str("opt ")?;
+                write_list(f, options)
+            }
+            Instruction::PushO => f.write_str("pusho"),
+            Instruction::PopO => f.write_str("popo"),
+            Instruction::PushS => f.write_str("pushs"),
+            Instruction::PopS => f.write_str("pops"),
+            Instruction::PragmaOnce => f.write_str("pragma once"),
+            Instruction::Export(names) => {
+                f.write_str("export ")?;
+                write_list(f, names)
+            }
+            Instruction::Namespace(name) => write!(f, "namespace {}", name),
+            Instruction::EndNamespace => f.write_str("endnamespace"),
+            Instruction::Union => f.write_str("union"),
+            Instruction::NextU => f.write_str("nextu"),
+            Instruction::EndU => f.write_str("endu"),
+            Instruction::Ret(flag) => write_conditional::<S>(f, "ret", flag, None),
+            Instruction::Call(flag, expr) => write_conditional(f, "call", flag, Some(expr)),
+            Instruction::JpI16(flag, expr) => write_conditional(f, "jp", flag, Some(expr)),
+            Instruction::Jr(flag, expr) => write_conditional(f, "jr", flag, Some(expr)),
+            Instruction::Rst(expr) => write!(f, "rst {}", expr),
+            Instruction::JpRhl => f.write_str("jp hl"),
+            Instruction::IncR16(reg) | Instruction::DecR16(reg) => {
+                write!(f, "{} {}", self.mnemonic().unwrap(), reg)
+            }
+            Instruction::AddRhlR16(reg) => write!(f, "add hl, {}", reg),
+            Instruction::AddRspI8(expr) => write!(f, "add sp, {}", expr),
+            Instruction::AddR8(reg)
+            | Instruction::SubR8(reg)
+            | Instruction::AndR8(reg)
+            | Instruction::OrR8(reg)
+            | Instruction::AdcR8(reg)
+            | Instruction::SbcR8(reg)
+            | Instruction::XorR8(reg)
+            | Instruction::CpR8(reg) => write!(f, "{} a, {}", self.mnemonic().unwrap(), reg),
+            Instruction::AddMRhl
+            | Instruction::SubMRhl
+            | Instruction::AndMRhl
+            | Instruction::OrMRhl
+            | Instruction::AdcMRhl
+            | Instruction::SbcMRhl
+            | Instruction::XorMRhl
+            | Instruction::CpMRhl => write!(f, "{} a, [hl]", self.mnemonic().unwrap()),
+            Instruction::AddI8(expr)
+            | Instruction::SubI8(expr)
+            | Instruction::AndI8(expr)
+            | Instruction::OrI8(expr)
+            | Instruction::AdcI8(expr)
+            | Instruction::SbcI8(expr)
+            | Instruction::XorI8(expr)
+            | Instruction::CpI8(expr) => write!(f, "{} a, {}", self.mnemonic().unwrap(), expr),
+            Instruction::LdR16I16(reg, expr) => write!(f, "ld {}, {}", reg, expr),
+            Instruction::LdMI16Rsp(expr) => write!(f, "ld [{}], sp", expr),
+            Instruction::LdMRbcRa => f.write_str("ld [bc], a"),
+            Instruction::LdMRdeRa => f.write_str("ld [de], a"),
+            Instruction::LdRaMRbc => f.write_str("ld a, [bc]"),
+            Instruction::LdRaMRde => f.write_str("ld a, [de]"),
+            Instruction::LdR8R8(reg1, reg2) => write!(f, "ld {}, {}", reg1, reg2),
+            Instruction::LdR8I8(reg, expr) => write!(f, "ld {}, {}", reg, expr),
+            Instruction::LdR8MRhl(reg) => write!(f, "ld {}, [hl]", reg),
+            Instruction::LdMRhlR8(reg) => write!(f, "ld [hl], {}", reg),
+            Instruction::LdMRhlI8(expr) => write!(f, "ld [hl], {}", expr),
+            Instruction::LdMI16Ra(expr) => write!(f, "ld [{}], a", expr),
+            Instruction::LdRaMI16(expr) => write!(f, "ld a, [{}]", expr),
+            Instruction::LdhRaMI8(expr) => write!(f, "ld a, [$FF00 + {}]", expr),
+            Instruction::LdhMI8Ra(expr) => write!(f, "ld [$FF00 + {}], a", expr),
+            Instruction::LdhRaMRc => f.write_str("ld a, [$FF00 + c]"),
+            Instruction::LdhMRcRa => f.write_str("ld [$FF00 + c], a"),
+            Instruction::LdiMRhlRa => f.write_str("ldi [hl], a"),
+            Instruction::LddMRhlRa => f.write_str("ldd [hl], a"),
+            Instruction::LdiRaMRhl => f.write_str("ldi a, [hl]"),
+            Instruction::LddRaMRhl => f.write_str("ldd a, [hl]"),
+            Instruction::LdRhlRspI8(expr) => write!(f, "ld hl, sp + {}", expr),
+            Instruction::LdRspRhl => f.write_str("ld sp, hl"),
+            Instruction::Push(reg) | Instruction::Pop(reg) => {
+                write!(f, "{} {}", self.mnemonic().unwrap(), reg)
+            }
+            Instruction::IncR8(reg)
+            | Instruction::DecR8(reg)
+            | Instruction::RlcR8(reg)
+            | Instruction::RrcR8(reg)
+            | Instruction::RlR8(reg)
+            | Instruction::RrR8(reg)
+            | Instruction::SlaR8(reg)
+            | Instruction::SraR8(reg)
+            | Instruction::SwapR8(reg)
+            | Instruction::SrlR8(reg) => write!(f, "{} {}", self.mnemonic().unwrap(), reg),
+            Instruction::IncMRhl
+            | Instruction::DecMRhl
+            | Instruction::RlcMRhl
+            | Instruction::RrcMRhl
+            | Instruction::RlMRhl
+            | Instruction::RrMRhl
+            | Instruction::SlaMRhl
+            | Instruction::SraMRhl
+            | Instruction::SwapMRhl
+            | Instruction::SrlMRhl => write!(f, "{} [hl]", self.mnemonic().unwrap()),
+            Instruction::BitBitR8(bit, reg)
+            | Instruction::ResBitR8(bit, reg)
+            | Instruction::SetBitR8(bit, reg) => {
+                write!(f, "{} {}, {}", self.mnemonic().unwrap(), bit, reg)
+            }
+            Instruction::BitBitMRhl(bit)
+            | Instruction::ResBitMRhl(bit)
+            | Instruction::SetBitMRhl(bit) => {
+                write!(f, "{} {}, [hl]", self.mnemonic().unwrap(), bit)
+            }
+            // the remaining instructions have no operands
+            instruction => f.write_str(instruction.mnemonic().unwrap()),
+        }
+    }
+}
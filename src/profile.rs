@@ -0,0 +1,82 @@
+//! Measures how long each phase of a build takes, enabled by [crate::RomBuilder::profile_build].
+//!
+//! Useful to notice performance regressions in ggbasm or to find which assets slow down a huge project.
+
+use std::fmt;
+use std::fs;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// The time spent in each phase of a build, included in [crate::layout::Layout::profile].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildProfile {
+    /// reading and parsing asm files
+    pub parsing: Duration,
+    /// resolving conditionals and placing labels as instructions are added, then resolving EQUs when compiling
+    pub constants: Duration,
+    /// converting images
+    pub images: Duration,
+    /// generating audio data
+    pub audio: Duration,
+    /// converting images and audio in parallel via [crate::RomBuilder::add_assets]
+    pub assets: Duration,
+    /// validating, linting and writing the bytes of the rom
+    pub encoding: Duration,
+    /// the most memory used by the process so far in bytes, None when the platform does not report it
+    pub peak_memory: Option<u64>,
+}
+
+impl BuildProfile {
+    /// Returns the total time spent in every phase.
+    pub fn total(&self) -> Duration {
+        self.parsing + self.constants + self.images + self.audio + self.assets + self.encoding
+    }
+}
+
+impl fmt::Display for BuildProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Build profile:")?;
+        let phases = [
+            ("parsing", self.parsing),
+            ("constants", self.constants),
+            ("images", self.images),
+            ("audio", self.audio),
+            ("assets", self.assets),
+            ("encoding", self.encoding),
+            ("total", self.total()),
+        ];
+        for (name, duration) in phases {
+            writeln!(
+                f,
+                "  {:<12}{:>10.3}ms",
+                name,
+                duration.as_secs_f64() * 1000.0
+            )?;
+        }
+        match self.peak_memory {
+            Some(bytes) => write!(
+                f,
+                "  {:<12}{:>10.1}MB",
+                "peak memory",
+                bytes as f64 / 1_000_000.0
+            ),
+            None => write!(f, "  {:<12}{:>12}", "peak memory", "unknown"),
+        }
+    }
+}
+
+/// Returns the most memory used by the process so far in bytes.
+/// Only supported on Linux, where it is read from /proc.
+pub(crate) fn peak_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|x| x.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
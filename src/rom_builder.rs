@@ -1,17 +1,34 @@
 //! Contains the main API of GGBASM.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Error};
 
-use crate::ast::{Expr, ExprRunError, Instruction};
-use crate::audio;
+use crate::address::{Bank, BankAddr, GlobalAddr};
+use crate::asset::{self, AssetJob, ConvertedAsset};
+use crate::ast::{self, AsmOption, DbItem, Expr, ExprRunError, Instruction, SectionType, Span};
+use crate::audio::AudioOptions;
 use crate::constants::*;
-use crate::header::{CartridgeType, Header};
+use crate::control_flow;
+use crate::debug;
+use crate::fixed_point;
+use crate::hardware::{self, HARDWARE_CONSTANTS};
+use crate::header::{self, CartridgeType, Header, Prologue};
+use crate::layout::{
+    symbol_drift, BankUsage, DataRegion, DeduplicatedData, Layout, RegionKind, Section,
+    SizeEstimate, Symbol,
+};
+use crate::lint::{self, LintProfile};
+use crate::mapper::Mapper;
+use crate::output::OutputWriter;
 use crate::parser;
+use crate::profile::{self, BuildProfile};
+use crate::symbols::{SymbolInfo, SymbolKind, SymbolTable};
+use crate::visit::Visitor;
 
 /// Represents a color in modern images.
 /// Used when mapping colors from modern images to gameboy graphics.
@@ -28,18 +45,77 @@ impl Color {
     }
 }
 
+/// The type of a RAM variable, used to describe the variable to emulator debuggers.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum VariableType {
+    U8,
+    I8,
+    U16,
+    I16,
+    Bool,
+    /// an array of bytes with the given length
+    Bytes(u16),
+}
+
+impl VariableType {
+    /// Returns how many bytes the variable takes up
+    pub fn size(&self) -> u16 {
+        match self {
+            VariableType::U8 | VariableType::I8 | VariableType::Bool => 1,
+            VariableType::U16 | VariableType::I16 => 2,
+            VariableType::Bytes(len) => *len,
+        }
+    }
+
+    fn description(&self) -> String {
+        match self {
+            VariableType::U8 => "u8".to_string(),
+            VariableType::I8 => "i8".to_string(),
+            VariableType::U16 => "u16".to_string(),
+            VariableType::I16 => "i16".to_string(),
+            VariableType::Bool => "bool".to_string(),
+            VariableType::Bytes(len) => format!("[u8; {}]", len),
+        }
+    }
+}
+
+struct DataRegionDeclaration {
+    identifier: String,
+    element_size: u32,
+    count: u32,
+}
+
+struct RamVariable {
+    identifier: String,
+    address: u16,
+    variable_type: VariableType,
+}
+
 enum Data {
     Instructions(Vec<Instruction>),
     Binary(Vec<u8>),
     Header(Header),
-    DummyInterruptsAndJumps,
+    /// The restart and interrupt vectors from 0x0000 to 0x00FF followed by an [Data::EntryPoint].
+    DummyInterruptsAndJumps(Option<String>),
+    /// `nop` followed by a jump to the identifier, or 0x0150 when None.
+    EntryPoint(Option<String>),
 }
 
+/// The most passes made over added instructions before giving up on their labels settling.
+const MAX_LABEL_PASSES: usize = 16;
+
 /// Keeps track of where data came from, used to generate error messages.
+#[derive(Clone)]
 enum DataSource {
     AsmFile(String),
+    /// The section name and the asm file it is in.
+    AsmSection(String, String),
     AudioFile(String),
+    BinaryFile(String),
     AudioPlayer,
+    DebugOverlay,
+    CrashHandler,
+    FixedPoint,
     Code, /* TODO: Include stacktrace */
 }
 
@@ -50,18 +126,62 @@ impl DataSource {
             DataSource::AudioPlayer => {
                 "instructions generated by the built-in ggbasm audio player".to_string()
             }
+            DataSource::DebugOverlay => {
+                "instructions generated by the built-in ggbasm debug overlay".to_string()
+            }
+            DataSource::CrashHandler => {
+                "instructions generated by the built-in ggbasm crash handler".to_string()
+            }
+            DataSource::FixedPoint => {
+                "instructions generated by the built-in ggbasm fixed point routines".to_string()
+            }
             DataSource::AudioFile(name) => {
                 format!("instructions generated by audio file: {}", name)
             }
             DataSource::AsmFile(name) => format!("instructions generated by asm file {}", name),
+            DataSource::AsmSection(section, file) => {
+                format!("section {} in asm file {}", section, file)
+            }
+            DataSource::BinaryFile(name) => format!("binary file {}", name),
         }
     }
 }
 
+/// A piece of an asm file or the files it includes.
+enum AsmChunk {
+    /// The instructions and where each was written.
+    Instructions(Vec<Instruction>, Vec<Span>, DataSource),
+    /// Places the following chunks, size is the number of bytes up to the next section.
+    Section {
+        name: String,
+        section_type: SectionType,
+        address: Option<Expr>,
+        bank: Option<Expr>,
+        size: u32,
+        file_name: String,
+        line: usize,
+    },
+    /// Saves the current section, see [Instruction::PushS].
+    PushSection,
+    /// Continues the section saved by the matching [AsmChunk::PushSection].
+    PopSection { file_name: String, line: usize },
+}
+
+/// The section continued by a POPS, used to check it does not grow into data placed before the POPS.
+struct PoppedSection {
+    start: u32,
+    /// the number of data added before the POPS
+    existing: usize,
+    file_name: String,
+    line: usize,
+}
+
 struct DataHolder {
     data: Data,
     #[allow(dead_code)]
     source: DataSource,
+    /// where each instruction was written, empty unless the instructions were parsed from an asm file
+    spans: Vec<Span>,
     /// address within the entire rom
     address: u32,
 }
@@ -79,103 +199,481 @@ pub struct RomBuilder {
     data: Vec<DataHolder>,
     address: u32,
     root_dir: PathBuf,
-    constants: HashMap<String, i64>,
+    /// asm and binary files are read from here, the gbasm folder in the root directory by default
+    source_dir: PathBuf,
+    symbols: SymbolTable,
+    /// the current value of each SET constant, updated as instructions are added
+    set_constants: HashMap<String, i64>,
+    ram_variables: Vec<RamVariable>,
+    data_regions: Vec<DataRegionDeclaration>,
+    /// number of anonymous labels added so far, used to give each one a unique name
+    anonymous_labels: usize,
+    lint_profile: LintProfile,
+    warn_unused: bool,
+    /// identifiers used by the conditions and SETs resolved so far, they are removed from the instructions
+    condition_references: HashSet<String>,
+    symbol_snapshot: Option<String>,
+    /// (identifier, value) pairs that the symbols must resolve to
+    pinned_symbols: Vec<(String, i64)>,
+    deduplicate_data: bool,
+    deduplicated: Vec<DeduplicatedData>,
+    fill_free_space: bool,
+    /// (address, capacity) of the table added by add_fill_region_table
+    fill_region_table: Option<(u32, u8)>,
+    pad_stop: bool,
+    pad_halt: bool,
+    outputs: Vec<Box<dyn OutputWriter>>,
+    /// the number of rom banks set by rom_banks, otherwise the fewest banks that fit the rom
+    rom_banks: Option<u32>,
+    /// sections of the layout loaded by previous_layout
+    previous_sections: Vec<Section>,
+    /// the time spent in each phase so far, when profile_build was called
+    profile: Option<BuildProfile>,
+    /// paths of the asm files containing `pragma once` that have been added
+    included_once: HashSet<PathBuf>,
+    /// the text of each string constant declared with EQUS by the asm files added so far
+    string_constants: HashMap<String, String>,
+    /// the namespaces opened by namespace, outermost first
+    namespaces: Vec<String>,
 }
 
 impl RomBuilder {
     /// Creates a RomBuilder.
     pub fn new() -> Result<RomBuilder, Error> {
+        let root_dir = RomBuilder::find_root_dir()?;
         Ok(RomBuilder {
             data: vec![],
             address: 0,
-            root_dir: RomBuilder::root_dir()?,
-            constants: HashMap::new(),
+            source_dir: root_dir.join("gbasm"),
+            root_dir,
+            symbols: SymbolTable::new(),
+            set_constants: HashMap::new(),
+            included_once: HashSet::new(),
+            string_constants: HashMap::new(),
+            namespaces: vec![],
+            ram_variables: vec![],
+            data_regions: vec![],
+            anonymous_labels: 0,
+            lint_profile: LintProfile::Default,
+            warn_unused: false,
+            condition_references: HashSet::new(),
+            symbol_snapshot: None,
+            pinned_symbols: vec![],
+            deduplicate_data: false,
+            deduplicated: vec![],
+            fill_free_space: false,
+            fill_region_table: None,
+            pad_stop: false,
+            pad_halt: true,
+            outputs: vec![],
+            rom_banks: None,
+            previous_sections: vec![],
+            profile: None,
         })
     }
 
+    /// Builds a rom from every *.asm and *.bin file in a directory, replacing the sgbasm crate.
+    ///
+    /// Adds basic interrupts and jumps and a header titled after the directory, using an MBC5 cartridge without ram.
+    /// Then adds `main.asm` followed by the other *.asm files and then the *.bin files, in alphabetical order.
+    /// Asm files included by another asm file are only added by their include.
+    /// Each *.bin file is added with [RomBuilder::add_bytes], named after the file without its extension.
+    ///
+    /// Files are read from and written to the directory instead of the gbasm folder and root directory.
+    /// Returns an error if encounters file system issues or any of the files fail to be added.
+    pub fn from_source_dir(path: impl AsRef<Path>) -> Result<RomBuilder, Error> {
+        let path = path.as_ref();
+        let mut asm_files = vec![];
+        let mut bin_files = vec![];
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(err) => bail!("Cannot read directory {} because: {}", path.display(), err),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if file_name.ends_with(".asm") {
+                asm_files.push(file_name);
+            } else if file_name.ends_with(".bin") {
+                bin_files.push(file_name);
+            }
+        }
+        asm_files.sort_by_key(|x| (x != "main.asm", x.clone()));
+        bin_files.sort();
+
+        // files included by another file are added by that include
+        let mut included = HashSet::new();
+        for file_name in &asm_files {
+            let bytes = fs::read(path.join(file_name))?;
+            let text = match parser::decode_asm(&bytes) {
+                Ok(text) => text,
+                Err(err) => bail!("Cannot read asm file {} because: {}", file_name, err),
+            };
+            for instruction in parser::parse_asm(text)?.into_iter().flatten() {
+                if let Instruction::Include(included_file) = instruction {
+                    included.insert(included_file);
+                }
+            }
+        }
+        asm_files.retain(|x| !included.contains(x));
+
+        let title = path
+            .canonicalize()?
+            .file_name()
+            .map(|x| x.to_string_lossy().to_ascii_uppercase())
+            .unwrap_or_default()
+            .chars()
+            .filter(|x| x.is_ascii_graphic())
+            .take(11)
+            .collect();
+        let header = Header {
+            title,
+            color_support: header::ColorSupport::Unsupported,
+            licence: String::new(),
+            sgb_support: false,
+            cartridge_type: CartridgeType::Mbc5,
+            ram_type: header::RamType::None,
+            japanese: false,
+            version_number: 0,
+        };
+
+        let mut builder = RomBuilder::new()?;
+        builder.root_dir = path.to_path_buf();
+        builder.source_dir = path.to_path_buf();
+        builder = builder
+            .add_basic_interrupts_and_jumps()?
+            .add_header(header)?;
+        for file_name in &asm_files {
+            builder = builder.add_asm_file(file_name)?;
+        }
+        for file_name in &bin_files {
+            let bytes = fs::read(path.join(file_name))?;
+            builder = builder.add_bytes(bytes, file_name.trim_end_matches(".bin"))?;
+        }
+        Ok(builder)
+    }
+
     /// Adds basic interrupt and jump data from 0x0000 to 0x0103.
     ///
     /// The entry point jumps to 0x0150.
     /// The interrupts return immediately.
     /// The RST commands jump to the entry point.
-    /// Returns an error if the RomBuilder address is not at 0x0000.
-    pub fn add_basic_interrupts_and_jumps(mut self) -> Result<Self, Error> {
-        if self.address != 0x0000 {
-            bail!("Attempted to add header data when address != 0x0000");
-        }
-
-        self.data.push(DataHolder {
-            data: Data::DummyInterruptsAndJumps,
-            address: 0,
-            source: DataSource::Code,
-        });
-        self.address = 0x104;
+    /// Except for `rst 0x38` which jumps to the crash handler if [RomBuilder::add_crash_handler] is used.
+    /// Returns an error if data was already added between 0x0000 and 0x0103.
+    pub fn add_basic_interrupts_and_jumps(self) -> Result<Self, Error> {
+        self.add_prologue(Prologue {
+            interrupts_and_jumps: true,
+            ..Prologue::default()
+        })
+    }
 
-        Ok(self)
+    /// Adds the parts of the first 0x150 bytes of the rom declared by the prologue.
+    ///
+    /// Each part is placed at its fixed address, so the parts can be added in any order,
+    /// e.g. the header can be added before the vectors, or the vectors can be supplied from an asm file followed by the header.
+    /// When the rom is compiled, the bytes from 0x0100 to 0x014F must be filled with a valid header.
+    ///
+    /// Returns an error if data was already added where a part is placed.
+    pub fn add_prologue(mut self, prologue: Prologue) -> Result<Self, Error> {
+        if prologue.interrupts_and_jumps {
+            let data = Data::DummyInterruptsAndJumps(prologue.entry);
+            self.add_fixed(data, 0x0000, 0x104, "interrupts and jumps")?;
+        } else if prologue.entry.is_some() {
+            self.add_fixed(Data::EntryPoint(prologue.entry), 0x0100, 4, "entry point")?;
+        }
+        match prologue.header {
+            Some(header) => self.add_header(header),
+            None => Ok(self),
+        }
     }
 
     /// Adds provided header data at 0x0104 to 0x149.
     ///
-    /// Returns an error if the RomBuilder address is not at 0x104
+    /// Returns an error if data was already added between 0x0104 and 0x014F.
     pub fn add_header(mut self, header: Header) -> Result<Self, Error> {
-        if self.address != 0x0104 {
-            bail!("Attempted to add header data when address != 0x0104");
-        }
-
-        if header.title.as_bytes().len() > 0x10 {
+        if header.title.len() > 0x10 {
             bail!("Header title was larger than 16 bytes.");
         }
 
-        if header.title.as_bytes().len() == 0x10 && header.color_support.is_supported() {
+        if header.title.len() == 0x10 && header.color_support.is_supported() {
             bail!("Header title was 16 bytes while supporting color.");
         }
 
-        if header.licence.as_bytes().len() > 2 {
+        if header.licence.len() > 2 {
             bail!("Header licence was larger than 2 bytes.");
         }
 
-        self.data.push(DataHolder {
-            data: Data::Header(header),
-            address: self.address,
-            source: DataSource::Code,
-        });
-        self.address = 0x150;
+        self.add_fixed(Data::Header(header), 0x0104, 0x4C, "header")?;
+
+        Ok(self)
+    }
+
+    /// Defines a constant that can be used by assembly code.
+    ///
+    /// Some RomBuilder features also check for defines, e.g. defining `DEBUG` as a non-zero
+    /// value compiles in debug only routines.
+    /// Returns an error if the identifier is already used.
+    pub fn define(mut self, identifier: &str, value: i64) -> Result<Self, Error> {
+        let identifier = self.qualify(identifier);
+        self.define_global(&identifier, value)?;
+        Ok(self)
+    }
+
+    fn define_global(&mut self, identifier: &str, value: i64) -> Result<(), Error> {
+        self.symbols.declare(
+            identifier,
+            value,
+            SymbolInfo::rust(SymbolKind::Define, None),
+        )
+    }
+
+    /// Defines every hardware register and flag constant from [crate::hardware], e.g. `rLCDC` and `LCDCF_ON`.
+    ///
+    /// The names match the community hardware.inc file.
+    /// The constants are never declared within a namespace.
+    /// Returns an error if any of the identifiers are already used.
+    pub fn add_hardware_constants(mut self) -> Result<Self, Error> {
+        for (identifier, value) in HARDWARE_CONSTANTS {
+            self.define_global(identifier, *value)?;
+        }
+        Ok(self)
+    }
+
+    /// Defines every constant from [crate::constants] under its rust name, e.g. `VBLANK_VECTOR` and `CYCLES_PER_FRAME`.
+    ///
+    /// The constants are never declared within a namespace.
+    /// Returns an error if any of the identifiers are already used.
+    pub fn add_constants(mut self) -> Result<Self, Error> {
+        for (identifier, value) in CONSTANTS {
+            self.define_global(identifier, *value)?;
+        }
+        Ok(self)
+    }
+
+    /// Opens a namespace, until the matching [RomBuilder::end_namespace] identifiers are declared within it.
+    ///
+    /// Labels and EQU constants of added instructions and asm files, and identifiers declared by rust code
+    /// e.g. via [RomBuilder::define] and [RomBuilder::add_bytes], are prefixed with the namespace and a `.`,
+    /// so `Init` becomes `Audio.Init` within `namespace("Audio")`.
+    /// Namespaces nest, asm files can also open namespaces with `namespace Audio` and `endnamespace`.
+    ///
+    /// Within a namespace an identifier refers to the identifier declared in the innermost namespace containing it,
+    /// e.g. `call Init` calls `Audio.Init` when it is declared, otherwise it calls `Init`.
+    /// Identifiers given to other RomBuilder methods are not resolved this way, they need the full name.
+    /// Returns an error if the name is not a valid identifier.
+    pub fn namespace(mut self, name: &str) -> Result<Self, Error> {
+        if name.is_empty()
+            || !name
+                .split('.')
+                .all(|x| !x.is_empty() && x.chars().all(|x| x.is_ascii_alphanumeric() || x == '_'))
+        {
+            bail!("Namespace {} is not a valid identifier", name);
+        }
+        self.namespaces.push(name.to_string());
+        Ok(self)
+    }
+
+    /// Closes the namespace opened by the last call to [RomBuilder::namespace].
+    /// Returns an error if no namespace is open.
+    pub fn end_namespace(mut self) -> Result<Self, Error> {
+        if self.namespaces.pop().is_none() {
+            bail!("end_namespace was called but no namespace is open");
+        }
+        Ok(self)
+    }
+
+    /// Returns the identifier within the open namespaces.
+    fn qualify(&self, identifier: &str) -> String {
+        qualify(&self.namespaces.join("."), identifier)
+    }
+
+    /// Defines the constants from a community hardware.inc file in the gbasm folder.
+    ///
+    /// Allows projects migrating from RGBDS to keep using their existing hardware.inc.
+    /// See [crate::hardware::parse_hardware_inc] for the supported subset.
+    /// Returns an error if encounters file system issues.
+    pub fn add_hardware_inc(self, file_name: &str) -> Result<Self, Error> {
+        let path = self.source_dir.join(file_name);
+        let bytes = match fs::read(path) {
+            Ok(file) => file,
+            Err(err) => bail!(
+                "Cannot read hardware.inc file {} because: {}",
+                file_name,
+                err
+            ),
+        };
+        let text = match parser::decode_asm(&bytes) {
+            Ok(text) => text,
+            Err(err) => bail!(
+                "Cannot read hardware.inc file {} because: {}",
+                file_name,
+                err
+            ),
+        };
+
+        self.add_instructions_inner(
+            hardware::parse_hardware_inc(text),
+            DataSource::AsmFile(file_name.to_string()),
+        )
+    }
+
+    /// Declares a variable in RAM at the provided address.
+    ///
+    /// The identifier can be used by assembly code to refer to the address.
+    /// Declared variables are listed in the RAM symbol file, see [RomBuilder::ram_sym_file].
+    /// Returns an error if the identifier is already used.
+    /// Returns an error if the variable is not in RAM (0x8000-0xFFFF)
+    pub fn declare_ram_variable(
+        mut self,
+        identifier: &str,
+        address: u16,
+        variable_type: VariableType,
+    ) -> Result<Self, Error> {
+        let identifier = &self.qualify(identifier);
+        if address < 0x8000 || address as u32 + variable_type.size() as u32 > 0x10000 {
+            bail!(
+                "RAM variable {} at 0x{:04x} is not within RAM (0x8000-0xFFFF)",
+                identifier,
+                address
+            );
+        }
 
+        self.symbols.declare(
+            identifier,
+            address as i64,
+            SymbolInfo::rust(SymbolKind::RamVariable, Some(variable_type.size() as u32)),
+        )?;
+        self.ram_variables.push(RamVariable {
+            identifier: identifier.to_string(),
+            address,
+            variable_type,
+        });
         Ok(self)
     }
 
+    /// Returns every identifier declared so far.
+    ///
+    /// EQU constants are only declared once the rom is compiled, see [Layout::symbols] for the symbols of a
+    /// compiled rom.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Returns true if `DEBUG` has been defined as a non-zero value.
+    pub fn is_debug(&self) -> bool {
+        self.symbols.get("DEBUG").is_some_and(|x| *x != 0)
+    }
+
     /// Includes raw bytes in the rom.
-    /// The name is used to reference the address in assembly code.
+    /// The name is used to reference the address in assembly code and `SIZEOF_<name>` is defined as the number of bytes.
     /// Returns an error if crosses rom bank boundaries.
-    pub fn add_bytes(mut self, bytes: Vec<u8>, identifier: &str) -> Result<Self, Error> {
+    pub fn add_bytes(self, bytes: Vec<u8>, identifier: &str) -> Result<Self, Error> {
+        self.add_binary(bytes, identifier)
+    }
+
+    /// Stores bytes identical to previously added bytes or images only once, see [RomBuilder::add_bytes]
+    /// and [RomBuilder::add_image].
+    ///
+    /// Instead of adding the bytes again, the identifier refers to the previous copy.
+    /// The previous copy is only used when it is in bank 0 or the current bank, so it can be accessed without
+    /// switching banks.
+    /// The bytes saved are listed in [Layout::deduplicated].
+    pub fn deduplicate_data(mut self) -> Result<Self, Error> {
+        self.deduplicate_data = true;
+        Ok(self)
+    }
+
+    fn add_binary(mut self, bytes: Vec<u8>, name: &str) -> Result<Self, Error> {
+        let identifier = &self.qualify(name);
         let len = bytes.len() as u32;
-        if self
-            .constants
-            .insert(identifier.to_string(), self.address as i64)
-            .is_some()
-        {
-            // TODO: Display first usage
-            bail!("Identifier {} is already used", identifier)
+        let bank = self.bank();
+        let previous = self.data.iter().find_map(|data| match &data.data {
+            Data::Binary(previous)
+                if self.deduplicate_data
+                    && !bytes.is_empty()
+                    && *previous == bytes
+                    && [0, bank].contains(&(data.address / ROM_BANK_SIZE)) =>
+            {
+                Some(data.address)
+            }
+            _ => None,
+        });
+        let address = previous.unwrap_or(self.address);
+        self.symbols.declare_label(
+            identifier,
+            GlobalAddr(address),
+            SymbolInfo::rust(SymbolKind::Label, Some(len)),
+        )?;
+
+        self.define_size(name, len)?;
+
+        if previous.is_some() {
+            self.deduplicated.push(DeduplicatedData {
+                name: identifier.to_string(),
+                address,
+                size: len,
+            });
+            return Ok(self);
         }
 
         self.data.push(DataHolder {
             data: Data::Binary(bytes),
             address: self.address,
             source: DataSource::Code,
+            spans: vec![],
         });
 
-        let prev_bank = self.get_bank();
-        self.address += len as u32;
-        if prev_bank == self.get_bank() {
+        self.address += len;
+        if bank == self.bank() {
             Ok(self)
         } else {
-            bail!("The added instructions cross bank boundaries.");
+            bail!("The added bytes cross bank boundaries.");
+        }
+    }
+
+    /// Includes a prebuilt binary file in the gbasm folder at the start of the specified rom bank.
+    ///
+    /// Useful for data produced by external pipelines e.g. video or prerendered music.
+    /// Returns an error if the file is larger than a rom bank, the bank is 0 or the current address is past the start of the bank.
+    pub fn add_bank_from_file(mut self, rom_bank: Bank, file_name: &str) -> Result<Self, Error> {
+        if rom_bank == Bank(0) {
+            bail!(
+                "Cannot add {} to rom bank 0, it is reserved for the header and interrupts",
+                file_name
+            );
+        }
+        let path = self.source_dir.join(file_name);
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) => bail!("Cannot read binary file {} because: {}", file_name, err),
+        };
+        if bytes.len() as u32 > ROM_BANK_SIZE {
+            bail!(
+                "Binary file {} is {} bytes which is larger than a rom bank of {} bytes",
+                file_name,
+                bytes.len(),
+                ROM_BANK_SIZE
+            );
         }
+
+        self = self.advance_address(rom_bank, BankAddr(ROM_BANK_SIZE as u16))?;
+        let len = bytes.len() as u32;
+        self.data.push(DataHolder {
+            data: Data::Binary(bytes),
+            address: self.address,
+            source: DataSource::BinaryFile(file_name.to_string()),
+            spans: vec![],
+        });
+        self.address += len;
+        Ok(self)
     }
 
     /// Includes graphics data generated from the provided image file in the graphics folder.
     ///
-    /// The name is used to reference the address in assembly code.
+    /// The name is used to reference the address in assembly code and `SIZEOF_<name>` is defined as the number of bytes.
     /// Returns an error if crosses rom bank boundaries.
     /// The color_map argument specifes how to convert 24 bit rgb color values into the 2 bit color values used by the gameboy.
     ///
@@ -186,60 +684,18 @@ impl RomBuilder {
         identifier: &str,
         color_map: &HashMap<Color, u8>,
     ) -> Result<Self, Error> {
-        if self
-            .constants
-            .insert(identifier.to_string(), self.address as i64)
-            .is_some()
-        {
-            // TODO: Display first usage
-            bail!("Identifier {} is already used", identifier)
-        }
-
-        let path = self.root_dir.as_path().join("graphics").join(file_name);
-        let image = match image::open(path) {
-            Ok(image) => image,
-            Err(err) => bail!("Cannot read file {} because: {}", file_name, err),
-        };
-        let mut bytes = vec![];
-        let image = image.to_rgb8();
-        for vert_tile in 0..(image.height() / 8) {
-            for hor_tile in 0..(image.width() / 8) {
-                for vert_line in 0..8 {
-                    let mut byte0 = 0x00;
-                    let mut byte1 = 0x00;
-                    for hor_line in 0..8 {
-                        let x = hor_tile * 8 + hor_line;
-                        let y = vert_tile * 8 + vert_line;
-                        let rgb = image.get_pixel(x, y);
-                        let color = Color::new(rgb[0], rgb[1], rgb[2]);
-
-                        if let Some(gb_color) = color_map.get(&color) {
-                            byte0 |= (gb_color & 0b01) << (7 - hor_line);
-                            byte1 |= ((gb_color & 0b10) >> 1) << (7 - hor_line);
-                        } else {
-                            bail!("Color::new(0x{:x}, 0x{:x}, 0x{:x}) is not mapped to a gameboy color", color.red, color.green, color.blue);
-                        }
-                    }
-                    bytes.push(byte0);
-                    bytes.push(byte1);
-                }
-            }
+        if let Some(info) = self.symbols.info(&self.qualify(identifier)) {
+            bail!(
+                "Identifier {} is already used, it is declared in {}",
+                identifier,
+                info.location()
+            )
         }
-        let size = bytes.len();
-
-        self.data.push(DataHolder {
-            data: Data::Binary(bytes),
-            address: self.address,
-            source: DataSource::Code,
-        });
 
-        let prev_bank = self.get_bank();
-        self.address += size as u32;
-        if prev_bank == self.get_bank() {
-            Ok(self)
-        } else {
-            bail!("The added bytes cross bank boundaries.");
-        }
+        let start = Instant::now();
+        let bytes = asset::convert_image(&self.root_dir, file_name, color_map)?;
+        self.record(|x| &mut x.images, start);
+        self.add_binary(bytes, identifier)
     }
 
     /// Includes audio data generated from the provided ggbasm audio text file in the audio folder.
@@ -332,30 +788,59 @@ impl RomBuilder {
     /// *   rest AA - rest AA frames before continuing
     /// *   jp foo  - set the GGBASMAudio
     /// *   disable - disables audio by setting the value at GGBASMAudioEnable to 0
+    /// *   transpose +N - shift the notes of all following lines by N semitones, use -N to shift down
     ///
     /// TODO: Maybe syntax highlighting could help make the audio format more readable
     pub fn add_audio_file(self, file_name: &str) -> Result<Self, Error> {
-        let path = self.root_dir.as_path().join("audio").join(file_name);
-        let text = match fs::read_to_string(path) {
-            Ok(file) => file,
-            Err(err) => bail!("Cannot read audio file {} because: {}", file_name, err),
-        };
+        self.add_audio_file_with_options(file_name, &AudioOptions::default())
+    }
 
-        let lines = match audio::parse_audio_text(&text) {
-            Ok(lines) => lines,
-            Err(err) => bail!("Cannot parse audio file {} because: {}", file_name, err),
-        };
+    /// Same as [RomBuilder::add_audio_file] but the provided options are applied to the generated data.
+    ///
+    /// e.g. set `transpose` to reuse a song in a different key.
+    pub fn add_audio_file_with_options(
+        mut self,
+        file_name: &str,
+        options: &AudioOptions,
+    ) -> Result<Self, Error> {
+        let start = Instant::now();
+        let data = asset::convert_audio(&self.root_dir, file_name, options)?;
+        self.record(|x| &mut x.audio, start);
+        self.add_instructions_inner(data, DataSource::AudioFile(file_name.to_string()))
+    }
 
-        let data = match audio::generate_audio_data(lines) {
-            Ok(lines) => lines,
-            Err(err) => bail!(
-                "Cannot generate audio from file {} because: {}",
+    /// Converts the provided image and audio assets in parallel then adds them in the order they were provided.
+    ///
+    /// The result is identical to calling [RomBuilder::add_image] and [RomBuilder::add_audio_file_with_options]
+    /// for each job in order.
+    /// Returns the error of the first failing job.
+    pub fn add_assets(mut self, jobs: &[AssetJob]) -> Result<Self, Error> {
+        let start = Instant::now();
+        let assets = asset::convert_assets(&self.root_dir, jobs)?;
+        self.record(|x| &mut x.assets, start);
+        for asset in assets {
+            self = self.add_converted_asset(asset)?;
+        }
+        Ok(self)
+    }
+
+    /// Adds an asset converted by [AssetJob::convert], e.g. on a worker thread.
+    ///
+    /// Returns an error if crosses rom bank boundaries.
+    pub fn add_converted_asset(self, asset: ConvertedAsset) -> Result<Self, Error> {
+        match asset {
+            ConvertedAsset::Image { identifier, bytes } => self.add_bytes(bytes, &identifier),
+            ConvertedAsset::Audio {
                 file_name,
-                err
-            ),
-        };
+                instructions,
+            } => self.add_instructions_inner(instructions, DataSource::AudioFile(file_name)),
+        }
+    }
 
-        self.add_instructions_inner(data, DataSource::AudioFile(file_name.to_string()))
+    /// Returns the directory containing the Cargo.toml of the project,
+    /// the graphics, audio and gbasm folders are located here.
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
     }
 
     /// Includes bytecodes generated from the audio player
@@ -396,470 +881,2904 @@ impl RomBuilder {
             .into_iter()
             .enumerate()
             .map(|(i, x)| {
-                x.unwrap_or_else(|| {
-                    panic!("Invalid instruction on line {} of audio_player.asm", i + 1)
+                x.unwrap_or_else(|err| {
+                    panic!(
+                        "Invalid instruction on line {} of audio_player.asm: {}",
+                        i + 1,
+                        err
+                    )
                 })
             })
             .collect();
         self.add_instructions_inner(instructions, DataSource::AudioPlayer)
     }
 
-    /// Includes bytecodes generated from the provided assembly file in the gbasm folder.
+    /// Includes bytecodes generated from the debug overlay.
     ///
-    /// TODO: Document the syntax.
-    /// Its very similar to the [RGBDS syntax](https://rednex.github.io/rgbds/gbz80.7.html) with the addition of the advance_address command.
-    /// However we should have our syntax documentation listing every instruction and every operator in rom compile time expressions.
+    /// The overlay draws the following as hex digits on the first row of the window layer,
+    /// which is displayed at the bottom of the screen:
+    /// *   an 8 bit frame counter
+    /// *   machine cycles left in VBlank when GGBASMDebugOverlayStep was called
+    /// *   the bytes at each of the provided watched_addresses, up to 4 addresses.
     ///
+    /// The overlay is only compiled in when `DEBUG` is defined, otherwise the functions return immediately.
     /// Returns an error if crosses rom bank boundaries.
-    /// Returns an error if encounters file system issues.
-    pub fn add_asm_file(self, file_name: &str) -> Result<Self, Error> {
-        let path = self.root_dir.as_path().join("gbasm").join(file_name);
-        let text = match fs::read_to_string(path) {
-            Ok(file) => file,
-            Err(err) => bail!("Cannot read asm file {} because: {}", file_name, err),
-        };
-
-        let option_instructions = match parser::parse_asm(&text) {
-            Ok(instructions) => instructions,
-            Err(err) => bail!("Cannot parse asm file {} because: {}", file_name, err),
-        };
+    ///
+    /// # Functions
+    ///
+    /// This should be called once during initialization, while the LCD is off:
+    /// ```asm
+    /// call GGBASMDebugOverlayInit
+    /// ```
+    ///
+    /// This should be called once per frame at the end of VBlank processing:
+    /// ```asm
+    /// call GGBASMDebugOverlayStep
+    /// ```
+    ///
+    /// # RAM Locations
+    ///
+    /// This identifier needs to be set to an unused ram value.
+    /// ```asm
+    /// GGBASMDebugFrameCounter EQU 0xC030
+    /// ```
+    ///
+    /// # VRAM usage
+    ///
+    /// The overlay overwrites tiles 0xEF-0xFF and the first row of the 0x9C00 tile map.
+    /// It also enables the window and selects the 0x9C00 tile map for it.
+    pub fn add_debug_overlay(self, watched_addresses: &[u16]) -> Result<Self, Error> {
+        if !self.is_debug() {
+            return self.add_instructions_inner(
+                debug::generate_debug_overlay_stub(),
+                DataSource::DebugOverlay,
+            );
+        }
 
         let mut instructions = vec![];
-        for (i, instruction) in option_instructions.into_iter().enumerate() {
-            match instruction {
-                Some(instruction) => instructions.push(instruction),
-                None => {
-                    // TODO: Return a proper BuildError enum instead of relying on failure::Error
-                    // TODO: Then I can have a pretty_print() method on it that displays something like:
-                    // ```
-                    // 103: halt   // color green
-                    // 104: nop    // color green
-                    // 105: foobar // color red
-                    // 106: nop    // color white
-                    // An error occured on line 105 of foo_file.asm // color red
-                    // ```
-                    //
-                    // TODO: Even better I could handle multiple errors in one message, I have the
-                    // information given from the parser already, I just need to handle it from RomBuilder.
-                    bail!("Invalid instruction on line {} of {}", i + 1, file_name)
-                }
-            }
+        if !self.symbols.contains_key("GGBASMHexFont") {
+            instructions.extend(debug::generate_hex_support());
         }
-
-        self.add_instructions_inner(instructions, DataSource::AsmFile(file_name.to_string()))
+        instructions.extend(debug::generate_debug_overlay(watched_addresses)?);
+        self.add_instructions_inner(instructions, DataSource::DebugOverlay)
     }
 
-    /// This function is used to include instructions in the rom.
+    /// Includes bytecodes generated from the crash handler.
+    ///
+    /// Executing 0xFF bytes, e.g. after jumping into uninitialized memory, runs `rst 0x38`.
+    /// Use [RomBuilder::fill_free_space] so that running into free space in the rom does too.
+    /// The crash handler is jumped to from `rst 0x38` where it:
+    /// *   writes a crash record to SRAM
+    /// *   draws the registers to the screen
+    /// *   loops forever
+    ///
+    /// Both the crash record and the screen contain the words SP AF BC DE HL PC, in that order.
+    /// The crash record stores them as little endian words.
+    /// SP is the value before `rst 0x38` and PC is the address following the `rst 0x38`.
+    ///
+    /// The `debug_assert z` pseudo instruction (or nz, c, nc) traps to the crash handler when the flag is not set.
+    /// `debug_assert a == Expected` (or !=, <, >=) compares `a` with the value first and traps when the comparison fails.
+    /// It is only included when `DEBUG` is defined before the assembly is added.
+    ///
+    /// If [RomBuilder::add_basic_interrupts_and_jumps] is used, `rst 0x38` jumps to the crash handler.
+    /// Otherwise `jp GGBASMCrashHandler` needs to be placed at 0x0038.
+    ///
+    /// Returns an error if not in the first rom bank, as it needs to run regardless of the selected bank.
     /// Returns an error if crosses rom bank boundaries.
-    pub fn add_instructions(self, instructions: Vec<Instruction>) -> Result<Self, Error> {
-        self.add_instructions_inner(instructions, DataSource::Code)
-    }
+    ///
+    /// # SRAM Locations
+    ///
+    /// This identifier needs to be set to 12 bytes of SRAM that the game does not use.
+    /// ```asm
+    /// GGBASMCrashRecord EQU 0xA000
+    /// ```
+    ///
+    /// # VRAM usage
+    ///
+    /// The crash handler overwrites tiles 0xEF-0xFF and the 0x9800 tile map.
+    pub fn add_crash_handler(self) -> Result<Self, Error> {
+        if self.bank() != 0 {
+            bail!("The crash handler must be added to the first rom bank");
+        }
 
-    fn add_instructions_inner(
-        mut self,
-        instructions: Vec<Instruction>,
-        source: DataSource,
-    ) -> Result<Self, Error> {
-        let mut cur_address = self.address;
-        for (i, instruction) in instructions.iter().enumerate() {
-            if let Instruction::Label(label) = instruction {
-                if self
-                    .constants
-                    .insert(label.to_string(), cur_address as i64)
-                    .is_some()
-                {
-                    // TODO: Display first usage
-                    bail!(
-                        "Identifier {} is used twice: One usage occured in {} on line {}",
-                        label,
-                        source.description(),
-                        i + 1
-                    );
-                }
-            } else {
-                cur_address += instruction.bytes_len((cur_address % ROM_BANK_SIZE) as u16) as u32;
+        let mut instructions = vec![];
+        match self.symbols.get("GGBASMHexFont") {
+            Some(address) if *address >= ROM_BANK_SIZE as i64 => {
+                bail!("The crash handler needs the debug overlay to be in the first rom bank, or to be added before the debug overlay")
             }
+            Some(_) => {}
+            None => instructions.extend(debug::generate_hex_support()),
         }
+        instructions.extend(debug::generate_crash_handler());
+        self.add_instructions_inner(instructions, DataSource::CrashHandler)
+    }
+
+    /// Includes bytecodes generated from the fixed point math routines.
+    ///
+    /// Adds a 512 byte sine table labeled GGBASMSineTable followed by the routines:
+    /// *   GGBASMSinLookup - sets de to the sine of a as signed 8.8 fixed point, where 256 is a full turn
+    /// *   GGBASMCosLookup - sets de to the cosine of a as signed 8.8 fixed point, where 256 is a full turn
+    /// *   GGBASMMulFixed - sets hl to de * bc where all three are signed 8.8 fixed point
+    /// *   GGBASMMulFixedUnsigned - sets hl to de * bc where all three are unsigned 8.8 fixed point
+    ///
+    /// The lookups clobber a and hl, the multiplications clobber af, bc and de.
+    /// Other tables can be generated with the [crate::fixed_point] module.
+    pub fn add_fixed_point_routines(self) -> Result<Self, Error> {
+        self.add_instructions_inner(fixed_point::generate_routines(), DataSource::FixedPoint)
+    }
+
+    /// Includes bytecodes generated from the provided assembly file in the gbasm folder.
+    ///
+    /// TODO: Document the syntax.
+    /// Its very similar to the [RGBDS syntax](https://rednex.github.io/rgbds/gbz80.7.html) with the addition of the advance_address command.
+    /// However we should have our syntax documentation listing every instruction and every operator in rom compile time expressions.
+    ///
+    /// Fixed point literals are scaled by 2 to the power of the number after the q, e.g. `12.5q8` is 0x0C80.
+    ///
+    /// Other asm files in the gbasm folder can be included with `include "other.asm"`.
+    /// The included file is added in place of the include, it can be used to share constants and routines.
+    /// Macros are not shared between files.
+    ///
+    /// Binary files in the gbasm folder can be included as bytes with `incbin "data/level1.bin"`.
+    /// An offset and length can be provided to only include part of the file: `incbin "data/level1.bin", 0x100, 0x20`
+    ///
+    /// Sections declare where the following instructions belong, e.g. `SECTION "Levels", ROMX, BANK[2]`:
+    /// *   `ROM0` sections continue at the current address, which must be in the first bank.
+    /// *   `ROMX` sections without a bank continue in the current bank when they fit, otherwise they start the next bank.
+    /// *   `ROMX` sections with a bank continue in that bank when it is the current bank, otherwise they start it.
+    /// *   An address can be given e.g. `ROMX[0x4100]` to start the section there.
+    ///
+    /// The address is never moved backwards, so sections must be in order of their bank.
+    /// Each section named with a valid identifier defines `SIZEOF_<name>` as the number of bytes it contains,
+    /// e.g. `SIZEOF_Levels`, which can be referred to anywhere except in conditions.
+    ///
+    /// Labels can only be referred to by the asm file and the files it includes, unless exported with
+    /// `export Label` or `global Label`. Exported labels can be referred to by other asm files and rust code.
+    /// EQU constants are always visible everywhere, string constants declared with EQUS are visible to the lines
+    /// after them, including in the files that include them and the asm files added afterwards.
+    ///
+    /// Constants can also be declared in the modern RGBDS form, e.g. `DEF Lives EQU 3` or `DEF Counter = 0`.
+    /// Constants declared with `Counter SET 0`, `Counter = Counter + 1` or `REDEF Counter EQU 2` can be reassigned.
+    /// References use the value of the nearest previous assignment, including assignments in previously added files.
+    ///
+    /// `dbfill 64, 0xFF` repeats a byte, e.g. to pad a table or add blank tiles.
+    ///
+    /// `opt Werror, h, radix=16` sets options for the rest of the file, see [AsmOption].
+    /// `pusho` saves the options and `popo` restores them.
+    ///
+    /// `pushs` saves the current section so another section can be started, e.g. to put a table in another bank,
+    /// then `pops` continues the saved section where it left off. They must be matched within a file.
+    ///
+    /// A file containing `pragma once` is skipped when it is included or added again,
+    /// so shared constant files can be included by every file that uses them.
+    ///
+    /// `print "Table is ", TableEnd - Table, " bytes"` adds the message to [Layout::messages] when the rom is compiled,
+    /// `warn` adds it to [Layout::warnings] and `fail` fails the build with it.
+    ///
+    /// Returns an error if crosses rom bank boundaries.
+    /// Returns an error if encounters file system issues.
+    /// Returns an error if a file includes itself, directly or indirectly.
+    pub fn add_asm_file(mut self, file_name: &str) -> Result<Self, Error> {
+        let start = Instant::now();
+        let mut chunks = vec![];
+        let mut state = AsmFileState {
+            sets: std::mem::take(&mut self.set_constants),
+            once: std::mem::take(&mut self.included_once),
+            references: std::mem::take(&mut self.condition_references),
+            strings: std::mem::take(&mut self.string_constants),
+        };
+        let loaded = self.load_asm_file(file_name, &mut vec![], &mut state, &mut chunks);
+        self.set_constants = state.sets;
+        self.included_once = state.once;
+        self.condition_references = state.references;
+        self.string_constants = state.strings;
+        loaded?;
+        make_labels_private(&mut chunks, file_name)?;
+        self.record(|x| &mut x.parsing, start);
+
+        // the name and start address of the current section, its size is known once the next section starts
+        let mut section: Option<(String, u32)> = None;
+        // the address to continue from once the current section ends, when it was placed before the address
+        let mut resume = None;
+        // the section, address and resume address saved by each PUSHS
+        let mut pushed = vec![];
+        // where the section continued by the last POPS starts and the number of data added before it
+        let mut popped: Option<PoppedSection> = None;
+        for chunk in chunks {
+            self = match chunk {
+                AsmChunk::Instructions(instructions, spans, source) => {
+                    self.add_instructions_with_spans(instructions, spans, source)?
+                }
+                AsmChunk::Section {
+                    name,
+                    section_type,
+                    address,
+                    bank,
+                    size,
+                    file_name,
+                    line,
+                } => {
+                    let error = |err: Error| {
+                        anyhow::anyhow!("Error occured in {} on line {}: {}", file_name, line, err)
+                    };
+                    self.check_popped_section(popped.take())?;
+                    if let Some((name, start)) = section.take() {
+                        self.define_size(&name, self.address - start)
+                            .map_err(error)?;
+                    }
+                    if let Some(address) = resume.take() {
+                        self.address = self.address.max(address);
+                    }
+                    let before = self.address;
+                    let source = DataSource::AsmSection(name.clone(), file_name.clone());
+                    self = self
+                        .place_section(&name, &source, section_type, address, bank, size)
+                        .map_err(error)?;
+                    if self.address < before {
+                        resume = Some(before);
+                    }
+                    section = Some((name, self.address));
+                    self
+                }
+                AsmChunk::PushSection => {
+                    self.check_popped_section(popped.take())?;
+                    pushed.push((section.take(), self.address, resume.take()));
+                    self
+                }
+                AsmChunk::PopSection { file_name, line } => {
+                    self.check_popped_section(popped.take())?;
+                    if let Some((name, start)) = section.take() {
+                        self.define_size(&name, self.address - start)
+                            .map_err(|err| {
+                                anyhow::anyhow!(
+                                    "Error occured in {} on line {}: {}",
+                                    file_name,
+                                    line,
+                                    err
+                                )
+                            })?;
+                    }
+                    let (outer, address, outer_resume) = match pushed.pop() {
+                        Some(pushed) => pushed,
+                        None => bail!(
+                            "Error occured in {} on line {}: POPS has no matching PUSHS",
+                            file_name,
+                            line
+                        ),
+                    };
+                    let furthest = self.address.max(resume.unwrap_or(0));
+                    resume = Some(furthest.max(outer_resume.unwrap_or(0)));
+                    self.address = address;
+                    section = outer;
+                    popped = Some(PoppedSection {
+                        start: address,
+                        existing: self.data.len(),
+                        file_name,
+                        line,
+                    });
+                    self
+                }
+            };
+        }
+        self.check_popped_section(popped)?;
+        if let Some((name, start)) = section {
+            self.define_size(&name, self.address - start)?;
+        }
+        if let Some(address) = resume {
+            self.address = self.address.max(address);
+        }
+        Ok(self)
+    }
+
+    /// Returns an error if the section continued after a POPS grew into data placed before the POPS.
+    fn check_popped_section(&self, popped: Option<PoppedSection>) -> Result<(), Error> {
+        let popped = match popped {
+            Some(popped) => popped,
+            None => return Ok(()),
+        };
+        let overlapping = self.data[..popped.existing].iter().find(|x| {
+            x.address < self.address
+                && popped.start < x.address + data_len(&x.data, x.address, &self.symbols)
+        });
+        if let Some(existing) = overlapping {
+            bail!(
+                "Error occured in {} on line {}: The section continued after POPS at 0x{:04x}-0x{:04x} overlaps {} at 0x{:04x}",
+                popped.file_name,
+                popped.line,
+                popped.start,
+                self.address - 1,
+                existing.source.description(),
+                existing.address
+            );
+        }
+        Ok(())
+    }
+
+    /// Defines `SIZEOF_<identifier>` as size, within the open namespaces.
+    /// Nothing is defined when the identifier contains characters that cant be used in an identifier.
+    fn define_size(&mut self, identifier: &str, size: u32) -> Result<(), Error> {
+        if !identifier
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || x == '_')
+        {
+            return Ok(());
+        }
+        self.symbols.declare(
+            &self.qualify(&format!("SIZEOF_{}", identifier)),
+            size as i64,
+            SymbolInfo::rust(SymbolKind::Define, None),
+        )
+    }
+
+    /// Replaces stop and halt according to [RomBuilder::stop_halt_padding],
+    /// and applies the options of each [Instruction::Opt] to the instructions after it.
+    fn apply_options(&self, instructions: &mut [Instruction]) {
+        let mut options = AsmOptions::new(self.pad_halt);
+        for instruction in instructions {
+            options.apply(instruction, self.pad_stop);
+        }
+    }
+
+    /// Reads the asm file and the files it includes into chunks, in the order they are added.
+    /// `including` is the chain of files that included this file, used to detect include cycles.
+    ///
+    /// Each line is processed as it is parsed and included files are loaded as their include is reached,
+    /// so they see the SET constants assigned before the include and the file sees the string constants
+    /// declared by the files it includes.
+    fn load_asm_file(
+        &self,
+        file_name: &str,
+        including: &mut Vec<String>,
+        state: &mut AsmFileState,
+        chunks: &mut Vec<AsmChunk>,
+    ) -> Result<(), Error> {
+        if including.iter().any(|x| x == file_name) {
+            bail!(
+                "Include cycle detected: {} -> {}",
+                including.join(" -> "),
+                file_name
+            );
+        }
+
+        let path = self.source_dir.join(file_name);
+        let bytes = match fs::read(&path) {
+            Ok(file) => file,
+            Err(err) => bail!("Cannot read asm file {} because: {}", file_name, err),
+        };
+        let text = match parser::decode_asm(&bytes) {
+            Ok(text) => text,
+            Err(err) => bail!("Cannot read asm file {} because: {}", file_name, err),
+        };
+        let parse_error =
+            |err: Error| anyhow::anyhow!("Cannot parse asm file {} because: {}", file_name, err);
+
+        // Conditionals are resolved for the whole file so that includes and incbins can be conditional.
+        let source = DataSource::AsmFile(file_name.to_string());
+        including.push(file_name.to_string());
+        let mut instructions = vec![];
+        let mut spans = vec![];
+        let mut included = HashMap::new();
+        let mut options = AsmOptions::new(self.pad_halt);
+        let mut conditionals = Conditionals::new(&self.symbols, &source);
+        let mut parser = parser::SpannedParser::new(text);
+        while let Some(line) = parser.next_line(&mut state.strings).map_err(parse_error)? {
+            for instruction in line {
+                let (mut instruction, span) = match instruction {
+                    Ok(spanned) => (
+                        spanned.inner,
+                        Span {
+                            file: Some(file_name.to_string()),
+                            ..spanned.span
+                        },
+                    ),
+                    Err(diagnostic) => {
+                        // TODO: Handle multiple errors in one message, the parser already provides a diagnostic
+                        // for every invalid line.
+                        bail!(
+                            "Invalid instruction on line {} of {}: {}",
+                            diagnostic.line,
+                            file_name,
+                            diagnostic
+                        )
+                    }
+                };
+                // the lines before `pragma once` are expected to declare nothing
+                if instruction == Instruction::PragmaOnce
+                    && !state
+                        .once
+                        .insert(path.canonicalize().unwrap_or(path.clone()))
+                {
+                    including.pop();
+                    return Ok(());
+                }
+                if !self.is_debug() {
+                    strip_debug_instructions(std::slice::from_mut(&mut instruction));
+                }
+                options.apply(&mut instruction, self.pad_stop);
+                conditionals.resolve(&mut instruction, span.line, &mut state.sets)?;
+                if let Instruction::Include(include) = &instruction {
+                    let mut include_chunks = vec![];
+                    self.load_asm_file(include, including, state, &mut include_chunks)?;
+                    included.insert(instructions.len(), include_chunks);
+                }
+                instructions.push(instruction);
+                spans.push(span);
+            }
+        }
+        parser.finish().map_err(parse_error)?;
+        state.references.extend(conditionals.finish()?);
+        resolve_namespaces(
+            &mut instructions,
+            &self.namespaces,
+            &self.symbols,
+            &source,
+            &spans,
+        )?;
+
+        for (i, instruction) in instructions.iter_mut().enumerate() {
+            if let Instruction::Incbin(bin_name, offset, length) = instruction {
+                let bytes = self
+                    .read_incbin(bin_name, *offset, *length)
+                    .map_err(|err| {
+                        anyhow::anyhow!(
+                            "Error occured in {} on line {}: {}",
+                            file_name,
+                            spans[i].line,
+                            err
+                        )
+                    })?;
+                *instruction = Instruction::Db(bytes);
+            }
+        }
+
+        // The size of each section up to the next section, used to find a bank with room for it.
+        // Included files add to the section until they start a section of their own.
+        let mut section_sizes = vec![0; instructions.len()];
+        let mut section_start = None;
+        let mut pushed_starts = vec![];
+        for (i, instruction) in instructions.iter().enumerate() {
+            match instruction {
+                Instruction::Section(..) => section_start = Some(i),
+                Instruction::Include(_) => {
+                    let (size, starts_section) =
+                        included_section_size(included.get(&i).map_or(&[], |x| x.as_slice()));
+                    if let Some(start) = section_start {
+                        section_sizes[start] += size;
+                    }
+                    if starts_section {
+                        section_start = None;
+                    }
+                }
+                Instruction::PushS => pushed_starts.push(section_start.take()),
+                Instruction::PopS => section_start = pushed_starts.pop().flatten(),
+                instruction => {
+                    if let Some(start) = section_start {
+                        // The address only matters for advance_address, which is validated once the section is placed.
+                        section_sizes[start] += instruction.bytes_len(0).unwrap_or(0) as u32;
+                    }
+                }
+            }
+        }
+
+        // The instructions are split at each include and section, each chunk keeps the spans of its instructions.
+        // PUSHS and POPS must be matched within a file, each PUSHS saves the source and its line.
+        let mut source = source;
+        let mut pushed_sources = vec![];
+        let mut chunk = vec![];
+        let mut chunk_spans = vec![];
+        let take = |chunk: &mut Vec<Instruction>, chunk_spans: &mut Vec<Span>, source| {
+            AsmChunk::Instructions(std::mem::take(chunk), std::mem::take(chunk_spans), source)
+        };
+        for (i, (instruction, span)) in instructions.into_iter().zip(spans).enumerate() {
+            let line = span.line;
+            match instruction {
+                Instruction::PushS => {
+                    chunks.push(take(&mut chunk, &mut chunk_spans, source.clone()));
+                    chunks.push(AsmChunk::PushSection);
+                    pushed_sources.push((source.clone(), line));
+                }
+                Instruction::PopS => {
+                    chunks.push(take(&mut chunk, &mut chunk_spans, source));
+                    source = match pushed_sources.pop() {
+                        Some((source, _)) => source,
+                        None => bail!(
+                            "Error occured in {} on line {}: POPS has no matching PUSHS",
+                            file_name,
+                            line
+                        ),
+                    };
+                    chunks.push(AsmChunk::PopSection {
+                        file_name: file_name.to_string(),
+                        line,
+                    });
+                }
+                Instruction::Include(_) => {
+                    chunks.push(take(&mut chunk, &mut chunk_spans, source.clone()));
+                    chunks.extend(included.remove(&i).unwrap_or_default());
+                }
+                Instruction::Section(name, section_type, address, bank) => {
+                    chunks.push(take(&mut chunk, &mut chunk_spans, source));
+                    chunks.push(AsmChunk::Section {
+                        name: name.clone(),
+                        section_type,
+                        address,
+                        bank,
+                        size: section_sizes[i],
+                        file_name: file_name.to_string(),
+                        line,
+                    });
+                    source = DataSource::AsmSection(name, file_name.to_string());
+                }
+                instruction => {
+                    chunk.push(instruction);
+                    chunk_spans.push(span);
+                }
+            }
+        }
+        including.pop();
+        if let Some((_, line)) = pushed_sources.pop() {
+            bail!(
+                "Error occured in {} on line {}: PUSHS has no matching POPS",
+                file_name,
+                line
+            );
+        }
+
+        chunks.push(AsmChunk::Instructions(chunk, chunk_spans, source));
+        Ok(())
+    }
+
+    /// Moves the address to where a section of size bytes is placed.
+    ///
+    /// Sections kept from the previous layout can move the address backwards,
+    /// see [RomBuilder::previous_layout].
+    fn place_section(
+        self,
+        name: &str,
+        source: &DataSource,
+        section_type: SectionType,
+        address: Option<Expr>,
+        bank: Option<Expr>,
+        size: u32,
+    ) -> Result<Self, Error> {
+        if self.previous_sections.is_empty() || address.is_some() {
+            return self.advance_to_section(name, section_type, address, bank, size);
+        }
+
+        let fixed_bank = match &bank {
+            Some(expr) => Some(expr.run(&self.symbols)?),
+            None => None,
+        };
+        if let Some(previous) =
+            self.previous_section_address(source, section_type, fixed_bank, size)
+        {
+            let mut builder = self;
+            builder.address = previous;
+            return Ok(builder);
+        }
+        self.advance_to_section(name, section_type, None, bank, size)?
+            .skip_previous_sections(name, section_type, fixed_bank.is_some(), size)
+    }
+
+    /// Returns the address of the section in the previous layout, if it can be placed there again.
+    fn previous_section_address(
+        &self,
+        source: &DataSource,
+        section_type: SectionType,
+        bank: Option<i64>,
+        size: u32,
+    ) -> Option<u32> {
+        let source = source.description();
+        let previous = self.previous_sections.iter().filter(|x| x.source == source);
+        let start = previous.clone().map(|x| x.address).min()?;
+        let end = previous.map(|x| x.address + x.size).max()?;
+
+        let in_region = match section_type {
+            SectionType::Rom0 => start < ROM_BANK_SIZE && bank.is_none(),
+            SectionType::Romx => {
+                start >= ROM_BANK_SIZE
+                    && bank.is_none_or(|bank| bank == (start / ROM_BANK_SIZE) as i64)
+            }
+        };
+        let fits = size <= end - start && start % ROM_BANK_SIZE + size <= ROM_BANK_SIZE;
+        let free = start >= self.address || self.overlapping_data(start, start + size).is_none();
+        (in_region && fits && free).then_some(start)
+    }
+
+    /// Moves the address past any space used by the previous layout, so a section of size bytes does not overlap
+    /// sections that are kept at their previous address.
+    fn skip_previous_sections(
+        mut self,
+        name: &str,
+        section_type: SectionType,
+        fixed_bank: bool,
+        size: u32,
+    ) -> Result<Self, Error> {
+        let bank = self.bank();
+        loop {
+            let end = self.address + size;
+            let next = self
+                .previous_sections
+                .iter()
+                .filter(|x| x.address < end && self.address < x.address + x.size)
+                .map(|x| x.address + x.size)
+                .max();
+            let mut next = match next {
+                Some(next) => next,
+                None => return Ok(self),
+            };
+            if next % ROM_BANK_SIZE + size > ROM_BANK_SIZE {
+                next = (next / ROM_BANK_SIZE + 1) * ROM_BANK_SIZE;
+            }
+            if next / ROM_BANK_SIZE != bank && (section_type == SectionType::Rom0 || fixed_bank) {
+                bail!(
+                    "Section {} is {} bytes which does not fit in bank {} around the sections kept from the previous layout",
+                    name,
+                    size,
+                    bank
+                );
+            }
+            self.address = next;
+        }
+    }
+
+    /// Advances the address to where a section of size bytes is placed when there is no previous layout.
+    fn advance_to_section(
+        self,
+        name: &str,
+        section_type: SectionType,
+        address: Option<Expr>,
+        bank: Option<Expr>,
+        size: u32,
+    ) -> Result<Self, Error> {
+        let address = match address {
+            Some(expr) => Some(expr.run(&self.symbols)?),
+            None => None,
+        };
+        let bank = match bank {
+            Some(expr) => Some(expr.run(&self.symbols)?),
+            None => None,
+        };
+
+        match section_type {
+            SectionType::Rom0 => {
+                if let Some(bank) = bank {
+                    bail!(
+                        "Section {} is in ROM0 so cannot be placed in bank {}",
+                        name,
+                        bank
+                    );
+                }
+                if self.bank() != 0 {
+                    bail!(
+                        "Section {} is in ROM0 but the address is already in bank {}",
+                        name,
+                        self.bank()
+                    );
+                }
+                match address {
+                    Some(address) if !(0..ROM_BANK_SIZE as i64).contains(&address) => bail!(
+                        "Section {} is in ROM0 so its address must be 0x0000-0x3FFF, was 0x{:x}",
+                        name,
+                        address
+                    ),
+                    Some(address) => self.advance_section(name, 0, address as u32),
+                    None => Ok(self),
+                }
+            }
+            SectionType::Romx => {
+                let offset = match address {
+                    Some(address) if !(0x4000..0x8000).contains(&address) => bail!(
+                        "Section {} is in ROMX so its address must be 0x4000-0x7FFF, was 0x{:x}",
+                        name,
+                        address
+                    ),
+                    Some(address) => Some(address as u32 - ROM_BANK_SIZE),
+                    None => None,
+                };
+                let bank = match bank {
+                    Some(bank) if !(1..0x200).contains(&bank) => {
+                        bail!(
+                            "Section {} is in ROMX so its bank must be 1-511, was {}",
+                            name,
+                            bank
+                        )
+                    }
+                    Some(bank) => bank as u32,
+                    // Use the current bank when the section fits, otherwise the next bank
+                    None => {
+                        let start = offset.unwrap_or(self.offset_in_bank());
+                        if self.bank() >= 1
+                            && start >= self.offset_in_bank()
+                            && start + size <= ROM_BANK_SIZE
+                        {
+                            self.bank()
+                        } else {
+                            self.bank() + 1
+                        }
+                    }
+                };
+                if size > ROM_BANK_SIZE - offset.unwrap_or(0) {
+                    bail!(
+                        "Section {} is {} bytes which does not fit in a rom bank",
+                        name,
+                        size
+                    );
+                }
+                match offset {
+                    Some(offset) => self.advance_section(name, bank, offset),
+                    None if bank == self.bank() => Ok(self),
+                    None => self.advance_section(name, bank, 0),
+                }
+            }
+        }
+    }
+
+    fn advance_section(self, name: &str, rom_bank: u32, address: u32) -> Result<Self, Error> {
+        let current = self.get_address_global();
+        let target = GlobalAddr(rom_bank * ROM_BANK_SIZE + address);
+        self.advance_to(target.0).map_err(|_| {
+            anyhow::anyhow!(
+                "Section {} at {} in bank {} is before the current address {} in bank {}",
+                name,
+                target.bank_addr(),
+                rom_bank,
+                current.bank_addr(),
+                current.bank().0
+            )
+        })
+    }
+
+    /// Reads the bytes of a binary file in the gbasm folder, starting at offset.
+    /// When length is None the rest of the file is read.
+    fn read_incbin(
+        &self,
+        file_name: &str,
+        offset: u32,
+        length: Option<u32>,
+    ) -> Result<Vec<u8>, Error> {
+        let path = self.source_dir.join(file_name);
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) => bail!("Cannot read binary file {} because: {}", file_name, err),
+        };
+
+        let start = offset as usize;
+        if start > bytes.len() {
+            bail!(
+                "Cannot read binary file {} from offset {} because it is only {} bytes",
+                file_name,
+                start,
+                bytes.len()
+            );
+        }
+        let end = match length {
+            Some(length) => start + length as usize,
+            None => bytes.len(),
+        };
+        if end > bytes.len() {
+            bail!(
+                "Cannot read bytes {}..{} of binary file {} because it is only {} bytes",
+                start,
+                end,
+                file_name,
+                bytes.len()
+            );
+        }
+        Ok(bytes[start..end].to_vec())
+    }
+
+    /// Adds data that must be placed at a fixed address, regardless of the current address.
+    /// The current address is only moved forwards.
+    fn add_fixed(&mut self, data: Data, address: u32, size: u32, name: &str) -> Result<(), Error> {
+        let end = address + size;
+        if let Some(existing) = self.overlapping_data(address, end) {
+            bail!(
+                "The {} at 0x{:04x}-0x{:04x} overlaps {} at 0x{:04x}",
+                name,
+                address,
+                end - 1,
+                existing.source.description(),
+                existing.address
+            );
+        }
+        self.data.push(DataHolder {
+            data,
+            address,
+            source: DataSource::Code,
+            spans: vec![],
+        });
+        self.address = self.address.max(end);
+        Ok(())
+    }
+
+    /// Returns the first added data that overlaps the addresses from start up to end.
+    fn overlapping_data(&self, start: u32, end: u32) -> Option<&DataHolder> {
+        self.data.iter().find(|x| {
+            x.address < end && start < x.address + data_len(&x.data, x.address, &self.symbols)
+        })
+    }
+
+    /// This function is used to include instructions in the rom.
+    /// Instructions can refer to labels declared after them, the labels are placed before the rom is compiled.
+    /// Returns an error if crosses rom bank boundaries.
+    pub fn add_instructions(self, instructions: Vec<Instruction>) -> Result<Self, Error> {
+        self.add_instructions_inner(instructions, DataSource::Code)
+    }
+
+    fn add_instructions_inner(
+        self,
+        instructions: Vec<Instruction>,
+        source: DataSource,
+    ) -> Result<Self, Error> {
+        self.add_instructions_with_spans(instructions, vec![], source)
+    }
+
+    /// Adds the instructions, spans holds where each instruction was written and is empty when they were not
+    /// parsed from an asm file.
+    fn add_instructions_with_spans(
+        mut self,
+        mut instructions: Vec<Instruction>,
+        spans: Vec<Span>,
+        source: DataSource,
+    ) -> Result<Self, Error> {
+        if !self.is_debug() {
+            strip_debug_instructions(&mut instructions);
+        }
+        // asm files have their options applied as they are loaded, so options dont carry over between files
+        if !matches!(source, DataSource::AsmFile(_) | DataSource::AsmSection(..)) {
+            self.apply_options(&mut instructions);
+        }
+        // asm files resolve their namespaces as they are loaded, the built-in routines are never namespaced
+        if matches!(source, DataSource::Code) {
+            resolve_namespaces(
+                &mut instructions,
+                &self.namespaces,
+                &self.symbols,
+                &source,
+                &spans,
+            )?;
+        }
+        let start = Instant::now();
+        let references = resolve_conditionals(
+            &mut instructions,
+            &self.symbols,
+            &mut self.set_constants,
+            &source,
+        )?;
+        self.condition_references.extend(references);
+        self.anonymous_labels =
+            resolve_anonymous_labels(&mut instructions, self.anonymous_labels, &source, &spans)?;
+        if let Some(i) = instructions.iter().position(|x| {
+            matches!(
+                x,
+                Instruction::Union | Instruction::NextU | Instruction::EndU
+            )
+        }) {
+            bail!(
+                "Error occured in {} on line {}: Unions can only be placed in RAM sections, which are not supported yet",
+                source.description(),
+                Span::line_of(&spans, i)
+            );
+        }
+        if let Some(i) = instructions
+            .iter()
+            .position(|x| matches!(x, Instruction::PushS | Instruction::PopS))
+        {
+            bail!(
+                "Error occured in {} on line {}: PUSHS and POPS can only be used in asm files",
+                source.description(),
+                Span::line_of(&spans, i)
+            );
+        }
+
+        let cur_address = self.place_labels(&instructions, &source, &spans)?;
+        self.record(|x| &mut x.constants, start);
 
         self.data.push(DataHolder {
             data: Data::Instructions(instructions),
             address: self.address,
             source,
+            spans,
         });
 
-        let prev_bank = self.get_bank();
-        self.address = cur_address as u32;
-        if prev_bank == self.get_bank() {
-            Ok(self)
-        } else {
-            bail!("The added instructions cross bank boundaries.");
+        let prev_bank = self.bank();
+        self.address = cur_address;
+        if prev_bank == self.bank() {
+            Ok(self)
+        } else {
+            bail!("The added instructions cross bank boundaries.");
+        }
+    }
+
+    /// Declares each label in the instructions as its address, returning the address after the last instruction.
+    ///
+    /// The size of an instruction can depend on the value of a label declared after it, so the instructions are sized
+    /// in passes, each using the label addresses of the previous pass.
+    /// Once a pass places every label at the same address as the previous pass the addresses are final.
+    fn place_labels(
+        &mut self,
+        instructions: &[Instruction],
+        source: &DataSource,
+        spans: &[Span],
+    ) -> Result<u32, Error> {
+        for (i, instruction) in instructions.iter().enumerate() {
+            if let Instruction::Label(label) = instruction {
+                let line = Span::line_of(spans, i);
+                if self.set_constants.contains_key(label) {
+                    bail!(
+                        "Identifier {} is used twice: One usage occured in {} on line {}, the other is assigned with SET",
+                        label,
+                        source.description(),
+                        line
+                    );
+                }
+                // the address is set by place_labels_passes
+                self.symbols.declare_label(
+                    label,
+                    GlobalAddr(self.address),
+                    SymbolInfo::asm(SymbolKind::Label, source.description(), line),
+                )?;
+            }
+        }
+
+        // EQU constants are defined when the rom is compiled, but advance_address can depend on them,
+        // so they are temporarily defined while placing labels.
+        let mut equs = vec![];
+        let result = self.place_labels_passes(instructions, source, spans, &mut equs);
+        for equ in equs {
+            self.symbols.remove_value(equ);
+        }
+        result
+    }
+
+    fn place_labels_passes<'a>(
+        &mut self,
+        instructions: &'a [Instruction],
+        source: &DataSource,
+        spans: &[Span],
+        equs: &mut Vec<&'a str>,
+    ) -> Result<u32, Error> {
+        let mut previous = None;
+        for _ in 0..MAX_LABEL_PASSES {
+            let mut cur_address = self.address;
+            let mut addresses = vec![];
+            for (i, instruction) in instructions.iter().enumerate() {
+                match instruction {
+                    Instruction::Label(label) => {
+                        self.symbols.set_label(label, GlobalAddr(cur_address));
+                        addresses.push(cur_address);
+                    }
+                    Instruction::Equ(identifier, expr) => {
+                        if !self.symbols.contains_key(identifier) {
+                            if let Ok(value) = expr.run(&self.symbols) {
+                                self.symbols.set_value(identifier, value);
+                                equs.push(identifier);
+                            }
+                        }
+                    }
+                    instruction => match instruction
+                        .bytes_len_with((cur_address % ROM_BANK_SIZE) as u16, &self.symbols)
+                    {
+                        Ok(len) => cur_address += len as u32,
+                        Err(err) => bail!(
+                            "Error occured in {} on line {}: {}",
+                            source.description(),
+                            Span::line_of(spans, i),
+                            err
+                        ),
+                    },
+                }
+            }
+            if previous.as_ref() == Some(&addresses) {
+                return Ok(cur_address);
+            }
+            previous = Some(addresses);
+        }
+        bail!(
+            "The labels in {} are still moving after {} passes, the size of an instruction depends on a label in a way that never settles",
+            source.description(),
+            MAX_LABEL_PASSES
+        )
+    }
+
+    /// Sets which lints are run when the rom is compiled, any lints found are reported in [Layout::warnings].
+    pub fn lint_profile(mut self, profile: LintProfile) -> Result<Self, Error> {
+        self.lint_profile = profile;
+        Ok(self)
+    }
+
+    /// Warns about labels and EQU constants that no expression refers to, when the rom is compiled.
+    ///
+    /// Data added by rust code or labels of db/dw data are reported as data that is never referred to by a jump,
+    /// call or pointer, which often means an asset that is no longer used still takes up space in the rom.
+    /// Labels at the interrupt and rst vectors or the entry point are reached by the hardware and are never reported.
+    /// The warnings are included in [Layout::warnings].
+    pub fn warn_unused(mut self) -> Result<Self, Error> {
+        self.warn_unused = true;
+        Ok(self)
+    }
+
+    /// Writes every symbol to the file at the root of the project as JSON, each time the rom is compiled.
+    ///
+    /// If the file was written by a previous build, symbols that moved or were removed since then are included in
+    /// [Layout::symbol_drift].
+    /// Useful for external tools such as cheat codes and save editors that depend on stable addresses.
+    pub fn symbol_snapshot(mut self, file_name: &str) -> Result<Self, Error> {
+        self.symbol_snapshot = Some(file_name.to_string());
+        Ok(self)
+    }
+
+    /// Places asm sections at their address in the layout JSON written by a previous build,
+    /// see [RomBuilder::write_to_disk_with_layout].
+    ///
+    /// Sections that are no larger than in the previous build are kept at their previous address,
+    /// so their labels stay at the same address when the section is unchanged.
+    /// New and larger sections are placed as usual, but skip over the space used by the previous build.
+    /// Useful for patch releases that stay binary compatible with a shipped rom.
+    /// Use [RomBuilder::symbol_snapshot] to list the symbols that moved anyway.
+    ///
+    /// Only affects asm files added afterwards and sections without an address.
+    /// Does nothing when the file does not exist, e.g. for the first build.
+    pub fn previous_layout(mut self, file_name: &str) -> Result<Self, Error> {
+        let path = self.root_dir.join(file_name);
+        if let Ok(json) = fs::read_to_string(path) {
+            match Layout::from_json(&json) {
+                Ok(layout) => self.previous_sections = layout.sections,
+                Err(err) => bail!("Cannot read previous layout {} because: {}", file_name, err),
+            }
+        }
+        Ok(self)
+    }
+
+    /// Pins the symbol to the value, compile returns an error if the symbol resolves to any other value.
+    /// Labels are pinned to their address within the entire rom, as in [Layout::symbols].
+    ///
+    /// Guarantees addresses that external tools depend on do not move when the layout changes.
+    /// Labels private to a file must be exported to be pinned.
+    /// Returns an error if the identifier is already pinned.
+    pub fn pin_symbol(mut self, identifier: &str, value: i64) -> Result<Self, Error> {
+        if self.pinned_symbols.iter().any(|(x, _)| x == identifier) {
+            bail!("Symbol {} is already pinned", identifier);
+        }
+        self.pinned_symbols.push((identifier.to_string(), value));
+        Ok(self)
+    }
+
+    /// Measures how long each phase of the build takes, e.g. parsing, image conversion and encoding.
+    ///
+    /// The profile is included in [Layout::profile] when the rom is compiled, its Display implementation formats it
+    /// as a table.
+    /// Only phases after this call are measured, so call it first.
+    pub fn profile_build(mut self) -> Result<Self, Error> {
+        self.profile = Some(BuildProfile::default());
+        Ok(self)
+    }
+
+    /// Adds the time since start to a phase of the profile, when profile_build was called.
+    fn record(&mut self, phase: fn(&mut BuildProfile) -> &mut Duration, start: Instant) {
+        if let Some(profile) = &mut self.profile {
+            *phase(profile) += start.elapsed();
+        }
+    }
+
+    /// Sets whether `stop` and `halt` are followed by a `nop`.
+    ///
+    /// By default `stop` is 1 byte and `halt` is followed by a `nop` to avoid the halt bug.
+    /// Only affects instructions added afterwards.
+    /// To choose for a single instruction use [Instruction::StopPadded] or [Instruction::HaltUnpadded] instead,
+    /// or for the rest of an asm file use `opt h` or `opt !h`, see [AsmOption::HaltPadding].
+    pub fn stop_halt_padding(mut self, stop: bool, halt: bool) -> Result<Self, Error> {
+        self.pad_stop = stop;
+        self.pad_halt = halt;
+        Ok(self)
+    }
+
+    /// Sets the number of rom banks in the header instead of using the fewest banks that fit the rom.
+    ///
+    /// The rom is padded to the size of the banks, e.g. to test a 64 bank image on a flash cart when only 5 banks are used.
+    /// Compile returns an error if the rom does not fit in the banks or the cartridge type does not support that many banks.
+    /// Returns an error if banks is not a power of two from 2 to 512.
+    pub fn rom_banks(mut self, banks: u32) -> Result<Self, Error> {
+        if !banks.is_power_of_two() || !(2..=512).contains(&banks) {
+            bail!(
+                "Cannot use {} rom banks, it must be a power of two from 2 to 512",
+                banks
+            );
+        }
+        self.rom_banks = Some(banks);
+        Ok(self)
+    }
+
+    /// Returns the rom size factor written to the header, the rom is 32KB << factor.
+    fn rom_size_factor(&self) -> Result<u8, Error> {
+        if let Some(banks) = self.rom_banks {
+            if self.address > banks * ROM_BANK_SIZE {
+                bail!(
+                    "ROM is too big for the {} rom banks set by rom_banks, raw ROM size was {}",
+                    banks,
+                    self.address
+                );
+            }
+            return Ok(banks.trailing_zeros() as u8 - 1);
+        }
+        let mut factor = 0;
+        while self.address > (ROM_BANK_SIZE * 2) << factor {
+            factor += 1;
+            if factor > 8 {
+                bail!("ROM is too big, there is no MBC that supports a ROM size larger than 8MB, raw ROM size was {}", self.address);
+            }
+        }
+        Ok(factor)
+    }
+
+    /// Fills free space with 0xFF instead of 0x00 when the rom is compiled.
+    ///
+    /// 0xFF is the opcode of `rst 0x38`, so execution that runs into free space traps to the crash handler,
+    /// see [RomBuilder::add_crash_handler].
+    /// Compile returns an error if a jump or call targets free space.
+    pub fn fill_free_space(mut self) -> Result<Self, Error> {
+        self.fill_free_space = true;
+        Ok(self)
+    }
+
+    /// Includes a table of the free space filled by [RomBuilder::fill_free_space], which is enabled by this method.
+    ///
+    /// The table is labelled `GGBASMFillRegions`, QA tools and debug routines can use it to check whether the PC
+    /// in a crash record is in free space, meaning execution ran into it.
+    /// The first byte is the number of regions followed by 5 bytes for each region:
+    /// *   the bank, only the lower 8 bits for banks above 255
+    /// *   the start address as a little endian word
+    /// *   the end address, exclusive, as a little endian word
+    ///
+    /// Addresses are as seen by the cpu, so 0x4000-0x8000 for banks other than 0.
+    /// The table has room for capacity regions, compile returns an error if there are more regions than that.
+    /// Returns an error if crosses rom bank boundaries.
+    pub fn add_fill_region_table(mut self, capacity: u8) -> Result<Self, Error> {
+        if self.fill_region_table.is_some() {
+            bail!("The fill region table is already added");
+        }
+        let len = 1 + capacity as u32 * 5;
+        self.symbols.declare_label(
+            "GGBASMFillRegions",
+            GlobalAddr(self.address),
+            SymbolInfo::rust(SymbolKind::Label, Some(len)),
+        )?;
+        self.fill_free_space = true;
+        self.fill_region_table = Some((self.address, capacity));
+
+        self.data.push(DataHolder {
+            data: Data::Binary(vec![0; len as usize]),
+            address: self.address,
+            source: DataSource::Code,
+            spans: vec![],
+        });
+
+        let prev_bank = self.bank();
+        self.address += len;
+        if prev_bank == self.bank() {
+            Ok(self)
+        } else {
+            bail!("The added bytes cross bank boundaries.");
+        }
+    }
+
+    /// Sets the current address to the address in the bank, e.g. `advance_address(Bank(1), BankAddr(0x4000))`
+    /// is the start of bank 1.
+    /// Returns an error if attempts to go backwards or the address is not where the bank is mapped in.
+    /// To cross bank boundaries you need to use this function.
+    pub fn advance_address(self, bank: Bank, address: BankAddr) -> Result<Self, Error> {
+        let address = GlobalAddr::new(bank, address)?;
+        self.advance_to(address.0)
+    }
+
+    /// Sets the current address within the entire rom.
+    fn advance_to(mut self, address: u32) -> Result<Self, Error> {
+        if address >= self.address {
+            self.address = address;
+            Ok(self)
+        } else {
+            bail!("Attempted to advance to a previous address.")
+        }
+    }
+
+    /// Gets the current address within the entire rom.
+    pub fn get_address_global(&self) -> GlobalAddr {
+        GlobalAddr(self.address)
+    }
+
+    /// Gets the current address as seen by the cpu while the current bank is mapped in.
+    pub fn get_address_bank(&self) -> BankAddr {
+        self.get_address_global().bank_addr()
+    }
+
+    /// Gets the current bank.
+    pub fn get_bank(&self) -> Bank {
+        self.get_address_global().bank()
+    }
+
+    fn bank(&self) -> u32 {
+        self.address / ROM_BANK_SIZE
+    }
+
+    fn offset_in_bank(&self) -> u32 {
+        self.address % ROM_BANK_SIZE
+    }
+
+    // TODO: Doesnt include EQU constants. consume self, move EQU processing into another function
+    // then call it here as well.
+    pub fn print_variables_by_value(self) -> Result<Self, Error> {
+        let mut sorted: Vec<_> = self.symbols.iter().collect();
+        sorted.sort_by_key(|x| x.1);
+        for (ident, value, _) in sorted {
+            println!("0x{:x} - {}", value, ident);
+        }
+        Ok(self)
+    }
+
+    pub fn print_variables_by_identifier(self) -> Result<Self, Error> {
+        let mut sorted: Vec<_> = self.symbols.iter().collect();
+        sorted.sort_by_key(|x| x.0.to_lowercase());
+        for (ident, value, _) in sorted {
+            println!("{} - 0x{:x}", ident, value);
+        }
+        Ok(self)
+    }
+
+    /// Registers a table of data that external tools such as randomizers can safely modify.
+    ///
+    /// The identifier must refer to the start of the table in the rom, e.g. a label before some db lines.
+    /// The table contains count elements, each element_size bytes long.
+    /// Registered data regions are included in the [Layout] returned by [RomBuilder::compile_with_layout].
+    ///
+    /// The identifier is resolved during compilation, so it can be declared after this is called.
+    pub fn register_data_region(
+        mut self,
+        identifier: &str,
+        element_size: u32,
+        count: u32,
+    ) -> Result<Self, Error> {
+        if element_size == 0 {
+            bail!("Data region {} has an element size of 0", identifier);
+        }
+        if self.data_regions.iter().any(|x| x.identifier == identifier) {
+            bail!("Data region {} is already registered", identifier);
+        }
+
+        self.data_regions.push(DataRegionDeclaration {
+            identifier: identifier.to_string(),
+            element_size,
+            count,
+        });
+        Ok(self)
+    }
+
+    /// Generates a `.sym` file labelling the addresses of the declared RAM variables, with their types in comments.
+    ///
+    /// There is no watch expression or Lua script format that both SameBoy and Emulicious load,
+    /// but both load a `.sym` file named the same as the rom automatically, as does BGB.
+    /// Their debuggers and memory viewers then refer to each variable by name, e.g. `print PlayerX` in SameBoy.
+    /// Use [crate::output::sym_file] instead to also label the rom.
+    pub fn ram_sym_file(&self) -> String {
+        let mut variables: Vec<_> = self.ram_variables.iter().collect();
+        variables.sort_by_key(|x| x.address);
+
+        let mut text = String::from("; RAM variables generated by ggbasm\n");
+        for variable in variables {
+            text.push_str(&format!(
+                "00:{:04X} {} ; {}\n",
+                variable.address,
+                variable.identifier,
+                variable.variable_type.description()
+            ));
+        }
+        text
+    }
+
+    /// Writes the symbol file generated by [RomBuilder::ram_sym_file] to disk at the root of the project.
+    pub fn write_ram_sym_file(self, name: &str) -> Result<Self, Error> {
+        let output = self.root_dir.as_path().join(name);
+        fs::write(output, self.ram_sym_file())?;
+        Ok(self)
+    }
+
+    /// Projects the size of the rom and the usage of each bank from the data added so far, without compiling it.
+    ///
+    /// Assets are converted when they are added, so only the sizes of the instructions and bytes are measured,
+    /// nothing is encoded or written to disk. This is cheap enough to call repeatedly from planning tools.
+    /// The rom size is the size compile pads the rom to: the size in a header added as bytes or asm, otherwise the
+    /// banks set by [RomBuilder::rom_banks] or the fewest banks that fit the rom.
+    /// Bytes skipped by advance_address are counted as free.
+    ///
+    /// Returns an error if the rom does not fit in the banks set by rom_banks or is larger than any rom size.
+    pub fn estimate(&self) -> Result<SizeEstimate, Error> {
+        let mut used: Vec<u32> = vec![];
+        let mut add = |address: u32, size: u32| {
+            let mut address = address;
+            let end = address + size;
+            while address < end {
+                let bank = (address / ROM_BANK_SIZE) as usize;
+                let bank_end = ((bank as u32 + 1) * ROM_BANK_SIZE).min(end);
+                if used.len() <= bank {
+                    used.resize(bank + 1, 0);
+                }
+                used[bank] += bank_end - address;
+                address = bank_end;
+            }
+        };
+
+        for data in &self.data {
+            match &data.data {
+                Data::DummyInterruptsAndJumps(_) => add(data.address, 0x104),
+                Data::EntryPoint(_) => add(data.address, 4),
+                Data::Header(_) => add(data.address, 0x4C),
+                Data::Binary(bytes) => add(data.address, bytes.len() as u32),
+                Data::Instructions(instructions) => {
+                    let mut cur_address = data.address;
+                    for instruction in instructions {
+                        let len = instruction
+                            .bytes_len_with((cur_address % ROM_BANK_SIZE) as u16, &self.symbols)
+                            .expect("validated by add_instructions_inner")
+                            as u32;
+                        if !matches!(instruction, Instruction::AdvanceAddress(..)) {
+                            add(cur_address, len);
+                        }
+                        cur_address += len;
+                    }
+                }
+            }
+        }
+
+        let rom_size_factor = match self.header_byte(0x148)? {
+            Some(factor) if factor > 8 => bail!(
+                "The rom size factor 0x{:02X} in the header at 0x0148 is larger than any rom size",
+                factor
+            ),
+            Some(factor) => factor,
+            None => self.rom_size_factor()?,
+        };
+        let rom_size = (ROM_BANK_SIZE * 2) << rom_size_factor;
+        used.resize(
+            (rom_size / ROM_BANK_SIZE).max(self.address.div_ceil(ROM_BANK_SIZE)) as usize,
+            0,
+        );
+
+        Ok(SizeEstimate {
+            rom_size,
+            banks: used
+                .into_iter()
+                .enumerate()
+                .map(|(bank, used)| BankUsage {
+                    bank: bank as u32,
+                    used,
+                    free: ROM_BANK_SIZE - used,
+                })
+                .collect(),
+        })
+    }
+
+    /// Returns the byte at an address in a header added as bytes or asm, None when the header is added by add_header.
+    fn header_byte(&self, address: u32) -> Result<Option<u8>, Error> {
+        for data in &self.data {
+            match &data.data {
+                Data::Binary(bytes)
+                    if (data.address..data.address + bytes.len() as u32).contains(&address) =>
+                {
+                    return Ok(Some(bytes[(address - data.address) as usize]));
+                }
+                Data::Instructions(instructions) => {
+                    let mut cur_address = data.address;
+                    for instruction in instructions {
+                        let len = instruction
+                            .bytes_len_with((cur_address % ROM_BANK_SIZE) as u16, &self.symbols)?
+                            as u32;
+                        if (cur_address..cur_address + len).contains(&address) {
+                            let mut bytes = vec![];
+                            instruction.write_to_rom(&mut bytes, &self.symbols)?;
+                            return Ok(bytes.get((address - cur_address) as usize).copied());
+                        }
+                        cur_address += len;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Compiles assembly and binary data into binary rom data.
+    pub fn compile(self) -> Result<Vec<u8>, Error> {
+        self.compile_with_layout().map(|(rom, _)| rom)
+    }
+
+    /// Compiles assembly and binary data into binary rom data.
+    /// Also returns a [Layout] describing the structure of the rom for use by external tools.
+    pub fn compile_with_layout(self) -> Result<(Vec<u8>, Layout), Error> {
+        self.compile_inner(true)
+    }
+
+    /// Compiles assembly and binary data into binary data without a header.
+    ///
+    /// The header is not checked and the result is not padded to a valid rom size.
+    /// Useful for testing and for assembling snippets of code.
+    pub fn compile_raw(self) -> Result<Vec<u8>, Error> {
+        self.compile_inner(false).map(|(rom, _)| rom)
+    }
+
+    fn compile_inner(mut self, header: bool) -> Result<(Vec<u8>, Layout), Error> {
+        if self.data.last().is_none() {
+            bail!("No instructions or binary data was added to the RomBuilder");
+        }
+        // prologue parts can be added out of order
+        self.data.sort_by_key(|x| x.address);
+
+        let rom_size_factor = self.rom_size_factor()?;
+
+        let start = Instant::now();
+        let mut rom = vec![];
+
+        #[derive(Clone)]
+        struct EquHolder<'a> {
+            pub ident: &'a String,
+            pub expr: &'a Expr,
+            pub source: &'a DataSource,
+            pub line: u64,
+        }
+        let mut equs = vec![];
+
+        for data in &self.data {
+            match &data.data {
+                Data::DummyInterruptsAndJumps(_) => {}
+                Data::EntryPoint(_) => {}
+                Data::Header(_) => {}
+                Data::Binary { .. } => {}
+                Data::Instructions(instructions) => {
+                    for (i, instruction) in instructions.iter().enumerate() {
+                        if let Instruction::Equ(ident, expr) = instruction {
+                            equs.push(EquHolder {
+                                expr,
+                                ident,
+                                source: &data.source,
+                                line: Span::line_of(&data.spans, i) as u64,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let symbols = &mut self.symbols;
+        while !equs.is_empty() {
+            let prev_size = equs.len();
+            let mut outer_error = None;
+            let mut missing_idents = vec![];
+            equs.retain(|equ| {
+                match equ.expr.run(symbols) {
+                    Ok(value) => {
+                        let info = SymbolInfo::asm(
+                            SymbolKind::Equ,
+                            equ.source.description(),
+                            equ.line as usize,
+                        );
+                        if let Err(err) = symbols.declare(equ.ident, value, info) {
+                            outer_error = Some(err.to_string());
+                        }
+                        false
+                    }
+                    Err(ExprRunError::MissingIdentifier(ident)) => {
+                        // MissingIdentifier can mean:
+                        // *    There is a reference to an identifier that hasnt been processed yet. And it is succesfully processed later.
+                        // *    There is a reference to an identifier that hasnt been processed yet. But it turns out to be an infinite loop.
+                        // *    There is a reference to an identifier that is not declared anywhere.
+                        // We store the values so we can handle these cases after the `retain`, due to the mutable borrow.
+                        missing_idents.push((equ.clone(), ident));
+                        true
+                    }
+                    Err(ExprRunError::ResultDoesntFit(error))
+                    | Err(ExprRunError::ArithmeticError(error)) => {
+                        outer_error = Some(format!(
+                            "Error occured in {} on line {}: {}",
+                            equ.source.description(),
+                            equ.line,
+                            error
+                        ));
+                        true
+                    }
+                }
+            });
+            if let Some(error) = outer_error {
+                bail!(error);
+            }
+
+            // Check if the reason the ident was missing is because it is never declared.
+            for (missing_ident_equ, missing_ident) in missing_idents {
+                let mut found_ident = false;
+                for search_equ in &equs {
+                    if &missing_ident == search_equ.ident {
+                        found_ident = true;
+                        break;
+                    }
+                }
+                if !found_ident {
+                    bail!(format!(
+                        "Identifier {} is used in {} on line {} but is never declared.",
+                        missing_ident,
+                        missing_ident_equ.source.description(),
+                        missing_ident_equ.line
+                    ));
+                }
+            }
+
+            // Generic check for an infinite loop.
+            if prev_size == equs.len() {
+                let mut fail_string = String::from("Cannot resolve constants, there is an infinite loop involving the following identifiers:\n");
+                for equ in equs {
+                    fail_string.push_str(&format!("*   {}\n", equ.ident));
+                }
+                bail!(fail_string);
+            }
+        }
+
+        self.record(|x| &mut x.constants, start);
+        let start = Instant::now();
+
+        let mut reassigned: Vec<_> = self
+            .set_constants
+            .keys()
+            .filter(|ident| self.symbols.contains_key(*ident))
+            .collect();
+        reassigned.sort();
+        if let Some(ident) = reassigned.first() {
+            bail!(
+                "Identifier {} is assigned with SET but is also declared as a constant that cannot be reassigned.",
+                ident
+            );
+        }
+
+        let mut moved = String::new();
+        for (identifier, pinned) in &self.pinned_symbols {
+            match self.symbols.global_value(identifier) {
+                Some(value) if value == *pinned => {}
+                Some(value) => moved.push_str(&format!(
+                    "*   {} is pinned to 0x{:04X} but is at 0x{:04X}\n",
+                    identifier, pinned, value
+                )),
+                None => moved.push_str(&format!(
+                    "*   {} is pinned to 0x{:04X} but is never declared\n",
+                    identifier, pinned
+                )),
+            }
+        }
+        if !moved.is_empty() {
+            bail!("Pinned symbols moved:\n{}", moved);
+        }
+
+        let sources: Vec<_> = self
+            .data
+            .iter()
+            .filter_map(|data| match &data.data {
+                Data::Instructions(instructions) => Some((
+                    data.source.description(),
+                    instructions.as_slice(),
+                    data.spans.as_slice(),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        // Without a header the cartridge type is unknown, so writes to ROM are not validated.
+        let mapper = self
+            .data
+            .iter()
+            .find_map(|data| match &data.data {
+                Data::Header(header) => Some(header.cartridge_type.mapper()),
+                _ => None,
+            })
+            .unwrap_or(Mapper::Other);
+        // The crash handler enables SRAM through the MBC, the writes are harmlessly ignored by carts without one.
+        let validated: Vec<_> = self
+            .data
+            .iter()
+            .filter_map(|data| match (&data.data, &data.source) {
+                (_, DataSource::CrashHandler) => None,
+                (Data::Instructions(instructions), source) => Some((
+                    source.description(),
+                    instructions.as_slice(),
+                    data.spans.as_slice(),
+                )),
+                _ => None,
+            })
+            .collect();
+        let mut warnings = vec![];
+        for error in lint::validate_addresses(&validated, &self.symbols, mapper) {
+            if !error.warning {
+                bail!(
+                    "Error occured in {} on line {}: {}",
+                    error.source,
+                    error.line,
+                    error.message
+                );
+            }
+            warnings.push(format!(
+                "Warning in {} on line {}: {}",
+                error.source, error.line, error.message
+            ));
+        }
+
+        self.check_jump_banks()?;
+
+        for lint in lint::lint(self.lint_profile, &sources, &self.symbols) {
+            warnings.push(format!(
+                "Warning in {} on line {}: {}",
+                lint.source, lint.line, lint.message
+            ));
+        }
+
+        let mut layout = Layout {
+            rom_size: 0,
+            sections: vec![],
+            symbols: vec![],
+            regions: vec![],
+            data_regions: vec![],
+            symbol_drift: vec![],
+            deduplicated: self.deduplicated.clone(),
+            warnings,
+            messages: vec![],
+            profile: None,
+        };
+
+        // generate rom
+        let mut charmap = HashMap::new();
+        for data in &self.data {
+            // pad to address
+            layout.push_region(
+                rom.len() as u32,
+                data.address.saturating_sub(rom.len() as u32),
+                RegionKind::Padding,
+            );
+            rom.resize(data.address as usize, 0x00);
+
+            match &data.data {
+                Data::DummyInterruptsAndJumps(entry) => {
+                    // jumps
+                    let crash_handler = self.symbols.get("GGBASMCrashHandler");
+                    for i in 0..8 {
+                        match crash_handler {
+                            Some(address) if i == 7 => {
+                                rom.push(0xc3);
+                                rom.push(*address as u8);
+                                rom.push((*address >> 8) as u8);
+                            }
+                            _ => {
+                                rom.push(0xc3);
+                                rom.push(0x00);
+                                rom.push(0x01);
+                            }
+                        }
+                        rom.push(0x00);
+
+                        rom.push(0x00);
+                        rom.push(0x00);
+                        rom.push(0x00);
+                        rom.push(0x00);
+                    }
+
+                    // interrupts
+                    for _ in 0..5 {
+                        rom.push(0xd9);
+                        rom.push(0x00);
+                        rom.push(0x00);
+                        rom.push(0x00);
+
+                        rom.push(0x00);
+                        rom.push(0x00);
+                        rom.push(0x00);
+                        rom.push(0x00);
+                    }
+
+                    // padding
+                    rom.resize(rom.len() + 0x98, 0x00);
+
+                    rom.extend(entry_point(entry, &self.symbols)?);
+                    layout.push_region(
+                        data.address,
+                        rom.len() as u32 - data.address,
+                        RegionKind::InterruptsAndJumps,
+                    );
+                }
+                Data::EntryPoint(entry) => {
+                    rom.extend(entry_point(entry, &self.symbols)?);
+                    layout.push_region(
+                        data.address,
+                        rom.len() as u32 - data.address,
+                        RegionKind::InterruptsAndJumps,
+                    );
+                }
+                Data::Header(header) => {
+                    header.write(&mut rom, rom_size_factor);
+                    layout.push_region(
+                        data.address,
+                        rom.len() as u32 - data.address,
+                        RegionKind::Header,
+                    );
+                }
+                Data::Binary(bytes) => {
+                    rom.extend(bytes);
+                    layout.push_region(data.address, bytes.len() as u32, RegionKind::Data);
+                }
+                Data::Instructions(instructions) => {
+                    for (i, instruction) in instructions.iter().enumerate() {
+                        let start = rom.len() as u32;
+                        let result = match instruction {
+                            Instruction::Charmap(c, expr) => expr
+                                .get_byte(&self.symbols)
+                                .map_err(Error::from)
+                                .map(|byte| {
+                                    charmap.insert(*c, byte);
+                                }),
+                            Instruction::Print(items) => ast::format_message(items, &self.symbols)
+                                .map(|message| layout.messages.push(message)),
+                            Instruction::Warn(items) => ast::format_message(items, &self.symbols)
+                                .map(|message| {
+                                    let warning = format!(
+                                        "Warning in {} on line {}: {}",
+                                        data.source.description(),
+                                        Span::line_of(&data.spans, i),
+                                        message
+                                    );
+                                    layout.warnings.push(warning);
+                                }),
+                            _ => instruction.write_to_rom_with_charmap(
+                                &mut rom,
+                                &self.symbols,
+                                &charmap,
+                            ),
+                        };
+                        layout.push_region(
+                            start,
+                            rom.len() as u32 - start,
+                            instruction_region_kind(instruction),
+                        );
+                        if let Err(err) = result {
+                            bail!(
+                                "Error occured in {} on line {}: {}{}",
+                                data.source.description(),
+                                Span::line_of(&data.spans, i),
+                                err,
+                                private_label_hint(&err, &self.symbols)
+                            );
+                        }
+                    }
+                }
+            }
+
+            layout.sections.push(Section {
+                address: data.address,
+                size: rom.len() as u32 - data.address,
+                source: data.source.description(),
+            });
+        }
+
+        if header {
+            check_header_and_pad(&mut rom, &mut layout)?;
+        }
+        if self.fill_free_space {
+            self.fill_padding(&mut rom, &layout)?;
+        }
+
+        layout.rom_size = rom.len() as u32;
+        for declaration in &self.data_regions {
+            let address = match self.symbols.global_value(&declaration.identifier) {
+                Some(address) => address,
+                None => bail!(
+                    "Data region {} is registered but the identifier is never declared.",
+                    declaration.identifier
+                ),
+            };
+            let size = declaration.element_size as i64 * declaration.count as i64;
+            if address < 0 || address + size > layout.rom_size as i64 {
+                bail!(
+                    "Data region {} at 0x{:x} with size 0x{:x} does not fit within the rom",
+                    declaration.identifier,
+                    address,
+                    size
+                );
+            }
+            layout.data_regions.push(DataRegion {
+                name: declaration.identifier.clone(),
+                address: address as u32,
+                element_size: declaration.element_size,
+                count: declaration.count,
+            });
+        }
+        layout.data_regions.sort_by_key(|x| x.address);
+
+        if self.warn_unused {
+            let warnings = self.unused_warnings(&layout);
+            layout.warnings.extend(warnings);
+        }
+
+        let mut symbols: Vec<_> = self
+            .symbols
+            .iter()
+            .map(|(name, value, info)| {
+                let (display_name, private_to) = match name.split_once('@') {
+                    Some((name, file_name)) => (name, Some(file_name.to_string())),
+                    None => (name, None),
+                };
+                Symbol {
+                    name: display_name.to_string(),
+                    value: self.symbols.global_value(name).unwrap_or(value),
+                    label: info.kind == SymbolKind::Label,
+                    kind: Some(info.kind),
+                    size: info.size,
+                    private_to,
+                }
+            })
+            .collect();
+        symbols.sort_by(|a, b| {
+            a.value
+                .cmp(&b.value)
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.private_to.cmp(&b.private_to))
+        });
+        layout.symbols = symbols;
+
+        if let Some(file_name) = &self.symbol_snapshot {
+            // anonymous labels are numbered in order, so they are not useful to external tools
+            let snapshot: Vec<_> = layout
+                .symbols
+                .iter()
+                .filter(|x| !x.name.starts_with("GGBASMAnonymous"))
+                .cloned()
+                .collect();
+            let path = self.root_dir.join(file_name);
+            if let Ok(previous) = fs::read_to_string(&path) {
+                let previous: Vec<Symbol> = match serde_json::from_str(&previous) {
+                    Ok(previous) => previous,
+                    Err(err) => bail!("Cannot read symbol snapshot {} because: {}", file_name, err),
+                };
+                layout.symbol_drift = symbol_drift(&previous, &snapshot);
+            }
+            fs::write(&path, serde_json::to_string_pretty(&snapshot)?)?;
+        }
+
+        self.record(|x| &mut x.encoding, start);
+        if let Some(mut profile) = self.profile.take() {
+            profile.peak_memory = profile::peak_memory();
+            layout.profile = Some(profile);
+        }
+
+        Ok((rom, layout))
+    }
+
+    /// Returns a warning for each label and EQU constant that nothing refers to, sorted by name.
+    fn unused_warnings(&self, layout: &Layout) -> Vec<String> {
+        let mut references = References::default();
+        references
+            .0
+            .extend(self.condition_references.iter().cloned());
+        references
+            .0
+            .extend(self.data_regions.iter().map(|x| x.identifier.clone()));
+        references
+            .0
+            .extend(self.pinned_symbols.iter().map(|(x, _)| x.clone()));
+        // the entry point jumps to 0x150 unless it is given a label
+        let mut entry_addresses = vec![];
+        for data in &self.data {
+            match &data.data {
+                Data::Instructions(instructions) => {
+                    for instruction in instructions {
+                        references.visit_instruction(instruction);
+                    }
+                }
+                Data::DummyInterruptsAndJumps(Some(entry)) | Data::EntryPoint(Some(entry)) => {
+                    references.visit_ident(entry);
+                }
+                Data::DummyInterruptsAndJumps(None) | Data::EntryPoint(None) => {
+                    entry_addresses.push(0x150)
+                }
+                _ => {}
+            }
+        }
+
+        let mut unused: Vec<_> = self
+            .symbols
+            .iter()
+            .map(|(name, value, info)| {
+                let value = self.symbols.global_value(name).unwrap_or(value);
+                (name, value, info)
+            })
+            .filter(|(name, value, info)| {
+                !name.starts_with("GGBASM")
+                    && !references.0.contains(*name)
+                    && match info.kind {
+                        SymbolKind::Label => *value >= 0x150 && !entry_addresses.contains(value),
+                        SymbolKind::Equ => true,
+                        SymbolKind::Define | SymbolKind::RamVariable => false,
+                    }
+            })
+            .collect();
+        unused.sort_by_key(|(name, _, _)| *name);
+
+        unused
+            .into_iter()
+            .map(|(name, value, info)| {
+                let region = layout.regions[..layout
+                    .regions
+                    .partition_point(|x| x.address as i64 <= value)]
+                    .last()
+                    .filter(|x| (x.address + x.size) as i64 > value);
+                let message = match info.kind {
+                    SymbolKind::Equ => format!("Constant {} is never referred to", name),
+                    _ if info.size.is_some()
+                        || region.is_some_and(|x| x.kind == RegionKind::Data) =>
+                    {
+                        format!(
+                            "Data {} is never referred to by a jump, call or pointer",
+                            name
+                        )
+                    }
+                    _ => format!("Label {} is never referred to", name),
+                };
+                format!("Warning in {}: {}", info.location(), message)
+            })
+            .collect()
+    }
+
+    /// Returns an error if a jump or call targets a label in a switchable rom bank other than the bank of the
+    /// jump, as the label is never mapped in while the jump executes.
+    /// Jumps from bank 0 can target any bank, the caller is expected to switch to it first.
+    fn check_jump_banks(&self) -> Result<(), Error> {
+        for data in &self.data {
+            let bank = Bank((data.address / ROM_BANK_SIZE) as u16);
+            let instructions = match &data.data {
+                Data::Instructions(instructions) if bank != Bank(0) => instructions,
+                _ => continue,
+            };
+            for (i, instruction) in instructions.iter().enumerate() {
+                let (target, jr) = match instruction {
+                    Instruction::Call(_, Expr::Ident(target))
+                    | Instruction::JpI16(_, Expr::Ident(target)) => (target, false),
+                    Instruction::Jr(_, Expr::Ident(target)) => (target, true),
+                    _ => continue,
+                };
+                match self.symbols.bank(target) {
+                    Some(target_bank) if target_bank != Bank(0) && target_bank != bank => {
+                        let error = if jr {
+                            format!(
+                                "jr cannot reach 0x{:x} in {} from {}, jr can only jump within its own bank or to bank 0, use jp instead",
+                                self.symbols[target],
+                                target_bank,
+                                bank
+                            )
+                        } else {
+                            format!(
+                                "Jumps to {} in rom bank {} from rom bank {}, only bank 0 and the current bank are mapped in",
+                                target,
+                                target_bank.0,
+                                bank.0
+                            )
+                        };
+                        bail!(
+                            "Error occured in {} on line {}: {}",
+                            data.source.description(),
+                            Span::line_of(&data.spans, i),
+                            error
+                        )
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills the padding of the compiled rom with `rst 0x38` and writes the fill region table.
+    /// Returns an error if a jump or call targets the padding.
+    fn fill_padding(&self, rom: &mut [u8], layout: &Layout) -> Result<(), Error> {
+        let padding: Vec<_> = layout
+            .regions
+            .iter()
+            .filter(|x| x.kind == RegionKind::Padding)
+            .collect();
+        for region in &padding {
+            let start = region.address as usize;
+            rom[start..start + region.size as usize].fill(0xFF);
+        }
+
+        for data in &self.data {
+            if let Data::Instructions(instructions) = &data.data {
+                for (i, instruction) in instructions.iter().enumerate() {
+                    let target = match instruction {
+                        Instruction::Call(_, expr)
+                        | Instruction::JpI16(_, expr)
+                        | Instruction::Jr(_, expr) => {
+                            expr.run(&self.symbols).ok().map(|x| (expr, x))
+                        }
+                        _ => None,
+                    };
+                    if let Some((expr, target)) = target {
+                        // labels know their bank, other addresses refer to the bank of the jump
+                        let global = match expr {
+                            Expr::Ident(ident) => self.symbols.global_addr(ident).map(|x| x.0),
+                            _ => None,
+                        }
+                        .or_else(|| control_flow::destination(data.address, &Expr::Const(target)));
+                        if global.is_some_and(|global| {
+                            padding
+                                .iter()
+                                .any(|x| (x.address..x.address + x.size).contains(&global))
+                        }) {
+                            bail!(
+                                "Error occured in {} on line {}: Jumps to 0x{:x} which is free space filled with `rst 0x38`",
+                                data.source.description(),
+                                Span::line_of(&data.spans, i),
+                                target
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some((address, capacity)) = self.fill_region_table {
+            // regions are split at bank boundaries as the table stores cpu addresses
+            let mut entries = vec![];
+            for region in &padding {
+                let mut start = region.address;
+                let end = region.address + region.size;
+                while start < end {
+                    let bank = start / ROM_BANK_SIZE;
+                    let bank_end = end.min((bank + 1) * ROM_BANK_SIZE);
+                    let cpu_address = |x: u32| {
+                        if bank == 0 {
+                            x as u16
+                        } else {
+                            (ROM_BANK_SIZE + x - bank * ROM_BANK_SIZE) as u16
+                        }
+                    };
+                    entries.push((bank, cpu_address(start), cpu_address(bank_end)));
+                    start = bank_end;
+                }
+            }
+            if entries.len() > capacity as usize {
+                bail!(
+                    "There are {} fill regions but the fill region table only has room for {}",
+                    entries.len(),
+                    capacity
+                );
+            }
+
+            let mut table = vec![entries.len() as u8];
+            for (bank, start, end) in entries {
+                table.push(bank as u8);
+                table.extend(start.to_le_bytes());
+                table.extend(end.to_le_bytes());
+            }
+            let address = address as usize;
+            rom[address..address + table.len()].copy_from_slice(&table);
+        }
+        Ok(())
+    }
+
+    /// Adds a writer that is run by [RomBuilder::write_outputs], e.g. [crate::output::SymFile] or a custom writer.
+    pub fn add_output(mut self, writer: impl OutputWriter + 'static) -> Result<Self, Error> {
+        self.outputs.push(Box::new(writer));
+        Ok(self)
+    }
+
+    /// Compile the ROM then run every writer added by [RomBuilder::add_output] in the order they were added.
+    /// The writers write to the root of the project.
+    /// Returns an error if no writers were added.
+    pub fn write_outputs(mut self) -> Result<(), Error> {
+        if self.outputs.is_empty() {
+            bail!("No outputs were added to the RomBuilder, use add_output");
+        }
+        let outputs = std::mem::take(&mut self.outputs);
+        let root_dir = self.root_dir.clone();
+        let (rom, layout) = self.compile_with_layout()?;
+        for output in outputs {
+            output.write(&root_dir, &rom, &layout)?;
+        }
+        Ok(())
+    }
+
+    /// Compile the ROM then write it to disk at the root of the project.
+    /// The root of the project is the outermost directory containing a Cargo.toml file.
+    pub fn write_to_disk(self, name: &str) -> Result<(), Error> {
+        let output = self.root_dir.as_path().join(name);
+        let rom = self.compile()?;
+        fs::write(output, rom)?;
+        Ok(())
+    }
+
+    /// Compile the ROM then write it and its layout as JSON to disk at the root of the project.
+    /// See [RomBuilder::compile_with_layout] for details on the layout.
+    pub fn write_to_disk_with_layout(self, name: &str, layout_name: &str) -> Result<(), Error> {
+        let output = self.root_dir.as_path().join(name);
+        let layout_output = self.root_dir.as_path().join(layout_name);
+        let (rom, layout) = self.compile_with_layout()?;
+        fs::write(output, rom)?;
+        fs::write(layout_output, layout.to_json()?)?;
+        Ok(())
+    }
+
+    /// Provide some sort of mechanism to generate an html file with embedded gb emulator and rom data.
+    /// Use Cargo.toml metadata to generate a link to repository, include developers name etc. (use panic-handler as a reference here)
+    /// This is completely unimplemented, its just a reminder to do this some day.
+    pub fn write_to_disk_html(self, _name: &str) -> Result<(), Error> {
+        unimplemented!();
+    }
+
+    /// Iteratively search for the innermost Cargo.toml starting at the current.
+    /// working directory and working up through its parents.
+    /// Returns the path to the directory the Cargo.toml is in.
+    /// Or an error if the file couldn't be found.
+    ///
+    /// TODO: This function returns the wrong path if called like `cargo run -p crate_name` in a workspace.
+    /// The only way to fix this would be get rustc provide an equivalent of env!("CARGO_MANIFEST_DIR") provided at runtime.
+    fn find_root_dir() -> Result<PathBuf, Error> {
+        let current_dir = env::current_dir()?;
+        let mut current = current_dir.as_path();
+
+        loop {
+            let toml = current.join("Cargo.toml");
+            if fs::metadata(&toml).is_ok() {
+                return Ok(toml.parent().unwrap().to_path_buf());
+            }
+
+            match current.parent() {
+                Some(p) => current = p,
+                None => bail!("Cant find a Cargo.toml in any of the parent directories"),
+            }
+        }
+    }
+}
+
+/// Verifies the header is compatible with the rom, fills in the header checksum,
+/// then pads the rom to the size specified in the header.
+fn check_header_and_pad(rom: &mut Vec<u8>, layout: &mut Layout) -> Result<(), Error> {
+    if rom.len() < 0x150 {
+        bail!(
+            "ROM is too small, header is not finished. ROM was only {} bytes",
+            rom.len()
+        );
+    }
+
+    // verify the entry point and header were added, by rust code or asm
+    if let Some(region) = layout
+        .regions
+        .iter()
+        .find(|x| x.kind == RegionKind::Padding && x.address < 0x150 && x.address + x.size > 0x100)
+    {
+        bail!(
+            "Nothing was added at 0x{:04x}, the entry point and header from 0x0100 to 0x014F must be added with add_prologue, add_header or asm",
+            region.address.max(0x100)
+        );
+    }
+    if rom[0x104..0x134] != header::LOGO {
+        bail!("The Nintendo logo at 0x0104-0x0133 is incorrect, the gameboy will not boot the rom");
+    }
+    // the checksum depends on the rest of the header so it is filled in here, the same as Header::write
+    rom[0x14D] = header::checksum(rom);
+
+    // verify cartridge_type and rom_size_factor are compatible
+    let cartridge_type = CartridgeType::variant(rom[0x0147]);
+    let final_size_factor = rom[0x0148];
+    if final_size_factor >= 0x20 {
+        bail!(
+            "ROM size factor (0x0148) is too big, needs to be less than 32 was {}",
+            final_size_factor
+        );
+    }
+    let final_size = (ROM_BANK_SIZE * 2) << final_size_factor;
+    match cartridge_type {
+        CartridgeType::RomOnly | CartridgeType::RomRam | CartridgeType::RomRamBattery => {
+            if final_size_factor != 0 {
+                bail!(
+                    "ROM is too big, there is no MBC so ROM size must be <= 32KB, was actually {}",
+                    final_size
+                );
+            }
+        }
+        CartridgeType::Mbc1 | CartridgeType::Mbc1Ram | CartridgeType::Mbc1RamBattery => {
+            if final_size_factor > 6 {
+                bail!(
+                    "ROM is too big, using MBC1 so ROM size must be <= 2MB, was actually {}",
+                    final_size
+                );
+            }
+        }
+        CartridgeType::Mbc2 | CartridgeType::Mbc2Battery => {
+            if final_size_factor > 3 {
+                bail!(
+                    "ROM is too big, using MBC2 so ROM size must be <= 256KB, was actually {}",
+                    final_size
+                );
+            }
+        }
+        CartridgeType::Mmm01 | CartridgeType::Mmm01Ram | CartridgeType::Mmm01RamBattery => {
+            // TODO
+        }
+        CartridgeType::Mbc3TimerBattery
+        | CartridgeType::Mbc3TimerRamBattery
+        | CartridgeType::Mbc3
+        | CartridgeType::Mbc3Ram
+        | CartridgeType::Mbc3RamBattery => {
+            if final_size_factor > 6 {
+                bail!(
+                    "ROM is too big, using MBC3 so ROM size must be <= 2MB, was actually {}",
+                    final_size
+                );
+            }
+        }
+        CartridgeType::Mbc5
+        | CartridgeType::Mbc5Ram
+        | CartridgeType::Mbc5RamBattery
+        | CartridgeType::Mbc5Rumble
+        | CartridgeType::Mbc5RumbleRam
+        | CartridgeType::Mbc5RumbleRamBattery => {
+            if final_size_factor > 8 {
+                bail!(
+                    "ROM is too big, using MBC5 so ROM size must be <= 8MB, was actually {}",
+                    final_size
+                );
+            }
+        }
+        CartridgeType::PocketCamera => {
+            if final_size_factor > 8 {
+                bail!("ROM is too big, using PocketCamera so ROM size must be <= 1MB, was actually {}", final_size);
+            }
+        }
+        CartridgeType::HuC3 => {
+            // TODO
+        }
+        CartridgeType::HuC1RamBattery => {
+            if final_size_factor > 6 {
+                bail!(
+                    "ROM is too big, using HuC1 so ROM size must be <= 2MB, was actually {}",
+                    final_size
+                );
+            }
+        }
+        CartridgeType::Unknown(_) => {
+            // Hopefully you know what your doing ...
+        }
+    }
+
+    // pad remainder of rom with 0's to fill size
+    layout.push_region(
+        rom.len() as u32,
+        final_size.saturating_sub(rom.len() as u32),
+        RegionKind::Padding,
+    );
+    rom.resize(final_size as usize, 0x00);
+    Ok(())
+}
+
+/// Returns the bytes of an entry point that jumps to the identifier, or 0x0150 when None.
+fn entry_point(entry: &Option<String>, constants: &HashMap<String, i64>) -> Result<[u8; 4], Error> {
+    let target = match entry {
+        Some(entry) => match constants.get(entry) {
+            Some(target) => *target,
+            None => bail!(
+                "The entry point jumps to {} but it is never declared.",
+                entry
+            ),
+        },
+        None => 0x150,
+    };
+    // nop; jp target
+    Ok([0x00, 0xc3, target as u8, (target >> 8) as u8])
+}
+
+/// Returns the number of bytes the data takes up in the rom when placed at the address.
+fn data_len(data: &Data, address: u32, constants: &HashMap<String, i64>) -> u32 {
+    match data {
+        Data::DummyInterruptsAndJumps(_) => 0x104,
+        Data::EntryPoint(_) => 4,
+        Data::Header(_) => 0x4C,
+        Data::Binary(bytes) => bytes.len() as u32,
+        Data::Instructions(instructions) => {
+            let mut cur_address = address;
+            for instruction in instructions {
+                cur_address += instruction
+                    .bytes_len_with((cur_address % ROM_BANK_SIZE) as u16, constants)
+                    .expect("validated by add_instructions_inner")
+                    as u32;
+            }
+            cur_address - address
+        }
+    }
+}
+
+/// Returns the number of bytes the chunks of an included file add to the section that includes it,
+/// and whether the file starts a section of its own, which the rest of the including section then belongs to.
+fn included_section_size(chunks: &[AsmChunk]) -> (u32, bool) {
+    let mut size = 0;
+    let mut pushed = 0;
+    for chunk in chunks {
+        match chunk {
+            AsmChunk::Instructions(instructions, _, _) if pushed == 0 => {
+                size += instructions
+                    .iter()
+                    .map(|x| x.bytes_len(0).unwrap_or(0) as u32)
+                    .sum::<u32>();
+            }
+            AsmChunk::Instructions(..) => {}
+            AsmChunk::Section { .. } if pushed == 0 => return (size, true),
+            AsmChunk::Section { .. } => {}
+            AsmChunk::PushSection => pushed += 1,
+            AsmChunk::PopSection { .. } => pushed -= 1,
+        }
+    }
+    (size, false)
+}
+
+/// Classifies the bytes written by an instruction for the layout.
+fn instruction_region_kind(instruction: &Instruction) -> RegionKind {
+    match instruction {
+        Instruction::AdvanceAddress(..) => RegionKind::Padding,
+        Instruction::Db(_)
+        | Instruction::DbExpr8(_)
+        | Instruction::DbExpr16(_)
+        | Instruction::DbExprList8(_)
+        | Instruction::DbFill(..)
+        | Instruction::DbExprList16(_)
+        | Instruction::DbString(_) => RegionKind::Data,
+        _ => RegionKind::Code,
+    }
+}
+
+/// Renames the labels of an asm file and the files it includes so they cannot be referred to by other
+/// asm files or rust code, unless they are exported.
+/// A private label is renamed to `{label}@{file_name}`, [Layout::symbols] splits it back into the label and
+/// [Symbol::private_to].
+fn make_labels_private(chunks: &mut [AsmChunk], file_name: &str) -> Result<(), Error> {
+    let mut labels = HashSet::new();
+    let mut equs = HashSet::new();
+    let mut exports = vec![];
+    for chunk in chunks.iter_mut() {
+        if let AsmChunk::Instructions(instructions, spans, source) = chunk {
+            for (i, instruction) in instructions.iter_mut().enumerate() {
+                match instruction {
+                    Instruction::Label(label) => {
+                        labels.insert(label.clone());
+                    }
+                    Instruction::Equ(ident, _) => {
+                        equs.insert(ident.clone());
+                    }
+                    Instruction::Export(idents) => {
+                        for ident in idents.drain(..) {
+                            exports.push((ident, source.description(), spans[i].line));
+                        }
+                        *instruction = Instruction::EmptyLine;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    for (ident, source, line) in &exports {
+        if !labels.contains(ident) && !equs.contains(ident) {
+            bail!(
+                "Error occured in {} on line {}: {} is exported but is not declared in {} or the files it includes",
+                source,
+                line,
+                ident,
+                file_name
+            );
+        }
+        labels.remove(ident);
+    }
+
+    let rename = |ident: &str| format!("{}@{}", ident, file_name);
+    for chunk in chunks.iter_mut() {
+        if let AsmChunk::Instructions(instructions, _, _) = chunk {
+            for instruction in instructions.iter_mut() {
+                if let Instruction::Label(label) = instruction {
+                    if labels.contains(label) {
+                        *label = rename(label);
+                    }
+                }
+                for expr in instruction.exprs_mut() {
+                    rename_idents(expr, &|ident| labels.contains(ident).then(|| rename(ident)));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the identifier within the namespace, the namespace is empty outside of any namespace.
+fn qualify(namespace: &str, identifier: &str) -> String {
+    if namespace.is_empty() {
+        identifier.to_string()
+    } else {
+        format!("{}.{}", namespace, identifier)
+    }
+}
+
+/// Declares the labels and EQU constants of the instructions within their namespace, starting within the outer
+/// namespaces, and resolves the identifiers referred to within a namespace.
+///
+/// An identifier refers to the innermost namespace containing it, checking the identifiers declared by the
+/// instructions and the already declared symbols, e.g. `Init` within `namespace Audio` becomes `Audio.Init` when
+/// that is declared.
+/// Namespace directives are replaced with empty lines, so the instructions still line up with their spans.
+fn resolve_namespaces(
+    instructions: &mut [Instruction],
+    outer: &[String],
+    symbols: &SymbolTable,
+    source: &DataSource,
+    spans: &[Span],
+) -> Result<(), Error> {
+    if outer.is_empty()
+        && !instructions
+            .iter()
+            .any(|x| matches!(x, Instruction::Namespace(_) | Instruction::EndNamespace))
+    {
+        return Ok(());
+    }
+
+    // the namespace of each instruction and the line of each opened namespace
+    let mut namespaces = Vec::with_capacity(instructions.len());
+    let mut opened: Vec<(String, usize)> = vec![];
+    let mut declared = HashSet::new();
+    for (i, instruction) in instructions.iter_mut().enumerate() {
+        let namespace = outer
+            .iter()
+            .chain(opened.iter().map(|(name, _)| name))
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(".");
+        match instruction {
+            Instruction::Namespace(name) => {
+                opened.push((name.clone(), Span::line_of(spans, i)));
+                *instruction = Instruction::EmptyLine;
+            }
+            Instruction::EndNamespace => {
+                if opened.pop().is_none() {
+                    bail!(
+                        "Error occured in {} on line {}: endnamespace has no matching namespace",
+                        source.description(),
+                        Span::line_of(spans, i)
+                    );
+                }
+                *instruction = Instruction::EmptyLine;
+            }
+            Instruction::Label(ident) | Instruction::Equ(ident, _) => {
+                *ident = qualify(&namespace, ident);
+                declared.insert(ident.clone());
+            }
+            _ => {}
         }
+        namespaces.push(namespace);
+    }
+    if let Some((name, line)) = opened.pop() {
+        bail!(
+            "Error occured in {} on line {}: namespace {} has no matching endnamespace",
+            source.description(),
+            line,
+            name
+        );
     }
 
-    /// Sets the current address and bank as specified.
-    /// Returns an error if attempts to go backwards.
-    /// To cross bank boundaries you need to use this function.
-    pub fn advance_address(mut self, rom_bank: u32, address: u32) -> Result<Self, Error> {
-        let new_address = address + rom_bank * ROM_BANK_SIZE;
-        if new_address >= self.address {
-            self.address = new_address;
-            Ok(self)
-        } else {
-            bail!("Attempted to advance to a previous address.")
+    for (instruction, namespace) in instructions.iter_mut().zip(namespaces) {
+        if namespace.is_empty() {
+            continue;
+        }
+        let resolve = |ident: &str| {
+            let mut namespace = namespace.as_str();
+            loop {
+                let qualified = qualify(namespace, ident);
+                if declared.contains(&qualified) || symbols.contains_key(&qualified) {
+                    return Some(qualified);
+                }
+                namespace = namespace.rsplit_once('.')?.0;
+            }
+        };
+        if let Instruction::Export(idents) = instruction {
+            for ident in idents {
+                if let Some(qualified) = resolve(ident) {
+                    *ident = qualified;
+                }
+            }
+        }
+        for expr in instruction.exprs_mut() {
+            rename_idents(expr, &resolve);
         }
     }
+    Ok(())
+}
 
-    /// Gets the current address within the entire rom.
-    pub fn get_address_global(&self) -> u32 {
-        self.address
+/// Explains a missing identifier that is a label private to other asm files.
+fn private_label_hint(err: &Error, constants: &HashMap<String, i64>) -> String {
+    if let Some(ExprRunError::MissingIdentifier(ident)) = err.downcast_ref() {
+        let prefix = format!("{}@", ident);
+        let mut files: Vec<_> = constants
+            .keys()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .collect();
+        if !files.is_empty() {
+            files.sort();
+            return format!(
+                " It is a label private to {}, export it with `export {}` to use it in other files.",
+                files.join(", "),
+                ident
+            );
+        }
     }
+    String::new()
+}
 
-    /// Gets the current address within the current bank.
-    pub fn get_address_bank(&self) -> u16 {
-        (self.address % ROM_BANK_SIZE) as u16
+fn rename_idents(expr: &mut Expr, rename: &dyn Fn(&str) -> Option<String>) {
+    match expr {
+        Expr::Ident(ident) => {
+            if let Some(renamed) = rename(ident) {
+                *ident = renamed;
+            }
+        }
+        Expr::Binary(binary) => {
+            rename_idents(&mut binary.left, rename);
+            rename_idents(&mut binary.right, rename);
+        }
+        Expr::Unary(unary) => rename_idents(&mut unary.expr, rename),
+        Expr::AnonymousLabelRef(_) | Expr::Const(_) => {}
     }
+}
 
-    /// Gets the current bank.
-    pub fn get_bank(&self) -> u32 {
-        self.address / ROM_BANK_SIZE
+/// Replaces anonymous labels with labels named `GGBASMAnonymous{N}` and references to them with the matching identifier.
+///
+/// A reference refers to the nearest anonymous labels forward or backward within the same instructions.
+/// `first` is the N used for the first anonymous label, the next unused N is returned.
+fn resolve_anonymous_labels(
+    instructions: &mut [Instruction],
+    first: usize,
+    source: &DataSource,
+    spans: &[Span],
+) -> Result<usize, Error> {
+    let positions: Vec<usize> = instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| matches!(x, Instruction::AnonymousLabel))
+        .map(|(i, _)| i)
+        .collect();
+    let name = |index: usize| format!("GGBASMAnonymous{}", first + index);
+
+    for (i, instruction) in instructions.iter_mut().enumerate() {
+        for expr in instruction.exprs_mut() {
+            resolve_anonymous_label_refs(expr, &mut |offset| {
+                // index into positions of the first anonymous label after this instruction
+                let next = positions.partition_point(|x| *x <= i) as i64;
+                let index = if offset > 0 {
+                    next + offset as i64 - 1
+                } else {
+                    next + offset as i64
+                };
+                if index < 0 || index >= positions.len() as i64 {
+                    bail!(
+                        "Anonymous label reference in {} on line {} has no matching anonymous label",
+                        source.description(),
+                        Span::line_of(spans, i)
+                    );
+                }
+                Ok(name(index as usize))
+            })?;
+        }
     }
 
-    // TODO: Doesnt include EQU constants. consume self, move EQU processing into another function
-    // then call it here as well.
-    pub fn print_variables_by_value(self) -> Result<Self, Error> {
-        let mut sorted: Vec<_> = self.constants.iter().collect();
-        sorted.sort_by_key(|x| x.1);
-        for (ident, value) in sorted {
-            println!("0x{:x} - {}", value, ident);
-        }
-        Ok(self)
+    for (index, position) in positions.iter().enumerate() {
+        instructions[*position] = Instruction::Label(name(index));
     }
+    Ok(first + positions.len())
+}
 
-    pub fn print_variables_by_identifier(self) -> Result<Self, Error> {
-        let mut sorted: Vec<_> = self.constants.iter().collect();
-        sorted.sort_by_key(|x| x.0.to_lowercase());
-        for (ident, value) in sorted {
-            println!("{} - 0x{:x}", ident, value);
+fn resolve_anonymous_label_refs(
+    expr: &mut Expr,
+    resolve: &mut dyn FnMut(i32) -> Result<String, Error>,
+) -> Result<(), Error> {
+    match expr {
+        Expr::AnonymousLabelRef(offset) => *expr = Expr::Ident(resolve(*offset)?),
+        Expr::Binary(binary) => {
+            resolve_anonymous_label_refs(&mut binary.left, resolve)?;
+            resolve_anonymous_label_refs(&mut binary.right, resolve)?;
         }
-        Ok(self)
+        Expr::Unary(unary) => resolve_anonymous_label_refs(&mut unary.expr, resolve)?,
+        Expr::Ident(_) | Expr::Const(_) => {}
     }
+    Ok(())
+}
 
-    /// Compiles assembly and binary data into binary rom data.
-    pub fn compile(mut self) -> Result<Vec<u8>, Error> {
-        if self.data.last().is_none() {
-            bail!("No instructions or binary data was added to the RomBuilder");
-        }
+/// Resolves the conditional blocks and SET constants of the added instructions via [Conditionals], replacing the
+/// directives, SET assignments and excluded lines with empty lines so the line numbers stay intact.
+/// Conditions can use the identifiers defined before the instructions were added and the EQUs declared earlier in
+/// them, `sets` holds the value of each SET constant carried over between calls, and the identifiers used by the
+/// conditions and SETs are returned so they are not reported as unused.
+fn resolve_conditionals(
+    instructions: &mut [Instruction],
+    constants: &HashMap<String, i64>,
+    sets: &mut HashMap<String, i64>,
+    source: &DataSource,
+) -> Result<HashSet<String>, Error> {
+    let needs_resolving = |x: &Instruction| {
+        matches!(
+            x,
+            Instruction::If(_)
+                | Instruction::Elif(_)
+                | Instruction::Else
+                | Instruction::Endc
+                | Instruction::Set(..)
+        )
+    };
+    if sets.is_empty() && !instructions.iter().any(needs_resolving) {
+        return Ok(HashSet::new());
+    }
 
-        let rom_size_factor = if self.address <= ROM_BANK_SIZE * 2 {
-            0
-        } else if self.address <= ROM_BANK_SIZE * 4 {
-            1
-        } else if self.address <= ROM_BANK_SIZE * 8 {
-            2
-        } else if self.address <= ROM_BANK_SIZE * 16 {
-            3
-        } else if self.address <= ROM_BANK_SIZE * 32 {
-            4
-        } else if self.address <= ROM_BANK_SIZE * 64 {
-            5
-        } else if self.address <= ROM_BANK_SIZE * 128 {
-            6
-        } else if self.address <= ROM_BANK_SIZE * 256 {
-            7
-        } else if self.address <= ROM_BANK_SIZE * 512 {
-            8
-        } else {
-            bail!("ROM is too big, there is no MBC that supports a ROM size larger than 8MB, raw ROM size was {}", self.address);
-        };
+    let mut conditionals = Conditionals::new(constants, source);
+    for (i, instruction) in instructions.iter_mut().enumerate() {
+        conditionals.resolve(instruction, i + 1, sets)?;
+    }
+    conditionals.finish()
+}
 
-        let mut rom = vec![];
+/// The state carried from each asm file to the files it includes and the files added after it.
+struct AsmFileState {
+    /// the value of each SET constant
+    sets: HashMap<String, i64>,
+    /// the paths of the files containing `pragma once` that were already loaded, they are skipped
+    once: HashSet<PathBuf>,
+    /// the identifiers used by conditions and SETs
+    references: HashSet<String>,
+    /// the text of each string constant declared with EQUS
+    strings: HashMap<String, String>,
+}
 
-        #[derive(Clone)]
-        struct EquHolder<'a> {
-            pub ident: &'a String,
-            pub expr: &'a Expr,
-            pub source: &'a DataSource,
-            pub line: u64,
-        }
-        let mut equs = vec![];
+/// The options set by each [Instruction::Opt] so far, applied to the instructions after them.
+struct AsmOptions {
+    pad_halt: bool,
+    warnings_as_errors: bool,
+    /// the options saved by each PUSHO
+    pushed: Vec<(bool, bool)>,
+}
 
-        for data in &self.data {
-            match &data.data {
-                Data::DummyInterruptsAndJumps => {}
-                Data::Header(_) => {}
-                Data::Binary { .. } => {}
-                Data::Instructions(instructions) => {
-                    for (i, instruction) in instructions.iter().enumerate() {
-                        if let Instruction::Equ(ident, expr) = instruction {
-                            equs.push(EquHolder {
-                                expr,
-                                ident,
-                                source: &data.source,
-                                line: i as u64 + 1,
-                            });
-                        }
-                    }
-                }
-            }
+impl AsmOptions {
+    fn new(pad_halt: bool) -> AsmOptions {
+        AsmOptions {
+            pad_halt,
+            warnings_as_errors: false,
+            pushed: vec![],
         }
+    }
 
-        let constants = &mut self.constants;
-        while !equs.is_empty() {
-            let prev_size = equs.len();
-            let mut outer_error = None;
-            let mut missing_idents = vec![];
-            equs.retain(|equ| {
-                match equ.expr.run(constants) {
-                    Ok(value) => {
-                        if constants.insert(equ.ident.clone(), value).is_some() {
-                            // TODO: Display first usage
-                            outer_error = Some(format!("Identifier {} is declared twice: One usage occured in {} on line {}", &equ.ident, equ.source.description(), equ.line));
-                        }
-                        false
-                    }
-                    Err(ExprRunError::MissingIdentifier (ident)) => {
-                        // MissingIdentifier can mean:
-                        // *    There is a reference to an identifier that hasnt been processed yet. And it is succesfully processed later.
-                        // *    There is a reference to an identifier that hasnt been processed yet. But it turns out to be an infinite loop.
-                        // *    There is a reference to an identifier that is not declared anywhere.
-                        // We store the values so we can handle these cases after the `retain`, due to the mutable borrow.
-                        missing_idents.push((equ.clone(), ident));
-                        true
-                    }
-                    Err(ExprRunError::ResultDoesntFit (error)) |
-                    Err(ExprRunError::ArithmeticError (error)) => {
-                        outer_error = Some(format!("Error occured in {} on line {}: {}", equ.source.description(), equ.line, error));
-                        true
+    /// Applies the options to the instruction or updates them, stop is padded when pad_stop is true.
+    fn apply(&mut self, instruction: &mut Instruction, pad_stop: bool) {
+        match instruction {
+            Instruction::Opt(options) => {
+                for option in options {
+                    match option {
+                        AsmOption::WarningsAsErrors(enabled) => self.warnings_as_errors = *enabled,
+                        AsmOption::HaltPadding(enabled) => self.pad_halt = *enabled,
+                        AsmOption::Radix(_) => {}
                     }
                 }
-            });
-            if let Some(error) = outer_error {
-                bail!(error);
             }
-
-            // Check if the reason the ident was missing is because it is never declared.
-            for (missing_ident_equ, missing_ident) in missing_idents {
-                let mut found_ident = false;
-                for search_equ in &equs {
-                    if &missing_ident == search_equ.ident {
-                        found_ident = true;
-                        break;
-                    }
-                }
-                if !found_ident {
-                    bail!(format!(
-                        "Identifier {} is used in {} on line {} but is never declared.",
-                        missing_ident,
-                        missing_ident_equ.source.description(),
-                        missing_ident_equ.line
-                    ));
+            Instruction::PushO => self.pushed.push((self.pad_halt, self.warnings_as_errors)),
+            Instruction::PopO => {
+                if let Some(options) = self.pushed.pop() {
+                    (self.pad_halt, self.warnings_as_errors) = options;
                 }
             }
-
-            // Generic check for an infinite loop.
-            if prev_size == equs.len() {
-                let mut fail_string = String::from("Cannot resolve constants, there is an infinite loop involving the following identifiers:\n");
-                for equ in equs {
-                    fail_string.push_str(&format!("*   {}\n", equ.ident));
-                }
-                bail!(fail_string);
+            Instruction::Stop if pad_stop => *instruction = Instruction::StopPadded,
+            Instruction::Halt if !self.pad_halt => *instruction = Instruction::HaltUnpadded,
+            Instruction::Warn(items) if self.warnings_as_errors => {
+                let mut message = vec![DbItem::String(String::from("Warning treated as error: "))];
+                message.append(items);
+                *instruction = Instruction::Fail(message);
             }
+            _ => {}
         }
+    }
+}
 
-        // generate rom
-        for data in &self.data {
-            // pad to address
-            rom.resize(data.address as usize, 0x00);
-
-            match &data.data {
-                Data::DummyInterruptsAndJumps => {
-                    // jumps
-                    for _ in 0..8 {
-                        rom.push(0xc3);
-                        rom.push(0x00);
-                        rom.push(0x01);
-                        rom.push(0x00);
-
-                        rom.push(0x00);
-                        rom.push(0x00);
-                        rom.push(0x00);
-                        rom.push(0x00);
-                    }
-
-                    // interrupts
-                    for _ in 0..5 {
-                        rom.push(0xd9);
-                        rom.push(0x00);
-                        rom.push(0x00);
-                        rom.push(0x00);
-
-                        rom.push(0x00);
-                        rom.push(0x00);
-                        rom.push(0x00);
-                        rom.push(0x00);
-                    }
+struct ConditionalBlock {
+    /// true if the enclosing block is included
+    parent_included: bool,
+    /// true if any branch of this block has been included
+    taken: bool,
+    included: bool,
+    seen_else: bool,
+}
 
-                    // padding
-                    rom.resize(rom.len() + 0x98, 0x00);
+/// Resolves the conditional blocks and SET constants of a source, one instruction at a time.
+///
+/// Excluded instructions and SET assignments are replaced with empty lines, keeping the line numbers intact.
+/// References to SET constants are replaced with the value assigned before them.
+struct Conditionals<'a> {
+    source: &'a DataSource,
+    blocks: Vec<ConditionalBlock>,
+    /// constants and the EQUs declared so far
+    known: HashMap<String, i64>,
+    references: References,
+}
 
-                    // jump to 0x0150
-                    rom.push(0x00);
-                    rom.push(0xc3);
-                    rom.push(0x50);
-                    rom.push(0x01);
-                }
-                Data::Header(header) => {
-                    header.write(&mut rom, rom_size_factor as u8);
-                }
-                Data::Binary(bytes) => {
-                    rom.extend(bytes);
-                }
-                Data::Instructions(instructions) => {
-                    for (i, instruction) in instructions.iter().enumerate() {
-                        if let Err(err) = instruction.write_to_rom(&mut rom, &self.constants) {
-                            bail!(
-                                "Error occured in {} on line {}: {}",
-                                data.source.description(),
-                                i + 1,
-                                err
-                            );
-                        }
-                    }
-                }
-            }
+impl<'a> Conditionals<'a> {
+    fn new(constants: &HashMap<String, i64>, source: &'a DataSource) -> Self {
+        Conditionals {
+            source,
+            blocks: vec![],
+            known: constants.clone(),
+            references: References::default(),
         }
+    }
 
-        if rom.len() < 0x14F {
-            bail!(
-                "ROM is too small, header is not finished. ROM was only {} bytes",
-                rom.len()
-            );
+    fn evaluate(&mut self, expr: &Expr, usage: &str, line: usize) -> Result<i64, Error> {
+        self.references.visit_expr(expr);
+        match expr.run(&self.known) {
+            Ok(value) => Ok(value),
+            Err(ExprRunError::MissingIdentifier(ident)) => bail!(
+                "Identifier {} is used in {} in {} on line {} but is not declared before it.",
+                ident,
+                usage,
+                self.source.description(),
+                line
+            ),
+            Err(ExprRunError::ResultDoesntFit(error))
+            | Err(ExprRunError::ArithmeticError(error)) => bail!(
+                "Error occured in {} on line {}: {}",
+                self.source.description(),
+                line,
+                error
+            ),
         }
+    }
 
-        // verify cartridge_type and rom_size_factor are compatible
-        let cartridge_type = CartridgeType::variant(rom[0x0147]);
-        let final_size_factor = rom[0x0148];
-        if final_size_factor >= 0x20 {
-            bail!(
-                "ROM size factor (0x0148) is too big, needs to be less than 32 was {}",
-                final_size_factor
-            );
-        }
-        let final_size = (ROM_BANK_SIZE * 2) << final_size_factor;
-        match cartridge_type {
-            CartridgeType::RomOnly | CartridgeType::RomRam | CartridgeType::RomRamBattery => {
-                if final_size_factor != 0 {
-                    bail!("ROM is too big, there is no MBC so ROM size must be <= 32KB, was actually {}", final_size);
-                }
+    /// Resolves the instruction on the 1 indexed line.
+    fn resolve(
+        &mut self,
+        instruction: &mut Instruction,
+        line: usize,
+        sets: &mut HashMap<String, i64>,
+    ) -> Result<(), Error> {
+        let source = self.source;
+        let included = self.blocks.last().is_none_or(|x| x.included);
+        if included {
+            for expr in instruction.exprs_mut() {
+                replace_idents(expr, sets);
             }
-            CartridgeType::Mbc1 | CartridgeType::Mbc1Ram | CartridgeType::Mbc1RamBattery => {
-                if final_size_factor > 6 {
-                    bail!(
-                        "ROM is too big, using MBC1 so ROM size must be <= 2MB, was actually {}",
-                        final_size
-                    );
-                }
+        }
+        match instruction {
+            Instruction::If(expr) => {
+                let taken = included && self.evaluate(expr, "a condition", line)? != 0;
+                self.blocks.push(ConditionalBlock {
+                    parent_included: included,
+                    taken,
+                    included: taken,
+                    seen_else: false,
+                });
             }
-            CartridgeType::Mbc2 | CartridgeType::Mbc2Battery => {
-                if final_size_factor > 3 {
-                    bail!(
-                        "ROM is too big, using MBC2 so ROM size must be <= 256KB, was actually {}",
-                        final_size
-                    );
-                }
+            Instruction::Elif(expr) => {
+                let (parent_included, taken) = match self.blocks.last() {
+                    Some(block) if !block.seen_else => (block.parent_included, block.taken),
+                    _ => bail!(
+                        "ELIF without a matching IF in {} on line {}",
+                        source.description(),
+                        line
+                    ),
+                };
+                let included =
+                    parent_included && !taken && self.evaluate(expr, "a condition", line)? != 0;
+                let block = self.blocks.last_mut().unwrap();
+                block.included = included;
+                block.taken |= included;
             }
-            CartridgeType::Mmm01 | CartridgeType::Mmm01Ram | CartridgeType::Mmm01RamBattery => {
-                // TODO
+            Instruction::Else => {
+                let block = match self.blocks.last_mut() {
+                    Some(block) if !block.seen_else => block,
+                    _ => bail!(
+                        "ELSE without a matching IF in {} on line {}",
+                        source.description(),
+                        line
+                    ),
+                };
+                block.seen_else = true;
+                block.included = block.parent_included && !block.taken;
+                block.taken = true;
             }
-            CartridgeType::Mbc3TimerBattery
-            | CartridgeType::Mbc3TimerRamBattery
-            | CartridgeType::Mbc3
-            | CartridgeType::Mbc3Ram
-            | CartridgeType::Mbc3RamBattery => {
-                if final_size_factor > 6 {
+            Instruction::Endc => {
+                if self.blocks.pop().is_none() {
                     bail!(
-                        "ROM is too big, using MBC3 so ROM size must be <= 2MB, was actually {}",
-                        final_size
+                        "ENDC without a matching IF in {} on line {}",
+                        source.description(),
+                        line
                     );
                 }
             }
-            CartridgeType::Mbc5
-            | CartridgeType::Mbc5Ram
-            | CartridgeType::Mbc5RamBattery
-            | CartridgeType::Mbc5Rumble
-            | CartridgeType::Mbc5RumbleRam
-            | CartridgeType::Mbc5RumbleRamBattery => {
-                if final_size_factor > 8 {
+            Instruction::Set(ident, expr) if included => {
+                if self.known.contains_key(ident) {
                     bail!(
-                        "ROM is too big, using MBC5 so ROM size must be <= 8MB, was actually {}",
-                        final_size
+                        "Identifier {} is assigned with SET in {} on line {} but is already declared as a constant that cannot be reassigned.",
+                        ident,
+                        source.description(),
+                        line
                     );
                 }
+                let value = self.evaluate(expr, "a SET", line)?;
+                sets.insert(ident.clone(), value);
             }
-            CartridgeType::PocketCamera => {
-                if final_size_factor > 8 {
-                    bail!("ROM is too big, using PocketCamera so ROM size must be <= 1MB, was actually {}", final_size);
+            Instruction::Equ(ident, expr) if included => {
+                // EQUs that cant be resolved yet are left for RomBuilder::compile to handle
+                if let Ok(value) = expr.run(&self.known) {
+                    self.known.insert(ident.clone(), value);
                 }
+                return Ok(());
             }
-            CartridgeType::HuC3 => {
-                // TODO
-            }
-            CartridgeType::HuC1RamBattery => {
-                if final_size_factor > 6 {
-                    bail!(
-                        "ROM is too big, using HuC1 so ROM size must be <= 2MB, was actually {}",
-                        final_size
-                    );
+            _ => {
+                if included {
+                    return Ok(());
                 }
             }
-            CartridgeType::Unknown(_) => {
-                // Hopefully you know what your doing ...
-            }
         }
-
-        // pad remainder of rom with 0's to fill size
-        rom.resize(final_size as usize, 0x00);
-
-        Ok(rom)
-    }
-
-    /// Compile the ROM then write it to disk at the root of the project.
-    /// The root of the project is the outermost directory containing a Cargo.toml file.
-    pub fn write_to_disk(self, name: &str) -> Result<(), Error> {
-        let output = self.root_dir.as_path().join(name);
-        let rom = self.compile()?;
-        fs::write(output, rom)?;
+        *instruction = Instruction::EmptyLine;
         Ok(())
     }
 
-    /// Provide some sort of mechanism to generate an html file with embedded gb emulator and rom data.
-    /// Use Cargo.toml metadata to generate a link to repository, include developers name etc. (use panic-handler as a reference here)
-    /// This is completely unimplemented, its just a reminder to do this some day.
-    pub fn write_to_disk_html(self, _name: &str) -> Result<(), Error> {
-        unimplemented!();
+    /// Returns the identifiers used by the conditions and SETs,
+    /// or an error if a conditional block is not closed.
+    fn finish(self) -> Result<HashSet<String>, Error> {
+        if !self.blocks.is_empty() {
+            bail!(
+                "IF without a matching ENDC in {}",
+                self.source.description()
+            );
+        }
+        Ok(self.references.0)
     }
+}
 
-    /// Iteratively search for the innermost Cargo.toml starting at the current.
-    /// working directory and working up through its parents.
-    /// Returns the path to the directory the Cargo.toml is in.
-    /// Or an error if the file couldn't be found.
-    ///
-    /// TODO: This function returns the wrong path if called like `cargo run -p crate_name` in a workspace.
-    /// The only way to fix this would be get rustc provide an equivalent of env!("CARGO_MANIFEST_DIR") provided at runtime.
-    fn root_dir() -> Result<PathBuf, Error> {
-        let current_dir = env::current_dir()?;
-        let mut current = current_dir.as_path();
+/// Collects every identifier an expression or export refers to.
+#[derive(Default)]
+struct References(HashSet<String>);
 
-        loop {
-            let toml = current.join("Cargo.toml");
-            if fs::metadata(&toml).is_ok() {
-                return Ok(toml.parent().unwrap().to_path_buf());
+impl Visitor for References {
+    fn visit_ident(&mut self, ident: &str) {
+        if !self.0.contains(ident) {
+            self.0.insert(ident.to_string());
+        }
+    }
+}
+
+/// Replaces identifiers with their value when they are in values.
+fn replace_idents(expr: &mut Expr, values: &HashMap<String, i64>) {
+    match expr {
+        Expr::Ident(ident) => {
+            if let Some(value) = values.get(ident) {
+                *expr = Expr::Const(*value);
             }
+        }
+        Expr::Binary(binary) => {
+            replace_idents(&mut binary.left, values);
+            replace_idents(&mut binary.right, values);
+        }
+        Expr::Unary(unary) => replace_idents(&mut unary.expr, values),
+        Expr::AnonymousLabelRef(_) | Expr::Const(_) => {}
+    }
+}
 
-            match current.parent() {
-                Some(p) => current = p,
-                None => bail!("Cant find a Cargo.toml in any of the parent directories"),
+/// Replaces debug only instructions with empty lines, keeping the line numbers intact.
+fn strip_debug_instructions(instructions: &mut [Instruction]) {
+    for instruction in instructions {
+        match instruction {
+            Instruction::DebugAssert(_)
+            | Instruction::DebugAssertCp(..)
+            | Instruction::DebugPrint(_)
+            | Instruction::DebugBreak => {
+                *instruction = Instruction::EmptyLine;
             }
+            _ => {}
         }
     }
 }
@@ -0,0 +1,252 @@
+//! Generates new ggbasm projects from starter templates.
+//!
+//! Used by `ggbasm new <path> --template <template>`.
+//! Each template is a complete crate that builds a rom with `cargo run`.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use image::{Rgb, RgbImage};
+
+/// A starter project that can be generated by [new_project].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Template {
+    /// Turns on the screen and runs an empty main loop.
+    Blank,
+    /// A sprite moved around the screen with the dpad.
+    SpriteDemo,
+    /// A player that walks and jumps on the ground.
+    Platformer,
+    /// Plays a song with the built-in audio player.
+    MusicDemo,
+}
+
+impl Template {
+    pub const ALL: [Template; 4] = [
+        Template::Blank,
+        Template::SpriteDemo,
+        Template::Platformer,
+        Template::MusicDemo,
+    ];
+
+    /// The name used to select the template, e.g. `platformer` in `ggbasm new --template platformer`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Template::Blank => "blank",
+            Template::SpriteDemo => "sprite-demo",
+            Template::Platformer => "platformer",
+            Template::MusicDemo => "music-demo",
+        }
+    }
+
+    fn main_asm(&self) -> &'static str {
+        match self {
+            Template::Blank => include_str!("templates/blank.asm"),
+            Template::SpriteDemo => include_str!("templates/sprite_demo.asm"),
+            Template::Platformer => include_str!("templates/platformer.asm"),
+            Template::MusicDemo => include_str!("templates/music_demo.asm"),
+        }
+    }
+
+    fn has_graphics(&self) -> bool {
+        matches!(self, Template::SpriteDemo | Template::Platformer)
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Template, Error> {
+        match Template::ALL.iter().find(|x| x.name() == s) {
+            Some(template) => Ok(*template),
+            None => {
+                let names: Vec<_> = Template::ALL.iter().map(|x| x.name()).collect();
+                bail!(
+                    "Unknown template {}, must be one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// Creates a new project at path from the template.
+/// The crate is named after the last component of the path.
+///
+/// Returns an error if the path already exists and is not an empty directory.
+pub fn new_project(path: &Path, template: Template) -> Result<(), Error> {
+    let name = match path.file_name().and_then(|x| x.to_str()) {
+        Some(name) => name,
+        None => bail!("Cannot name a project after the path {}", path.display()),
+    };
+    let valid_name = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && name.starts_with(|c: char| c.is_ascii_alphabetic());
+    if !valid_name {
+        bail!(
+            "{} is not a valid crate name, it must start with a letter and only contain letters, numbers, - and _",
+            name
+        );
+    }
+    if let Ok(mut entries) = fs::read_dir(path) {
+        if entries.next().is_some() {
+            bail!("{} already exists and is not empty", path.display());
+        }
+    } else if path.exists() {
+        bail!("{} already exists and is not a directory", path.display());
+    }
+
+    fs::create_dir_all(path.join("src"))?;
+    fs::create_dir_all(path.join("gbasm"))?;
+    fs::write(path.join("Cargo.toml"), cargo_toml(name))?;
+    fs::write(path.join(".gitignore"), "/target\n*.gb\n")?;
+    fs::write(path.join("src").join("main.rs"), main_rs(name, template))?;
+    fs::write(path.join("gbasm").join("main.asm"), template.main_asm())?;
+    if template != Template::MusicDemo {
+        fs::write(
+            path.join("gbasm").join("util.asm"),
+            include_str!("templates/util.asm"),
+        )?;
+    }
+    if template.has_graphics() {
+        fs::create_dir_all(path.join("graphics"))?;
+        tiles_image().save(path.join("graphics").join("tiles.png"))?;
+    }
+    if template == Template::MusicDemo {
+        fs::create_dir_all(path.join("audio"))?;
+        fs::write(
+            path.join("audio").join("song.txt"),
+            include_str!("templates/song.txt"),
+        )?;
+    }
+    Ok(())
+}
+
+fn cargo_toml(name: &str) -> String {
+    format!(
+        r#"[package]
+name = "{}"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+ggbasm = "{}"
+anyhow = "1"
+"#,
+        name,
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+fn main_rs(name: &str, template: Template) -> String {
+    let title: String = name.chars().take(0x10).collect();
+    let mut imports = String::new();
+    let mut colors = String::new();
+    let mut assets = String::new();
+    if template.has_graphics() {
+        imports.push_str("use std::collections::HashMap;\n\n");
+        colors.push_str(
+            r#"    // maps the colors used in the graphics folder to the 2 bit gameboy colors
+    let mut colors_map = HashMap::new();
+    colors_map.insert(Color::new(255, 255, 255), 0);
+    colors_map.insert(Color::new(170, 170, 170), 1);
+    colors_map.insert(Color::new(85, 85, 85), 2);
+    colors_map.insert(Color::new(0, 0, 0), 3);
+
+"#,
+        );
+        assets.push_str("        .add_image(\"tiles.png\", \"Tiles\", &colors_map)?\n");
+    }
+    if template == Template::MusicDemo {
+        assets.push_str("        .add_audio_player()?\n");
+        assets.push_str("        .add_audio_file(\"song.txt\")?\n");
+    }
+    let ggbasm_imports = if template.has_graphics() {
+        "use ggbasm::{Color, RomBuilder};"
+    } else {
+        "use ggbasm::RomBuilder;"
+    };
+
+    format!(
+        r#"{imports}use anyhow::Error;
+
+use ggbasm::header::{{CartridgeType, ColorSupport, Header, RamType}};
+{ggbasm_imports}
+
+fn main() {{
+    run().unwrap();
+    println!("Compiled project to {name}.gb");
+}}
+
+fn run() -> Result<(), Error> {{
+    let header = Header {{
+        title: String::from("{title}"),
+        color_support: ColorSupport::Unsupported,
+        licence: String::new(),
+        sgb_support: false,
+        cartridge_type: CartridgeType::RomOnly,
+        ram_type: RamType::None,
+        japanese: false,
+        version_number: 0,
+    }};
+
+{colors}    RomBuilder::new()?
+        .add_basic_interrupts_and_jumps()?
+        .add_header(header)?
+        .add_hardware_constants()?
+        .add_asm_file("main.asm")?
+{assets}        .write_to_disk("{name}.gb")?;
+    Ok(())
+}}
+"#
+    )
+}
+
+/// The tiles used by the sprite and platformer templates: blank, player and ground.
+const TILES: [[&str; 8]; 3] = [
+    [
+        "........", "........", "........", "........", "........", "........", "........",
+        "........",
+    ],
+    [
+        "..3333..", ".322223.", "32122123", "32222223", "32333323", ".322223.", "..3..3..",
+        ".33..33.",
+    ],
+    [
+        "33333333", "21212121", "22222222", "12221222", "22222222", "22122212", "22222222",
+        "11111111",
+    ],
+];
+
+fn tiles_image() -> RgbImage {
+    let mut image = RgbImage::new(TILES.len() as u32 * 8, 8);
+    for (tile_i, tile) in TILES.iter().enumerate() {
+        for (y, row) in tile.iter().enumerate() {
+            for (x, pixel) in row.chars().enumerate() {
+                let shade = match pixel {
+                    '1' => 170,
+                    '2' => 85,
+                    '3' => 0,
+                    _ => 255,
+                };
+                image.put_pixel(
+                    (tile_i * 8 + x) as u32,
+                    y as u32,
+                    Rgb([shade, shade, shade]),
+                );
+            }
+        }
+    }
+    image
+}
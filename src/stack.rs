@@ -0,0 +1,256 @@
+//! Estimates the worst case stack usage of each routine in a [ControlFlowGraph] and finds routines that leave the
+//! stack unbalanced, which on hardware usually shows up as a return to a garbage address long after the bug.
+//!
+//! Depths are measured in bytes from the stack pointer at the start of the routine, so they exclude the return
+//! address pushed by the call to the routine.
+//! `ld sp` starts measuring again from 0, as the stack is moved, e.g. when the entry point initializes the stack.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{Expr, Instruction, Reg16};
+use crate::control_flow::{destination, ControlFlowGraph, ENTRY_POINT, INTERRUPT_VECTORS};
+
+/// The stack usage of every routine in a [ControlFlowGraph], generated by [stack_analysis].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackAnalysis {
+    /// ordered by address
+    pub routines: Vec<RoutineStack>,
+    /// the deepest the stack gets while the entry point runs and the deepest interrupt handler interrupts it,
+    /// None if either is unknown
+    pub worst_case: Option<u32>,
+    pub issues: Vec<StackIssue>,
+}
+
+/// The stack usage of a routine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutineStack {
+    pub address: u32,
+    /// the label at the address, if any
+    pub name: Option<String>,
+    /// the most bytes pushed by the routine and the routines it calls, None if the routine is recursive or
+    /// calls a routine whose usage is unknown
+    pub max_depth: Option<u32>,
+}
+
+/// A routine that unbalances the stack or exceeds the stack budget.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackIssue {
+    /// name or address of the routine
+    pub routine: String,
+    /// address of the instruction the issue was found at, within the entire rom
+    pub address: u32,
+    pub message: String,
+}
+
+impl fmt::Display for StackIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at 0x{:04X}: {}",
+            self.routine, self.address, self.message
+        )
+    }
+}
+
+impl StackAnalysis {
+    /// Returns the stack usage of the routine starting at the label.
+    pub fn routine_by_name(&self, name: &str) -> Option<&RoutineStack> {
+        self.routines
+            .iter()
+            .find(|x| x.name.as_deref() == Some(name))
+    }
+}
+
+enum State {
+    Visiting,
+    Done(Option<u32>),
+}
+
+struct Analyzer<'a> {
+    graph: &'a ControlFlowGraph,
+    states: HashMap<u32, State>,
+    issues: Vec<StackIssue>,
+}
+
+impl Analyzer<'_> {
+    /// Returns the max depth of the routine, analyzing the routines it calls first.
+    fn routine_depth(&mut self, address: u32) -> Option<u32> {
+        match self.states.get(&address) {
+            Some(State::Done(depth)) => return *depth,
+            Some(State::Visiting) => return None,
+            None => {}
+        }
+        self.states.insert(address, State::Visiting);
+        let routine = self.graph.routine(address).unwrap();
+        let name = routine.display_name();
+        let mut issue = |address: u32, message: String| {
+            self.issues.push(StackIssue {
+                routine: name.clone(),
+                address,
+                message,
+            })
+        };
+
+        let mut max = 0;
+        let mut known = true;
+        let mut callees = vec![];
+        // the depth when each block is entered
+        let mut entry_depths: HashMap<u32, i64> = HashMap::new();
+        entry_depths.insert(address, 0);
+        let mut pending = vec![address];
+        while let Some(block_address) = pending.pop() {
+            let block = self.graph.block(block_address).unwrap();
+            let mut depth = entry_depths[&block_address];
+            for (address, instruction) in &block.instructions {
+                match instruction {
+                    Instruction::Push(_) => depth += 2,
+                    Instruction::Pop(_) => depth -= 2,
+                    Instruction::AddRspI8(Expr::Const(offset)) => depth -= offset,
+                    Instruction::IncR16(Reg16::SP) => depth -= 1,
+                    Instruction::DecR16(Reg16::SP) => depth += 1,
+                    Instruction::LdR16I16(Reg16::SP, _) | Instruction::LdRspRhl => depth = 0,
+                    Instruction::Call(_, target) | Instruction::Rst(target) => {
+                        // the return address is pushed, calls to code outside the graph such as RAM are assumed
+                        // to not push anything else
+                        max = max.max(depth + 2);
+                        let callee = destination(*address, target)
+                            .filter(|x| self.graph.routine(*x).is_some());
+                        if let Some(callee) = callee {
+                            callees.push((*address, depth, callee));
+                        }
+                    }
+                    Instruction::Ret(_) | Instruction::Reti if depth > 0 => issue(
+                        *address,
+                        format!("Returns with {} bytes still pushed", depth),
+                    ),
+                    Instruction::Ret(_) | Instruction::Reti if depth < 0 => issue(
+                        *address,
+                        format!("Returns after popping {} bytes more than it pushed", -depth),
+                    ),
+                    _ => {}
+                }
+                max = max.max(depth);
+            }
+            for successor in &block.successors {
+                match entry_depths.get(successor) {
+                    Some(previous) if *previous != depth => issue(
+                        *successor,
+                        format!(
+                            "Reached with both {} and {} bytes pushed",
+                            previous.min(&depth),
+                            previous.max(&depth)
+                        ),
+                    ),
+                    Some(_) => {}
+                    None => {
+                        entry_depths.insert(*successor, depth);
+                        pending.push(*successor);
+                    }
+                }
+            }
+        }
+
+        for (address, depth, callee) in callees {
+            match self.routine_depth(callee) {
+                Some(callee_depth) => max = max.max(depth + 2 + callee_depth as i64),
+                None => {
+                    if let Some(State::Visiting) = self.states.get(&callee) {
+                        self.issues.push(StackIssue {
+                            routine: name.clone(),
+                            address,
+                            message: format!(
+                                "Calls {} recursively, its stack usage is unbounded",
+                                self.graph.routine(callee).unwrap().display_name()
+                            ),
+                        });
+                    }
+                    known = false;
+                }
+            }
+        }
+
+        let depth = known.then_some(max.max(0) as u32);
+        self.states.insert(address, State::Done(depth));
+        depth
+    }
+}
+
+/// Analyzes the stack usage of every routine in the graph, see [crate::control_flow::control_flow_graph].
+///
+/// Reports routines that return with bytes still pushed or after popping more than they pushed,
+/// blocks reached with different depths, e.g. a loop that pushes on every iteration, and recursive calls.
+/// When a budget in bytes is given, also reports routines and the worst case that use more stack than it.
+///
+/// Only the code followed by the graph is analyzed, e.g. the destinations of `jp hl` are not included.
+pub fn stack_analysis(graph: &ControlFlowGraph, budget: Option<u32>) -> StackAnalysis {
+    let mut analyzer = Analyzer {
+        graph,
+        states: HashMap::new(),
+        issues: vec![],
+    };
+    let routines: Vec<_> = graph
+        .routines
+        .iter()
+        .map(|routine| RoutineStack {
+            address: routine.address,
+            name: routine.name.clone(),
+            max_depth: analyzer.routine_depth(routine.address),
+        })
+        .collect();
+    let mut issues = analyzer.issues;
+
+    // an interrupt pushes the return address on top of whatever the interrupted code has pushed
+    let depth = |address| routines.iter().find(|x| x.address == address)?.max_depth;
+    let worst_case = match graph.routine(ENTRY_POINT) {
+        Some(_) => {
+            let mut worst_interrupt = Some(0);
+            for vector in INTERRUPT_VECTORS {
+                if graph.routine(vector).is_some() {
+                    worst_interrupt = worst_interrupt
+                        .zip(depth(vector))
+                        .map(|(worst, depth)| worst.max(depth + 2));
+                }
+            }
+            depth(ENTRY_POINT)
+                .zip(worst_interrupt)
+                .map(|(entry, interrupt)| entry + interrupt)
+        }
+        None => None,
+    };
+
+    if let Some(budget) = budget {
+        for routine in &routines {
+            match routine.max_depth {
+                Some(depth) if depth > budget => issues.push(StackIssue {
+                    routine: graph.routine(routine.address).unwrap().display_name(),
+                    address: routine.address,
+                    message: format!(
+                        "Uses up to {} bytes of stack, exceeding the budget of {} bytes",
+                        depth, budget
+                    ),
+                }),
+                _ => {}
+            }
+        }
+        match worst_case {
+            Some(depth) if depth > budget => issues.push(StackIssue {
+                routine: String::from("Entry point and interrupts"),
+                address: ENTRY_POINT,
+                message: format!(
+                    "Use up to {} bytes of stack, exceeding the budget of {} bytes",
+                    depth, budget
+                ),
+            }),
+            _ => {}
+        }
+    }
+    issues.sort_by(|a, b| a.routine.cmp(&b.routine).then(a.address.cmp(&b.address)));
+    issues.dedup();
+
+    StackAnalysis {
+        routines,
+        worst_case,
+        issues,
+    }
+}
@@ -0,0 +1,89 @@
+//! Counts the instructions in a compiled rom, e.g. to find which instructions take up the most space
+//! or thousands of nops from accidental padding.
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::disassembler::disassemble_instruction;
+use crate::layout::{Layout, RegionKind};
+
+/// The instructions in a compiled rom, generated by [instruction_stats].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstructionStats {
+    /// every mnemonic used, ordered by bytes from most to least
+    pub mnemonics: Vec<MnemonicStats>,
+    /// the number of times each opcode is used, indexed by opcode
+    pub opcodes: Vec<u32>,
+    /// the number of times each opcode prefixed by 0xCB is used, indexed by the opcode after the prefix
+    pub prefixed_opcodes: Vec<u32>,
+    /// bytes of instructions
+    pub code_bytes: u32,
+    /// bytes of raw bytes, graphics, audio or db/dw data
+    pub data_bytes: u32,
+}
+
+/// How often a mnemonic is used.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MnemonicStats {
+    pub mnemonic: String,
+    /// number of instructions
+    pub count: u32,
+    /// bytes taken up by the instructions including their operands
+    pub bytes: u32,
+}
+
+/// Counts the instructions in the code regions of a rom compiled by [crate::RomBuilder::compile_with_layout].
+///
+/// Returns an error if a code region does not contain valid instructions, i.e. the rom does not match the layout.
+pub fn instruction_stats(rom: &[u8], layout: &Layout) -> Result<InstructionStats, Error> {
+    let mut stats = InstructionStats {
+        mnemonics: vec![],
+        opcodes: vec![0; 0x100],
+        prefixed_opcodes: vec![0; 0x100],
+        code_bytes: 0,
+        data_bytes: 0,
+    };
+
+    for region in &layout.regions {
+        let start = region.address as usize;
+        let end = start + region.size as usize;
+        match region.kind {
+            RegionKind::Data => stats.data_bytes += region.size,
+            RegionKind::Code => {
+                stats.code_bytes += region.size;
+                let mut address = start;
+                while address < end {
+                    let (instruction, len) = disassemble_instruction(&rom[address..end], address)?;
+                    match rom[address] {
+                        0xCB => stats.prefixed_opcodes[rom[address + 1] as usize] += 1,
+                        opcode => stats.opcodes[opcode as usize] += 1,
+                    }
+
+                    let mnemonic = instruction.mnemonic().unwrap();
+                    let index = match stats.mnemonics.iter().position(|x| x.mnemonic == mnemonic) {
+                        Some(index) => index,
+                        None => {
+                            stats.mnemonics.push(MnemonicStats {
+                                mnemonic: mnemonic.to_string(),
+                                count: 0,
+                                bytes: 0,
+                            });
+                            stats.mnemonics.len() - 1
+                        }
+                    };
+                    stats.mnemonics[index].count += 1;
+                    stats.mnemonics[index].bytes += len as u32;
+                    address += len;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    stats.mnemonics.sort_by(|a, b| {
+        b.bytes
+            .cmp(&a.bytes)
+            .then_with(|| a.mnemonic.cmp(&b.mnemonic))
+    });
+    Ok(stats)
+}
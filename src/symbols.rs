@@ -0,0 +1,184 @@
+//! The identifiers declared while building a rom, along with what declared them and where.
+//!
+//! A [SymbolTable] dereferences to the value of each identifier, so it can be used wherever expressions are
+//! evaluated, e.g. [crate::ast::Expr::run].
+
+use std::collections::HashMap;
+use std::ops::Deref;
+
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::address::{Bank, BankAddr, GlobalAddr};
+
+/// What declared a symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SymbolKind {
+    /// A label in asm or the name of data added by rust code, the value is its address as seen by the cpu while
+    /// [SymbolTable::bank] is mapped in.
+    Label,
+    /// An EQU constant in asm.
+    Equ,
+    /// A constant defined by rust code, e.g. via [crate::RomBuilder::define].
+    Define,
+    /// A variable declared via [crate::RomBuilder::declare_ram_variable], the value is its address.
+    RamVariable,
+}
+
+/// Describes the declaration of a symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolInfo {
+    pub kind: SymbolKind,
+    /// description of what declared the symbol, e.g. the asm file
+    pub source: String,
+    /// 1 indexed line within the source, None when declared by rust code
+    pub line: Option<usize>,
+    /// the number of bytes the symbol refers to, when known
+    pub size: Option<u32>,
+}
+
+impl SymbolInfo {
+    /// A symbol declared by rust code.
+    pub fn rust(kind: SymbolKind, size: Option<u32>) -> SymbolInfo {
+        SymbolInfo {
+            kind,
+            source: String::from("rust code"),
+            line: None,
+            size,
+        }
+    }
+
+    /// A symbol declared on a line of a source.
+    pub fn asm(kind: SymbolKind, source: String, line: usize) -> SymbolInfo {
+        SymbolInfo {
+            kind,
+            source,
+            line: Some(line),
+            size: None,
+        }
+    }
+
+    /// Describes where the symbol is declared, e.g. `asm file main.asm on line 4`.
+    pub fn location(&self) -> String {
+        match self.line {
+            Some(line) => format!("{} on line {}", self.source, line),
+            None => self.source.clone(),
+        }
+    }
+}
+
+/// Every identifier declared so far, with its value and declaration.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    values: HashMap<String, i64>,
+    infos: HashMap<String, SymbolInfo>,
+    banks: HashMap<String, Bank>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// Declares an identifier with its value.
+    /// Returns an error describing both declarations if the identifier is already declared.
+    pub fn declare(&mut self, name: &str, value: i64, info: SymbolInfo) -> Result<(), Error> {
+        if let Some(existing) = self.infos.get(name) {
+            match info.line {
+                Some(_) => bail!(
+                    "Identifier {} is used twice: One usage occured in {}, the other in {}",
+                    name,
+                    info.location(),
+                    existing.location()
+                ),
+                None => bail!(
+                    "Identifier {} is already used, it is declared in {}",
+                    name,
+                    existing.location()
+                ),
+            }
+        }
+        if self.values.contains_key(name) {
+            bail!("Identifier {} is already used", name);
+        }
+        self.values.insert(name.to_string(), value);
+        self.infos.insert(name.to_string(), info);
+        Ok(())
+    }
+
+    /// Declares a label at an address within the entire rom, its value is the address as seen by the cpu.
+    pub(crate) fn declare_label(
+        &mut self,
+        name: &str,
+        address: GlobalAddr,
+        info: SymbolInfo,
+    ) -> Result<(), Error> {
+        self.declare(name, address.bank_addr().0 as i64, info)?;
+        self.banks.insert(name.to_string(), address.bank());
+        Ok(())
+    }
+
+    /// Moves a declared label to an address within the entire rom.
+    pub(crate) fn set_label(&mut self, name: &str, address: GlobalAddr) {
+        self.values
+            .insert(name.to_string(), address.bank_addr().0 as i64);
+        self.banks.insert(name.to_string(), address.bank());
+    }
+
+    /// Updates the value of a declared identifier, or temporarily defines an undeclared one without any
+    /// [SymbolInfo], e.g. an EQU used while placing labels.
+    pub(crate) fn set_value(&mut self, name: &str, value: i64) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    /// Removes an identifier defined by [SymbolTable::set_value] that was never declared.
+    pub(crate) fn remove_value(&mut self, name: &str) {
+        if !self.infos.contains_key(name) {
+            self.values.remove(name);
+        }
+    }
+
+    /// Returns how the identifier was declared.
+    pub fn info(&self, name: &str) -> Option<&SymbolInfo> {
+        self.infos.get(name)
+    }
+
+    /// Returns the kind of the identifier.
+    pub fn kind(&self, name: &str) -> Option<SymbolKind> {
+        self.infos.get(name).map(|x| x.kind)
+    }
+
+    /// Returns the rom bank of a label.
+    pub fn bank(&self, name: &str) -> Option<Bank> {
+        self.banks.get(name).copied()
+    }
+
+    /// Returns the address of a label within the entire rom.
+    pub fn global_addr(&self, name: &str) -> Option<GlobalAddr> {
+        let bank = *self.banks.get(name)?;
+        GlobalAddr::new(bank, BankAddr(self.values[name] as u16)).ok()
+    }
+
+    /// Returns the value of the identifier, with labels as their address within the entire rom.
+    pub fn global_value(&self, name: &str) -> Option<i64> {
+        match self.global_addr(name) {
+            Some(address) => Some(address.0 as i64),
+            None => self.values.get(name).copied(),
+        }
+    }
+
+    /// Iterates over the name, value and declaration of every declared identifier in an arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, i64, &SymbolInfo)> {
+        self.infos
+            .iter()
+            .map(|(name, info)| (name.as_str(), self.values[name], info))
+    }
+}
+
+impl Deref for SymbolTable {
+    type Target = HashMap<String, i64>;
+
+    fn deref(&self) -> &HashMap<String, i64> {
+        &self.values
+    }
+}
@@ -0,0 +1,23 @@
+Start:
+    di
+    call WaitVBlank
+    xor a
+    ld [rLCDC], a
+
+    ; TODO: load graphics while the LCD is off
+
+    ld a, 0xE4
+    ld [rBGP], a
+    ld a, LCDCF_ON | LCDCF_BGON
+    ld [rLCDC], a
+
+    ld a, IEF_VBLANK
+    ld [rIE], a
+    ei
+
+MainLoop:
+    halt
+    ; TODO: game logic, VBlank has just started
+    jp MainLoop
+
+include "util.asm"
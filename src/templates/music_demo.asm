@@ -0,0 +1,30 @@
+GGBASMAudioEnable    EQU 0xC020 ; dont process music when 0 otherwise process it
+GGBASMAudioBank      EQU 0xC021 ; the bank the currently playing song is stored on
+GGBASMAudioPointerLo EQU 0xC022 ; pointer to the currently playing song
+GGBASMAudioPointerHi EQU 0xC023
+GGBASMAudioRest      EQU 0xC024 ; rest for this many steps
+
+Start:
+    di
+    call GGBASMAudioInit
+
+    ; start playing the song
+    ld hl, GGBASMAudioEnable
+    ld [hl], 0x01
+    ld hl, GGBASMAudioBank
+    ld [hl], 0x00
+    ld hl, GGBASMAudioPointerHi
+    ld [hl], MusicSong / 0x100
+    ld hl, GGBASMAudioPointerLo
+    ld [hl], MusicSong % 0x100
+    ld hl, GGBASMAudioRest
+    ld [hl], 0x20
+
+    ld a, IEF_VBLANK
+    ld [rIE], a
+    ei
+
+MainLoop:
+    halt
+    call GGBASMAudioStep
+    jp MainLoop
@@ -0,0 +1,107 @@
+TilesSize EQU 3 * 16 ; the blank, player and ground tiles in graphics/tiles.png
+PlayerVelocity EQU 0xC000 ; signed pixels per frame, positive is down
+
+; the ground is drawn on tile row 16, OAM positions are offset from the screen position
+GroundRow   EQU 16
+GroundY     EQU GroundRow * 8 - 8 + 16
+PlayerStartX EQU 16 + 8
+MaxFallSpeed EQU 4
+JumpVelocity EQU 0xFA ; -6
+
+Start:
+    di
+    call WaitVBlank
+    xor a
+    ld [rLCDC], a
+    ld [PlayerVelocity], a
+
+    ; copy the tiles into VRAM
+    ld hl, Tiles
+    ld de, _VRAM8000
+    ld bc, TilesSize
+    call MemCopy
+
+    ; clear the background, then draw the ground
+    ld hl, _SCRN0
+    ld bc, 32 * 32
+    call MemClear
+    ld hl, _SCRN0 + GroundRow * 32
+    ld b, SCRN_X_B
+    ld a, 2
+DrawGround:
+    ldi [hl], a
+    dec b
+    jr nz, DrawGround
+
+    ; hide every sprite
+    ld hl, _OAMRAM
+    ld bc, 160
+    call MemClear
+
+    ; the player is sprite 0, starting on the ground
+    ld hl, _OAMRAM
+    ld a, GroundY
+    ldi [hl], a
+    ld a, PlayerStartX
+    ldi [hl], a
+    ld a, 1
+    ldi [hl], a
+    xor a
+    ld [hl], a
+
+    ld a, 0xE4
+    ld [rBGP], a
+    ld [rOBP0], a
+    ld a, LCDCF_ON | LCDCF_BG8000 | LCDCF_BGON | LCDCF_OBJON
+    ld [rLCDC], a
+
+    ld a, IEF_VBLANK
+    ld [rIE], a
+    ei
+
+MainLoop:
+    halt
+    call ReadInput
+    ld b, a
+
+    ; walk left and right
+    ld hl, _OAMRAM + 1
+    bit PADB_LEFT, b
+    jr z, NotLeft
+    dec [hl]
+NotLeft:
+    bit PADB_RIGHT, b
+    jr z, NotRight
+    inc [hl]
+NotRight:
+
+    ; fall, accelerating up to the maximum speed
+    ld a, [PlayerVelocity]
+    cp MaxFallSpeed
+    jr z, AtMaxSpeed
+    inc a
+    ld [PlayerVelocity], a
+AtMaxSpeed:
+    ld c, a
+    ld hl, _OAMRAM
+    ld a, [hl]
+    add a, c
+    cp GroundY
+    jr c, InAir
+
+    ; standing on the ground, jump when A is pressed
+    ld a, GroundY
+    ld [hl], a
+    xor a
+    bit PADB_A, b
+    jr z, SetVelocity
+    ld a, JumpVelocity
+SetVelocity:
+    ld [PlayerVelocity], a
+    jp MainLoop
+
+InAir:
+    ld [hl], a
+    jp MainLoop
+
+include "util.asm"
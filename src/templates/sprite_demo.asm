@@ -0,0 +1,69 @@
+TilesSize EQU 3 * 16 ; the blank, player and ground tiles in graphics/tiles.png
+; OAM positions are offset from the screen position
+PlayerStartY EQU 72 + 16
+PlayerStartX EQU 80 + 8
+
+Start:
+    di
+    call WaitVBlank
+    xor a
+    ld [rLCDC], a
+
+    ; copy the tiles into VRAM
+    ld hl, Tiles
+    ld de, _VRAM8000
+    ld bc, TilesSize
+    call MemCopy
+
+    ; hide every sprite
+    ld hl, _OAMRAM
+    ld bc, 160
+    call MemClear
+
+    ; the player is sprite 0
+    ld hl, _OAMRAM
+    ld a, PlayerStartY
+    ldi [hl], a
+    ld a, PlayerStartX
+    ldi [hl], a
+    ld a, 1
+    ldi [hl], a
+    xor a
+    ld [hl], a
+
+    ld a, 0xE4
+    ld [rOBP0], a
+    ld a, LCDCF_ON | LCDCF_OBJON
+    ld [rLCDC], a
+
+    ld a, IEF_VBLANK
+    ld [rIE], a
+    ei
+
+MainLoop:
+    halt
+    call ReadInput
+    ld b, a
+
+    ; move the player with the dpad
+    ld hl, _OAMRAM
+    bit PADB_UP, b
+    jr z, NotUp
+    dec [hl]
+NotUp:
+    bit PADB_DOWN, b
+    jr z, NotDown
+    inc [hl]
+NotDown:
+    inc hl
+    bit PADB_LEFT, b
+    jr z, NotLeft
+    dec [hl]
+NotLeft:
+    bit PADB_RIGHT, b
+    jr z, NotRight
+    inc [hl]
+NotRight:
+    jp MainLoop
+
+include "util.asm"
@@ -0,0 +1,51 @@
+; Waits until the LCD reaches the VBlank period.
+WaitVBlank:
+    ld a, [rLY]
+    cp 144
+    jr c, WaitVBlank
+    ret
+
+; Copies bc bytes from hl to de.
+MemCopy:
+    ldi a, [hl]
+    ld [de], a
+    inc de
+    dec bc
+    ld a, b
+    or c
+    jr nz, MemCopy
+    ret
+
+; Clears bc bytes starting at hl.
+MemClear:
+    xor a
+    ldi [hl], a
+    dec bc
+    ld a, b
+    or c
+    jr nz, MemClear
+    ret
+
+; Returns the held buttons in a, each bit is set while the button is held.
+; The bits match PADF_DOWN, PADF_UP, PADF_LEFT, PADF_RIGHT, PADF_START, PADF_SELECT, PADF_B and PADF_A.
+ReadInput:
+    ld a, P1F_GET_DPAD
+    ld [rP1], a
+    ld a, [rP1]
+    ld a, [rP1]
+    cpl
+    and 0x0F
+    swap a
+    ld b, a
+    ld a, P1F_GET_BTN
+    ld [rP1], a
+    ld a, [rP1]
+    ld a, [rP1]
+    cpl
+    and 0x0F
+    or b
+    ld b, a
+    ld a, P1F_GET_NONE
+    ld [rP1], a
+    ld a, b
+    ret
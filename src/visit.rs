@@ -0,0 +1,168 @@
+//! Walks the instructions and expressions of the AST, so tools can inspect or rewrite them without matching on
+//! every [Instruction] variant.
+//!
+//! Implement [Visitor] to inspect instructions or [VisitorMut] to modify them, overriding only the methods of interest.
+//! The default methods continue walking into the expressions, so an overriding method calls
+//! [walk_instruction] or [walk_expr] to keep walking.
+//!
+//! ```
+//! use ggbasm::ast::Instruction;
+//! use ggbasm::visit::VisitorMut;
+//!
+//! /// Renames a label where it is defined and everywhere it is used.
+//! struct Rename;
+//!
+//! impl VisitorMut for Rename {
+//!     fn visit_name_mut(&mut self, name: &mut String) {
+//!         if name == "Main" {
+//!             *name = String::from("Start");
+//!         }
+//!     }
+//!
+//!     fn visit_ident_mut(&mut self, ident: &mut String) {
+//!         self.visit_name_mut(ident);
+//!     }
+//! }
+//!
+//! let mut instructions = vec![Instruction::label("Main").unwrap(), Instruction::jp("Main").unwrap()];
+//! for instruction in &mut instructions {
+//!     Rename.visit_instruction_mut(instruction);
+//! }
+//! assert_eq!(instructions, vec![Instruction::label("Start").unwrap(), Instruction::jp("Start").unwrap()]);
+//! ```
+
+use crate::ast::*;
+
+/// Visits instructions, their expressions and the names they define or refer to.
+pub trait Visitor {
+    /// Called for each instruction, by default visits the name it defines and its expressions.
+    fn visit_instruction(&mut self, instruction: &Instruction) {
+        walk_instruction(self, instruction);
+    }
+
+    /// Called for each expression and each of its sub expressions, by default visits the sub expressions.
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+
+    /// Called for each identifier used by an expression or exported.
+    fn visit_ident(&mut self, _ident: &str) {}
+
+    /// Called for the name of each label or constant defined by a [Instruction::Label], [Instruction::Equ]
+    /// or [Instruction::Set].
+    fn visit_name(&mut self, _name: &str) {}
+}
+
+/// Visits the name defined by the instruction, its expressions and the identifiers it exports.
+pub fn walk_instruction<V: Visitor + ?Sized>(visitor: &mut V, instruction: &Instruction) {
+    match instruction {
+        Instruction::Label(name) | Instruction::Equ(name, _) | Instruction::Set(name, _) => {
+            visitor.visit_name(name)
+        }
+        Instruction::Export(idents) => {
+            for ident in idents {
+                visitor.visit_ident(ident);
+            }
+        }
+        _ => {}
+    }
+    for expr in instruction.exprs() {
+        visitor.visit_expr(expr);
+    }
+}
+
+/// Visits the sub expressions or identifier of the expression.
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Ident(ident) => visitor.visit_ident(ident),
+        Expr::Binary(binary) => {
+            visitor.visit_expr(&binary.left);
+            visitor.visit_expr(&binary.right);
+        }
+        Expr::Unary(unary) => visitor.visit_expr(&unary.expr),
+        Expr::Const(_) | Expr::AnonymousLabelRef(_) => {}
+    }
+}
+
+/// Visits and modifies instructions, their expressions and the names they define or refer to.
+pub trait VisitorMut {
+    /// Called for each instruction, by default visits the name it defines and its expressions.
+    fn visit_instruction_mut(&mut self, instruction: &mut Instruction) {
+        walk_instruction_mut(self, instruction);
+    }
+
+    /// Called for each expression and each of its sub expressions, by default visits the sub expressions.
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+
+    /// Called for each identifier used by an expression or exported.
+    fn visit_ident_mut(&mut self, _ident: &mut String) {}
+
+    /// Called for the name of each label or constant defined by a [Instruction::Label], [Instruction::Equ]
+    /// or [Instruction::Set].
+    fn visit_name_mut(&mut self, _name: &mut String) {}
+}
+
+/// Visits the name defined by the instruction, its expressions and the identifiers it exports.
+pub fn walk_instruction_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    instruction: &mut Instruction,
+) {
+    match instruction {
+        Instruction::Label(name) | Instruction::Equ(name, _) | Instruction::Set(name, _) => {
+            visitor.visit_name_mut(name)
+        }
+        Instruction::Export(idents) => {
+            for ident in idents {
+                visitor.visit_ident_mut(ident);
+            }
+        }
+        _ => {}
+    }
+    for expr in instruction.exprs_mut() {
+        visitor.visit_expr_mut(expr);
+    }
+}
+
+/// Visits the sub expressions or identifier of the expression.
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Ident(ident) => visitor.visit_ident_mut(ident),
+        Expr::Binary(binary) => {
+            visitor.visit_expr_mut(&mut binary.left);
+            visitor.visit_expr_mut(&mut binary.right);
+        }
+        Expr::Unary(unary) => visitor.visit_expr_mut(&mut unary.expr),
+        Expr::Const(_) | Expr::AnonymousLabelRef(_) => {}
+    }
+}
+
+/// Calls f with every expression of the instructions, including sub expressions.
+///
+/// Sub expressions are passed to f before the expression containing them,
+/// so f sees the already rewritten sub expressions e.g. to fold constants.
+pub fn walk_exprs(instructions: &mut [Instruction], f: impl FnMut(&mut Expr)) {
+    struct ExprFn<F>(F);
+
+    impl<F: FnMut(&mut Expr)> VisitorMut for ExprFn<F> {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            walk_expr_mut(self, expr);
+            (self.0)(expr);
+        }
+    }
+
+    let mut visitor = ExprFn(f);
+    for instruction in instructions {
+        visitor.visit_instruction_mut(instruction);
+    }
+}
+
+/// Replaces each instruction with the instructions returned by f,
+/// e.g. an empty Vec to remove it or many instructions to expand it.
+pub fn map_instructions<I: IntoIterator<Item = Instruction>>(
+    instructions: Vec<Instruction>,
+    f: impl FnMut(Instruction) -> I,
+) -> Vec<Instruction> {
+    instructions.into_iter().flat_map(f).collect()
+}
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+
+use ggbasm::ast::*;
+
+#[test]
+fn test_constructors() {
+    assert_eq!(
+        Instruction::jp("main").unwrap(),
+        Instruction::JpI16(Flag::Always, Expr::Ident(String::from("main")))
+    );
+    assert_eq!(
+        Instruction::call_flag(Flag::Z, "foo_2").unwrap(),
+        Instruction::Call(Flag::Z, Expr::Ident(String::from("foo_2")))
+    );
+    assert_eq!(
+        Instruction::rst(0x38).unwrap(),
+        Instruction::Rst(Expr::Const(0x38))
+    );
+    assert_eq!(
+        Instruction::ld_r8_i8(Reg8::A, 0xFF).unwrap(),
+        Instruction::LdR8I8(Reg8::A, Expr::Const(0xFF))
+    );
+
+    let charmap: HashMap<char, u8> = vec![('H', 0x10), ('I', 0x11)].into_iter().collect();
+    assert_eq!(
+        Instruction::db_str("HI", &charmap).unwrap(),
+        Instruction::Db(vec![0x10, 0x11])
+    );
+}
+
+#[test]
+fn test_constructors_invalid() {
+    assert_eq!(
+        Instruction::jp("foo bar").unwrap_err().to_string(),
+        "Identifier \"foo bar\" contains invalid character ' '"
+    );
+    assert_eq!(
+        Instruction::label("").unwrap_err().to_string(),
+        "Identifier is empty"
+    );
+    assert!(Instruction::rst(0x39).is_err());
+    assert!(Instruction::ld_r8_i8(Reg8::A, 0x100).is_err());
+    assert!(Instruction::bit(8, Reg8::B).is_err());
+
+    let charmap = HashMap::new();
+    assert_eq!(
+        Instruction::db_str("a", &charmap).unwrap_err().to_string(),
+        "Character 'a' in \"a\" is not in the charmap"
+    );
+}
+
+#[test]
+fn test_cycles() {
+    let cycles = |text: &str| {
+        let instructions: Vec<_> = ggbasm::parser::parse_asm(text)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.unwrap())
+            .collect();
+        let cycles = instructions[0].cycles().unwrap();
+        (cycles.taken, cycles.not_taken)
+    };
+    assert_eq!(cycles("nop"), (1, 1));
+    assert_eq!(cycles("halt"), (2, 2));
+    assert_eq!(cycles("ld a, b"), (1, 1));
+    assert_eq!(cycles("ld [hl], 3"), (3, 3));
+    assert_eq!(cycles("ld a, [hl]"), (2, 2));
+    assert_eq!(cycles("ld [$C000], a"), (4, 4));
+    assert_eq!(cycles("ld [$C000], sp"), (5, 5));
+    assert_eq!(cycles("ldh a, [$FF00 + 4]"), (3, 3));
+    assert_eq!(cycles("add a, 4"), (2, 2));
+    assert_eq!(cycles("add sp, 4"), (4, 4));
+    assert_eq!(cycles("inc [hl]"), (3, 3));
+    assert_eq!(cycles("inc bc"), (2, 2));
+    assert_eq!(cycles("push bc"), (4, 4));
+    assert_eq!(cycles("pop bc"), (3, 3));
+    assert_eq!(cycles("swap a"), (2, 2));
+    assert_eq!(cycles("swap [hl]"), (4, 4));
+    assert_eq!(cycles("bit 3, [hl]"), (3, 3));
+    assert_eq!(cycles("set 3, [hl]"), (4, 4));
+    assert_eq!(cycles("jp 0"), (4, 4));
+    assert_eq!(cycles("jp nz, 0"), (4, 3));
+    assert_eq!(cycles("jp hl"), (1, 1));
+    assert_eq!(cycles("jr c, 0"), (3, 2));
+    assert_eq!(cycles("call z, 0"), (6, 3));
+    assert_eq!(cycles("ret"), (4, 4));
+    assert_eq!(cycles("ret nc"), (5, 2));
+    assert_eq!(cycles("rst $38"), (4, 4));
+    assert!(Instruction::<String>::Db(vec![0]).cycles().is_none());
+
+    let table = ggbasm::opcodes::decode_table();
+    for instruction in table.unprefixed.iter().chain(&table.prefixed).flatten() {
+        let cycles = instruction.cycles().unwrap();
+        let bytes = instruction.encoding().unwrap().bytes_len();
+        assert!(cycles.not_taken as u16 >= bytes, "{:?}", instruction);
+        assert!(cycles.taken >= cycles.not_taken, "{:?}", instruction);
+    }
+}
+
+#[test]
+fn test_encode() {
+    let mut constants = HashMap::new();
+    constants.insert(String::from("Loop"), 0x150);
+    constants.insert(String::from("Banked"), 0x4110);
+    let encode = |instruction: Instruction, address| {
+        instruction
+            .encode(address, &constants)
+            .map(|x| x.to_vec())
+            .map_err(|x| x.to_string())
+    };
+
+    assert_eq!(encode(Instruction::Nop, 0), Ok(vec![0x00]));
+    assert_eq!(
+        encode(Instruction::Label(String::from("Loop")), 0),
+        Ok(vec![])
+    );
+    assert_eq!(
+        encode(Instruction::LdR16I16(Reg16::HL, Expr::Const(0x1234)), 0),
+        Ok(vec![0x21, 0x34, 0x12])
+    );
+    assert_eq!(
+        encode(Instruction::Jr(Flag::Always, Expr::from("Loop")), 0x150),
+        Ok(vec![0x18, 0xFE])
+    );
+    assert_eq!(
+        encode(Instruction::Jr(Flag::NZ, Expr::from("Loop")), 0x140),
+        Ok(vec![0x20, 0x0E])
+    );
+    // labels are addresses as seen by the cpu, so a jr within bank 2 is relative to 0x4000-0x7FFF
+    assert_eq!(
+        encode(Instruction::Jr(Flag::Always, Expr::from("Banked")), 0x8100),
+        Ok(vec![0x18, 0x0E])
+    );
+    assert_eq!(
+        encode(Instruction::Jr(Flag::Always, Expr::from("Loop")), 0x8100),
+        Err(String::from("jr cannot reach 0x150, it is -16306 bytes away but jr can only jump from -128 to 127 bytes, use jp instead"))
+    );
+    assert_eq!(
+        encode(Instruction::AdvanceAddress(Expr::Const(0x153), None), 0x150),
+        Ok(vec![0x00, 0x00, 0x00])
+    );
+    assert_eq!(
+        encode(Instruction::Db(vec![1, 2, 3, 4]), 0),
+        Err(String::from(
+            "`db 1, 2, 3, 4` is 4 bytes, only instructions of up to 3 bytes can be encoded"
+        ))
+    );
+
+    // encode matches the bytes written to a rom
+    let table = ggbasm::opcodes::decode_table();
+    for instruction in table.unprefixed.iter().chain(&table.prefixed).flatten() {
+        let mut rom = vec![];
+        instruction.write_to_rom(&mut rom, &constants).unwrap();
+        let encoded = instruction.encode(0, &constants).unwrap();
+        assert_eq!(rom, encoded.as_slice(), "{:?}", instruction);
+    }
+}
+
+#[cfg(feature = "serde-ast")]
+#[test]
+fn test_serde() {
+    let instructions: Vec<Instruction> = ggbasm::parser::parse_asm(
+        "Main:\n    ld a, [hl]\n    jp nz, Main + 2 * 3\n    db \"HI\", 4\n    push af\n    opt Werror\n",
+    )
+    .unwrap()
+    .into_iter()
+    .map(|x| x.unwrap())
+    .collect();
+    let json = serde_json::to_string(&instructions).unwrap();
+    let deserialized: Vec<Instruction> = serde_json::from_str(&json).unwrap();
+    assert_eq!(instructions, deserialized);
+
+    assert_eq!(
+        serde_json::to_string(&Instruction::<String>::LdR8R8(Reg8::A, Reg8::B)).unwrap(),
+        r#"{"LdR8R8":["A","B"]}"#
+    );
+}
+
+#[test]
+fn test_fluent_constructors() {
+    use ggbasm::i;
+
+    let instructions = vec![
+        i::label("Loop"),
+        i::equ("Speed", 2),
+        i::ld_a(1),
+        i::ld_r8(Reg8::B, "Speed"),
+        i::ld_r16(Reg16::HL, 0xC000),
+        i::ld(Reg8::C, Reg8::A),
+        i::ld_mem_a("Loop"),
+        i::ld_a_mem(0xC000u16),
+        i::add(3),
+        i::xor_r8(Reg8::A),
+        i::cp(Expr::binary(
+            Expr::from("Speed"),
+            BinaryOperator::Mul,
+            Expr::from(2),
+        )),
+        i::dec(Reg8::B),
+        i::inc16(Reg16::DE),
+        i::push(Reg16Push::AF),
+        i::pop(Reg16Push::AF),
+        i::jr_flag(Flag::NZ, "Loop"),
+        i::call("Loop"),
+        i::jp(0x150),
+        i::rst(0x38),
+        i::ret_flag(Flag::C),
+        i::ret(),
+        i::db(&[1, 2]),
+        i::dw("Loop"),
+        i::di(),
+        i::halt(),
+    ];
+    let expected: Vec<Instruction> = ggbasm::parser::parse_asm(
+        "Loop:
+Speed EQU 2
+    ld a, 1
+    ld b, Speed
+    ld hl, 0xC000
+    ld c, a
+    ld [Loop], a
+    ld a, [0xC000]
+    add a, 3
+    xor a, a
+    cp a, Speed * 2
+    dec b
+    inc de
+    push af
+    pop af
+    jr nz, Loop
+    call Loop
+    jp 0x150
+    rst 0x38
+    ret c
+    ret
+    db 1, 2
+    dw Loop
+    di
+    halt",
+    )
+    .unwrap()
+    .into_iter()
+    .map(|x| x.unwrap())
+    .collect();
+    assert_eq!(instructions, expected);
+
+    let mut rom = vec![];
+    let constants = HashMap::new();
+    i::ldh_a(0x44u8).write_to_rom(&mut rom, &constants).unwrap();
+    i::ldh_mem_a(0x40)
+        .write_to_rom(&mut rom, &constants)
+        .unwrap();
+    assert_eq!(rom, vec![0xF0, 0x44, 0xE0, 0x40]);
+}
@@ -0,0 +1,77 @@
+use ggbasm::audio::*;
+
+fn generate(text: &str, options: &AudioOptions) -> Vec<ggbasm::ast::Instruction> {
+    generate_audio_data_with_options(parse_audio_text(text).unwrap(), options).unwrap()
+}
+
+#[test]
+fn test_transpose() {
+    let expected = generate(
+        "label Song\n07                       D4:0:22:F:0N:YY\n07                       f5:0:22:F:0N:YY\ndisable\n",
+        &AudioOptions::default(),
+    );
+    let transposed = generate(
+        "label Song\ntranspose +2\n07                       C4:0:22:F:0N:YY\ntranspose -3\n07                       A5:0:22:F:0N:YY\ndisable\n",
+        &AudioOptions::default(),
+    );
+    assert_eq!(expected, transposed);
+
+    let options = AudioOptions {
+        transpose: 2,
+        ..Default::default()
+    };
+    let global = generate(
+        "label Song\n07                       C4:0:22:F:0N:YY\ntranspose -5\n07                       A5:0:22:F:0N:YY\ndisable\n",
+        &options,
+    );
+    assert_eq!(expected, global);
+}
+
+#[test]
+fn test_transpose_out_of_range() {
+    let lines = parse_audio_text(
+        "label Song\ntranspose +12\n07                       C8:0:22:F:0N:YY\ndisable\n",
+    )
+    .unwrap();
+    assert!(generate_audio_data(lines).is_err());
+    assert!(parse_audio_text("transpose up\n").is_err());
+}
+
+#[test]
+fn test_echo() {
+    let options = AudioOptions {
+        echo: Some(Echo {
+            delay: 1,
+            attenuation: 0xC,
+        }),
+        ..Default::default()
+    };
+    let echoed = generate(
+        "label Song\n07                       D4:0:22:F:0N:YY\n07  C4:0:22:F:0N:YY:Y00\n07                       E4:0:22:F:0N:YY\n07                       F4:0:22:F:0N:YY\ndisable\n",
+        &options,
+    );
+    let expected = generate(
+        "label Song\n07                       D4:0:22:F:0N:YY\n07  C4:0:22:F:0N:YY:Y00\n07                       E4:0:22:F:0N:YY\n07  E4:0:22:3:0N:YY:Y00  F4:0:22:F:0N:YY\ndisable\n",
+        &AudioOptions::default(),
+    );
+    assert_eq!(echoed, expected);
+}
+
+#[test]
+fn test_cycle_budget() {
+    let text = "label Song\n07  C4:0:22:F:0N:YY:Y00  D4:0:22:F:0N:YY\nrest 07\ndisable\n";
+    // channel 1 and 2 are 9 register writes
+    let options = AudioOptions {
+        cycle_budget: Some(step_cycles(9)),
+        ..Default::default()
+    };
+    generate(text, &options);
+
+    let options = AudioOptions {
+        cycle_budget: Some(step_cycles(9) - 1),
+        ..Default::default()
+    };
+    let lines = parse_audio_text(text).unwrap();
+    let err = generate_audio_data_with_options(lines, &options).unwrap_err();
+    assert!(err.to_string().contains("Step 1 after label Song"));
+}
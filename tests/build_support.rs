@@ -0,0 +1,32 @@
+mod common;
+
+use ggbasm::ast::Instruction;
+use ggbasm::build_support::build_rom;
+use ggbasm::RomBuilder;
+
+fn builder() -> RomBuilder {
+    common::builder()
+        .add_instructions(vec![Instruction::Db(vec![0x42])])
+        .unwrap()
+}
+
+#[test]
+fn test_build_rom() {
+    // OUT_DIR is process wide so both cases are in the one test
+    std::env::remove_var("OUT_DIR");
+    assert_eq!(
+        build_rom(builder(), "test.gb").err().unwrap().to_string(),
+        "OUT_DIR is not set, build_rom must be called from a build script"
+    );
+
+    let out_dir = std::env::temp_dir().join("ggbasm_test_build_rom");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    std::env::set_var("OUT_DIR", &out_dir);
+    let built = build_rom(builder(), "test.gb").unwrap();
+    assert_eq!(built.path, out_dir.join("test.gb"));
+    assert_eq!(
+        std::fs::read(&built.path).unwrap(),
+        builder().compile().unwrap()
+    );
+    assert_eq!(built.sha256.len(), 64);
+}
@@ -0,0 +1,56 @@
+//! Fixtures shared by the integration tests, not every test file uses all of them.
+#![allow(dead_code)]
+
+use ggbasm::ast::Instruction;
+use ggbasm::control_flow::{control_flow_graph, ControlFlowGraph};
+use ggbasm::header::*;
+use ggbasm::layout::Layout;
+use ggbasm::RomBuilder;
+
+/// A rom only header without color or super gameboy support.
+pub fn header() -> Header {
+    Header {
+        title: String::from("TEST"),
+        color_support: ColorSupport::Unsupported,
+        licence: String::new(),
+        sgb_support: false,
+        cartridge_type: CartridgeType::RomOnly,
+        ram_type: RamType::None,
+        japanese: false,
+        version_number: 0,
+    }
+}
+
+/// A builder with the basic interrupts and jumps and the [header], instructions added to it start at 0x0150.
+pub fn builder() -> RomBuilder {
+    RomBuilder::new()
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_header(header())
+        .unwrap()
+}
+
+/// Parses the asm, panicking on any error.
+pub fn parse(text: &str) -> Vec<Instruction> {
+    ggbasm::parser::parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect()
+}
+
+/// Compiles the asm with the [builder], returning the rom and its layout.
+pub fn compile(text: &str) -> (Vec<u8>, Layout) {
+    builder()
+        .add_instructions(parse(text))
+        .unwrap()
+        .compile_with_layout()
+        .unwrap()
+}
+
+/// Compiles the asm with the [builder] and returns the control flow graph of the rom.
+pub fn graph(text: &str) -> ControlFlowGraph {
+    let (rom, layout) = compile(text);
+    control_flow_graph(&rom, &layout).unwrap()
+}
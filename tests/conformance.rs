@@ -0,0 +1,13 @@
+#[test]
+fn test_conformance() {
+    ggbasm::conformance::run("tests/conformance").unwrap();
+}
+
+#[test]
+fn test_hexdump_diff() {
+    let diff = ggbasm::conformance::hexdump_diff(&[0x00, 0x01, 0x02], &[0x00, 0x03, 0x02, 0x04]);
+    assert_eq!(
+        diff,
+        "expected 3 bytes but was 4 bytes\n-0x0000: 00 01 02\n+0x0000: 00 03 02 04\n            ^^    ^^\n"
+    );
+}
@@ -0,0 +1,8 @@
+; 0xCB prefixed instructions
+    rlc b
+    rrc [hl]
+    swap a
+    srl e
+    bit 7, h
+    res 0, [hl]
+    set 3, c
@@ -0,0 +1,13 @@
+; constant expressions
+Value EQU 0x10
+Other EQU Value * 2 + 1
+    ld a, Value
+    ld a, Other
+    ld a, (Value + 1) * 2
+    ld a, Other & 0x0F | 0x40
+    ld hl, Table / 0x100
+    ld bc, -1 + 0x10000
+Table:
+    db 0x01, 2, "ab"
+    dw 0x1234
+    ld a, 1 << 7 | 6 >> 1
@@ -0,0 +1,14 @@
+; labels, jumps and calls
+Start:
+    nop
+Loop:
+    jr nz, Loop
+    jp Start
+    call z, Function
+    jp hl
+Function:
+    ret nz
+    reti
+    rst 0x00
+    rst 0x08
+    rst 0x38
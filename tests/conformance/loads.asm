@@ -0,0 +1,16 @@
+; 8 and 16 bit loads
+    ld a, b
+    ld b, 0x12
+    ld hl, 0x1234
+    ld [hl], a
+    ld a, [hl]
+    ldi a, [hl]
+    ldd [hl], a
+    ld [0xC000], a
+    ld a, [0xC000]
+    ld [0xFF00+0x40], a
+    ld a, [0xFF00+c]
+    ld sp, hl
+    ld hl, sp+4
+    push af
+    pop bc
@@ -0,0 +1,103 @@
+mod common;
+
+use common::graph;
+
+#[test]
+fn test_control_flow_graph() {
+    let graph = graph(
+        r#"
+Main:
+    call Init
+Loop:
+    ld a, [hl]
+    cp 1
+    jr nz, Loop
+    jp Main
+Init:
+    push af
+    call Helper
+    pop af
+    ret
+Helper:
+    ret z
+    ld a, 2
+    ret
+Table:
+    db 1, 2
+"#,
+    );
+
+    let blocks: Vec<_> = graph
+        .blocks
+        .iter()
+        .filter(|x| x.address >= 0x100)
+        .map(|x| {
+            (
+                x.address,
+                x.instructions.len(),
+                x.successors.clone(),
+                x.calls.clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        blocks,
+        vec![
+            (0x100, 2, vec![0x150], vec![]),
+            (0x150, 1, vec![0x153], vec![0x15B]),
+            (0x153, 3, vec![0x153, 0x158], vec![]),
+            (0x158, 1, vec![0x150], vec![]),
+            (0x15B, 4, vec![], vec![0x161]),
+            (0x161, 1, vec![0x162], vec![]),
+            (0x162, 2, vec![], vec![]),
+        ]
+    );
+    assert!(graph.block(0x15B).unwrap().returns);
+    assert!(!graph.block(0x153).unwrap().returns);
+    assert!(graph.block(0x40).unwrap().returns);
+
+    let routines: Vec<_> = graph
+        .routines
+        .iter()
+        .map(|x| (x.display_name(), x.blocks.clone(), x.calls.clone()))
+        .collect();
+    assert_eq!(
+        routines,
+        vec![
+            (String::from("0x0040"), vec![0x40], vec![]),
+            (String::from("0x0048"), vec![0x48], vec![]),
+            (String::from("0x0050"), vec![0x50], vec![]),
+            (String::from("0x0058"), vec![0x58], vec![]),
+            (String::from("0x0060"), vec![0x60], vec![]),
+            (
+                String::from("0x0100"),
+                vec![0x100, 0x150, 0x153, 0x158],
+                vec![0x15B]
+            ),
+            (String::from("Init"), vec![0x15B], vec![0x161]),
+            (String::from("Helper"), vec![0x161, 0x162], vec![]),
+        ]
+    );
+    assert_eq!(graph.routine_by_name("Init").unwrap().address, 0x15B);
+    assert!(graph.routine(0x150).is_none());
+
+    let dot = graph.call_graph_dot();
+    assert!(dot.starts_with("digraph calls {\n"));
+    assert!(dot.contains("    \"015B\" [label=\"Init\"];\n"));
+    assert!(dot.contains("    \"0100\" -> \"015B\";\n    \"015B\" -> \"0161\";\n"));
+
+    let dot = graph.cfg_dot();
+    assert!(dot.contains("    \"0161\" [label=\"Helper:\\l    ret z\\l\"];\n"));
+    assert!(dot.contains("    \"0153\" -> \"0153\";\n    \"0153\" -> \"0158\";\n"));
+    assert!(dot.contains("    \"0150\" -> \"015B\" [style=dashed];\n"));
+}
+
+#[test]
+fn test_indirect_jump() {
+    let graph = graph("Main:\n    ld hl, Main\n    jp hl\n    db 0xD3\n");
+    let block = graph.block(0x150).unwrap();
+    assert!(block.indirect_jump);
+    assert!(block.successors.is_empty());
+    // the data after the jump is not decoded
+    assert_eq!(graph.blocks.last().unwrap().address, 0x150);
+}
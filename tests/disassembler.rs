@@ -0,0 +1,72 @@
+mod common;
+
+use std::collections::HashMap;
+
+use ggbasm::ast::*;
+use ggbasm::disassembler::*;
+
+#[test]
+fn test_disassemble_with_symbols() {
+    let symbols: HashMap<i64, String> = [
+        (0x8123, "Sub"),
+        (0x8003, "Loop"),
+        (0xC000, "wValue"),
+        (0xFF40, "rLCDC"),
+        (0x0123, "Bank0"),
+    ]
+    .into_iter()
+    .map(|(value, name)| (value, name.to_string()))
+    .collect();
+    let bytes = [
+        0xCD, 0x23, 0x41, // call $4123
+        0x18, 0xFE, // jr $8003
+        0xFA, 0x00, 0xC0, // ld a, [$C000]
+        0xE0, 0x40, // ldh [$40], a
+        0xCB, 0x7F, // bit 7, a
+        0x21, 0x00, 0x01, // ld hl, $0100
+        0xC3, 0x23, 0x01, // jp $0123
+        0x3E, 0x23, // ld a, $23
+    ];
+    // bank 2
+    let instructions = disassemble_with_symbols(&bytes, 0x8000, &symbols).unwrap();
+    let ident = |name: &str| Expr::Ident(name.to_string());
+    assert_eq!(
+        instructions,
+        vec![
+            Instruction::Call(Flag::Always, ident("Sub")),
+            Instruction::Jr(Flag::Always, ident("Loop")),
+            Instruction::LdRaMI16(ident("wValue")),
+            Instruction::LdhMI8Ra(Expr::binary(
+                ident("rLCDC"),
+                BinaryOperator::Sub,
+                Expr::Const(0xFF00)
+            )),
+            Instruction::BitBitR8(Expr::Const(7), Reg8::A),
+            Instruction::LdR16I16(Reg16::HL, Expr::Const(0x100)),
+            Instruction::JpI16(Flag::Always, ident("Bank0")),
+            Instruction::LdR8I8(Reg8::A, Expr::Const(0x23)),
+        ]
+    );
+
+    // in bank 1 $4123 is a different address
+    let instructions = disassemble_with_symbols(&bytes[..3], 0x4000, &symbols).unwrap();
+    assert_eq!(
+        instructions,
+        vec![Instruction::Call(Flag::Always, Expr::Const(0x4123))]
+    );
+}
+
+#[test]
+fn test_disassemble_compiled_rom() {
+    let text = "Main:\n    call Func\n    jr Main\nFunc:\n    ret\n";
+    let (rom, layout) = common::compile(text);
+
+    let symbols = label_names(&layout);
+    let main = layout.symbols.iter().find(|x| x.name == "Main").unwrap();
+    let start = main.value as usize;
+    let instructions = disassemble_with_symbols(&rom[start..start + 6], start, &symbols).unwrap();
+    assert_eq!(
+        ggbasm::printer::to_asm_string(&instructions),
+        "    call Func\n    jr Main\n    ret\n"
+    );
+}
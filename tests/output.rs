@@ -0,0 +1,80 @@
+mod common;
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::Error;
+use ggbasm::ast::{Expr, Instruction};
+use ggbasm::layout::Layout;
+use ggbasm::output::*;
+use ggbasm::RomBuilder;
+
+fn builder() -> RomBuilder {
+    common::builder()
+        .add_instructions(vec![
+            Instruction::Label(String::from("Main")),
+            Instruction::Equ(String::from("Lives"), Expr::Const(3)),
+            Instruction::Ret(ggbasm::ast::Flag::Always),
+        ])
+        .unwrap()
+        .add_bytes(vec![1, 2], "Tiles")
+        .unwrap()
+}
+
+#[test]
+fn test_sym_and_map_files() {
+    let (_, layout) = builder().compile_with_layout().unwrap();
+    assert_eq!(
+        sym_file(&layout),
+        "; File generated by ggbasm\n00:0150 Main\n00:0151 Tiles\n"
+    );
+
+    let map = map_file(&layout);
+    assert!(map.starts_with("ROM Bank #0:\n  $0000-$0103 ($0104 bytes) "));
+    assert!(map.contains("  $0150-$0150 ($0001 bytes) data generated by rust code\n"));
+    assert!(map.contains("  Free: $3EAD bytes\n\nROM Bank #1:\n"));
+    assert!(map.ends_with("ROM Bank #1:\n  Free: $4000 bytes\n\n"));
+
+    let html = html_file(&layout);
+    assert!(html.contains("<tr><td>Lives</td><td>0x3</td></tr>"));
+}
+
+#[test]
+fn test_sym_file_ram_variables() {
+    let (_, layout) = builder()
+        .declare_ram_variable("PlayerX", 0xC010, ggbasm::VariableType::U8)
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(
+        sym_file(&layout),
+        "; File generated by ggbasm\n00:0150 Main\n00:0151 Tiles\n00:C010 PlayerX\n"
+    );
+}
+
+struct Collect(Rc<RefCell<Vec<(usize, usize)>>>);
+
+impl OutputWriter for Collect {
+    fn write(&self, _dir: &Path, rom: &[u8], layout: &Layout) -> Result<(), Error> {
+        self.0.borrow_mut().push((rom.len(), layout.symbols.len()));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_custom_output() {
+    let collected = Rc::new(RefCell::new(vec![]));
+    builder()
+        .add_output(Collect(collected.clone()))
+        .unwrap()
+        .write_outputs()
+        .unwrap();
+    assert_eq!(*collected.borrow(), vec![(0x8000, 4)]);
+
+    let err = builder().write_outputs().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "No outputs were added to the RomBuilder, use add_output"
+    );
+}
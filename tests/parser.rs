@@ -1,5 +1,11 @@
+use std::borrow::Cow;
+use std::io::{BufReader, Cursor};
+
 use ggbasm::ast::*;
-use ggbasm::parser::parse_asm;
+use ggbasm::parser::{
+    decode_asm, parse_asm, parse_asm_borrowed, parse_asm_iter, parse_asm_lossless,
+    parse_asm_reader, parse_asm_spanned,
+};
 
 #[test]
 fn test_empty() {
@@ -68,6 +74,24 @@ fn test_final_newline_included() {
     );
 }
 
+#[test]
+fn test_crlf() {
+    let result: Vec<Instruction> = parse_asm("nop\r\nlabel:\r\n\r\nstop")
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::Nop,
+            Instruction::Label(String::from("label")),
+            Instruction::EmptyLine,
+            Instruction::Stop,
+        )
+    );
+}
+
 #[test]
 fn test_whacky_line_handling() {
     let text = r#" nop
@@ -623,6 +647,114 @@ fn test_equ() {
     );
 }
 
+#[test]
+fn test_def() {
+    let text = r#"
+DEF Lives EQU 3
+    def Counter = Lives + 1
+DEF Name EQUS "Lives"
+    ld a, Name
+DEFAULT EQU 2
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::Equ(String::from("Lives"), Expr::Const(3)),
+            Instruction::Set(
+                String::from("Counter"),
+                Expr::binary(
+                    Expr::Ident(String::from("Lives")),
+                    BinaryOperator::Add,
+                    Expr::Const(1)
+                )
+            ),
+            Instruction::EmptyLine,
+            Instruction::LdR8I8(Reg8::A, Expr::Ident(String::from("Lives"))),
+            Instruction::Equ(String::from("DEFAULT"), Expr::Const(2)),
+        )
+    );
+}
+
+#[test]
+fn test_fixed_point_literals() {
+    let text = r#"
+Gravity EQU 12.5q8
+Third EQU 0.333Q8
+One EQU 1.0q16
+    ld hl, 2.25q8 + 1
+    ld a, 1.5q40
+"#;
+    let result: Vec<_> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::Equ(
+                String::from("Gravity"),
+                Expr::Const(0x0C80)
+            )),
+            Some(Instruction::Equ(String::from("Third"), Expr::Const(0x55))),
+            Some(Instruction::Equ(String::from("One"), Expr::Const(0x10000))),
+            Some(Instruction::LdR16I16(
+                Reg16::HL,
+                Expr::binary(Expr::Const(0x240), BinaryOperator::Add, Expr::Const(1))
+            )),
+            None,
+        )
+    );
+}
+
+#[test]
+fn test_set() {
+    let text = r#"
+    counter SET 0
+    counter = counter + 1
+    counter=2
+    REDEF offset EQU 4
+    redef offset = offset * 2
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::Set(String::from("counter"), Expr::Const(0)),
+            Instruction::Set(
+                String::from("counter"),
+                Expr::binary(
+                    Expr::Ident(String::from("counter")),
+                    BinaryOperator::Add,
+                    Expr::Const(1)
+                )
+            ),
+            Instruction::Set(String::from("counter"), Expr::Const(2)),
+            Instruction::Set(String::from("offset"), Expr::Const(4)),
+            Instruction::Set(
+                String::from("offset"),
+                Expr::binary(
+                    Expr::Ident(String::from("offset")),
+                    BinaryOperator::Mul,
+                    Expr::Const(2)
+                )
+            ),
+        )
+    );
+}
+
 #[test]
 fn test_db() {
     let text = r#"
@@ -664,12 +796,263 @@ fn test_db() {
             Instruction::Db(vec!(0, 1)),
             Instruction::Db(vec!(0, 0, 1, 2, 3, 4)),
             Instruction::Db(vec!(0, 1, 2, 3, 5, 4)),
-            Instruction::Db(vec!(0x61)),
-            Instruction::Db(vec!(
-                0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x57, 0x6f, 0x72, 0x6c, 0x64, 0x21
+            Instruction::DbString(vec!(DbItem::String(String::from("a")))),
+            Instruction::DbString(vec!(DbItem::String(String::from("Hello World!")))),
+            Instruction::DbString(vec!(
+                DbItem::String(String::from("hi")),
+                DbItem::Expr(Expr::Const(0x13)),
+                DbItem::Expr(Expr::Const(37)),
+            )),
+            Instruction::DbString(vec!(
+                DbItem::Expr(Expr::Const(4)),
+                DbItem::Expr(Expr::Const(13)),
+                DbItem::String(String::from("hammers")),
+            )),
+        )
+    );
+}
+
+#[test]
+fn test_dbfill() {
+    let text = "    dbfill 64, 0xFF\n    DBFILL Size * 2, Blank ; comment\n    dbfill 1\n";
+    let result: Vec<_> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::DbFill(Expr::Const(64), Expr::Const(0xFF))),
+            Some(Instruction::DbFill(
+                Expr::binary(
+                    Expr::Ident(String::from("Size")),
+                    BinaryOperator::Mul,
+                    Expr::Const(2)
+                ),
+                Expr::Ident(String::from("Blank"))
+            )),
+            None,
+        )
+    );
+}
+
+#[test]
+fn test_db_expressions() {
+    let text = r#"
+    db MY_CONST + 3, SomeLabel & 0xFF
+    db "hi", foo
+    db -1
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::DbExprList8(vec!(
+                Expr::binary(
+                    Expr::Ident(String::from("MY_CONST")),
+                    BinaryOperator::Add,
+                    Expr::Const(3)
+                ),
+                Expr::binary(
+                    Expr::Ident(String::from("SomeLabel")),
+                    BinaryOperator::And,
+                    Expr::Const(0xFF)
+                ),
+            )),
+            Instruction::DbString(vec!(
+                DbItem::String(String::from("hi")),
+                DbItem::Expr(Expr::Ident(String::from("foo"))),
+            )),
+            Instruction::DbExpr8(Expr::unary(Expr::Const(1), UnaryOperator::Minus)),
+        )
+    );
+}
+
+#[test]
+fn test_out_of_range() {
+    let text = r#"
+    db 256
+    ld a, $1FF ; comment
+    ld hl, 65536
+    ld hl, 65535
+    ld a, -128
+    bit 8, a
+    dw -1, 70000
+    dbfill 300, 300
+    ld a, 99999999999999999999
+    ld a, 1.5q100
+    ld a, 0x10000000000000000
+    ld a, 0xFFFFFFFFFFFFFFFF
+"#;
+    let result = parse_asm(text).unwrap();
+    let error = |i: usize| {
+        let diagnostic = result[i].clone().unwrap_err();
+        (
+            diagnostic.line,
+            diagnostic.text,
+            diagnostic.message.unwrap(),
+        )
+    };
+    assert_eq!(
+        error(1),
+        (
+            2,
+            String::from("256"),
+            String::from(
+                "Value 256 is out of range for an 8 bit operand, which must be from -128 to 255"
+            )
+        )
+    );
+    assert_eq!(
+        error(2),
+        (
+            3,
+            String::from("$1FF"),
+            String::from(
+                "Value 511 is out of range for an 8 bit operand, which must be from -128 to 255"
+            )
+        )
+    );
+    assert_eq!(
+        error(3).2,
+        "Value 65536 is out of range for a 16 bit operand, which must be from -32768 to 65535"
+    );
+    assert_eq!(
+        result[4],
+        Ok(Instruction::LdR16I16(Reg16::HL, Expr::Const(65535)))
+    );
+    assert!(result[5].is_ok());
+    assert_eq!(
+        error(6),
+        (
+            7,
+            String::from("8"),
+            String::from("Value 8 is out of range for a bit index, which must be from 0 to 7")
+        )
+    );
+    assert_eq!(error(7).1, "70000");
+    assert_eq!(error(8).1, "300");
+    assert_eq!(
+        error(9),
+        (
+            10,
+            String::from("99999999999999999999"),
+            String::from("Value 99999999999999999999 is out of range, numbers must fit in 64 bits")
+        )
+    );
+    assert_eq!(result[10].clone().unwrap_err().message, None);
+    assert_eq!(error(11).1, "0x10000000000000000");
+    assert_eq!(
+        result[12],
+        Ok(Instruction::LdR8I8(Reg8::A, Expr::Const(-1)))
+    );
+
+    let diagnostic = result[2].clone().unwrap_err();
+    assert_eq!(
+        diagnostic.to_string(),
+        "Value 511 is out of range for an 8 bit operand, which must be from -128 to 255\n    ld a, $1FF ; comment\n          ^^^^"
+    );
+}
+
+#[test]
+fn test_signed_operands() {
+    let text = r#"
+    add sp, -2
+    ld hl, sp - 4 + 1
+    ld hl, sp+-128
+    add sp, 128
+    ld hl, sp - 129
+    add sp, 0xFE
+"#;
+    let result = parse_asm(text).unwrap();
+    assert_eq!(
+        result[1],
+        Ok(Instruction::AddRspI8(Expr::unary(
+            Expr::Const(2),
+            UnaryOperator::Minus
+        )))
+    );
+    assert_eq!(
+        result[2],
+        Ok(Instruction::LdRhlRspI8(Expr::binary(
+            Expr::unary(Expr::Const(4), UnaryOperator::Minus),
+            BinaryOperator::Add,
+            Expr::Const(1)
+        )))
+    );
+    assert!(result[3].is_ok());
+    let message = |i: usize| result[i].clone().unwrap_err().message.unwrap();
+    assert_eq!(
+        message(4),
+        "Value 128 is out of range for a signed 8 bit operand, which must be from -128 to 127"
+    );
+    assert_eq!(
+        message(5),
+        "Value -129 is out of range for a signed 8 bit operand, which must be from -128 to 127"
+    );
+    assert_eq!(result[5].clone().unwrap_err().text, "129");
+    assert!(result[6].is_err());
+}
+
+#[test]
+fn test_dw_expressions() {
+    let text = r#"
+    dw JumpTable0, JumpTable1, BASE+0x40
+    dw Foo
+    dw 0x1234, 0xABCD
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::DbExprList16(vec!(
+                Expr::Ident(String::from("JumpTable0")),
+                Expr::Ident(String::from("JumpTable1")),
+                Expr::binary(
+                    Expr::Ident(String::from("BASE")),
+                    BinaryOperator::Add,
+                    Expr::Const(0x40)
+                ),
+            )),
+            Instruction::DbExpr16(Expr::Ident(String::from("Foo"))),
+            Instruction::Db(vec!(0x34, 0x12, 0xCD, 0xAB)),
+        )
+    );
+}
+
+#[test]
+fn test_charmap() {
+    let text = r#"
+    charmap "A", 0x80
+    CHARMAP " ", BLANK_TILE ; comment
+    charmap "AB", 1
+"#;
+    let result: Vec<Option<Instruction>> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::Charmap('A', Expr::Const(0x80))),
+            Some(Instruction::Charmap(
+                ' ',
+                Expr::Ident(String::from("BLANK_TILE"))
             )),
-            Instruction::Db(vec!(0x68, 0x69, 0x13, 37)),
-            Instruction::Db(vec!(4, 13, 0x68, 0x61, 0x6d, 0x6d, 0x65, 0x72, 0x73)),
+            None,
         )
     );
 }
@@ -711,6 +1094,89 @@ fn test_dw() {
     );
 }
 
+#[test]
+fn test_debug_assert() {
+    let text = r#"
+    debug_assert z
+    DEBUG_ASSERT nc ; comment
+    debug_assert a == 3
+    debug_assert A>=Lives ; comment
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::DebugAssert(Flag::Z),
+            Instruction::DebugAssert(Flag::NC),
+            Instruction::DebugAssertCp(Comparison::Equal, Expr::Const(3)),
+            Instruction::DebugAssertCp(
+                Comparison::GreaterOrEqual,
+                Expr::Ident(String::from("Lives"))
+            ),
+        )
+    );
+}
+
+#[test]
+fn test_messages() {
+    let text = r#"
+    print "Size: ", End - Start
+    WARN "Nearly full"
+    fail "Too big by ", 2, " bytes"
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::Print(vec!(
+                DbItem::String(String::from("Size: ")),
+                DbItem::Expr(Expr::binary(
+                    Expr::Ident(String::from("End")),
+                    BinaryOperator::Sub,
+                    Expr::Ident(String::from("Start"))
+                )),
+            )),
+            Instruction::Warn(vec!(DbItem::String(String::from("Nearly full")))),
+            Instruction::Fail(vec!(
+                DbItem::String(String::from("Too big by ")),
+                DbItem::Expr(Expr::Const(2)),
+                DbItem::String(String::from(" bytes")),
+            )),
+        )
+    );
+}
+
+#[test]
+fn test_debug_print() {
+    let text = r#"
+    debug_print "Hello World"
+    debug_break
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::DebugPrint(String::from("Hello World")),
+            Instruction::DebugBreak,
+        )
+    );
+}
+
 #[test]
 fn test_advance_address() {
     let text = r#"
@@ -719,6 +1185,8 @@ fn test_advance_address() {
     advance_address 413
     advance_address 0x1337
     advance_address 0xFFFF
+    advance_address Start + 2
+    advance_address 2, 0x4000
 "#;
     let result: Vec<Instruction> = parse_asm(text)
         .unwrap()
@@ -729,11 +1197,20 @@ fn test_advance_address() {
         result,
         vec!(
             Instruction::EmptyLine,
-            Instruction::AdvanceAddress(0),
-            Instruction::AdvanceAddress(0x0),
-            Instruction::AdvanceAddress(413),
-            Instruction::AdvanceAddress(0x1337),
-            Instruction::AdvanceAddress(0xFFFF),
+            Instruction::AdvanceAddress(Expr::Const(0), None),
+            Instruction::AdvanceAddress(Expr::Const(0x0), None),
+            Instruction::AdvanceAddress(Expr::Const(413), None),
+            Instruction::AdvanceAddress(Expr::Const(0x1337), None),
+            Instruction::AdvanceAddress(Expr::Const(0xFFFF), None),
+            Instruction::AdvanceAddress(
+                Expr::binary(
+                    Expr::Ident(String::from("Start")),
+                    BinaryOperator::Add,
+                    Expr::Const(2)
+                ),
+                None
+            ),
+            Instruction::AdvanceAddress(Expr::Const(0x4000), Some(Expr::Const(2))),
         )
     );
 }
@@ -753,7 +1230,11 @@ fn test_invalid_instruction() {
 a b c d
 "#;
     assert_eq!(
-        parse_asm(text).unwrap(),
+        parse_asm(text)
+            .unwrap()
+            .into_iter()
+            .map(Result::ok)
+            .collect::<Vec<_>>(),
         vec!(
             Some(Instruction::EmptyLine),
             Some(Instruction::Nop),
@@ -1998,3 +2479,1223 @@ fn test_set_bit_r8() {
         )
     );
 }
+
+#[test]
+fn test_macro() {
+    let text = r#"
+LoadByte: MACRO
+    ld a, \2
+    ld [\1], a ; comment
+ENDM
+    LoadByte 0xC000, (1 + 2)
+    nop
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::EmptyLine,
+            Instruction::EmptyLine,
+            Instruction::EmptyLine,
+            Instruction::EmptyLine,
+            Instruction::LdR8I8(
+                Reg8::A,
+                Expr::binary(Expr::Const(1), BinaryOperator::Add, Expr::Const(2))
+            ),
+            Instruction::LdMI16Ra(Expr::Const(0xC000)),
+            Instruction::Nop,
+        )
+    );
+}
+
+#[test]
+fn test_macro_nested_unique_labels() {
+    let text = r#"
+Wait: MACRO
+loop\@:
+    dec \1
+    jr nz, loop\@
+ENDM
+WaitTwice: MACRO
+    Wait b
+    Wait c
+ENDM
+    WaitTwice
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .skip(10)
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::Label(String::from("loop_2")),
+            Instruction::DecR8(Reg8::B),
+            Instruction::Jr(Flag::NZ, Expr::Ident(String::from("loop_2"))),
+            Instruction::Label(String::from("loop_3")),
+            Instruction::DecR8(Reg8::C),
+            Instruction::Jr(Flag::NZ, Expr::Ident(String::from("loop_3"))),
+        )
+    );
+}
+
+#[test]
+fn test_macro_missing_endm() {
+    assert!(parse_asm("Foo: MACRO\n    nop\n").is_err());
+}
+
+#[test]
+fn test_macro_missing_argument() {
+    assert!(parse_asm("Foo: MACRO\n    ld a, \\2\nENDM\n    Foo 1\n").is_err());
+}
+
+#[test]
+fn test_string_constants() {
+    let text = r#"
+Coord EQUS "PlayerX"
+Target EQUS "[0xC000 + Coord]"
+PlayerX EQU 2
+    ld a, Target ; Target
+    db "Target"
+Inc: MACRO
+    inc \1
+ENDM
+Reg EQUS "b"
+Op EQUS "Inc Reg"
+    Op
+REDEF Reg EQUS "c"
+    Op
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::EmptyLine,
+            Instruction::EmptyLine,
+            Instruction::Equ(String::from("PlayerX"), Expr::Const(2)),
+            Instruction::LdRaMI16(Expr::binary(
+                Expr::Const(0xC000),
+                BinaryOperator::Add,
+                Expr::Ident(String::from("PlayerX"))
+            )),
+            Instruction::DbString(vec![DbItem::String(String::from("Target"))]),
+            Instruction::EmptyLine,
+            Instruction::EmptyLine,
+            Instruction::EmptyLine,
+            Instruction::EmptyLine,
+            Instruction::EmptyLine,
+            Instruction::IncR8(Reg8::B),
+            Instruction::EmptyLine,
+            Instruction::IncR8(Reg8::C),
+        )
+    );
+}
+
+#[test]
+fn test_string_constants_errors() {
+    assert!(parse_asm("A EQUS \"1\"\nA EQUS \"2\"\n").is_err());
+    assert!(parse_asm("A EQUS \"B\"\nB EQUS \"A\"\n    db A\n").is_err());
+}
+
+#[test]
+fn test_conditionals() {
+    let text = r#"
+IF DEBUG
+    nop
+elif FOO + 1 ; comment
+ELSE
+ENDC
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::If(Expr::Ident(String::from("DEBUG"))),
+            Instruction::Nop,
+            Instruction::Elif(Expr::binary(
+                Expr::Ident(String::from("FOO")),
+                BinaryOperator::Add,
+                Expr::Const(1)
+            )),
+            Instruction::Else,
+            Instruction::Endc,
+        )
+    );
+}
+
+#[test]
+fn test_include() {
+    let text = r#"
+    include "constants.asm"
+    INCLUDE "routines/math.asm" ; comment
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::Include(String::from("constants.asm")),
+            Instruction::Include(String::from("routines/math.asm")),
+        )
+    );
+}
+
+#[test]
+fn test_section() {
+    let text = r#"
+    SECTION "Main", ROM0
+    section "Levels", ROMX, BANK[2] ; comment
+    SECTION "Music", romx[0x4100]
+    SECTION "Tiles", ROMX[ 0x4000 + 0x20 ], BANK[ LevelBank ]
+    SECTION Main, ROM0
+    SECTION "Main", WRAM0
+"#;
+    let result: Vec<Option<Instruction>> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::Section(
+                String::from("Main"),
+                SectionType::Rom0,
+                None,
+                None
+            )),
+            Some(Instruction::Section(
+                String::from("Levels"),
+                SectionType::Romx,
+                None,
+                Some(Expr::Const(2))
+            )),
+            Some(Instruction::Section(
+                String::from("Music"),
+                SectionType::Romx,
+                Some(Expr::Const(0x4100)),
+                None
+            )),
+            Some(Instruction::Section(
+                String::from("Tiles"),
+                SectionType::Romx,
+                Some(Expr::binary(
+                    Expr::Const(0x4000),
+                    BinaryOperator::Add,
+                    Expr::Const(0x20)
+                )),
+                Some(Expr::Ident(String::from("LevelBank")))
+            )),
+            None,
+            None,
+        )
+    );
+}
+
+#[test]
+fn test_export() {
+    let text = r#"
+    export Main
+    GLOBAL Init, Step ; comment
+    export
+"#;
+    let result: Vec<Option<Instruction>> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::Export(vec![String::from("Main")])),
+            Some(Instruction::Export(vec![
+                String::from("Init"),
+                String::from("Step")
+            ])),
+            None,
+        )
+    );
+}
+
+#[test]
+fn test_namespace() {
+    let text = r#"
+    namespace Audio
+Audio.Init:
+Sfx.Volume EQU 3
+    call Audio.Sfx.Play
+    export Audio.Init, Main
+    ENDNAMESPACE
+    ld a, 1.5
+    namespace
+"#;
+    let result: Vec<Option<Instruction>> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::Namespace(String::from("Audio"))),
+            Some(Instruction::Label(String::from("Audio.Init"))),
+            Some(Instruction::Equ(String::from("Sfx.Volume"), Expr::Const(3))),
+            Some(Instruction::Call(
+                Flag::Always,
+                Expr::Ident(String::from("Audio.Sfx.Play"))
+            )),
+            Some(Instruction::Export(vec![
+                String::from("Audio.Init"),
+                String::from("Main")
+            ])),
+            Some(Instruction::EndNamespace),
+            None,
+            None,
+        )
+    );
+}
+
+#[test]
+fn test_state_stack() {
+    let text = r#"
+    pusho
+    opt radix=16
+    ld a, 10
+    POPO
+    ld a, 10
+    pushs
+    pops
+    pragma once
+"#;
+    let result: Vec<_> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::PushO),
+            Some(Instruction::Opt(vec![AsmOption::Radix(16)])),
+            Some(Instruction::LdR8I8(Reg8::A, Expr::Const(16))),
+            Some(Instruction::PopO),
+            Some(Instruction::LdR8I8(Reg8::A, Expr::Const(10))),
+            Some(Instruction::PushS),
+            Some(Instruction::PopS),
+            Some(Instruction::PragmaOnce),
+        )
+    );
+
+    let err = parse_asm("    popo\n").unwrap_err();
+    assert_eq!(err.to_string(), "POPO has no matching PUSHO");
+
+    // the borrowed parser restores the radix the same way
+    let borrowed: Vec<_> = parse_asm_borrowed(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.map(Instruction::into_owned).ok())
+        .collect();
+    assert_eq!(borrowed, result);
+    let err = parse_asm_borrowed("    popo\n").unwrap_err();
+    assert_eq!(err.to_string(), "POPO has no matching PUSHO");
+}
+
+#[test]
+fn test_opt() {
+    let text = r#"
+    opt Werror, h, radix=16
+    ld a, 10
+    ld a, $10 + %10 + 0x10 + 0b10
+    db "10", 1.5q4 ; 10
+    OPT radix=10, Wno-error, !h
+    ld a, 10
+    opt radix=17
+    opt foo
+"#;
+    let result: Vec<_> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    let sum = Expr::binary(
+        Expr::binary(
+            Expr::binary(Expr::Const(16), BinaryOperator::Add, Expr::Const(2)),
+            BinaryOperator::Add,
+            Expr::Const(16),
+        ),
+        BinaryOperator::Add,
+        Expr::Const(2),
+    );
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::Opt(vec![
+                AsmOption::WarningsAsErrors(true),
+                AsmOption::HaltPadding(false),
+                AsmOption::Radix(16)
+            ])),
+            Some(Instruction::LdR8I8(Reg8::A, Expr::Const(16))),
+            Some(Instruction::LdR8I8(Reg8::A, sum)),
+            Some(Instruction::DbString(vec![
+                DbItem::String(String::from("10")),
+                DbItem::Expr(Expr::Const(0x18))
+            ])),
+            Some(Instruction::Opt(vec![
+                AsmOption::Radix(10),
+                AsmOption::WarningsAsErrors(false),
+                AsmOption::HaltPadding(true)
+            ])),
+            Some(Instruction::LdR8I8(Reg8::A, Expr::Const(10))),
+            None,
+            None,
+        )
+    );
+}
+
+#[test]
+fn test_union() {
+    let text = "    UNION\nwTitleCursor:\n    nextu ; comment\nwLevelTimer:\n    endu\n";
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::Union,
+            Instruction::Label(String::from("wTitleCursor")),
+            Instruction::NextU,
+            Instruction::Label(String::from("wLevelTimer")),
+            Instruction::EndU,
+        )
+    );
+}
+
+#[test]
+fn test_incbin() {
+    let text = r#"
+    incbin "data/level1.bin"
+    incbin "data/level1.bin", 0x100
+    INCBIN "data/level1.bin", 0x100, 32 ; comment
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::Incbin(String::from("data/level1.bin"), 0, None),
+            Instruction::Incbin(String::from("data/level1.bin"), 0x100, None),
+            Instruction::Incbin(String::from("data/level1.bin"), 0x100, Some(32)),
+        )
+    );
+}
+
+#[test]
+fn test_anonymous_labels() {
+    let text = r#"
+:
+    jr :+
+    jr nz, :--
+    jp :+ + 1
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::AnonymousLabel,
+            Instruction::Jr(Flag::Always, Expr::AnonymousLabelRef(1)),
+            Instruction::Jr(Flag::NZ, Expr::AnonymousLabelRef(-2)),
+            Instruction::JpI16(
+                Flag::Always,
+                Expr::binary(
+                    Expr::AnonymousLabelRef(1),
+                    BinaryOperator::Add,
+                    Expr::Const(1)
+                )
+            ),
+        )
+    );
+}
+
+#[test]
+fn test_rst() {
+    let text = r#"
+    rst 0x38
+    RST Vector
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::Rst(Expr::Const(0x38)),
+            Instruction::Rst(Expr::Ident(String::from("Vector"))),
+        )
+    );
+}
+
+#[test]
+fn test_exprs_shift() {
+    let text = r#"
+    jp foo << 2 + 1 | bar >> baz
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::JpI16(
+                Flag::Always,
+                Expr::binary(
+                    Expr::binary(
+                        Expr::Ident(String::from("foo")),
+                        BinaryOperator::Shl,
+                        Expr::binary(Expr::Const(2), BinaryOperator::Add, Expr::Const(1))
+                    ),
+                    BinaryOperator::Or,
+                    Expr::binary(
+                        Expr::Ident(String::from("bar")),
+                        BinaryOperator::Shr,
+                        Expr::Ident(String::from("baz"))
+                    )
+                )
+            ),
+        )
+    );
+}
+
+#[test]
+fn test_exprs_logical() {
+    let text = r#"
+    assert foo == 1 || bar < 2 && baz != 3
+    ASSERT TableEnd - Table <= 0x100, "Table is too big"
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::Assert(
+                Expr::binary(
+                    Expr::binary(
+                        Expr::Ident(String::from("foo")),
+                        BinaryOperator::Equal,
+                        Expr::Const(1)
+                    ),
+                    BinaryOperator::LogicalOr,
+                    Expr::binary(
+                        Expr::binary(
+                            Expr::Ident(String::from("bar")),
+                            BinaryOperator::Less,
+                            Expr::Const(2)
+                        ),
+                        BinaryOperator::LogicalAnd,
+                        Expr::binary(
+                            Expr::Ident(String::from("baz")),
+                            BinaryOperator::NotEqual,
+                            Expr::Const(3)
+                        )
+                    )
+                ),
+                None
+            ),
+            Instruction::Assert(
+                Expr::binary(
+                    Expr::binary(
+                        Expr::Ident(String::from("TableEnd")),
+                        BinaryOperator::Sub,
+                        Expr::Ident(String::from("Table"))
+                    ),
+                    BinaryOperator::LessEqual,
+                    Expr::Const(0x100)
+                ),
+                Some(String::from("Table is too big"))
+            ),
+        )
+    );
+}
+
+#[test]
+fn test_exprs_left_associative() {
+    let text = r#"
+    ld a, 10 - 3 - 2
+    ld a, 8 / 4 * 2 & 3 && 1
+"#;
+    let result: Vec<Instruction> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Instruction::EmptyLine,
+            Instruction::LdR8I8(
+                Reg8::A,
+                Expr::binary(
+                    Expr::binary(Expr::Const(10), BinaryOperator::Sub, Expr::Const(3)),
+                    BinaryOperator::Sub,
+                    Expr::Const(2)
+                )
+            ),
+            Instruction::LdR8I8(
+                Reg8::A,
+                Expr::binary(
+                    Expr::binary(
+                        Expr::binary(
+                            Expr::binary(Expr::Const(8), BinaryOperator::Div, Expr::Const(4)),
+                            BinaryOperator::Mul,
+                            Expr::Const(2)
+                        ),
+                        BinaryOperator::And,
+                        Expr::Const(3)
+                    ),
+                    BinaryOperator::LogicalAnd,
+                    Expr::Const(1)
+                )
+            ),
+        )
+    );
+    assert_eq!(
+        result[1].clone().exprs_mut()[0]
+            .run(&Default::default())
+            .unwrap(),
+        5
+    );
+
+    // long expressions are parsed without backtracking
+    let terms: Vec<_> = (0..200).map(|x| x.to_string()).collect();
+    let text = format!("    dw {}\n", terms.join(" - "));
+    let result = parse_asm(&text).unwrap();
+    let mut instruction = result[0].clone().unwrap();
+    let value = instruction.exprs_mut()[0].run(&Default::default()).unwrap();
+    assert_eq!(value, -(1..200).sum::<i64>());
+}
+
+#[test]
+fn test_identifiers_starting_with_keywords() {
+    let text = r#"
+add_score EQU 5
+callback:
+nop_count = 1
+section_end:
+    ld a, add_score
+    call callback
+    jp hl_target
+    ld hl, sp_base
+    ld a, [c_offset]
+    jr nc_label
+    db ret_value
+    nopx
+"#;
+    let ident = |x: &str| Expr::Ident(String::from(x));
+    let result: Vec<Option<Instruction>> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::Equ(String::from("add_score"), Expr::Const(5))),
+            Some(Instruction::Label(String::from("callback"))),
+            Some(Instruction::Set(String::from("nop_count"), Expr::Const(1))),
+            Some(Instruction::Label(String::from("section_end"))),
+            Some(Instruction::LdR8I8(Reg8::A, ident("add_score"))),
+            Some(Instruction::Call(Flag::Always, ident("callback"))),
+            Some(Instruction::JpI16(Flag::Always, ident("hl_target"))),
+            Some(Instruction::LdR16I16(Reg16::HL, ident("sp_base"))),
+            Some(Instruction::LdRaMI16(ident("c_offset"))),
+            Some(Instruction::Jr(Flag::Always, ident("nc_label"))),
+            Some(Instruction::DbExpr8(ident("ret_value"))),
+            None,
+        )
+    );
+}
+
+#[test]
+fn test_binary_literals() {
+    let text = r#"
+    ld a, 0b10010001
+    ld a, %10000011
+    ld a, 10 %0b11
+    ld a, 0b
+"#;
+    let result: Vec<Option<Instruction>> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::LdR8I8(Reg8::A, Expr::Const(0x91))),
+            Some(Instruction::LdR8I8(Reg8::A, Expr::Const(0x83))),
+            Some(Instruction::LdR8I8(
+                Reg8::A,
+                Expr::binary(Expr::Const(10), BinaryOperator::Rem, Expr::Const(3))
+            )),
+            None,
+        )
+    );
+}
+
+#[test]
+fn test_rgbds_literals() {
+    let text = r#"
+    ld a, $2a
+    ld hl, $C0DE + %1010
+    ld [$FF00 + c], a
+    ld a, [$ff00+$44]
+    SECTION "Main", ROMX[$4100], BANK[$2]
+    ld a, $
+"#;
+    let result: Vec<Option<Instruction>> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::LdR8I8(Reg8::A, Expr::Const(0x2a))),
+            Some(Instruction::LdR16I16(
+                Reg16::HL,
+                Expr::binary(Expr::Const(0xC0DE), BinaryOperator::Add, Expr::Const(10))
+            )),
+            Some(Instruction::LdhMRcRa),
+            Some(Instruction::LdhRaMI8(Expr::Const(0x44))),
+            Some(Instruction::Section(
+                String::from("Main"),
+                SectionType::Romx,
+                Some(Expr::Const(0x4100)),
+                Some(Expr::Const(2))
+            )),
+            None,
+        )
+    );
+}
+
+#[test]
+fn test_rgbds_loads() {
+    let text = r#"
+    ld [hl+], a
+    ld [ hl - ], a
+    LD A, [HL+]
+    ld a, [hl-]
+    ldh [c], a
+    ldh a, [ $FF00 + c ]
+    ld [c], a
+    ldh [$FF40], a
+    ldh a, [$44]
+    ldi [hl-], a
+"#;
+    let result: Vec<Option<Instruction>> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    let high = |address| {
+        let in_high_ram = Expr::binary(
+            Expr::Const(address),
+            BinaryOperator::GreaterEqual,
+            Expr::Const(0xFF00),
+        );
+        Expr::binary(
+            Expr::Const(address),
+            BinaryOperator::Sub,
+            Expr::binary(Expr::Const(0xFF00), BinaryOperator::Mul, in_high_ram),
+        )
+    };
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::LdiMRhlRa),
+            Some(Instruction::LddMRhlRa),
+            Some(Instruction::LdiRaMRhl),
+            Some(Instruction::LddRaMRhl),
+            Some(Instruction::LdhMRcRa),
+            Some(Instruction::LdhRaMRc),
+            Some(Instruction::LdhMRcRa),
+            Some(Instruction::LdhMI8Ra(high(0xFF40))),
+            Some(Instruction::LdhRaMI8(high(0x44))),
+            None,
+        )
+    );
+}
+
+#[test]
+fn test_diagnostics() {
+    let text = "    nop\n    ld a, b, c ; comment\n\tfoobar 1, 2\n    ret\nCount EQU\n";
+    let result = parse_asm(text).unwrap();
+    assert_eq!(result[0], Ok(Instruction::Nop));
+
+    let diagnostic = result[1].clone().unwrap_err();
+    assert_eq!(diagnostic.line, 2);
+    assert_eq!(diagnostic.columns, 7..14);
+    assert_eq!(diagnostic.text, "a, b, c");
+    assert_eq!(diagnostic.expected.len(), 21);
+    assert_eq!(diagnostic.expected[0], "ld r8, r8");
+    assert!(diagnostic
+        .to_string()
+        .ends_with("found `a, b, c`\n    ld a, b, c ; comment\n       ^^^^^^^"));
+
+    let diagnostic = result[2].clone().unwrap_err();
+    assert_eq!(diagnostic.line, 3);
+    assert_eq!(diagnostic.text, "foobar");
+    assert_eq!(
+        diagnostic.to_string(),
+        "expected one of `an instruction, directive, label or constant definition`, found `foobar`\n\tfoobar 1, 2\n\t^^^^^^"
+    );
+
+    assert_eq!(result[3], Ok(Instruction::Ret(Flag::Always)));
+
+    let diagnostic = result[4].clone().unwrap_err();
+    assert_eq!(diagnostic.text, "EQU");
+    assert_eq!(diagnostic.expected, vec![String::from("NAME EQU n16")]);
+}
+
+#[test]
+fn test_line_continuation() {
+    let text = r#"
+    db 1, 2, \
+       3, 4 \ ; comment
+       , 5
+Add: MACRO
+    ld a, \1
+    add \
+        \2
+ENDM
+    Add 1, \
+        b
+    foo \
+    bar
+"#;
+    let result = parse_asm(text).unwrap();
+    assert_eq!(
+        result[..10],
+        [
+            Ok(Instruction::EmptyLine),
+            Ok(Instruction::Db(vec![1, 2, 3, 4, 5])),
+            Ok(Instruction::EmptyLine),
+            Ok(Instruction::EmptyLine),
+            Ok(Instruction::EmptyLine),
+            Ok(Instruction::EmptyLine),
+            Ok(Instruction::EmptyLine),
+            Ok(Instruction::EmptyLine),
+            Ok(Instruction::EmptyLine),
+            Ok(Instruction::LdR8I8(Reg8::A, Expr::Const(1))),
+        ]
+    );
+    assert_eq!(result[10], Ok(Instruction::AddR8(Reg8::B)));
+    assert_eq!(result[11], Ok(Instruction::EmptyLine));
+    assert_eq!(result[12].clone().unwrap_err().line, 13);
+    assert_eq!(result[13], Ok(Instruction::EmptyLine));
+    assert_eq!(result.len(), 14);
+
+    assert_eq!(parse_asm_iter(text).collect::<Vec<_>>(), result);
+    let borrowed: Vec<_> = parse_asm_borrowed(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.map(Instruction::into_owned))
+        .collect();
+    assert_eq!(borrowed, result);
+}
+
+#[test]
+fn test_line_endings() {
+    let expected = vec![
+        Ok(Instruction::Nop),
+        Ok(Instruction::EmptyLine),
+        Ok(Instruction::Halt),
+        Ok(Instruction::Stop),
+    ];
+    for text in [
+        "nop\n\nhalt\nstop",
+        "nop\r\n\r\nhalt\r\nstop\r\n",
+        "nop\r\rhalt\rstop\r",
+        "\u{FEFF}nop\r\n\nhalt\rstop",
+    ] {
+        assert_eq!(parse_asm(text).unwrap(), expected, "{:?}", text);
+        assert_eq!(parse_asm_iter(text).collect::<Vec<_>>(), expected);
+        let borrowed: Vec<_> = parse_asm_borrowed(text)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.map(Instruction::into_owned))
+            .collect();
+        assert_eq!(borrowed, expected);
+    }
+
+    let result = parse_asm("nop\rfoo\rnop").unwrap();
+    assert_eq!(result[1].clone().unwrap_err().line, 2);
+}
+
+#[test]
+fn test_decode_asm() {
+    assert_eq!(decode_asm(b"nop\n").unwrap(), "nop\n");
+    assert_eq!(decode_asm(b"\xEF\xBB\xBFnop\n").unwrap(), "nop\n");
+    let err = decode_asm(b"nop\r\nhalt\n    db \xFF\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid UTF-8 at byte offset 17 on line 3, asm files must be UTF-8"
+    );
+}
+
+#[test]
+fn test_string_functions() {
+    let text = r#"
+Name EQUS STRCAT("player", "_", "x")
+Name: ; STRLEN("ignored")
+    db STRLEN("Hello"), STRSUB("Hello", 2, 3), STRSUB("Hello", -2)
+    ld a, STRLEN(STRCAT("ab", "cd")) * 2
+Greet: MACRO
+    db STRSUB(\1, 1, 1 + 1)
+ENDM
+    Greet "Hello"
+    db "STRLEN(\"x\")"
+"#;
+    let result = parse_asm(text).unwrap();
+    assert_eq!(result[1], Ok(Instruction::EmptyLine));
+    assert_eq!(result[2], Ok(Instruction::Label(String::from("player_x"))));
+    assert_eq!(
+        result[3],
+        Ok(Instruction::DbString(vec![
+            DbItem::Expr(Expr::Const(5)),
+            DbItem::String(String::from("ell")),
+            DbItem::String(String::from("lo")),
+        ]))
+    );
+    assert_eq!(
+        result[4],
+        Ok(Instruction::LdR8I8(
+            Reg8::A,
+            Expr::binary(Expr::Const(4), BinaryOperator::Mul, Expr::Const(2))
+        ))
+    );
+    assert_eq!(
+        result[8],
+        Ok(Instruction::DbString(vec![DbItem::String(String::from(
+            "He"
+        ))]))
+    );
+    assert_eq!(parse_asm_iter(text).collect::<Vec<_>>(), result);
+
+    let err = parse_asm("    db STRSUB(\"abc\", 3, 2)").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "STRSUB length 2 from position 3 is outside of the 3 characters of \"abc\""
+    );
+    let err = parse_asm("    db STRSUB(\"abc\", -9223372036854775807 - 1)").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "STRSUB position -9223372036854775808 is outside of the 3 characters of \"abc\""
+    );
+    let err = parse_asm("    db STRSUB(\"abc\", 3, 9223372036854775807)").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "STRSUB length 9223372036854775807 from position 3 is outside of the 3 characters of \"abc\""
+    );
+    let err = parse_asm("    db STRLEN(3)").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "STRLEN argument 1 must be a string but was: 3"
+    );
+    let err = parse_asm("    db STRCAT(\"a\"").unwrap_err();
+    assert_eq!(err.to_string(), "STRCAT is missing a closing parenthesis");
+}
+
+#[test]
+fn test_parse_asm_iter() {
+    let text = r#"
+Inc2: MACRO
+    inc \1
+    inc \1
+ENDM
+    Inc2 a
+    foo
+    nop
+"#;
+    let result: Vec<_> = parse_asm_iter(text).collect();
+    assert_eq!(result, parse_asm(text).unwrap());
+    assert_eq!(result[7].clone().unwrap_err().line, 8);
+
+    let mut iter = parse_asm_iter(text).skip(5);
+    assert_eq!(iter.next(), Some(Ok(Instruction::IncR8(Reg8::A))));
+
+    let result: Vec<_> = parse_asm_iter("    nop\nFoo: MACRO\n    nop\n").collect();
+    assert_eq!(result.len(), 4);
+    let diagnostic = result[3].clone().unwrap_err();
+    assert_eq!(diagnostic.line, 4);
+    assert_eq!(
+        diagnostic.message,
+        Some(String::from("Macro Foo is missing ENDM"))
+    );
+
+    let text = "A EQUS \"1\"\n    nop\nA EQUS \"2\"\n    nop\n";
+    let result: Vec<_> = parse_asm_iter(text).collect();
+    assert_eq!(result.len(), 3);
+    assert_eq!(
+        result[2].clone().unwrap_err().to_string(),
+        "String constant A is defined twice\nA EQUS \"2\"\n^^^^^^^^^^"
+    );
+}
+
+#[test]
+fn test_parse_asm_borrowed() {
+    let text = r#"
+Load: MACRO
+    ld a, \1
+ENDM
+Player EQUS "0xC000"
+    ld a, [Foo + 1]
+    Load Bar
+    ld [Player], a
+    foo
+"#;
+    let result = parse_asm_borrowed(text).unwrap();
+    let owned: Vec<_> = result
+        .iter()
+        .cloned()
+        .map(|x| x.map(Instruction::into_owned))
+        .collect();
+    assert_eq!(owned, parse_asm(text).unwrap());
+    assert_eq!(result[8].clone().unwrap_err().line, 9);
+
+    match &result[5] {
+        Ok(Instruction::LdRaMI16(Expr::Binary(binary))) => {
+            assert!(matches!(binary.left, Expr::Ident(Cow::Borrowed("Foo"))))
+        }
+        _ => panic!("{:?}", result[5]),
+    }
+    match &result[6] {
+        Ok(Instruction::LdR8I8(Reg8::A, Expr::Ident(ident))) => {
+            assert!(matches!(ident, Cow::Owned(_)))
+        }
+        _ => panic!("{:?}", result[6]),
+    }
+
+    // options change how the following lines are parsed, so they are not plain lines
+    let text = "    opt radix=16\n    ld a, 10\n";
+    let borrowed: Vec<_> = parse_asm_borrowed(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.map(Instruction::into_owned))
+        .collect();
+    assert_eq!(borrowed, parse_asm(text).unwrap());
+    assert_eq!(
+        borrowed[1],
+        Ok(Instruction::LdR8I8(Reg8::A, Expr::Const(16)))
+    );
+}
+
+#[test]
+fn test_keyword_names() {
+    let text = r#"
+Stop:
+    NOP
+    Ld A, B
+    jr stop
+ld EQU 3
+db = 4
+    LDH [c], a
+    dbfill 2, 0x10
+"#;
+    let result: Vec<_> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(Result::ok)
+        .collect();
+    assert_eq!(
+        result,
+        vec!(
+            Some(Instruction::EmptyLine),
+            Some(Instruction::Label(String::from("Stop"))),
+            Some(Instruction::Nop),
+            Some(Instruction::LdR8R8(Reg8::A, Reg8::B)),
+            Some(Instruction::Jr(
+                Flag::Always,
+                Expr::Ident(String::from("stop"))
+            )),
+            Some(Instruction::Equ(String::from("ld"), Expr::Const(3))),
+            Some(Instruction::Set(String::from("db"), Expr::Const(4))),
+            Some(Instruction::LdhMRcRa),
+            Some(Instruction::DbFill(Expr::Const(2), Expr::Const(0x10))),
+        )
+    );
+}
+
+#[test]
+fn test_parse_asm_reader() {
+    let text = "\u{FEFF}Load: MACRO\r\n    ld a, \\1\r\nENDM\rName EQUS \"0xC000\"\n    db 1, \\\n       2\r\n\n    Load Name\n    foo";
+    let expected = parse_asm(text).unwrap();
+    assert_eq!(parse_asm_reader(text.as_bytes()).unwrap(), expected);
+    // a tiny buffer to read the lines in many parts
+    let reader = BufReader::with_capacity(3, Cursor::new(text));
+    assert_eq!(parse_asm_reader(reader).unwrap(), expected);
+
+    let err = parse_asm_reader(&b"nop\r\nhalt\rdi\n    db \xFF\n"[..]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid UTF-8 at byte offset 20 on line 4, asm files must be UTF-8"
+    );
+
+    let err = parse_asm_reader(&b"Foo: MACRO\n    nop\n"[..]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        parse_asm("Foo: MACRO\n    nop\n").unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn test_parse_asm_spanned() {
+    let text = "Load: MACRO\n    ld a, \\1\n    ld b, a\nENDM\n\tLoad 3\n    db 1, \\\n       2\nMain:  \n    foo\n";
+    let spanned = parse_asm_spanned(text).unwrap();
+    let instructions: Vec<_> = spanned
+        .iter()
+        .map(|x| x.clone().map(|x| x.inner).map_err(|x| x.text))
+        .collect();
+    let expected: Vec<_> = parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.map_err(|x| x.text))
+        .collect();
+    assert_eq!(instructions, expected);
+
+    let spans: Vec<_> = spanned
+        .iter()
+        .filter_map(|x| x.as_ref().ok())
+        .map(|x| (x.span.line, x.span.column))
+        .collect();
+    assert_eq!(
+        spans,
+        vec![
+            (1, 1),
+            (2, 5),
+            (3, 5),
+            (4, 1),
+            (5, 2),
+            (5, 2),
+            (6, 5),
+            (7, 1),
+            (8, 1)
+        ]
+    );
+    assert!(spanned.iter().flatten().all(|x| x.span.file.is_none()));
+    assert_eq!(
+        spanned[5].as_ref().unwrap().inner,
+        Instruction::LdR8R8(Reg8::B, Reg8::A)
+    );
+
+    // the macro expands to an extra instruction, the diagnostic has the line of the span rather than the index
+    assert_eq!(spanned.len(), 10);
+    assert_eq!(spanned[9].as_ref().unwrap_err().line, 9);
+}
+
+#[test]
+fn test_parse_asm_lossless() {
+    let text = "\u{FEFF}Load: MACRO ; loads a\r\n    ld a, \\1\r\nENDM\n\tLoad 3  ; twice\n    db 1, \\\n       2\rMain:\n    db \"a;b\"\t\n; done";
+    let asm = parse_asm_lossless(text).unwrap();
+    assert_eq!(asm.to_string(), text);
+    assert!(asm.byte_order_mark);
+
+    let instructions: Vec<_> = asm
+        .lines
+        .iter()
+        .flat_map(|x| x.instructions.clone())
+        .collect();
+    assert_eq!(instructions, parse_asm(text).unwrap());
+
+    let line = &asm.lines[0];
+    assert_eq!(line.indent, "");
+    assert_eq!(line.code, "Load: MACRO");
+    assert_eq!(line.trailing, " ");
+    assert_eq!(line.comment.as_deref(), Some("; loads a"));
+    assert_eq!(line.line_ending, "\r\n");
+
+    let line = &asm.lines[3];
+    assert_eq!(line.indent, "\t");
+    assert_eq!(line.code, "Load 3");
+    assert_eq!(line.trailing, "  ");
+    assert_eq!(line.comment.as_deref(), Some("; twice"));
+    assert_eq!(
+        line.instructions,
+        vec!(Ok(Instruction::LdR8I8(Reg8::A, Expr::Const(3))))
+    );
+
+    let line = &asm.lines[4];
+    assert_eq!(line.code, "db 1, \\");
+    assert_eq!(line.comment, None);
+    assert_eq!(asm.lines[5].code, "2");
+    assert_eq!(asm.lines[5].line_ending, "\r");
+    assert_eq!(asm.lines[5].instructions, vec!(Ok(Instruction::EmptyLine)));
+
+    let line = &asm.lines[7];
+    assert_eq!(line.code, "db \"a;b\"");
+    assert_eq!(line.comment, None);
+    assert_eq!(line.trailing, "\t");
+    assert_eq!(asm.lines[8].code, "");
+    assert_eq!(asm.lines[8].comment.as_deref(), Some("; done"));
+    assert_eq!(asm.lines[8].line_ending, "");
+    assert_eq!(asm.lines.len(), 9);
+
+    // a formatter can change the whitespace and keep everything else
+    let mut asm = asm;
+    for line in &mut asm.lines {
+        if !line.indent.is_empty() {
+            line.indent = String::from("    ");
+        }
+    }
+    assert!(asm.to_string().contains("\n    Load 3  ; twice\n"));
+}
@@ -0,0 +1,125 @@
+use ggbasm::ast::*;
+use ggbasm::parser::parse_asm;
+use ggbasm::printer::to_asm_string;
+
+fn parse(text: &str) -> Vec<Instruction> {
+    parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect()
+}
+
+#[test]
+fn test_print_round_trip() {
+    let text = r#"
+SECTION "Main", ROMX[$4000], BANK[2]
+Lives EQU 3
+Counter = Lives * (2 + 1) - -4
+    opt Werror, !h, radix=16
+    pusho
+    popo
+Main:
+    export Main, Lives
+    ld a, [$FF00+$40]
+    ldh [c], a
+    ld a, [hl+]
+    ld [hl-], a
+    ld hl, sp - 4
+    add sp, -2
+    ld [Lives + 0xC000], sp
+    jr nz, Main
+    jp hl
+    call c, :+
+:
+    ret nz
+    ret
+    rst $38
+    bit 3, [hl]
+    set 7, b
+    add a, [hl]
+    sub 5
+    cp a, Lives & $0F | 1 << 4
+    push af
+    db "Hi", 1, Lives
+    db 1, 2, 0x300 >> 8
+    dw Main, $1234
+    dbfill 4, $FF
+    charmap "A", $80
+    incbin "data.bin", 2, 8
+    include "other.asm"
+    if Lives >= 3 && 1 == 1 || Lives != 1
+    print "Lives: ", Lives
+    elif Lives
+    warn "few lives"
+    else
+    fail "no lives"
+    endc
+    assert Lives < 10, "too many lives"
+    debug_assert z
+    debug_assert a < MaxLives + 1
+    debug_assert a != 0
+    debug_print "message"
+    debug_break
+    advance_address 2, $4100
+    union
+    nextu
+    endu
+    pragma once
+"#;
+    let instructions = parse(text);
+    let printed = to_asm_string(&instructions);
+    assert_eq!(parse(&printed), instructions, "{}", printed);
+}
+
+#[test]
+fn test_print_instructions() {
+    assert_eq!(
+        Instruction::<String>::LdR8I8(Reg8::A, Expr::Const(0x40)).to_string(),
+        "ld a, $40"
+    );
+    assert_eq!(
+        Instruction::Jr(Flag::NC, Expr::Ident("Loop")).to_string(),
+        "jr nc, Loop"
+    );
+    assert_eq!(
+        Instruction::<String>::LdhMI8Ra(Expr::Const(0x40)).to_string(),
+        "ld [$FF00 + $40], a"
+    );
+    assert_eq!(
+        Instruction::<String>::LdRhlRspI8(Expr::Const(-2)).to_string(),
+        "ld hl, sp + -2"
+    );
+    assert_eq!(Instruction::<String>::SwapMRhl.to_string(), "swap [hl]");
+    assert_eq!(Instruction::<String>::HaltUnpadded.to_string(), "halt");
+    assert_eq!(
+        Instruction::<String>::Db(vec![1, 0xFF]).to_string(),
+        "db 1, $FF"
+    );
+    assert_eq!(
+        Instruction::<String>::Section(String::from("Data"), SectionType::Rom0, None, None)
+            .to_string(),
+        "section \"Data\", rom0"
+    );
+}
+
+#[test]
+fn test_print_exprs() {
+    let expr = |text: &str| match &parse(&format!("x EQU {}", text))[0] {
+        Instruction::Equ(_, expr) => expr.to_string(),
+        instruction => panic!("{:?}", instruction),
+    };
+    assert_eq!(expr("1+2*3"), "1 + 2 * 3");
+    assert_eq!(expr("(1+2)*3"), "(1 + 2) * 3");
+    assert_eq!(expr("1-(2-3)"), "1 - (2 - 3)");
+    assert_eq!(expr("(1-2)-3"), "1 - 2 - 3");
+    assert_eq!(expr("-(a+1)"), "-(a + 1)");
+    assert_eq!(expr("--a"), "--a");
+    assert_eq!(expr("255"), "$FF");
+    assert_eq!(expr("a == 1 && b"), "a == 1 && b");
+    assert_eq!(
+        Expr::<&str>::unary(Expr::Const(-5), UnaryOperator::Minus).to_string(),
+        "-(-5)"
+    );
+    assert_eq!(Expr::<&str>::AnonymousLabelRef(-2).to_string(), ":--");
+}
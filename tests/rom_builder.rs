@@ -0,0 +1,2449 @@
+mod common;
+
+use common::{builder, header, parse};
+use ggbasm::address::{Bank, BankAddr, GlobalAddr};
+use ggbasm::ast::*;
+use ggbasm::header::*;
+use ggbasm::layout::*;
+use ggbasm::lint::LintProfile;
+use ggbasm::{Color, RomBuilder, VariableType};
+
+#[test]
+fn test_prologue() {
+    let expected = builder()
+        .add_instructions(vec![Instruction::Nop])
+        .unwrap()
+        .compile()
+        .unwrap();
+    let rom = RomBuilder::new()
+        .unwrap()
+        .add_header(header())
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_instructions(vec![Instruction::Nop])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(rom, expected);
+
+    let rom = RomBuilder::new()
+        .unwrap()
+        .add_prologue(Prologue {
+            header: Some(header()),
+            entry: Some(String::from("Main")),
+            interrupts_and_jumps: true,
+        })
+        .unwrap()
+        .add_instructions(vec![
+            Instruction::Nop,
+            Instruction::Label(String::from("Main")),
+            Instruction::Halt,
+        ])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x100..0x104], &[0x00, 0xC3, 0x51, 0x01]);
+    assert_eq!(&rom[0x104..0x150], &expected[0x104..0x150]);
+
+    let err = builder()
+        .add_header(header())
+        .and_then(|x| x.compile())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The header at 0x0104-0x014f overlaps data generated by rust code at 0x0104"
+    );
+
+    let err = RomBuilder::new()
+        .unwrap()
+        .add_prologue(Prologue {
+            entry: Some(String::from("Main")),
+            ..Prologue::default()
+        })
+        .unwrap()
+        .advance_address(Bank(0), BankAddr(0x150))
+        .unwrap()
+        .add_instructions(vec![
+            Instruction::Label(String::from("Main")),
+            Instruction::Halt,
+        ])
+        .unwrap()
+        .compile()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Nothing was added at 0x0104, the entry point and header from 0x0100 to 0x014F must be added with add_prologue, add_header or asm"
+    );
+
+    let err = RomBuilder::new()
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_bytes(vec![0; 0x4C], "Header")
+        .unwrap()
+        .compile()
+        .unwrap_err();
+    assert!(err.to_string().starts_with("The Nintendo logo"));
+
+    // the header checksum is filled in when the header is added as bytes or asm
+    let mut bytes = expected[0x104..0x150].to_vec();
+    bytes[0x49] = 0;
+    let rom = RomBuilder::new()
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_bytes(bytes, "Header")
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(rom[0x14D], expected[0x14D]);
+}
+
+#[test]
+fn test_debug_overlay_release() {
+    let rom = builder()
+        .add_debug_overlay(&[0xC000])
+        .unwrap()
+        .add_instructions(vec![Instruction::Call(
+            Flag::Always,
+            Expr::Ident(String::from("GGBASMDebugOverlayStep")),
+        )])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x154], &[0xC9, 0xCD, 0x50, 0x01]);
+}
+
+#[test]
+fn test_debug_overlay_debug() {
+    let rom = builder()
+        .define("DEBUG", 1)
+        .unwrap()
+        .define("GGBASMDebugFrameCounter", 0xC030)
+        .unwrap()
+        .add_debug_overlay(&[0xC000, 0xC001])
+        .unwrap()
+        .compile()
+        .unwrap();
+    let font: Vec<u8> = ggbasm::debug::HEX_FONT.iter().flatten().cloned().collect();
+    assert_eq!(&rom[0x150..0x150 + font.len()], font.as_slice());
+}
+
+#[test]
+fn test_debug_overlay_too_many_watched() {
+    let result = builder()
+        .define("DEBUG", 1)
+        .unwrap()
+        .add_debug_overlay(&[0xC000, 0xC001, 0xC002, 0xC003, 0xC004]);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_crash_handler() {
+    let rom = builder()
+        .add_crash_handler()
+        .unwrap()
+        .add_instructions(vec![Instruction::Equ(
+            String::from("GGBASMCrashRecord"),
+            Expr::Const(0xA000),
+        )])
+        .unwrap()
+        .compile()
+        .unwrap();
+    // the crash handler follows the hex font and the 15 byte GGBASMDrawHex routine
+    let handler = 0x150 + ggbasm::debug::HEX_FONT.len() * 8 + 15;
+    assert_eq!(
+        &rom[0x38..0x3B],
+        &[0xC3, handler as u8, (handler >> 8) as u8]
+    );
+    assert_eq!(rom[handler], 0xF3);
+}
+
+#[test]
+fn test_crash_handler_not_bank_0() {
+    let result = builder()
+        .advance_address(Bank(1), BankAddr(0x4000))
+        .unwrap()
+        .add_crash_handler();
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_fixed_point_routines() {
+    use ggbasm::fixed_point;
+
+    let sine = fixed_point::sine_table::<256>();
+    assert_eq!(&sine[..3], &[0, 6, 13]);
+    assert_eq!(sine[64], 0x100);
+    assert_eq!(sine[192], -0x100);
+    assert_eq!(fixed_point::cosine_table::<4>(), [0x100, 0, -0x100, 0]);
+    assert_eq!(
+        fixed_point::reciprocal_table::<4>(),
+        [i16::MAX, 0x100, 0x80, 0x55]
+    );
+    assert_eq!(fixed_point::multiply_table::<3>(1.5), [0, 0x180, 0x300]);
+    assert_eq!(
+        fixed_point::table_bytes(&[0x180, -1]),
+        [0x80, 0x01, 0xFF, 0xFF]
+    );
+
+    let rom = builder()
+        .add_fixed_point_routines()
+        .unwrap()
+        .add_instructions(vec![
+            Instruction::Call(Flag::Always, Expr::Ident(String::from("GGBASMSinLookup"))),
+            Instruction::Call(Flag::Always, Expr::Ident(String::from("GGBASMMulFixed"))),
+        ])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(
+        &rom[0x150..0x350],
+        fixed_point::table_bytes(&sine).as_slice()
+    );
+    // ld l, a; ld h, 0
+    assert_eq!(&rom[0x350..0x353], &[0x6F, 0x26, 0x00]);
+    // call GGBASMSinLookup
+    assert!(rom.windows(3).any(|x| x == [0xCD, 0x50, 0x03]));
+}
+
+#[test]
+fn test_debug_assert_release() {
+    let rom = builder()
+        .add_instructions(vec![Instruction::DebugAssert(Flag::Z), Instruction::Nop])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x152], &[0x00, 0x00]);
+}
+
+#[test]
+fn test_debug_assert_debug() {
+    let rom = builder()
+        .define("DEBUG", 1)
+        .unwrap()
+        .add_instructions(vec![Instruction::DebugAssert(Flag::Z), Instruction::Nop])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x154], &[0x28, 0x01, 0xFF, 0x00]);
+
+    // cp 10; jr c, 1; rst 0x38
+    let rom = builder()
+        .define("DEBUG", 1)
+        .unwrap()
+        .add_instructions(parse(
+            "MaxLives EQU 10\n    debug_assert a < MaxLives\n    nop\n",
+        ))
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x156], &[0xFE, 0x0A, 0x38, 0x01, 0xFF, 0x00]);
+}
+
+#[test]
+fn test_debug_print() {
+    let rom = builder()
+        .define("DEBUG", 1)
+        .unwrap()
+        .add_instructions(vec![
+            Instruction::DebugPrint(String::from("hi")),
+            Instruction::DebugBreak,
+        ])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(
+        &rom[0x150..0x159],
+        &[0x52, 0x18, 0x06, 0x64, 0x64, 0x00, 0x00, b'h', b'i']
+    );
+    assert_eq!(rom[0x159], 0x40);
+}
+
+#[test]
+fn test_ram_sym_file() {
+    let builder = builder()
+        .declare_ram_variable("PlayerY", 0xC001, VariableType::I8)
+        .unwrap()
+        .declare_ram_variable("PlayerX", 0xC000, VariableType::U8)
+        .unwrap()
+        .declare_ram_variable("Inventory", 0xC002, VariableType::Bytes(8))
+        .unwrap();
+    assert_eq!(
+        builder.ram_sym_file(),
+        "; RAM variables generated by ggbasm\n00:C000 PlayerX ; u8\n00:C001 PlayerY ; i8\n00:C002 Inventory ; [u8; 8]\n"
+    );
+}
+
+#[test]
+fn test_ram_variable_not_in_ram() {
+    assert!(builder()
+        .declare_ram_variable("Foo", 0x4000, VariableType::U8)
+        .is_err());
+    assert!(builder()
+        .declare_ram_variable("Foo", 0xFFFF, VariableType::U16)
+        .is_err());
+}
+
+#[test]
+fn test_layout() {
+    let (rom, layout) = builder()
+        .add_instructions(vec![
+            Instruction::Label(String::from("Start")),
+            Instruction::Nop,
+            Instruction::Db(vec![0x12, 0x34]),
+        ])
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(layout.rom_size, rom.len() as u32);
+    assert_eq!(layout.symbol("Start"), Some(0x150));
+    assert_eq!(layout.sections.len(), 3);
+    assert_eq!(
+        layout.regions,
+        vec![
+            Region {
+                address: 0x0,
+                size: 0x104,
+                kind: RegionKind::InterruptsAndJumps
+            },
+            Region {
+                address: 0x104,
+                size: 0x4C,
+                kind: RegionKind::Header
+            },
+            Region {
+                address: 0x150,
+                size: 1,
+                kind: RegionKind::Code
+            },
+            Region {
+                address: 0x151,
+                size: 2,
+                kind: RegionKind::Data
+            },
+            Region {
+                address: 0x153,
+                size: 0x8000 - 0x153,
+                kind: RegionKind::Padding
+            },
+        ]
+    );
+    assert_eq!(
+        Layout::from_json(&layout.to_json().unwrap()).unwrap(),
+        layout
+    );
+}
+
+#[test]
+fn test_data_region() {
+    let (_, layout) = builder()
+        .register_data_region("EnemyStats", 3, 2)
+        .unwrap()
+        .add_instructions(vec![
+            Instruction::Nop,
+            Instruction::Label(String::from("EnemyStats")),
+            Instruction::Db(vec![1, 2, 3, 4, 5, 6]),
+        ])
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    let region = layout.data_region("EnemyStats").unwrap();
+    assert_eq!(
+        region,
+        &DataRegion {
+            name: String::from("EnemyStats"),
+            address: 0x151,
+            element_size: 3,
+            count: 2,
+        }
+    );
+    assert_eq!(region.element_address(1), Some(0x154));
+    assert_eq!(region.element_address(2), None);
+}
+
+#[test]
+fn test_data_region_undeclared() {
+    let result = builder()
+        .register_data_region("EnemyStats", 3, 2)
+        .unwrap()
+        .add_instructions(vec![Instruction::Nop])
+        .unwrap()
+        .compile();
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_patch_constant() {
+    let (mut rom, layout) = builder()
+        .register_data_region("StartingLives", 1, 1)
+        .unwrap()
+        .register_data_region("StartingGold", 2, 1)
+        .unwrap()
+        .add_instructions(vec![
+            Instruction::Label(String::from("StartingLives")),
+            Instruction::Db(vec![3]),
+            Instruction::Label(String::from("StartingGold")),
+            Instruction::Db(vec![0x00, 0x01]),
+        ])
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+
+    layout.patch_constant(&mut rom, "StartingLives", 5).unwrap();
+    layout
+        .patch_constant(&mut rom, "StartingGold", 0x1234)
+        .unwrap();
+    assert_eq!(&rom[0x150..0x153], &[5, 0x34, 0x12]);
+
+    assert!(layout
+        .patch_constant(&mut rom, "StartingLives", 0x100)
+        .is_err());
+    assert!(layout.patch_constant(&mut rom, "Foo", 1).is_err());
+
+    // layouts read from disk are not validated by register_data_region
+    let mut layout = layout;
+    layout.data_regions.push(DataRegion {
+        name: String::from("Empty"),
+        address: 0x150,
+        element_size: 0,
+        count: 1,
+    });
+    let err = layout.patch_constant(&mut rom, "Empty", 0).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Data region Empty has an element size of 0"
+    );
+}
+
+#[test]
+fn test_patch_data_region() {
+    use ggbasm::asset::ConvertedAsset;
+
+    let song = |note: u8| {
+        vec![
+            Instruction::Label(String::from("Song")),
+            Instruction::Db(vec![0x13, note, 0xFF, 0x10]),
+            Instruction::Db(vec![0xFE]),
+            Instruction::DbExpr16(Expr::Ident(String::from("Song"))),
+        ]
+    };
+    let (mut rom, layout) = builder()
+        .register_data_region("Tiles", 16, 1)
+        .unwrap()
+        .register_data_region("Song", 7, 1)
+        .unwrap()
+        .add_bytes(vec![0; 16], "Tiles")
+        .unwrap()
+        .add_instructions(song(0x40))
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(
+        &rom[0x160..0x167],
+        &[0x13, 0x40, 0xFF, 0x10, 0xFE, 0x60, 0x01]
+    );
+
+    let tiles = ConvertedAsset::Image {
+        identifier: String::from("Tiles"),
+        bytes: vec![0xFF; 16],
+    };
+    let bytes = tiles.to_bytes(0x150, &layout).unwrap();
+    layout.patch_data_region(&mut rom, "Tiles", &bytes).unwrap();
+    assert_eq!(&rom[0x150..0x160], &[0xFF; 16]);
+
+    let audio = ConvertedAsset::Audio {
+        file_name: String::from("song.txt"),
+        instructions: song(0x80),
+    };
+    let address = layout.data_region("Song").unwrap().address;
+    let bytes = audio.to_bytes(address, &layout).unwrap();
+    layout.patch_data_region(&mut rom, "Song", &bytes).unwrap();
+    assert_eq!(
+        &rom[0x160..0x167],
+        &[0x13, 0x80, 0xFF, 0x10, 0xFE, 0x60, 0x01]
+    );
+
+    assert!(layout
+        .patch_data_region(&mut rom, "Tiles", &[0; 17])
+        .is_err());
+    assert!(layout.patch_data_region(&mut rom, "Foo", &[0]).is_err());
+}
+
+#[test]
+fn test_patch_position_dependent_asset() {
+    use ggbasm::asset::ConvertedAsset;
+
+    let song = |note: u8| {
+        vec![
+            Instruction::Label(String::from("Loop")),
+            Instruction::Db(vec![0x13, note]),
+            Instruction::Jr(Flag::Always, Expr::Ident(String::from("Loop"))),
+        ]
+    };
+    let (mut rom, layout) = builder()
+        .register_data_region("Loop", 4, 1)
+        .unwrap()
+        .add_instructions(song(0x40))
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x154], &[0x13, 0x40, 0x18, 0xFC]);
+
+    // the relative jump is encoded at the address of the asset in the rom
+    let audio = ConvertedAsset::Audio {
+        file_name: String::from("loop.txt"),
+        instructions: song(0x80),
+    };
+    let bytes = audio.to_bytes(0x150, &layout).unwrap();
+    layout.patch_data_region(&mut rom, "Loop", &bytes).unwrap();
+    assert_eq!(&rom[0x150..0x154], &[0x13, 0x80, 0x18, 0xFC]);
+}
+
+#[test]
+fn test_hardware_constants() {
+    let rom = builder()
+        .add_hardware_constants()
+        .unwrap()
+        .add_instructions(vec![
+            Instruction::LdR8I8(
+                Reg8::A,
+                Expr::binary(
+                    Expr::Ident(String::from("LCDCF_ON")),
+                    BinaryOperator::Or,
+                    Expr::Ident(String::from("LCDCF_BGON")),
+                ),
+            ),
+            Instruction::LdMI16Ra(Expr::Ident(String::from("rLCDC"))),
+        ])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x155], &[0x3E, 0x81, 0xEA, 0x40, 0xFF]);
+}
+
+#[test]
+fn test_constants() {
+    use ggbasm::constants::*;
+
+    assert_eq!(CYCLES_PER_FRAME, 70224);
+    assert_eq!(VBLANK_LINES, 10);
+    assert_eq!(WRAMX_START, WRAM0_START + WRAM0_SIZE);
+    assert_eq!(HRAM_START as u32 + HRAM_SIZE as u32, REG_IE as u32);
+
+    let text = "    ld a, [REG_LY]\n    ld hl, VBLANK_VECTOR + OAM_SIZE\n";
+    let rom = conditional_rom(builder().add_constants().unwrap(), text).unwrap();
+    assert_eq!(&rom[0x150..0x156], &[0xFA, 0x44, 0xFF, 0x21, 0xE0, 0x00]);
+
+    let err = builder()
+        .define("REG_LY", 0)
+        .unwrap()
+        .add_constants()
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Identifier REG_LY is already used, it is declared in rust code"
+    );
+}
+
+#[test]
+fn test_ldh() {
+    let text = "    ldh [$FF40], a\n    ldh a, [$44]\n    ldh a, [REG_IE]\n    ld a, [hl+]\n";
+    let rom = conditional_rom(builder().add_constants().unwrap(), text).unwrap();
+    assert_eq!(
+        &rom[0x150..0x157],
+        &[0xE0, 0x40, 0xF0, 0x44, 0xF0, 0xFF, 0x2A]
+    );
+
+    let err = conditional_rom(builder(), "    ldh [$C000], a\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 1: 0xc000 > 0xFF This is invalid because the value needs to fit in one byte"
+    );
+}
+
+#[test]
+fn test_stop_halt_padding() {
+    let text = "    stop\n    halt\n    ret\n";
+    let rom = conditional_rom(builder(), text).unwrap();
+    assert_eq!(&rom[0x150..0x154], &[0x10, 0x76, 0x00, 0xC9]);
+
+    let padded = builder().stop_halt_padding(true, false).unwrap();
+    let rom = conditional_rom(padded, text).unwrap();
+    assert_eq!(&rom[0x150..0x154], &[0x10, 0x00, 0x76, 0xC9]);
+
+    let instructions = vec![Instruction::StopPadded, Instruction::HaltUnpadded];
+    let rom = builder()
+        .add_instructions(instructions)
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x153], &[0x10, 0x00, 0x76]);
+}
+
+#[test]
+fn test_opt() {
+    let rom = conditional_rom(
+        builder(),
+        "    halt\n    opt h\n    halt\n    opt !h\n    halt\n",
+    )
+    .unwrap();
+    assert_eq!(&rom[0x150..0x156], &[0x76, 0x00, 0x76, 0x76, 0x00, 0x00]);
+
+    let err = conditional_rom(builder(), "    opt Werror\n    warn \"Slow\"\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 2: Warning treated as error: Slow"
+    );
+    assert!(conditional_rom(builder(), "    opt Werror, Wno-error\n    warn \"Slow\"\n").is_ok());
+
+    // options end with the file that sets them
+    let dir = std::env::temp_dir().join("ggbasm_test_opt");
+    std::fs::create_dir_all(&dir).unwrap();
+    let main = dir.join("main.asm");
+    let other = dir.join("other.asm");
+    std::fs::write(&other, "    opt h, radix=2\n    halt\n    db 10\n").unwrap();
+    std::fs::write(
+        &main,
+        format!(
+            "    include \"{}\"\n    halt\n    db 10\n",
+            other.to_str().unwrap()
+        ),
+    )
+    .unwrap();
+    let rom = builder()
+        .add_asm_file(main.to_str().unwrap())
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x155], &[0x76, 0x02, 0x76, 0x00, 0x0A]);
+}
+
+#[test]
+fn test_asm_file_encoding() {
+    let dir = std::env::temp_dir().join("ggbasm_test_asm_file_encoding");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("main.asm");
+    std::fs::write(&path, b"\xEF\xBB\xBF    nop\r    halt\r").unwrap();
+    let rom = builder()
+        .add_asm_file(path.to_str().unwrap())
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x152], &[0x00, 0x76]);
+
+    std::fs::write(&path, b"    nop\n    db \"caf\xE9\"\n").unwrap();
+    let err = builder()
+        .add_asm_file(path.to_str().unwrap())
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Cannot read asm file {} because: Invalid UTF-8 at byte offset 19 on line 2, asm files must be UTF-8",
+            path.to_str().unwrap()
+        )
+    );
+}
+
+#[test]
+fn test_state_stack() {
+    let dir = std::env::temp_dir().join("ggbasm_test_state_stack");
+    std::fs::create_dir_all(&dir).unwrap();
+    let shared = dir.join("shared.asm");
+    let main = dir.join("main.asm");
+    std::fs::write(&shared, "    pragma once\nLives EQU 5\n").unwrap();
+    std::fs::write(
+        &main,
+        format!(
+            r#"    include "{0}"
+    include "{0}"
+    SECTION "Code", ROM0
+    ld a, Lives
+    pushs
+    SECTION "Table", ROMX
+    pusho
+    opt h
+    halt
+    popo
+    halt
+    db SIZEOF_Code
+    pops
+    nop
+"#,
+            shared.to_str().unwrap()
+        ),
+    )
+    .unwrap();
+    let rom = builder()
+        .add_asm_file(main.to_str().unwrap())
+        .unwrap()
+        .add_asm_file(shared.to_str().unwrap())
+        .unwrap()
+        .add_instructions(vec![Instruction::Stop])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x153], &[0x3E, 0x05, 0x00]);
+    assert_eq!(&rom[0x4000..0x4005], &[0x76, 0x76, 0x00, 0x03, 0x10]);
+
+    std::fs::write(
+        &main,
+        "    SECTION \"A\", ROM0\n    nop\n    pushs\n    SECTION \"B\", ROM0\n    db 1\n    pops\n    nop\n",
+    )
+    .unwrap();
+    let err = builder()
+        .add_asm_file(main.to_str().unwrap())
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Error occured in {0} on line 6: The section continued after POPS at 0x0151-0x0151 overlaps section B in asm file {0} at 0x0151",
+            main.to_str().unwrap()
+        )
+    );
+
+    std::fs::write(&main, "    nop\n    pushs\n").unwrap();
+    let err = builder()
+        .add_asm_file(main.to_str().unwrap())
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Error occured in {} on line 2: PUSHS has no matching POPS",
+            main.to_str().unwrap()
+        )
+    );
+}
+
+#[test]
+fn test_string_constants_across_files() {
+    let dir = std::env::temp_dir().join("ggbasm_test_string_constants_across_files");
+    std::fs::create_dir_all(&dir).unwrap();
+    let defs = dir.join("defs.asm");
+    let main = dir.join("main.asm");
+    let other = dir.join("other.asm");
+    std::fs::write(&defs, "    pragma once\nPlayerX EQUS \"[0xC000]\"\n").unwrap();
+    std::fs::write(
+        &main,
+        format!(
+            "    include \"{}\"\n    ld a, PlayerX\n",
+            defs.to_str().unwrap()
+        ),
+    )
+    .unwrap();
+    // string constants declared by an earlier file are also visible to the files added after it
+    std::fs::write(&other, "    ld PlayerX, a\n").unwrap();
+    let rom = builder()
+        .add_asm_file(main.to_str().unwrap())
+        .unwrap()
+        .add_asm_file(other.to_str().unwrap())
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x156], &[0xFA, 0x00, 0xC0, 0xEA, 0x00, 0xC0]);
+}
+
+#[test]
+fn test_signed_operands() {
+    let text = r#"
+Offset EQU -16
+    add sp, -2
+    ld hl, sp - 4
+    ld hl, sp + Offset
+    add sp, 127
+Loop:
+    jr Loop
+"#;
+    let rom = conditional_rom(builder(), text).unwrap();
+    assert_eq!(
+        &rom[0x150..0x15A],
+        &[0xE8, 0xFE, 0xF8, 0xFC, 0xF8, 0xF0, 0xE8, 0x7F, 0x18, 0xFE]
+    );
+
+    let err = conditional_rom(builder(), "Offset EQU 200\n    add sp, Offset\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 2: 200 is invalid because the value needs to fit in a signed byte, from -128 to 127"
+    );
+}
+
+#[test]
+fn test_jr_banks() {
+    let banked = || {
+        let mut mbc5 = header();
+        mbc5.cartridge_type = CartridgeType::Mbc5;
+        RomBuilder::new()
+            .unwrap()
+            .add_basic_interrupts_and_jumps()
+            .unwrap()
+            .add_header(mbc5)
+            .unwrap()
+            .advance_address(Bank(0), BankAddr(0x3FF0))
+            .unwrap()
+            .add_instructions(vec![Instruction::Label(String::from("Home"))])
+            .unwrap()
+            .advance_address(Bank(1), BankAddr(0x7FF0))
+            .unwrap()
+            .add_instructions(vec![Instruction::Label(String::from("Bank1"))])
+            .unwrap()
+            .advance_address(Bank(2), BankAddr(0x4000))
+            .unwrap()
+    };
+    let jr = |label: &str| {
+        vec![Instruction::Jr(
+            Flag::Always,
+            Expr::Ident(String::from(label)),
+        )]
+    };
+
+    // bank 0 is directly before every switchable bank
+    let rom = banked()
+        .add_instructions(jr("Home"))
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x8000..0x8002], &[0x18, 0xEE]);
+
+    let err = banked()
+        .add_instructions(jr("Bank1"))
+        .unwrap()
+        .compile()
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 1: jr cannot reach 0x7ff0 in bank 1 from bank 2, jr can only jump within its own bank or to bank 0, use jp instead"
+    );
+
+    let text = "Start:\n    advance_address 0x200\n    jr Start\n";
+    let err = conditional_rom(builder(), text).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 3: jr cannot reach 0x150, it is -178 bytes away but jr can only jump from -128 to 127 bytes, use jp instead"
+    );
+}
+
+#[test]
+fn test_rom_banks() {
+    let mut mbc5 = header();
+    mbc5.cartridge_type = CartridgeType::Mbc5;
+    let rom = RomBuilder::new()
+        .unwrap()
+        .rom_banks(64)
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_header(mbc5)
+        .unwrap()
+        .add_instructions(vec![Instruction::Nop])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(rom.len(), 64 * 0x4000);
+    assert_eq!(rom[0x148], 5);
+
+    let err = builder().rom_banks(4).unwrap().compile().err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "ROM is too big, there is no MBC so ROM size must be <= 32KB, was actually 65536"
+    );
+
+    let err = builder()
+        .rom_banks(2)
+        .unwrap()
+        .advance_address(Bank(2), BankAddr(0x4000))
+        .unwrap()
+        .add_instructions(vec![Instruction::Nop])
+        .unwrap()
+        .compile()
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "ROM is too big for the 2 rom banks set by rom_banks, raw ROM size was 32769"
+    );
+
+    let err = builder().rom_banks(48).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Cannot use 48 rom banks, it must be a power of two from 2 to 512"
+    );
+}
+
+fn conditional_rom(builder: RomBuilder, text: &str) -> Result<Vec<u8>, anyhow::Error> {
+    builder.add_instructions(parse(text))?.compile()
+}
+
+#[test]
+fn test_conditionals() {
+    let text = r#"
+Sound EQU 2
+IF DEBUG
+    db 0x01
+    IF Sound
+        db 0x02
+    ENDC
+ELIF Sound - 2
+    db 0x03
+ELSE
+    db 0x04
+ENDC
+"#;
+    let rom = conditional_rom(builder().define("DEBUG", 1).unwrap(), text).unwrap();
+    assert_eq!(&rom[0x150..0x153], &[0x01, 0x02, 0x00]);
+
+    let rom = conditional_rom(builder().define("DEBUG", 0).unwrap(), text).unwrap();
+    assert_eq!(&rom[0x150..0x152], &[0x04, 0x00]);
+}
+
+#[test]
+fn test_conditionals_errors() {
+    assert!(conditional_rom(builder(), "IF 1\n").is_err());
+    assert!(conditional_rom(builder(), "ENDC\n").is_err());
+    assert!(conditional_rom(builder(), "IF 1\nELSE\nELSE\nENDC\n").is_err());
+    assert!(conditional_rom(builder(), "IF Undeclared\nENDC\n").is_err());
+}
+
+#[test]
+fn test_set_constants() {
+    let text = r#"
+Offset SET 0
+Health EQU Offset
+Offset SET Offset + 1
+Mana EQU Offset
+Offset = Offset + 2
+IF Offset == 3
+    db Health, Mana, Offset
+ENDC
+REDEF Offset EQU 0x10
+    ld a, Offset
+"#;
+    let rom = conditional_rom(builder(), text).unwrap();
+    assert_eq!(&rom[0x150..0x155], &[0x00, 0x01, 0x03, 0x3E, 0x10]);
+
+    // assignments in excluded blocks are skipped
+    let rom = conditional_rom(
+        builder(),
+        "Value SET 1
+IF 0
+Value SET 2
+ENDC
+db Value
+",
+    )
+    .unwrap();
+    assert_eq!(rom[0x150], 0x01);
+
+    // the value carries over to instructions added later
+    let rom = builder()
+        .add_instructions(vec![Instruction::Set(
+            String::from("Value"),
+            Expr::Const(7),
+        )])
+        .unwrap()
+        .add_instructions(vec![Instruction::DbExpr8(Expr::Ident(String::from(
+            "Value",
+        )))])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(rom[0x150], 0x07);
+
+    assert_eq!(
+        conditional_rom(builder(), "Value EQU 1
+Value SET 2
+")
+            .unwrap_err()
+            .to_string(),
+        "Identifier Value is assigned with SET in data generated by rust code on line 2 but is already declared as a constant that cannot be reassigned."
+    );
+    assert!(conditional_rom(
+        builder(),
+        "Value SET 1
+Value EQU 2
+"
+    )
+    .is_err());
+    assert!(conditional_rom(
+        builder(),
+        "Value SET 1
+Value:
+"
+    )
+    .is_err());
+    assert!(conditional_rom(
+        builder(),
+        "Value SET Later
+Later:
+"
+    )
+    .is_err());
+}
+
+#[test]
+fn test_hardware_inc() {
+    let text = r#"
+; hardware.inc excerpt
+IF !DEF(HARDWARE_INC)
+DEF HARDWARE_INC EQU 1
+
+MACRO rev_Check_hardware_inc
+    IF \1 > 4
+        FAIL "too new"
+    ENDC
+ENDM
+
+DEF rLCDC EQU $FF40
+DEF LCDCF_ON EQU %10000000 ; LCD Control Operation
+LCDCF_BGON EQU %00000001
+DEF rLCDC EQU $FF41
+DEF rUNSUPPORTED EQUS "rLCDC"
+ENDC
+"#;
+    let instructions = ggbasm::hardware::parse_hardware_inc(text);
+    assert_eq!(instructions.len(), text.lines().count());
+    let rom = builder()
+        .add_instructions(instructions)
+        .unwrap()
+        .add_instructions(vec![
+            Instruction::LdR8I8(
+                Reg8::A,
+                Expr::binary(
+                    Expr::Ident(String::from("LCDCF_ON")),
+                    BinaryOperator::Or,
+                    Expr::Ident(String::from("LCDCF_BGON")),
+                ),
+            ),
+            Instruction::LdMI16Ra(Expr::Ident(String::from("rLCDC"))),
+        ])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x155], &[0x3E, 0x81, 0xEA, 0x40, 0xFF]);
+}
+
+#[test]
+fn test_anonymous_labels() {
+    let text = r#"
+:
+    dec a
+    jr nz, :-
+    jr :+
+    nop
+:
+    jp :--
+"#;
+    let rom = ggbasm::conformance::assemble(text).unwrap();
+    assert_eq!(
+        rom,
+        vec![0x3D, 0x20, 0xFD, 0x18, 0x01, 0x00, 0xC3, 0x00, 0x00]
+    );
+
+    assert!(ggbasm::conformance::assemble(":\njr :++\n").is_err());
+    assert!(ggbasm::conformance::assemble("jr :-\n").is_err());
+}
+
+#[test]
+fn test_estimate() {
+    let estimate = builder()
+        .add_instructions(vec![Instruction::Nop, Instruction::Db(vec![1, 2, 3])])
+        .unwrap()
+        .advance_address(Bank(1), BankAddr(0x4010))
+        .unwrap()
+        .add_bytes(vec![0; 0x10], "Data")
+        .unwrap()
+        .estimate()
+        .unwrap();
+    assert_eq!(estimate.rom_size, 0x8000);
+    assert_eq!(
+        estimate.banks,
+        vec![
+            BankUsage {
+                bank: 0,
+                used: 0x154,
+                free: 0x4000 - 0x154
+            },
+            BankUsage {
+                bank: 1,
+                used: 0x10,
+                free: 0x4000 - 0x10
+            },
+        ]
+    );
+
+    // the size matches the size compile pads the rom to
+    let estimate = builder().rom_banks(8).unwrap().estimate().unwrap();
+    assert_eq!(estimate.rom_size, 0x20000);
+    assert_eq!(estimate.banks.len(), 8);
+    let err = builder()
+        .rom_banks(2)
+        .unwrap()
+        .advance_address(Bank(2), BankAddr(0x4000))
+        .unwrap()
+        .add_bytes(vec![0], "Data")
+        .unwrap()
+        .estimate()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "ROM is too big for the 2 rom banks set by rom_banks, raw ROM size was 32769"
+    );
+
+    let mut bytes = RomBuilder::new()
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_header(Header {
+            cartridge_type: CartridgeType::Mbc5,
+            ..header()
+        })
+        .unwrap()
+        .rom_banks(4)
+        .unwrap()
+        .compile()
+        .unwrap()[0x104..0x150]
+        .to_vec();
+    let estimate = RomBuilder::new()
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_bytes(bytes.clone(), "Header")
+        .unwrap()
+        .estimate()
+        .unwrap();
+    assert_eq!(estimate.rom_size, 0x10000);
+    bytes[0x44] = 0x20;
+    let err = RomBuilder::new()
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_bytes(bytes, "Header")
+        .unwrap()
+        .estimate()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The rom size factor 0x20 in the header at 0x0148 is larger than any rom size"
+    );
+}
+
+#[test]
+fn test_rst_invalid_vector() {
+    assert!(ggbasm::conformance::assemble("rst 0x40\n").is_err());
+    assert!(ggbasm::conformance::assemble("rst 0x09\n").is_err());
+}
+
+#[test]
+fn test_shift_out_of_range() {
+    assert!(ggbasm::conformance::assemble("ld a, 1 << 64\n").is_err());
+    assert!(ggbasm::conformance::assemble("ld a, 1 >> -1\n").is_err());
+}
+
+#[test]
+fn test_add_assets_error() {
+    let jobs = vec![
+        ggbasm::asset::AssetJob::Audio {
+            file_name: String::from("missing_song.txt"),
+            options: Default::default(),
+        },
+        ggbasm::asset::AssetJob::Image {
+            file_name: String::from("missing_image.png"),
+            identifier: String::from("Image"),
+            color_map: Default::default(),
+        },
+    ];
+    let err = builder().add_assets(&jobs).err().unwrap();
+    assert!(err.to_string().contains("missing_song.txt"));
+    assert!(builder().add_assets(&[]).is_ok());
+}
+
+#[test]
+fn test_unmapped_colors() {
+    let dir = std::env::temp_dir().join("ggbasm_test_unmapped_colors");
+    std::fs::create_dir_all(dir.join("graphics")).unwrap();
+    let convert = |colors: &[[u8; 3]], color_map: &[([u8; 3], u8)]| {
+        // one 8x8 tile, each color fills a row and the last color fills the remaining rows
+        let image = image::RgbImage::from_fn(8, 8, |_, y| {
+            image::Rgb(colors[(y as usize).min(colors.len() - 1)])
+        });
+        image.save(dir.join("graphics").join("tile.png")).unwrap();
+        ggbasm::asset::AssetJob::Image {
+            file_name: String::from("tile.png"),
+            identifier: String::from("Tile"),
+            color_map: color_map
+                .iter()
+                .map(|([r, g, b], gb_color)| (Color::new(*r, *g, *b), *gb_color))
+                .collect(),
+        }
+        .convert(&dir)
+        .err()
+        .unwrap()
+        .to_string()
+    };
+
+    let white = [0xFF, 0xFF, 0xFF];
+    let red = [0xFF, 0x00, 0x00];
+    let black = [0x00, 0x00, 0x00];
+    assert_eq!(
+        convert(&[white, red, black], &[(white, 0)]),
+        r#"Image tile.png contains 2 colors that are not mapped to a gameboy color:
+    Color::new(0x0, 0x0, 0x0) used by 48 pixels
+    Color::new(0xff, 0x0, 0x0) used by 8 pixels
+The image only uses 3 colors so this color map could be used:
+    let mut color_map = HashMap::new();
+    color_map.insert(Color::new(0xff, 0xff, 0xff), 0);
+    color_map.insert(Color::new(0xff, 0x0, 0x0), 2);
+    color_map.insert(Color::new(0x0, 0x0, 0x0), 3);
+"#
+    );
+
+    // too many colors to suggest a color map
+    let colors = [white, red, [0x00, 0xFF, 0x00], [0x00, 0x00, 0xFF], black];
+    assert_eq!(
+        convert(&colors, &[(white, 0), (red, 1), (black, 3)]),
+        r#"Image tile.png contains 2 colors that are not mapped to a gameboy color:
+    Color::new(0x0, 0x0, 0xff) used by 8 pixels
+    Color::new(0x0, 0xff, 0x0) used by 8 pixels
+"#
+    );
+}
+
+#[test]
+fn test_assert() {
+    let text = r#"
+Table:
+    db 1, 2, 3
+TableEnd:
+    assert TableEnd - Table == 3 && Table >= 0x150, "Table is the wrong size"
+"#;
+    assert!(conditional_rom(builder(), text).is_ok());
+
+    let err = conditional_rom(builder(), "assert 2 > 3, \"Two is too small\"\n").unwrap_err();
+    assert!(err.to_string().contains("Two is too small"));
+
+    // the right side of || is not evaluated when the left side is true
+    assert!(conditional_rom(builder(), "assert 1 || Undeclared\n").is_ok());
+}
+
+#[test]
+fn test_messages() {
+    let text = r#"
+TableSize EQU 3
+Table:
+    db 1, 2, 3
+TableEnd:
+    print "Table is ", TableEnd - Table, " bytes"
+    warn "Table starts at ", Table
+IF TableSize > 2
+    fail "Table is ", TableEnd - Table, " bytes but must fit in 2"
+ENDC
+"#;
+    let err = conditional_rom(builder(), text).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 9: Table is 3 bytes but must fit in 2"
+    );
+
+    let (_, layout) = builder()
+        .add_instructions(parse(&text.replace("> 2", "> 3")))
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(
+        layout.warnings,
+        vec![String::from(
+            "Warning in data generated by rust code on line 7: Table starts at 336"
+        )]
+    );
+    assert_eq!(layout.messages, vec![String::from("Table is 3 bytes")]);
+}
+
+#[test]
+fn test_warn_unused() {
+    let text = r#"
+Main:
+    ld a, [Used]
+    call Routine
+Sound EQU 2
+Unused EQU 3
+IF Sound
+    ld a, Volume
+ENDC
+Volume EQU 4
+Routine:
+    ret
+Dead:
+    ret
+Used:
+    db 1
+Table:
+    db 2, 3
+"#;
+    let instructions = parse(text);
+    let (_, layout) = builder()
+        .warn_unused()
+        .unwrap()
+        .add_instructions(instructions)
+        .unwrap()
+        .add_bytes(vec![1, 2], "Pinned")
+        .unwrap()
+        .add_bytes(vec![1, 2], "Asset")
+        .unwrap()
+        .pin_symbol("Pinned", 0x15D)
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(
+        layout.warnings,
+        vec![
+            String::from("Warning in rust code: Data Asset is never referred to by a jump, call or pointer"),
+            String::from("Warning in data generated by rust code on line 13: Label Dead is never referred to"),
+            String::from("Warning in data generated by rust code on line 17: Data Table is never referred to by a jump, call or pointer"),
+            String::from("Warning in data generated by rust code on line 6: Constant Unused is never referred to"),
+        ]
+    );
+
+    // only reported when enabled
+    let (_, layout) = builder()
+        .add_bytes(vec![1, 2], "Asset")
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert!(layout.warnings.is_empty());
+}
+
+#[test]
+fn test_conditionals_comparison() {
+    let text = "IF DEBUG == 1 && Level >= 2\n    db 0x01\nELSE\n    db 0x02\nENDC\n";
+    let rom = conditional_rom(
+        builder()
+            .define("DEBUG", 1)
+            .unwrap()
+            .define("Level", 2)
+            .unwrap(),
+        text,
+    )
+    .unwrap();
+    assert_eq!(rom[0x150], 0x01);
+}
+
+#[test]
+fn test_db_expressions() {
+    let text = r#"
+Offset EQU 3
+Table:
+    db Offset + 1, Table & 0xFF, Table >> 8, -1
+"#;
+    let rom = conditional_rom(builder(), text).unwrap();
+    assert_eq!(&rom[0x150..0x154], &[0x04, 0x50, 0x01, 0xFF]);
+
+    let err = conditional_rom(builder(), "db Undeclared\n").unwrap_err();
+    assert!(err.to_string().contains("Undeclared"));
+}
+
+#[test]
+fn test_dbfill() {
+    let text = r#"
+Blank EQU 0xFF
+Count EQU 2
+    dbfill 4, Blank
+    dbfill Count, 0x12
+After:
+    db After & 0xFF
+    dbfill 0, 1
+"#;
+    let rom = conditional_rom(builder(), text).unwrap();
+    assert_eq!(
+        &rom[0x150..0x158],
+        &[0xFF, 0xFF, 0xFF, 0xFF, 0x12, 0x12, 0x56, 0x00]
+    );
+
+    let err = conditional_rom(builder(), "    dbfill -1, 0\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 1: dbfill count -1 must be from 0 to 0x4000, the size of a rom bank"
+    );
+    let err = conditional_rom(builder(), "Big EQU 0x100\n    dbfill 2, Big\n").unwrap_err();
+    assert!(err.to_string().contains("0x100"));
+}
+
+#[test]
+fn test_sizeof() {
+    let data = builder()
+        .add_bytes(vec![0xAA; 0x123], "Tiles")
+        .unwrap()
+        .add_bytes(vec![], "Empty")
+        .unwrap();
+    let rom = conditional_rom(data, "    ld bc, SIZEOF_Tiles\n    ld de, SIZEOF_Empty\n").unwrap();
+    assert_eq!(&rom[0x273..0x279], &[0x01, 0x23, 0x01, 0x11, 0x00, 0x00]);
+
+    let err = builder()
+        .define("SIZEOF_Tiles", 2)
+        .unwrap()
+        .add_bytes(vec![0; 4], "Tiles")
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Identifier SIZEOF_Tiles is already used, it is declared in rust code"
+    );
+}
+
+#[test]
+fn test_label_placement() {
+    let text = "    jp Later\n    db 0x01\nLater:\n    dw Later\n";
+    let rom = conditional_rom(builder(), text).unwrap();
+    assert_eq!(&rom[0x150..0x156], &[0xC3, 0x54, 0x01, 0x01, 0x54, 0x01]);
+
+    let err = conditional_rom(builder(), "Foo:\n    nop\nFoo:\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Identifier Foo is used twice: One usage occured in data generated by rust code on line 3, the other in data generated by rust code on line 1"
+    );
+
+    let data = builder().add_bytes(vec![0], "Foo").unwrap();
+    let err = conditional_rom(data, "Foo:\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Identifier Foo is used twice: One usage occured in data generated by rust code on line 1, the other in rust code"
+    );
+}
+
+#[test]
+fn test_symbol_table() {
+    use ggbasm::symbols::SymbolKind;
+
+    let data = builder()
+        .define("Lives", 3)
+        .unwrap()
+        .declare_ram_variable("PlayerX", 0xC000, VariableType::U16)
+        .unwrap()
+        .add_bytes(vec![0; 4], "Tiles")
+        .unwrap()
+        .add_instructions(vec![Instruction::Label(String::from("Main"))])
+        .unwrap();
+    let symbols = data.symbols();
+    assert_eq!(symbols.kind("Lives"), Some(SymbolKind::Define));
+    assert_eq!(symbols.kind("SIZEOF_Tiles"), Some(SymbolKind::Define));
+    assert_eq!(symbols.info("PlayerX").unwrap().size, Some(2));
+    assert_eq!(symbols.info("Tiles").unwrap().size, Some(4));
+    assert_eq!(symbols.bank("Tiles"), Some(Bank(0)));
+    assert_eq!(symbols.bank("PlayerX"), None);
+    assert_eq!(
+        symbols.info("Main").unwrap().location(),
+        "data generated by rust code on line 1"
+    );
+    assert_eq!(symbols["Main"], 0x154);
+
+    let instructions = vec![Instruction::Equ(String::from("Speed"), Expr::Const(2))];
+    let (_, layout) = data
+        .add_instructions(instructions)
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    let kind = |name: &str| layout.symbols.iter().find(|x| x.name == name).unwrap().kind;
+    assert_eq!(kind("Main"), Some(SymbolKind::Label));
+    assert_eq!(kind("Speed"), Some(SymbolKind::Equ));
+    assert_eq!(kind("PlayerX"), Some(SymbolKind::RamVariable));
+
+    let err = conditional_rom(builder(), "Foo:\n    nop\nFoo EQU 2\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Identifier Foo is used twice: One usage occured in data generated by rust code on line 3, the other in data generated by rust code on line 1"
+    );
+}
+
+#[test]
+fn test_namespaces() {
+    let text = "namespace Audio\nInit:\n    call Play\nVolume EQU 3\n    ld a, Volume\nPlay:\n    ret\nendnamespace\n    call Audio.Init\n    jp Init\nInit:\n";
+    let rom = conditional_rom(builder(), text).unwrap();
+    assert_eq!(
+        &rom[0x150..0x15C],
+        &[0xCD, 0x55, 0x01, 0x3E, 0x03, 0xC9, 0xCD, 0x50, 0x01, 0xC3, 0x5C, 0x01]
+    );
+
+    let (_, layout) = builder()
+        .namespace("Player")
+        .unwrap()
+        .define("Speed", 2)
+        .unwrap()
+        .add_bytes(vec![1, 2], "Sprite")
+        .unwrap()
+        .namespace("Input")
+        .unwrap()
+        .add_instructions(parse("Read:\n    ld a, Speed\n    ld hl, Sprite\n"))
+        .unwrap()
+        .end_namespace()
+        .unwrap()
+        .add_instructions(parse(
+            "X EQU Speed + SIZEOF_Sprite\nUpdate:\n    call Input.Read\n",
+        ))
+        .unwrap()
+        .end_namespace()
+        .unwrap()
+        .add_instructions(parse("    call Player.Update\n    ld a, Player.X\n"))
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    let value = |name: &str| {
+        layout
+            .symbols
+            .iter()
+            .find(|x| x.name == name)
+            .unwrap()
+            .value
+    };
+    assert_eq!(value("Player.Speed"), 2);
+    assert_eq!(value("Player.SIZEOF_Sprite"), 2);
+    assert_eq!(value("Player.X"), 4);
+    assert_eq!(value("Player.Sprite"), 0x150);
+    assert_eq!(value("Player.Input.Read"), 0x152);
+    assert_eq!(value("Player.Update"), 0x157);
+
+    let dir = std::env::temp_dir().join("ggbasm_test_namespaces");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("audio.asm");
+    std::fs::write(
+        &path,
+        "    export Init\nInit:\n    call Play\nPlay:\n    ret\n",
+    )
+    .unwrap();
+    let rom = builder()
+        .namespace("Audio")
+        .unwrap()
+        .add_asm_file(path.to_str().unwrap())
+        .unwrap()
+        .end_namespace()
+        .unwrap()
+        .add_instructions(parse("    call Audio.Init\n"))
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(
+        &rom[0x150..0x157],
+        &[0xCD, 0x53, 0x01, 0xC9, 0xCD, 0x50, 0x01]
+    );
+
+    let err = conditional_rom(builder(), "    nop\n    endnamespace\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 2: endnamespace has no matching namespace"
+    );
+    let err =
+        conditional_rom(builder(), "namespace Audio\nnamespace Sfx\nendnamespace\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 1: namespace Audio has no matching endnamespace"
+    );
+    let err = builder().end_namespace().err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "end_namespace was called but no namespace is open"
+    );
+    let err = builder().namespace("Audio.").err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Namespace Audio. is not a valid identifier"
+    );
+}
+
+#[test]
+fn test_jump_banks() {
+    let banked = |instructions: Vec<Instruction>| {
+        let mut mbc5 = header();
+        mbc5.cartridge_type = CartridgeType::Mbc5;
+        RomBuilder::new()
+            .unwrap()
+            .add_basic_interrupts_and_jumps()
+            .unwrap()
+            .add_header(mbc5)
+            .unwrap()
+            .add_instructions(vec![
+                Instruction::Label(String::from("Home")),
+                Instruction::Call(Flag::Always, Expr::Ident(String::from("Bank1"))),
+            ])
+            .unwrap()
+            .advance_address(Bank(1), BankAddr(0x4000))
+            .unwrap()
+            .add_instructions(vec![Instruction::Label(String::from("Bank1"))])
+            .unwrap()
+            .advance_address(Bank(2), BankAddr(0x4000))
+            .unwrap()
+            .add_instructions(instructions)
+            .unwrap()
+            .compile()
+    };
+    let call = |label: &str| Instruction::Call(Flag::Always, Expr::Ident(String::from(label)));
+
+    banked(vec![
+        call("Home"),
+        Instruction::Label(String::from("Bank2")),
+        call("Bank2"),
+    ])
+    .unwrap();
+
+    let err = banked(vec![Instruction::Nop, call("Bank1")]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 2: Jumps to Bank1 in rom bank 1 from rom bank 2, only bank 0 and the current bank are mapped in"
+    );
+}
+
+#[test]
+fn test_typed_addresses() {
+    use ggbasm::address::GlobalAddr;
+
+    let address = GlobalAddr::new(Bank(5), BankAddr(0x4010)).unwrap();
+    assert_eq!(address, GlobalAddr(0x14010));
+    assert_eq!(address.bank(), Bank(5));
+    assert_eq!(address.bank_addr(), BankAddr(0x4010));
+    assert_eq!(GlobalAddr(0x150).bank_addr(), BankAddr(0x150));
+
+    let builder = builder()
+        .advance_address(Bank(1), BankAddr(0x4010))
+        .unwrap();
+    assert_eq!(builder.get_address_global(), GlobalAddr(0x4010));
+    assert_eq!(builder.get_address_bank(), BankAddr(0x4010));
+    assert_eq!(builder.get_bank(), Bank(1));
+
+    let err = builder
+        .advance_address(Bank(2), BankAddr(0x10))
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Address 0x0010 is not in bank 2, which is mapped to 0x4000-0x7FFF"
+    );
+    let err = GlobalAddr::new(Bank(0), BankAddr(0x4000)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Address 0x4000 is not in bank 0, which is mapped to 0x0000-0x3FFF"
+    );
+    let err = GlobalAddr::new(Bank(512), BankAddr(0x4000)).unwrap_err();
+    assert_eq!(err.to_string(), "Bank 512 is larger than the last bank 511");
+}
+
+#[test]
+fn test_advance_address_backwards() {
+    let text = "    db 0x01\n    advance_address 0x100\n";
+    let err = conditional_rom(builder(), text).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 2: advance_address 0x100 is before the current address 0x151"
+    );
+}
+
+#[test]
+fn test_advance_address_expressions() {
+    let text = "Table EQU 0x4010\n    db 1\n    advance_address 1, Table\n    db 2\n    advance_address SIZEOF_Padding * 8\n    db 3\n";
+    let bank_1 = || {
+        builder()
+            .add_bytes(vec![0; 4], "Padding")
+            .unwrap()
+            .advance_address(Bank(1), BankAddr(0x4000))
+            .unwrap()
+    };
+    let rom = conditional_rom(bank_1(), text).unwrap();
+    assert_eq!(rom[0x4000], 1);
+    assert_eq!(rom[0x4010], 2);
+    assert_eq!(rom[0x4011..0x4020], [0; 15]);
+    assert_eq!(rom[0x4020], 3);
+
+    let text = "    advance_address 2, 0x4010\n";
+    let err = conditional_rom(bank_1(), text).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 1: advance_address is for bank 2 but is in bank 1"
+    );
+
+    let text = "    advance_address 1, 0x10\n";
+    let err = conditional_rom(bank_1(), text).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 1: advance_address 0x10 is not in a switchable bank, which is mapped to 0x4000-0x7FFF"
+    );
+}
+
+#[test]
+fn test_advance_address_private_label() {
+    let dir = std::env::temp_dir().join("ggbasm_test_advance_address_private_label");
+    std::fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("main.asm");
+    std::fs::write(
+        &asm,
+        "Start:\n    nop\n    advance_address Start + 4\n    db 1\n",
+    )
+    .unwrap();
+    let rom = builder()
+        .add_asm_file(asm.to_str().unwrap())
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x155], &[0x00, 0x00, 0x00, 0x00, 0x01]);
+}
+
+#[test]
+fn test_dw_jump_table() {
+    let text = r#"
+JumpTable:
+    dw Jump0, Jump1
+Jump0:
+    ret
+Jump1:
+    ret
+"#;
+    let rom = conditional_rom(builder(), text).unwrap();
+    assert_eq!(&rom[0x150..0x156], &[0x54, 0x01, 0x55, 0x01, 0xC9, 0xC9]);
+}
+
+#[test]
+fn test_add_bank_from_file_errors() {
+    let err = builder()
+        .add_bank_from_file(Bank(0), "bank.bin")
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Cannot add bank.bin to rom bank 0, it is reserved for the header and interrupts"
+    );
+
+    let err = builder()
+        .add_bank_from_file(Bank(1), "does_not_exist.bin")
+        .err()
+        .unwrap();
+    assert!(err
+        .to_string()
+        .starts_with("Cannot read binary file does_not_exist.bin"));
+}
+
+#[test]
+fn test_charmap() {
+    let text = r#"
+    db "AB"
+    charmap "A", 0x80
+    charmap "B", 0x80 + 1
+    db "AB!", 0x01
+"#;
+    let rom = conditional_rom(builder(), text).unwrap();
+    assert_eq!(&rom[0x150..0x156], &[0x41, 0x42, 0x80, 0x81, 0x21, 0x01]);
+
+    let err = conditional_rom(builder(), "db \"é\"\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 1: Character 'é' is not ASCII and is not in the charmap"
+    );
+}
+
+fn strict_warnings(text: &str) -> Vec<String> {
+    let instructions = parse(text);
+    let (_, layout) = builder()
+        .lint_profile(LintProfile::HardwareStrict)
+        .unwrap()
+        .add_instructions(instructions)
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    layout.warnings
+}
+
+#[test]
+fn test_lint_hardware_strict() {
+    let text = r#"
+    ld a, [0xC000]
+    ld [0xC001], a
+    ld a, [0xC001]
+    ld [0xFF00 + 0x41], a
+    ld a, [0xFF44]
+"#;
+    assert!(conditional_rom(builder(), text).is_ok());
+
+    assert_eq!(
+        strict_warnings(text),
+        vec![
+            "Warning in data generated by rust code on line 2: 0xC000 is read but never written, RAM is uninitialized on real hardware",
+            "Warning in data generated by rust code on line 5: Writing to rSTAT (0xFF41) can trigger a spurious STAT interrupt on DMG hardware",
+        ]
+    );
+
+    // clearing WRAM through hl initializes it, but not HRAM
+    let text = r#"
+    ld hl, 0xC000
+    ld bc, 0x2000
+    xor a
+Clear:
+    ld [hl+], a
+    dec bc
+    ld a, b
+    or c
+    jr nz, Clear
+    ld a, [0xC123]
+    ld a, [0xFF90]
+"#;
+    assert_eq!(
+        strict_warnings(text),
+        vec![
+            "Warning in data generated by rust code on line 12: 0xFF90 is read but never written, RAM is uninitialized on real hardware",
+        ]
+    );
+
+    // a store through an unknown pointer could initialize any RAM
+    let text = "    pop de\n    ld [de], a\n    ld a, [0xFF90]\n";
+    assert!(strict_warnings(text).is_empty());
+}
+
+#[test]
+fn test_illegal_addresses() {
+    let err = conditional_rom(builder(), "ld a, [0xE010]").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 1: 0xE010 is in echo RAM (0xE000-0xFDFF), it must not be accessed"
+    );
+
+    let err = conditional_rom(builder(), "ld [0xFEA0], a").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 1: 0xFEA0 is in the unusable region (0xFEA0-0xFEFF), it must not be accessed"
+    );
+
+    let err = conditional_rom(builder(), "nop\nld [0x2000], a").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 2: Invalid write to 0x2000 in ROM: there is no MBC to receive the write"
+    );
+
+    // reading rom and writing to rom with an MBC are fine
+    assert!(conditional_rom(builder(), "ld a, [0x2000]").is_ok());
+    assert!(mapper_rom(CartridgeType::Mbc5, "ld [0x2000], a").is_ok());
+}
+
+fn mapper_rom(cartridge_type: CartridgeType, text: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut header = header();
+    header.cartridge_type = cartridge_type;
+    let builder = RomBuilder::new()
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_header(header)
+        .unwrap();
+    conditional_rom(builder, text)
+}
+
+#[test]
+fn test_mbc_register_writes() {
+    // selecting bank 0 selects bank 1 instead, which is a common idiom so it is only a warning
+    let mut header = header();
+    header.cartridge_type = CartridgeType::Mbc1;
+    let instructions = parse("xor a\nld [0x2000], a");
+    let (_, layout) = RomBuilder::new()
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_header(header)
+        .unwrap()
+        .add_instructions(instructions)
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(
+        layout.warnings,
+        vec![String::from(
+            "Warning in data generated by rust code on line 2: Write to 0x2000 in ROM: writing bank 0 to the Mbc1 ROM bank register selects bank 1"
+        )]
+    );
+
+    let err = mapper_rom(CartridgeType::Mbc1, "ld a, 0x20\nld [0x2000], a").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 2: Invalid write to 0x2000 in ROM: 0x20 is too large for the Mbc1 ROM bank register, the maximum is 0x1F"
+    );
+
+    let err = mapper_rom(CartridgeType::Mbc5, "ld a, 2\nld [0x3000], a").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 2: Invalid write to 0x3000 in ROM: 0x02 is too large for the Mbc5 ROM bank high register, the maximum is 0x01"
+    );
+
+    let err = mapper_rom(CartridgeType::Mbc5, "ld [0x6000], a").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error occured in data generated by rust code on line 1: Invalid write to 0x6000 in ROM: there is no Mbc5 register at this address"
+    );
+
+    // MBC5 can select bank 0 and unknown values of a are not validated
+    assert!(mapper_rom(CartridgeType::Mbc5, "xor a\nld [0x2000], a").is_ok());
+    assert!(mapper_rom(CartridgeType::Mbc1, "ld a, b\nld [0x2000], a").is_ok());
+    assert!(mapper_rom(CartridgeType::Mbc3, "ld a, 0x08\nld [0x4000], a").is_ok());
+}
+
+#[test]
+fn test_from_source_dir() {
+    let dir = std::env::temp_dir().join("ggbasm_test_source_dir");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("main.asm"),
+        "    ld hl, Tiles\n    include \"util.asm\"\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("util.asm"), "    ld a, SIZEOF_Tiles\n").unwrap();
+    std::fs::write(dir.join("audio.asm"), "    ret\n").unwrap();
+    std::fs::write(dir.join("Tiles.bin"), [0xAB, 0xCD]).unwrap();
+    std::fs::write(dir.join("notes.txt"), "ignored").unwrap();
+
+    let rom = RomBuilder::from_source_dir(&dir)
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(&rom[0x134..0x13F], b"GGBASM_TEST");
+    assert_eq!(rom[0x147], 0x19); // MBC5
+    assert_eq!(
+        &rom[0x150..0x159],
+        &[0x21, 0x56, 0x01, 0x3E, 0x02, 0xC9, 0xAB, 0xCD, 0x00]
+    );
+
+    let err = RomBuilder::from_source_dir(dir.join("missing"))
+        .err()
+        .unwrap();
+    assert!(err.to_string().starts_with("Cannot read directory"));
+}
+
+#[test]
+fn test_symbol_snapshot() {
+    let path = std::env::temp_dir().join("ggbasm_test_symbol_snapshot.json");
+    let _ = std::fs::remove_file(&path);
+    let snapshot = path.to_str().unwrap();
+
+    let (_, layout) = builder()
+        .symbol_snapshot(snapshot)
+        .unwrap()
+        .add_instructions(vec![
+            Instruction::Label(String::from("Main")),
+            Instruction::Label(String::from("Removed")),
+            Instruction::Equ(String::from("Same"), Expr::Const(3)),
+        ])
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(layout.symbol_drift, vec![]);
+
+    let (_, layout) = builder()
+        .symbol_snapshot(snapshot)
+        .unwrap()
+        .add_instructions(vec![
+            Instruction::Nop,
+            Instruction::Label(String::from("Main")),
+            Instruction::Equ(String::from("Same"), Expr::Const(3)),
+        ])
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(
+        layout.symbol_drift,
+        vec![
+            SymbolDrift {
+                name: String::from("Main"),
+                previous: 0x150,
+                current: Some(0x151),
+            },
+            SymbolDrift {
+                name: String::from("Removed"),
+                previous: 0x150,
+                current: None,
+            },
+        ]
+    );
+    assert_eq!(
+        layout.symbol_drift[0].to_string(),
+        "Main moved from 0x150 to 0x151"
+    );
+    assert_eq!(
+        layout.symbol_drift[1].to_string(),
+        "Removed at 0x150 was removed"
+    );
+}
+
+#[test]
+fn test_pin_symbol() {
+    let pinned = || {
+        builder()
+            .declare_ram_variable("PlayerHP", 0xC100, VariableType::U8)
+            .unwrap()
+            .pin_symbol("PlayerHP", 0xC100)
+            .unwrap()
+            .pin_symbol("Main", 0x150)
+            .unwrap()
+    };
+
+    pinned()
+        .add_instructions(vec![Instruction::Label(String::from("Main"))])
+        .unwrap()
+        .compile()
+        .unwrap();
+
+    let err = pinned()
+        .add_instructions(vec![
+            Instruction::Nop,
+            Instruction::Label(String::from("Main")),
+        ])
+        .unwrap()
+        .compile()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Pinned symbols moved:\n*   Main is pinned to 0x0150 but is at 0x0151\n"
+    );
+
+    let err = pinned().compile().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Pinned symbols moved:\n*   Main is pinned to 0x0150 but is never declared\n"
+    );
+
+    assert!(pinned().pin_symbol("Main", 0x150).is_err());
+}
+
+#[test]
+fn test_deduplicate_data() {
+    let table = vec![1, 2, 3, 4];
+    let header = Header {
+        cartridge_type: CartridgeType::Mbc1,
+        ..header()
+    };
+    let (rom, layout) = RomBuilder::new()
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_header(header)
+        .unwrap()
+        .deduplicate_data()
+        .unwrap()
+        .add_bytes(table.clone(), "TableA")
+        .unwrap()
+        .add_bytes(vec![5, 6], "Other")
+        .unwrap()
+        .add_bytes(table.clone(), "TableB")
+        .unwrap()
+        .advance_address(Bank(1), BankAddr(0x4000))
+        .unwrap()
+        .add_bytes(vec![7], "Unique")
+        .unwrap()
+        .add_bytes(vec![5, 6], "OtherCopy")
+        .unwrap()
+        .advance_address(Bank(2), BankAddr(0x4000))
+        .unwrap()
+        .add_bytes(vec![7], "UniqueCopy")
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(&rom[0x150..0x157], &[1, 2, 3, 4, 5, 6, 0]);
+    assert_eq!(layout.symbol("TableB"), Some(0x150));
+    assert_eq!(layout.symbol("OtherCopy"), Some(0x154));
+    // bank 1 cannot be accessed while bank 2 is switched in
+    assert_eq!(layout.symbol("UniqueCopy"), Some(0x8000));
+    assert_eq!(
+        layout.deduplicated,
+        vec![
+            DeduplicatedData {
+                name: String::from("TableB"),
+                address: 0x150,
+                size: 4,
+            },
+            DeduplicatedData {
+                name: String::from("OtherCopy"),
+                address: 0x154,
+                size: 2,
+            },
+        ]
+    );
+    assert_eq!(layout.deduplicated_bytes(), 6);
+
+    // disabled by default
+    let (_, layout) = builder()
+        .add_bytes(table.clone(), "TableA")
+        .unwrap()
+        .add_bytes(table, "TableB")
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(layout.symbol("TableB"), Some(0x154));
+    assert_eq!(layout.deduplicated, vec![]);
+}
+
+#[test]
+fn test_fill_free_space() {
+    let fill = |capacity, target| {
+        builder()
+            .add_fill_region_table(capacity)
+            .unwrap()
+            .advance_address(Bank(0), BankAddr(0x200))
+            .unwrap()
+            .add_instructions(vec![
+                Instruction::Label(String::from("Main")),
+                Instruction::JpI16(Flag::Always, target),
+            ])
+            .unwrap()
+            .compile_with_layout()
+    };
+
+    let (rom, layout) = fill(3, Expr::Ident(String::from("Main"))).unwrap();
+    let table = layout.symbol("GGBASMFillRegions").unwrap() as usize;
+    assert_eq!(
+        &rom[table..table + 16],
+        &[3, 0, 0x60, 0x01, 0x00, 0x02, 0, 0x03, 0x02, 0x00, 0x40, 1, 0x00, 0x40, 0x00, 0x80]
+    );
+    assert_eq!(&rom[0x15F..0x161], &[0x80, 0xFF]);
+    assert_eq!(&rom[0x1FF..0x204], &[0xFF, 0xC3, 0x00, 0x02, 0xFF]);
+    assert_eq!(rom[0x7FFF], 0xFF);
+
+    assert_eq!(
+        fill(2, Expr::Ident(String::from("Main")))
+            .unwrap_err()
+            .to_string(),
+        "There are 3 fill regions but the fill region table only has room for 2"
+    );
+    assert_eq!(
+        fill(3, Expr::Const(0x180)).unwrap_err().to_string(),
+        "Error occured in data generated by rust code on line 2: Jumps to 0x180 which is free space filled with `rst 0x38`"
+    );
+
+    // free space is zeroed by default
+    let rom = builder()
+        .add_instructions(vec![Instruction::Nop])
+        .unwrap()
+        .compile()
+        .unwrap();
+    assert_eq!(rom[0x151], 0x00);
+}
+
+#[test]
+fn test_union_needs_ram() {
+    let text = "    nop\n    union\nwTitleCursor:\n    endu\n";
+    assert_eq!(
+        conditional_rom(builder(), text).unwrap_err().to_string(),
+        "Error occured in data generated by rust code on line 2: Unions can only be placed in RAM sections, which are not supported yet"
+    );
+}
+
+#[test]
+fn test_previous_layout() {
+    let dir = std::env::temp_dir().join("ggbasm_test_previous_layout");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("main.asm");
+    let layout_path = dir.join("layout.json");
+    let build = |text: &str| {
+        std::fs::write(&asm, text).unwrap();
+        builder()
+            .previous_layout(layout_path.to_str().unwrap())
+            .unwrap()
+            .add_asm_file(asm.to_str().unwrap())
+            .unwrap()
+            .compile_with_layout()
+            .unwrap()
+    };
+
+    // without a previous layout the sections are placed as usual
+    let (rom, layout) =
+        build("SECTION \"First\", ROMX\n    db 1, 2\nSECTION \"Second\", ROMX\n    db 3, 4\n");
+    assert_eq!(&rom[0x4000..0x4004], &[1, 2, 3, 4]);
+    std::fs::write(&layout_path, layout.to_json().unwrap()).unwrap();
+
+    // First no longer fits so it moves after the space used by the previous build, Second stays in place
+    let (rom, _) = build(
+        "SECTION \"First\", ROMX\n    db 1, 2, 5\nSECTION \"Second\", ROMX\n    db 3, 4\nSECTION \"Third\", ROMX\n    db 6, SIZEOF_First\n",
+    );
+    assert_eq!(&rom[0x4000..0x4009], &[0, 0, 3, 4, 1, 2, 5, 6, 3]);
+
+    std::fs::write(&layout_path, "not json").unwrap();
+    let err = builder()
+        .previous_layout(layout_path.to_str().unwrap())
+        .err()
+        .unwrap();
+    assert!(err.to_string().starts_with("Cannot read previous layout"));
+}
+
+#[test]
+fn test_profile_build() {
+    let (_, layout) = builder()
+        .add_instructions(vec![Instruction::Nop])
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(layout.profile, None);
+
+    let (_, layout) = builder()
+        .profile_build()
+        .unwrap()
+        .add_instructions(vec![Instruction::Nop])
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    let profile = layout.profile.unwrap();
+    assert!(profile.encoding > std::time::Duration::ZERO);
+    assert_eq!(profile.images, std::time::Duration::ZERO);
+    assert_eq!(
+        profile.total(),
+        profile.parsing + profile.constants + profile.encoding
+    );
+    if cfg!(target_os = "linux") {
+        assert!(profile.peak_memory.unwrap() > 0);
+    }
+    let report = profile.to_string();
+    assert!(report.starts_with("Build profile:\n  parsing"));
+    assert!(report.contains("  peak memory"));
+}
+
+#[test]
+fn test_asm_file_line_numbers() {
+    let dir = std::env::temp_dir().join("ggbasm_test_asm_file_line_numbers");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("main.asm");
+    std::fs::write(
+        &path,
+        "TwoNops: MACRO\n    nop\n    nop\nENDM\n    TwoNops\n    db 1, \\\n       2\n    jp Missing\n",
+    )
+    .unwrap();
+    let err = builder()
+        .add_asm_file(path.to_str().unwrap())
+        .unwrap()
+        .compile()
+        .unwrap_err();
+    assert!(err.to_string().starts_with(&format!(
+        "Error occured in instructions generated by asm file {} on line 8: ",
+        path.to_str().unwrap()
+    )));
+
+    std::fs::write(
+        &path,
+        "TwoNops: MACRO\n    nop\n    nop\nENDM\n    TwoNops\n    SECTION \"Code\", ROM0\n    ld a, [$E000]\n",
+    )
+    .unwrap();
+    let err = builder()
+        .add_asm_file(path.to_str().unwrap())
+        .unwrap()
+        .compile()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Error occured in section Code in asm file {} on line 7: 0xE000 is in echo RAM (0xE000-0xFDFF), it must not be accessed",
+            path.to_str().unwrap()
+        )
+    );
+}
+
+#[test]
+fn test_label_privacy() {
+    let dir = std::env::temp_dir().join("ggbasm_test_label_privacy");
+    std::fs::create_dir_all(&dir).unwrap();
+    let audio = dir.join("audio.asm");
+    let main = dir.join("main.asm");
+    std::fs::write(
+        &audio,
+        "    export Init\nInit:\n    call Play\n    ret\nPlay:\n    ret\n",
+    )
+    .unwrap();
+    std::fs::write(&main, "    call Init\n").unwrap();
+    let (rom, layout) = builder()
+        .add_asm_file(audio.to_str().unwrap())
+        .unwrap()
+        .add_asm_file(main.to_str().unwrap())
+        .unwrap()
+        .compile_with_layout()
+        .unwrap();
+    assert_eq!(
+        &rom[0x150..0x158],
+        &[0xCD, 0x54, 0x01, 0xC9, 0xC9, 0xCD, 0x50, 0x01]
+    );
+
+    // private labels are listed by their name along with the file they are private to
+    let play = layout.symbols.iter().find(|x| x.value == 0x154).unwrap();
+    assert_eq!(play.name, "Play");
+    assert_eq!(play.private_to, Some(audio.to_str().unwrap().to_string()));
+    let init = layout.symbols.iter().find(|x| x.name == "Init").unwrap();
+    assert_eq!(init.private_to, None);
+    assert!(ggbasm::output::sym_file(&layout).contains("00:0154 Play\n"));
+
+    // an unexported label can not be referred to by another asm file
+    std::fs::write(&main, "    call Play\n").unwrap();
+    let err = builder()
+        .add_asm_file(audio.to_str().unwrap())
+        .unwrap()
+        .add_asm_file(main.to_str().unwrap())
+        .unwrap()
+        .compile()
+        .unwrap_err();
+    assert_eq!(err.to_string(), format!("Error occured in instructions generated by asm file {} on line 1: Identifier Play can not be found. It is a label private to {}, export it with `export Play` to use it in other files.", main.display(), audio.display()));
+
+    // or by rust code
+    let instructions = parse("    call Play\n");
+    let err = builder()
+        .add_asm_file(audio.to_str().unwrap())
+        .unwrap()
+        .add_instructions(instructions)
+        .unwrap()
+        .compile()
+        .unwrap_err();
+    assert_eq!(err.to_string(), format!("Error occured in data generated by rust code on line 1: Identifier Play can not be found. It is a label private to {}, export it with `export Play` to use it in other files.", audio.display()));
+
+    std::fs::write(&main, "    export Missing\n    nop\n").unwrap();
+    let err = builder()
+        .add_asm_file(main.to_str().unwrap())
+        .err()
+        .unwrap();
+    assert_eq!(err.to_string(), format!("Error occured in instructions generated by asm file {} on line 1: Missing is exported but is not declared in {} or the files it includes", main.display(), main.display()));
+}
+
+#[test]
+fn test_section_placement() {
+    let dir = std::env::temp_dir().join("ggbasm_test_section_placement");
+    std::fs::create_dir_all(&dir).unwrap();
+    let main = dir.join("main.asm");
+    let big = dir.join("big.asm");
+    let build = |text: &str| {
+        std::fs::write(&main, text).unwrap();
+        let mut header = header();
+        header.cartridge_type = CartridgeType::Mbc5;
+        RomBuilder::new()
+            .unwrap()
+            .add_basic_interrupts_and_jumps()
+            .unwrap()
+            .add_header(header)
+            .unwrap()
+            .add_asm_file(main.to_str().unwrap())
+            .and_then(|x| x.compile())
+    };
+
+    let rom = build(
+        "SECTION \"Code\", ROMX[0x4100]\n    db 1\nSECTION \"Levels\", ROMX, BANK[3]\n    db 3\n",
+    )
+    .unwrap();
+    assert_eq!(rom[0x4100], 1);
+    assert_eq!(rom[0xC000], 3);
+
+    // the bytes of an included file count towards the size of the section including it
+    std::fs::write(&big, "    dbfill 0x3000, 2\n").unwrap();
+    let rom = build(&format!(
+        "SECTION \"Fill\", ROMX\n    dbfill 0x2000, 1\nSECTION \"Big\", ROMX\n    include \"{}\"\n    db 3\n",
+        big.to_str().unwrap()
+    ))
+    .unwrap();
+    assert_eq!(rom[0x5FFF], 1);
+    assert_eq!(rom[0x8000], 2);
+    assert_eq!(rom[0xB000], 3);
+
+    let err = build("SECTION \"Code\", ROMX\n    nop\nSECTION \"Home\", ROM0\n").unwrap_err();
+    assert_eq!(err.to_string(), format!("Error occured in {} on line 3: Section Home is in ROM0 but the address is already in bank 1", main.display()));
+    let err = build(
+        "SECTION \"Code\", ROMX[0x4100]\n    nop\nSECTION \"Table\", ROMX[0x4050], BANK[1]\n",
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), format!("Error occured in {} on line 3: Section Table at 0x4050 in bank 1 is before the current address 0x4101 in bank 1", main.display()));
+    let err = build("SECTION \"Code\", ROMX\n    dbfill 0x4000, 0\n    db 1\n").unwrap_err();
+    assert_eq!(err.to_string(), format!("Error occured in {} on line 1: Section Code is 16385 bytes which does not fit in a rom bank", main.display()));
+}
+
+#[test]
+fn test_banked_labels() {
+    let dir = std::env::temp_dir().join("ggbasm_test_banked_labels");
+    std::fs::create_dir_all(&dir).unwrap();
+    let main = dir.join("main.asm");
+    std::fs::write(
+        &main,
+        "    export Far\n    export Table\n    export Level\nMain:\n    jp Far\nSECTION \"Far\", ROMX, BANK[2]\nFar:\n    nop\n    jr Far\n    jp Far\n    ld hl, Table\nTable:\n    dw Far\nSECTION \"Level\", ROMX, BANK[5]\nLevel:\n    jp Level\n",
+    )
+    .unwrap();
+    let mut header = header();
+    header.cartridge_type = CartridgeType::Mbc5;
+    let builder = RomBuilder::new()
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_header(header)
+        .unwrap()
+        .add_asm_file(main.to_str().unwrap())
+        .unwrap();
+
+    // labels are addresses as seen by the cpu, along with their bank
+    let symbols = builder.symbols();
+    assert_eq!(symbols["Far"], 0x4000);
+    assert_eq!(symbols.bank("Far"), Some(Bank(2)));
+    assert_eq!(symbols.global_addr("Far"), Some(GlobalAddr(0x8000)));
+    assert_eq!(symbols["Table"], 0x4009);
+    assert_eq!(symbols["Level"], 0x4000);
+    assert_eq!(symbols.bank("Level"), Some(Bank(5)));
+    assert_eq!(symbols.global_addr("Level"), Some(GlobalAddr(0x14000)));
+
+    let (rom, layout) = builder.compile_with_layout().unwrap();
+    assert_eq!(&rom[0x150..0x153], &[0xC3, 0x00, 0x40]);
+    assert_eq!(
+        &rom[0x8000..0x800B],
+        &[0x00, 0x18, 0xFD, 0xC3, 0x00, 0x40, 0x21, 0x09, 0x40, 0x00, 0x40]
+    );
+    assert_eq!(&rom[0x14000..0x14003], &[0xC3, 0x00, 0x40]);
+
+    // the layout has the address within the entire rom
+    let far = layout.symbols.iter().find(|x| x.name == "Far").unwrap();
+    assert_eq!(far.value, 0x8000);
+}
+
+#[test]
+fn test_include_cycle() {
+    let dir = std::env::temp_dir().join("ggbasm_test_include_cycle");
+    std::fs::create_dir_all(&dir).unwrap();
+    let a = dir.join("a.asm");
+    let b = dir.join("b.asm");
+    std::fs::write(&a, format!("    include \"{}\"\n", b.display())).unwrap();
+    std::fs::write(&b, format!("    nop\n    include \"{}\"\n", a.display())).unwrap();
+    let err = builder().add_asm_file(a.to_str().unwrap()).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Include cycle detected: {0} -> {1} -> {0}",
+            a.display(),
+            b.display()
+        )
+    );
+}
+
+#[test]
+fn test_incbin_bounds() {
+    let dir = std::env::temp_dir().join("ggbasm_test_incbin_bounds");
+    std::fs::create_dir_all(&dir).unwrap();
+    let bin = dir.join("data.bin");
+    let main = dir.join("main.asm");
+    std::fs::write(&bin, [1, 2, 3, 4]).unwrap();
+    let build = |args: &str| {
+        std::fs::write(&main, format!("    incbin \"{}\"{}\n", bin.display(), args)).unwrap();
+        builder()
+            .add_asm_file(main.to_str().unwrap())
+            .and_then(|x| x.compile())
+    };
+
+    assert_eq!(&build(", 1, 2").unwrap()[0x150..0x152], &[2, 3]);
+
+    let err = build(", 5").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Error occured in {} on line 1: Cannot read binary file {} from offset 5 because it is only 4 bytes",
+            main.display(),
+            bin.display()
+        )
+    );
+
+    let err = build(", 2, 3").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Error occured in {} on line 1: Cannot read bytes 2..5 of binary file {} because it is only 4 bytes",
+            main.display(),
+            bin.display()
+        )
+    );
+}
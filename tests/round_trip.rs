@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use ggbasm::ast::*;
+use ggbasm::disassembler::disassemble;
+use ggbasm::parser::parse_asm;
+use ggbasm::printer::to_asm_string;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+fn reg8() -> impl Strategy<Value = Reg8> {
+    prop_oneof![
+        Just(Reg8::A),
+        Just(Reg8::B),
+        Just(Reg8::C),
+        Just(Reg8::D),
+        Just(Reg8::E),
+        Just(Reg8::H),
+        Just(Reg8::L),
+    ]
+}
+
+fn reg16() -> impl Strategy<Value = Reg16> {
+    prop_oneof![
+        Just(Reg16::BC),
+        Just(Reg16::DE),
+        Just(Reg16::HL),
+        Just(Reg16::SP),
+    ]
+}
+
+fn reg16_push() -> impl Strategy<Value = Reg16Push> {
+    prop_oneof![
+        Just(Reg16Push::BC),
+        Just(Reg16Push::DE),
+        Just(Reg16Push::HL),
+        Just(Reg16Push::AF),
+    ]
+}
+
+fn flag() -> impl Strategy<Value = Flag> {
+    prop_oneof![
+        Just(Flag::Always),
+        Just(Flag::Z),
+        Just(Flag::NZ),
+        Just(Flag::C),
+        Just(Flag::NC),
+    ]
+}
+
+fn i8() -> impl Strategy<Value = Expr> {
+    (0..=0xFFi64).prop_map(Expr::Const)
+}
+
+fn s8() -> impl Strategy<Value = Expr> {
+    (-0x80..=0x7Fi64).prop_map(Expr::Const)
+}
+
+fn i16() -> impl Strategy<Value = Expr> {
+    (0..=0xFFFFi64).prop_map(Expr::Const)
+}
+
+fn bit() -> impl Strategy<Value = Expr> {
+    (0..=7i64).prop_map(Expr::Const)
+}
+
+/// Every instruction that encodes to machine code which decodes back into the same instruction.
+/// Jr holds the relative offset, which is converted to an absolute address once the address is known.
+fn instruction() -> impl Strategy<Value = Instruction> {
+    let no_operands = prop_oneof![
+        Just(Instruction::Nop),
+        Just(Instruction::Stop),
+        Just(Instruction::Halt),
+        Just(Instruction::Di),
+        Just(Instruction::Ei),
+        Just(Instruction::Rrca),
+        Just(Instruction::Rra),
+        Just(Instruction::Cpl),
+        Just(Instruction::Ccf),
+        Just(Instruction::Rlca),
+        Just(Instruction::Rla),
+        Just(Instruction::Daa),
+        Just(Instruction::Scf),
+        Just(Instruction::Reti),
+        Just(Instruction::JpRhl),
+        Just(Instruction::IncMRhl),
+        Just(Instruction::DecMRhl),
+        Just(Instruction::AddMRhl),
+        Just(Instruction::SubMRhl),
+        Just(Instruction::AndMRhl),
+        Just(Instruction::OrMRhl),
+        Just(Instruction::AdcMRhl),
+        Just(Instruction::SbcMRhl),
+        Just(Instruction::XorMRhl),
+        Just(Instruction::CpMRhl),
+        Just(Instruction::LdMRbcRa),
+        Just(Instruction::LdMRdeRa),
+        Just(Instruction::LdRaMRbc),
+        Just(Instruction::LdRaMRde),
+        Just(Instruction::LdhRaMRc),
+        Just(Instruction::LdhMRcRa),
+        Just(Instruction::LdiMRhlRa),
+        Just(Instruction::LddMRhlRa),
+        Just(Instruction::LdiRaMRhl),
+        Just(Instruction::LddRaMRhl),
+        Just(Instruction::LdRspRhl),
+        Just(Instruction::RlcMRhl),
+        Just(Instruction::RrcMRhl),
+        Just(Instruction::RlMRhl),
+        Just(Instruction::RrMRhl),
+        Just(Instruction::SlaMRhl),
+        Just(Instruction::SraMRhl),
+        Just(Instruction::SwapMRhl),
+        Just(Instruction::SrlMRhl),
+    ];
+    let control_flow = prop_oneof![
+        flag().prop_map(Instruction::Ret),
+        (flag(), i16()).prop_map(|(flag, expr)| Instruction::Call(flag, expr)),
+        (0..8i64).prop_map(|x| Instruction::Rst(Expr::Const(x * 8))),
+        (flag(), i16()).prop_map(|(flag, expr)| Instruction::JpI16(flag, expr)),
+        (flag(), -0x80..=0x7Fi64).prop_map(|(flag, x)| Instruction::Jr(flag, Expr::Const(x))),
+    ];
+    let registers = prop_oneof![
+        reg16().prop_map(Instruction::IncR16),
+        reg8().prop_map(Instruction::IncR8),
+        reg16().prop_map(Instruction::DecR16),
+        reg8().prop_map(Instruction::DecR8),
+        reg16().prop_map(Instruction::AddRhlR16),
+        reg8().prop_map(Instruction::AddR8),
+        reg8().prop_map(Instruction::SubR8),
+        reg8().prop_map(Instruction::AndR8),
+        reg8().prop_map(Instruction::OrR8),
+        reg8().prop_map(Instruction::AdcR8),
+        reg8().prop_map(Instruction::SbcR8),
+        reg8().prop_map(Instruction::XorR8),
+        reg8().prop_map(Instruction::CpR8),
+        (reg8(), reg8()).prop_map(|(a, b)| Instruction::LdR8R8(a, b)),
+        reg8().prop_map(Instruction::LdR8MRhl),
+        reg8().prop_map(Instruction::LdMRhlR8),
+        reg16_push().prop_map(Instruction::Push),
+        reg16_push().prop_map(Instruction::Pop),
+    ];
+    let immediates = prop_oneof![
+        i8().prop_map(Instruction::AddI8),
+        s8().prop_map(Instruction::AddRspI8),
+        i8().prop_map(Instruction::SubI8),
+        i8().prop_map(Instruction::AndI8),
+        i8().prop_map(Instruction::OrI8),
+        i8().prop_map(Instruction::AdcI8),
+        i8().prop_map(Instruction::SbcI8),
+        i8().prop_map(Instruction::XorI8),
+        i8().prop_map(Instruction::CpI8),
+        (reg16(), i16()).prop_map(|(reg, expr)| Instruction::LdR16I16(reg, expr)),
+        i16().prop_map(Instruction::LdMI16Rsp),
+        (reg8(), i8()).prop_map(|(reg, expr)| Instruction::LdR8I8(reg, expr)),
+        i8().prop_map(Instruction::LdMRhlI8),
+        i16().prop_map(Instruction::LdMI16Ra),
+        i16().prop_map(Instruction::LdRaMI16),
+        i8().prop_map(Instruction::LdhRaMI8),
+        i8().prop_map(Instruction::LdhMI8Ra),
+        s8().prop_map(Instruction::LdRhlRspI8),
+    ];
+    let cb = prop_oneof![
+        reg8().prop_map(Instruction::RlcR8),
+        reg8().prop_map(Instruction::RrcR8),
+        reg8().prop_map(Instruction::RlR8),
+        reg8().prop_map(Instruction::RrR8),
+        reg8().prop_map(Instruction::SlaR8),
+        reg8().prop_map(Instruction::SraR8),
+        reg8().prop_map(Instruction::SwapR8),
+        reg8().prop_map(Instruction::SrlR8),
+        (bit(), reg8()).prop_map(|(bit, reg)| Instruction::BitBitR8(bit, reg)),
+        bit().prop_map(Instruction::BitBitMRhl),
+        (bit(), reg8()).prop_map(|(bit, reg)| Instruction::ResBitR8(bit, reg)),
+        bit().prop_map(Instruction::ResBitMRhl),
+        (bit(), reg8()).prop_map(|(bit, reg)| Instruction::SetBitR8(bit, reg)),
+        bit().prop_map(Instruction::SetBitMRhl),
+    ];
+    prop_oneof![no_operands, control_flow, registers, immediates, cb]
+}
+
+proptest! {
+    #[test]
+    fn test_round_trip(instructions in vec(instruction(), 1..50)) {
+        let constants = HashMap::new();
+        let mut rom = vec![];
+        let mut expected = vec![];
+        for mut instruction in instructions {
+            if let Instruction::Jr(_, Expr::Const(offset)) = &mut instruction {
+                *offset += rom.len() as i64 + 2;
+            }
+            let start = rom.len();
+            instruction.write_to_rom(&mut rom, &constants).unwrap();
+            prop_assert_eq!(rom.len() - start, instruction.bytes_len(start as u16).unwrap() as usize);
+            expected.push(instruction);
+        }
+        prop_assert_eq!(disassemble(&rom, 0).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_print_round_trip(instructions in vec(instruction(), 1..50)) {
+        let constants = HashMap::new();
+        let mut expected = vec![];
+        let mut printed = vec![];
+        for mut instruction in instructions {
+            if let Instruction::Jr(_, Expr::Const(offset)) = &mut instruction {
+                *offset += expected.len() as i64 + 2;
+            }
+            instruction.write_to_rom(&mut expected, &constants).unwrap();
+            printed.push(instruction);
+        }
+
+        // negative constants are parsed as negated expressions, so compare the encoded bytes
+        let mut rom = vec![];
+        for instruction in parse_asm(&to_asm_string(&printed)).unwrap() {
+            instruction.unwrap().write_to_rom(&mut rom, &constants).unwrap();
+        }
+        prop_assert_eq!(rom, expected);
+    }
+}
+
+#[test]
+fn test_decode_table_complete() {
+    let table = ggbasm::opcodes::decode_table();
+    assert!(table.prefixed.iter().all(|x| x.is_some()));
+    let missing: Vec<usize> = table
+        .unprefixed
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.is_none())
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(
+        missing,
+        vec![0xCB, 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD]
+    );
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use ggbasm::header::*;
+use ggbasm::scaffold::{new_project, Template};
+use ggbasm::{Color, RomBuilder};
+
+/// Builds the rom of a generated project the same way its src/main.rs does.
+fn build(template: Template) -> Vec<u8> {
+    let mut builder = RomBuilder::new()
+        .unwrap()
+        .add_basic_interrupts_and_jumps()
+        .unwrap()
+        .add_header(Header {
+            title: String::from(template.name()),
+            color_support: ColorSupport::Unsupported,
+            licence: String::new(),
+            sgb_support: false,
+            cartridge_type: CartridgeType::RomOnly,
+            ram_type: RamType::None,
+            japanese: false,
+            version_number: 0,
+        })
+        .unwrap()
+        .add_hardware_constants()
+        .unwrap()
+        .add_asm_file("main.asm")
+        .unwrap();
+    if matches!(template, Template::SpriteDemo | Template::Platformer) {
+        let mut colors_map = HashMap::new();
+        colors_map.insert(Color::new(255, 255, 255), 0);
+        colors_map.insert(Color::new(170, 170, 170), 1);
+        colors_map.insert(Color::new(85, 85, 85), 2);
+        colors_map.insert(Color::new(0, 0, 0), 3);
+        builder = builder
+            .add_image("tiles.png", "Tiles", &colors_map)
+            .unwrap();
+    }
+    if template == Template::MusicDemo {
+        builder = builder
+            .add_audio_player()
+            .unwrap()
+            .add_audio_file("song.txt")
+            .unwrap();
+    }
+    builder.compile().unwrap()
+}
+
+#[test]
+fn test_new_project() {
+    let root = std::env::temp_dir().join("ggbasm_test_scaffold");
+    let _ = std::fs::remove_dir_all(&root);
+    for template in Template::ALL {
+        let path = root.join(template.name());
+        new_project(&path, template).unwrap();
+        for file in ["Cargo.toml", "src/main.rs", "gbasm/main.asm"] {
+            assert!(path.join(file).exists(), "{} missing {}", template, file);
+        }
+        for asm in std::fs::read_dir(path.join("gbasm")).unwrap() {
+            let text = std::fs::read_to_string(asm.unwrap().path()).unwrap();
+            assert!(ggbasm::parser::parse_asm(&text)
+                .unwrap()
+                .iter()
+                .all(|x| x.is_ok()));
+        }
+        assert_eq!(
+            path.join("graphics/tiles.png").exists(),
+            matches!(template, Template::SpriteDemo | Template::Platformer)
+        );
+        assert_eq!(
+            path.join("audio/song.txt").exists(),
+            template == Template::MusicDemo
+        );
+
+        // the RomBuilder finds the project from the current directory, the same as `cargo run` in the project
+        std::env::set_current_dir(&path).unwrap();
+        let rom = build(template);
+        assert_eq!(rom.len(), 0x8000, "{}", template);
+
+        let err = new_project(&path, template).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("{} already exists and is not empty", path.display())
+        );
+    }
+}
+
+#[test]
+fn test_template_names() {
+    for template in Template::ALL {
+        assert_eq!(template.name().parse::<Template>().unwrap(), template);
+    }
+    assert_eq!(
+        "rpg".parse::<Template>().err().unwrap().to_string(),
+        "Unknown template rpg, must be one of: blank, sprite-demo, platformer, music-demo"
+    );
+}
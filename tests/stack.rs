@@ -0,0 +1,126 @@
+mod common;
+
+use common::graph;
+use ggbasm::stack::*;
+
+#[test]
+fn test_stack_depth() {
+    let graph = graph(
+        r#"
+Main:
+    ld sp, $FFFE
+    call Balanced
+    call Deep
+    jr Main
+Balanced:
+    push af
+    push bc
+    pop bc
+    pop af
+    ret
+Deep:
+    push hl
+    add sp, -4
+    call Balanced
+    add sp, 4
+    pop hl
+    ret
+"#,
+    );
+    let analysis = stack_analysis(&graph, None);
+    assert_eq!(
+        analysis.routine_by_name("Balanced").unwrap().max_depth,
+        Some(4)
+    );
+    assert_eq!(
+        analysis.routine_by_name("Deep").unwrap().max_depth,
+        Some(12)
+    );
+    let entry = analysis
+        .routines
+        .iter()
+        .find(|x| x.address == 0x100)
+        .unwrap();
+    assert_eq!(entry.max_depth, Some(14));
+    // the dummy interrupts only push their return address
+    assert_eq!(analysis.worst_case, Some(16));
+    assert!(analysis.issues.is_empty());
+
+    let analysis = stack_analysis(&graph, Some(12));
+    let issues: Vec<_> = analysis.issues.iter().map(|x| x.to_string()).collect();
+    assert_eq!(
+        issues,
+        vec![
+            "0x0100 at 0x0100: Uses up to 14 bytes of stack, exceeding the budget of 12 bytes",
+            "Entry point and interrupts at 0x0100: Use up to 16 bytes of stack, exceeding the budget of 12 bytes",
+        ]
+    );
+}
+
+#[test]
+fn test_unbalanced_stack() {
+    let graph = graph(
+        r#"
+Main:
+    call Leaky
+    call Popper
+    call Looping
+    call Recursive
+    jr Main
+Leaky:
+    push af
+    ret z
+    pop af
+    ret
+Popper:
+    pop af
+    ret
+Looping:
+    push af
+    dec b
+    jr nz, Looping
+    pop af
+    ret
+Recursive:
+    call Recursive
+    ret
+"#,
+    );
+    let leaky = graph.routine_by_name("Leaky").unwrap().address;
+    let popper = graph.routine_by_name("Popper").unwrap().address;
+    let looping = graph.routine_by_name("Looping").unwrap().address;
+    let recursive = graph.routine_by_name("Recursive").unwrap().address;
+
+    let analysis = stack_analysis(&graph, Some(0x100));
+    assert_eq!(
+        analysis.routine_by_name("Leaky").unwrap().max_depth,
+        Some(2)
+    );
+    assert_eq!(
+        analysis.routine_by_name("Recursive").unwrap().max_depth,
+        None
+    );
+    assert_eq!(analysis.worst_case, None);
+    let issues: Vec<_> = analysis
+        .issues
+        .iter()
+        .map(|x| (x.routine.as_str(), x.address, x.message.as_str()))
+        .collect();
+    assert_eq!(
+        issues,
+        vec![
+            ("Leaky", leaky + 1, "Returns with 2 bytes still pushed"),
+            ("Looping", looping, "Reached with both 0 and 2 bytes pushed"),
+            (
+                "Popper",
+                popper + 1,
+                "Returns after popping 2 bytes more than it pushed"
+            ),
+            (
+                "Recursive",
+                recursive,
+                "Calls Recursive recursively, its stack usage is unbounded"
+            ),
+        ]
+    );
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use ggbasm::opcodes::decode_table;
+use ggbasm::stats::*;
+
+#[test]
+fn test_instruction_stats() {
+    let text =
+        "    ld a, 1\n    ld [hl], a\n    nop\n    nop\n    bit 7, a\n    db 1, 2, 3\n    ret\n";
+    let (rom, layout) = common::compile(text);
+
+    let stats = instruction_stats(&rom, &layout).unwrap();
+    let nop = stats
+        .mnemonics
+        .iter()
+        .find(|x| x.mnemonic == "nop")
+        .unwrap();
+    assert_eq!((nop.count, nop.bytes), (2, 2));
+    let ld = stats.mnemonics.iter().find(|x| x.mnemonic == "ld").unwrap();
+    assert_eq!((ld.count, ld.bytes), (2, 3));
+    assert_eq!(stats.opcodes[0x3E], 1);
+    assert_eq!(stats.opcodes[0x77], 1);
+    assert_eq!(stats.prefixed_opcodes[0x7F], 1);
+    assert_eq!(stats.data_bytes, 3);
+    assert_eq!(
+        stats.code_bytes,
+        stats.mnemonics.iter().map(|x| x.bytes).sum::<u32>()
+    );
+    assert!(stats.mnemonics.windows(2).all(|x| x[0].bytes >= x[1].bytes));
+}
+
+#[test]
+fn test_every_opcode_has_a_mnemonic() {
+    let table = decode_table();
+    for instruction in table.unprefixed.iter().chain(&table.prefixed).flatten() {
+        assert!(instruction.mnemonic().is_some(), "{:?}", instruction);
+    }
+}
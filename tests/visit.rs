@@ -0,0 +1,88 @@
+use ggbasm::ast::*;
+use ggbasm::parser::parse_asm;
+use ggbasm::visit::*;
+
+fn parse(text: &str) -> Vec<Instruction> {
+    parse_asm(text)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect()
+}
+
+#[derive(Default)]
+struct Names {
+    idents: Vec<String>,
+    names: Vec<String>,
+    instructions: usize,
+}
+
+impl Visitor for Names {
+    fn visit_instruction(&mut self, instruction: &Instruction) {
+        self.instructions += 1;
+        walk_instruction(self, instruction);
+    }
+
+    fn visit_ident(&mut self, ident: &str) {
+        self.idents.push(ident.to_string());
+    }
+
+    fn visit_name(&mut self, name: &str) {
+        self.names.push(name.to_string());
+    }
+}
+
+#[test]
+fn test_visitor() {
+    let text = r#"SECTION "Main", ROMX[Base], BANK[Bank]
+Lives EQU 3
+Main:
+    export Main
+    ld a, Lives + Extra * 2
+    jr nz, Main
+    db "text", Char
+    bit Bit, [hl]
+"#;
+    let mut names = Names::default();
+    for instruction in &parse(text) {
+        names.visit_instruction(instruction);
+    }
+    assert_eq!(names.instructions, 8);
+    assert_eq!(names.names, vec!["Lives", "Main"]);
+    assert_eq!(
+        names.idents,
+        vec!["Base", "Bank", "Main", "Lives", "Extra", "Main", "Char", "Bit"]
+    );
+}
+
+#[test]
+fn test_walk_exprs() {
+    let mut instructions = parse("    ld a, 1 + 2 * Three\n    jp (4 - 1) * Three\n");
+    walk_exprs(&mut instructions, |expr| {
+        if *expr == Expr::Ident(String::from("Three")) {
+            *expr = Expr::Const(3);
+        }
+        // sub expressions are already folded
+        if let Ok(value) = expr.run(&Default::default()) {
+            *expr = Expr::Const(value);
+        }
+    });
+    assert_eq!(
+        instructions,
+        vec![
+            Instruction::LdR8I8(Reg8::A, Expr::Const(7)),
+            Instruction::JpI16(Flag::Always, Expr::Const(9)),
+        ]
+    );
+}
+
+#[test]
+fn test_map_instructions() {
+    let instructions = parse("    nop\n    halt\n    nop\n");
+    let result = map_instructions(instructions, |instruction| match instruction {
+        Instruction::Nop => vec![],
+        Instruction::Halt => vec![Instruction::Di, Instruction::Halt],
+        instruction => vec![instruction],
+    });
+    assert_eq!(result, vec![Instruction::Di, Instruction::Halt]);
+}